@@ -8,6 +8,42 @@ set -euo pipefail
 # - OUTPUT_PREFIX: S3 prefix for output files (e.g., /run/<run_id>)
 # - MAX_ZOOM: Max Web Mercator zoom (optional; defaults handled by the sharder binary)
 # - MAX_NODES_PER_SHARD: Max nodes per shard (optional; defaults handled by the sharder binary)
+# - DUAL_OUTPUT_SCHEMA: Legacy manifest schema to keep writing during a migration (optional)
+# - DUAL_OUTPUT_RUNS: Number of runs to keep writing the legacy manifest (optional)
+# - CHECKPOINT_INTERVAL: Seconds between scan checkpoints (optional; default 300)
+# - MEMORY_LIMIT: Cap on in-memory counts before spilling to /data, e.g. 12G (optional)
+# - SCAN_THREADS / SCAN_CPUS: Scan worker count and CPU pinning, e.g. 4 / 0-3 (optional)
+# - PROGRESS: Scan progress on stderr: text, json or none (optional, default text)
+# - PROGRESS_INTERVAL: Seconds between progress updates (optional, default 5)
+# - LOG_FORMAT: Log output on stderr: text or json (optional, default text)
+# - PUSHGATEWAY_URL / OTEL_EXPORTER_OTLP_ENDPOINT: Push run metrics over HTTP at exit (optional)
+# - METRICS_JOB: Job name on exported metrics (optional, default osm-sharding)
+# - METRICS: Set to cloudwatch-emf to log run metrics in CloudWatch Embedded Metric Format (optional)
+# - COORDINATE_PRECISION: Decimal places in manifest coordinates, 0-15 (optional, default 9)
+# - MANIFEST_FORMAT: Manifest file format: geojson, geojsonseq, flatgeobuf, geoparquet, csv, tsv, kml, kmz or quadkeys (optional, default geojson)
+# - MANIFEST_COMPRESSION: Compress the uploaded manifest with gzip or zstd (optional)
+# - COMPACT_OUTPUT: Set to true to write the manifest on one line (optional)
+# - PMTILES_MAX_ZOOM: Highest zoom of the shards.pmtiles preview (optional, default 8)
+# - VALIDATE_GEOMETRY: Set to true to check shard polygons before writing the manifest (optional)
+# - S3_PART_SIZE_MB / S3_MAX_ATTEMPTS: Multipart part size and attempts per S3 request for checkpoints (optional, default 64 / 5)
+# - S3_INITIAL_BACKOFF_MS / S3_TIMEOUT: First retry delay and per-attempt timeout in seconds for the sharder's S3 requests (optional)
+# - REQUESTER_PAYS: Set to true to accept requester-pays charges when reading from S3, e.g. the planet (optional)
+# - S3_KEY_TEMPLATE: Key of every uploaded object, with {run_id}, {date}, {grid}, {format} and {name} placeholders (optional, default <OUTPUT_PREFIX>/shards/{name})
+# - SSE_KMS_KEY_ID / S3_STORAGE_CLASS: KMS key and storage class for every uploaded object (optional)
+# - S3_TAGS: Comma-separated key=value tags for every uploaded object (optional)
+# - S3_ENDPOINT / S3_FORCE_PATH_STYLE: S3-compatible endpoint (e.g. MinIO) and path-style bucket addressing for the sharder's own S3 access (optional)
+# - ASSUME_ROLE_ARN / ASSUME_ROLE_EXTERNAL_ID / ASSUME_ROLE_SESSION_NAME: Role for the sharder's own S3 access, e.g. in another account (optional)
+# - NOTIFY_TOPIC_ARN / NOTIFY_EVENT_BUS: SNS topic and EventBridge bus to send the run's success or failure event to (optional)
+# - TASK_TOKEN / TASK_HEARTBEAT_INTERVAL: Step Functions task token to send heartbeats (every 60 seconds by default) and the run's success or failure to (optional)
+# - PRESIGN_EXPIRY: Seconds for presigned manifest and summary URLs, printed and added to the run event (optional)
+# - RUNS_TABLE: DynamoDB table (partition key run_id) to record each run in (optional)
+# - BLOB_INDEX_ZOOM: Zoom of the tiles the planet's blob index records per blob (optional, default 10)
+
+# With arguments, the image runs them as a command of the binary instead, such as the
+# `extract-pois` of a Batch job definition, which then gets the SIGTERM that stops the job.
+if [ "$#" -gt 0 ]; then
+    exec osm-planet-sharding "$@"
+fi
 
 echo "========================================"
 echo "OSM-H3 Sharder"
@@ -18,6 +54,7 @@ echo "Input Prefix: ${INPUT_PREFIX:-not set}"
 echo "Output Prefix: ${OUTPUT_PREFIX:-not set}"
 echo "Max Zoom (MAX_ZOOM): ${MAX_ZOOM:-<unset>}"
 echo "Max Nodes Per Shard (MAX_NODES_PER_SHARD): ${MAX_NODES_PER_SHARD:-<unset>}"
+echo "Dual Output Schema (DUAL_OUTPUT_SCHEMA): ${DUAL_OUTPUT_SCHEMA:-<unset>}"
 echo ""
 
 # Validate required env vars
@@ -30,8 +67,12 @@ fi
 PLANET_KEY="${INPUT_PREFIX#/}/planet.osm.pbf"
 PLANET_PATH="/data/planet.osm.pbf"
 
+DOWNLOAD_ARGS=()
+if [ "${REQUESTER_PAYS:-false}" = "true" ]; then
+    DOWNLOAD_ARGS=(--request-payer requester)
+fi
 echo "Downloading s3://${S3_BUCKET}/${PLANET_KEY}..."
-if ! aws s3 cp "s3://${S3_BUCKET}/${PLANET_KEY}" "${PLANET_PATH}"; then
+if ! aws s3 cp "${DOWNLOAD_ARGS[@]}" "s3://${S3_BUCKET}/${PLANET_KEY}" "${PLANET_PATH}"; then
     echo "aws s3 cp failed, falling back to HTTPS..."
     # -f makes curl exit nonzero on 4xx/5xx so we don't accept AccessDenied XML.
     curl -sS -L -f "https://${S3_BUCKET}.s3.amazonaws.com/${PLANET_KEY}" -o "${PLANET_PATH}"
@@ -46,20 +87,127 @@ fi
 
 echo "Downloaded $(du -h ${PLANET_PATH} | cut -f1)"
 
-# Run the sharder (outputs GeoJSON to stdout)
+# Every object is uploaded under a key from S3_KEY_TEMPLATE; the sharder expands the same
+# template for the objects it writes itself.
+if [ -z "${S3_KEY_TEMPLATE:-}" ]; then
+    S3_KEY_TEMPLATE="${OUTPUT_PREFIX#/}/shards/{name}"
+fi
+export S3_KEY_TEMPLATE
+RUN_DATE=$(date -u +%F)
+object_key() {
+    local key="${S3_KEY_TEMPLATE}"
+    key="${key//\{run_id\}/${RUN_ID}}"
+    key="${key//\{date\}/${RUN_DATE}}"
+    key="${key//\{grid\}/web-mercator-quadtree}"
+    key="${key//\{format\}/${MANIFEST_FORMAT:-geojson}}"
+    key="${key//\{name\}/$1}"
+    echo "${key}"
+}
+
+# Upload FILE to KEY with the encryption, storage class and tags every object gets; any
+# further arguments go to `aws s3 cp`.
+S3_CP_ARGS=()
+if [ -n "${SSE_KMS_KEY_ID:-}" ]; then
+    S3_CP_ARGS+=(--sse aws:kms --sse-kms-key-id "${SSE_KMS_KEY_ID}")
+fi
+if [ -n "${S3_STORAGE_CLASS:-}" ]; then
+    S3_CP_ARGS+=(--storage-class "${S3_STORAGE_CLASS}")
+fi
+TAG_SET=""
+if [ -n "${S3_TAGS:-}" ]; then
+    IFS=',' read -ra TAGS <<< "${S3_TAGS}"
+    for TAG in "${TAGS[@]}"; do
+        TAG_SET+="${TAG_SET:+,}{\"Key\":\"${TAG%%=*}\",\"Value\":\"${TAG#*=}\"}"
+    done
+    TAG_SET="{\"TagSet\":[${TAG_SET}]}"
+fi
+upload() {
+    local file="$1" key="$2"
+    shift 2
+    aws s3 cp "${S3_CP_ARGS[@]}" "$@" --checksum-algorithm SHA256 \
+        --metadata "sha256=$(sha256sum "${file}" | cut -d' ' -f1)" \
+        "${file}" "s3://${S3_BUCKET}/${key}"
+    if [ -n "${TAG_SET}" ]; then
+        aws s3api put-object-tagging --bucket "${S3_BUCKET}" --key "${key}" --tagging "${TAG_SET}"
+    fi
+}
+
+# Dual-output migration mode: the run counter lives in the bucket so it survives between runs.
+MIGRATION_STATE_KEY="shards/migration-state.json"
+if [ -n "${DUAL_OUTPUT_SCHEMA:-}" ]; then
+    export DUAL_OUTPUT_DIR="/data/migration"
+    export MIGRATION_STATE="/data/migration-state.json"
+    aws s3 cp "s3://${S3_BUCKET}/${MIGRATION_STATE_KEY}" "${MIGRATION_STATE}" \
+        || echo "No migration state found, starting a new dual-output window."
+fi
+
+# Checkpoint the scan to S3 so a retried job (e.g. after a spot interruption) resumes it.
+export SCAN_CHECKPOINT="s3://${S3_BUCKET}"
+export RESUME=true
+# Spill files (only written when MEMORY_LIMIT is set) go next to the planet on the data volume.
+export SPILL_DIR="/data"
+
+# Run the sharder. It publishes the manifest itself: under a content-addressed key, then
+# its usual key, then the runs' latest.json pointer, so workers never see a partial one.
 echo ""
 echo "Running sharder..."
-MANIFEST_PATH="/data/manifest.json"
-osm-planet-sharding "${PLANET_PATH}" > "${MANIFEST_PATH}"
+# The manifest is staged on the data volume before upload.
+export TMPDIR="/data"
+LATEST_KEY="${OUTPUT_PREFIX#/}"
+LATEST_KEY="${LATEST_KEY%/*}/latest.json"
+# Keep the count histogram so shards can be re-planned later without rescanning the planet.
+export SAVE_COUNTS="/data/counts.hist.gz"
+# Run summary for orchestration health checks, uploaded by the sharder next to the manifest.
+export SUMMARY_PATH="s3://${S3_BUCKET}"
+# Byte ranges of each shard's node blobs in the planet, for ranged reads by extract workers.
+export BLOB_INDEX="s3://${S3_BUCKET}"
+# Vector tiles of the shard plan for viewing in MapLibre.
+export SHARDS_PMTILES="/data/shards.pmtiles"
+# The uploads below follow it, so rather than exec it, pass it the SIGTERM that stops the job
+# and exit as it does.
+osm-planet-sharding \
+    --output "s3://${S3_BUCKET}" \
+    --latest-pointer "s3://${S3_BUCKET}/${LATEST_KEY}" \
+    "${PLANET_PATH}" &
+SHARDER_PID=$!
+trap 'kill -TERM "${SHARDER_PID}" 2>/dev/null' TERM INT
+# A trapped signal ends `wait` early, so wait until the sharder has exited.
+SHARDER_STATUS=0
+wait "${SHARDER_PID}" || SHARDER_STATUS=$?
+while kill -0 "${SHARDER_PID}" 2>/dev/null; do
+    SHARDER_STATUS=0
+    wait "${SHARDER_PID}" || SHARDER_STATUS=$?
+done
+trap - TERM INT
+if [ "${SHARDER_STATUS}" -ne 0 ]; then
+    exit "${SHARDER_STATUS}"
+fi
 
-# Upload manifest to S3
-MANIFEST_KEY="${OUTPUT_PREFIX#/}/shards/manifest.json"
 echo ""
-echo "Uploading manifest to s3://${S3_BUCKET}/${MANIFEST_KEY}..."
-aws s3 cp "${MANIFEST_PATH}" "s3://${S3_BUCKET}/${MANIFEST_KEY}"
+COUNTS_KEY=$(object_key counts.hist.gz)
+echo "Uploading count histogram to s3://${S3_BUCKET}/${COUNTS_KEY}..."
+upload "${SAVE_COUNTS}" "${COUNTS_KEY}"
+
+PMTILES_KEY=$(object_key shards.pmtiles)
+echo "Uploading shard plan tiles to s3://${S3_BUCKET}/${PMTILES_KEY}..."
+upload "${SHARDS_PMTILES}" "${PMTILES_KEY}"
+
+if [ -n "${DUAL_OUTPUT_SCHEMA:-}" ]; then
+    if [ -d "${DUAL_OUTPUT_DIR}" ]; then
+        echo "Uploading legacy manifest and migration report..."
+        for FILE in "${DUAL_OUTPUT_DIR}"/*; do
+            upload "${FILE}" "$(object_key "$(basename "${FILE}")")"
+        done
+        rm -rf "${DUAL_OUTPUT_DIR}"
+    fi
+    if [ -f "${MIGRATION_STATE}" ]; then
+        upload "${MIGRATION_STATE}" "${MIGRATION_STATE_KEY}"
+        rm -f "${MIGRATION_STATE}"
+    fi
+fi
 
 # Cleanup
-rm -f "${PLANET_PATH}" "${MANIFEST_PATH}"
+rm -f "${PLANET_PATH}" "${SAVE_COUNTS}" "${SHARDS_PMTILES}"
 
 echo ""
 echo "Sharding complete!"
@@ -0,0 +1,157 @@
+//! AWS Batch array jobs of shards: `submit` submits an array job with a child per shard of
+//! a manifest, and each child's `extract-pois --manifest` extracts the shard at its
+//! `AWS_BATCH_JOB_ARRAY_INDEX` in the manifest, which both read the same way, so the job
+//! always matches the manifest it was submitted for.
+//!
+//! Like the notifications, this goes through `aws`.
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde_json::{json, Value};
+use std::io::Write;
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+
+use crate::extract::read_manifest;
+use crate::keys;
+use crate::store::run_cli;
+
+/// Most children of an array job.
+const MAX_ARRAY_SIZE: usize = 10_000;
+
+#[derive(Args, Debug)]
+pub struct SubmitArgs {
+    /// Manifest to submit a child per shard of, in any format `extract` reads: a URI the
+    /// children can read too, which they are given as `MANIFEST`.
+    #[arg(long, env = "MANIFEST")]
+    manifest: String,
+
+    /// Job queue to submit to.
+    #[arg(long, env = "BATCH_JOB_QUEUE")]
+    job_queue: String,
+
+    /// Job definition (name, `name:revision` or ARN) of the children, one running
+    /// `extract-pois` with the image's other settings.
+    #[arg(long, env = "BATCH_JOB_DEFINITION")]
+    job_definition: String,
+
+    /// Name of the job, with the placeholders of `--s3-key-template`.
+    #[arg(long, env = "BATCH_JOB_NAME", default_value = "extract-pois-{run_id}")]
+    job_name: String,
+
+    /// Further environment of the children, `KEY=VALUE`, over the job definition's.
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    environment: Vec<String>,
+
+    /// Attempts of each child, so one on a reclaimed spot instance is run again.
+    #[arg(
+        long,
+        env = "BATCH_ATTEMPTS",
+        value_parser = clap::value_parser!(u8).range(1..=10)
+    )]
+    attempts: Option<u8>,
+
+    /// Jobs (by ID) the array job waits for, such as the sharder's.
+    #[arg(long, env = "BATCH_DEPENDS_ON", value_delimiter = ',')]
+    depends_on: Vec<String>,
+}
+
+pub fn submit(args: &SubmitArgs) -> Result<()> {
+    let manifest = keys::location(&args.manifest, "manifest.json")?;
+    if !manifest.contains("://") {
+        warn!("The children need to read the manifest at {manifest} too.");
+    }
+    let shards = read_manifest(&manifest)?.len();
+    if shards == 0 {
+        bail!("{manifest} has no shards to submit");
+    }
+    if shards > MAX_ARRAY_SIZE {
+        bail!(
+            "{manifest} has {shards} shards, more than the {MAX_ARRAY_SIZE} children of an \
+             array job; enqueue them for extract-pois --queue-url workers instead"
+        );
+    }
+    let job_name = keys::expand(&args.job_name)?;
+
+    let mut environment = vec![json!({"name": "MANIFEST", "value": manifest})];
+    if let Some(run_id) = keys::run_id() {
+        environment.push(json!({"name": "RUN_ID", "value": run_id}));
+    }
+    for variable in &args.environment {
+        let Some((name, value)) = variable.split_once('=') else {
+            bail!("--env {variable} is not KEY=VALUE");
+        };
+        environment.push(json!({"name": name, "value": value}));
+    }
+    let mut request = json!({
+        "jobName": job_name,
+        "jobQueue": args.job_queue,
+        "jobDefinition": args.job_definition,
+        "containerOverrides": {"environment": environment},
+    });
+    // An array job needs two children at least; a single shard is a plain job, whose child
+    // takes the manifest's only shard.
+    if shards > 1 {
+        request["arrayProperties"] = json!({ "size": shards });
+    }
+    if let Some(attempts) = args.attempts {
+        request["retryStrategy"] = json!({ "attempts": attempts });
+    }
+    if !args.depends_on.is_empty() {
+        let depends_on: Vec<Value> = args
+            .depends_on
+            .iter()
+            .map(|job_id| json!({ "jobId": job_id }))
+            .collect();
+        request["dependsOn"] = json!(depends_on);
+    }
+
+    let mut body = NamedTempFile::new()?;
+    serde_json::to_writer(&mut body, &request)?;
+    body.flush()?;
+    let path = format!("file://{}", body.path().display());
+    let args = [
+        "batch",
+        "submit-job",
+        "--output",
+        "json",
+        "--cli-input-json",
+        &path,
+    ];
+    let stdout = match run_cli("aws", &args)? {
+        Ok(stdout) => stdout,
+        Err(stderr) => bail!("unable to submit {job_name}: {stderr}"),
+    };
+    let response: Value =
+        serde_json::from_slice(&stdout).context("unexpected submit-job response")?;
+    let job_id = response["jobId"]
+        .as_str()
+        .context("submit-job response without a jobId")?;
+    info!(
+        job_id,
+        shards, "Submitted {job_name} ({job_id}) for the {shards} shard(s) of {manifest}."
+    );
+    println!("{job_id}");
+    Ok(())
+}
+
+/// The shard of an array job's child: the one at `index` in the manifest, or its only one.
+pub fn array_shard(manifest: &str, index: Option<usize>) -> Result<String> {
+    let mut shards = read_manifest(manifest)?;
+    let index = match index {
+        Some(index) => index,
+        None if shards.len() == 1 => 0,
+        None => bail!(
+            "{manifest} has {} shards, but no AWS_BATCH_JOB_ARRAY_INDEX (--shard-index) says \
+             which to extract",
+            shards.len()
+        ),
+    };
+    if index >= shards.len() {
+        bail!(
+            "shard index {index} is past the {} shards of {manifest}",
+            shards.len()
+        );
+    }
+    Ok(shards.swap_remove(index).id)
+}
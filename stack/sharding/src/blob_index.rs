@@ -0,0 +1,169 @@
+//! Blob index: the byte ranges of a PBF that hold each shard's nodes, so an extract worker
+//! can fetch the header and just those blobs with ranged reads instead of streaming the
+//! whole planet. Recorded during the scan and published as JSON next to the manifest.
+//!
+//! Node IDs are not spatially ordered, so the scan notes the tiles every node blob touches
+//! at a coarse index zoom (not the max zoom, which would take a set of up to 8000 tiles per
+//! blob). A shard's ranges therefore cover all of its nodes, plus some of its neighbours'.
+
+use anyhow::Result;
+use hashbrown::HashMap;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use tracing::info;
+
+use crate::store::Store;
+use crate::summary::Summary;
+use crate::Shard;
+
+/// Every node blob of one PBF with the index-zoom tiles its nodes fall in.
+pub struct BlobIndex {
+    zoom: u8,
+    /// Offset and length of the header blob.
+    header: Option<(u64, u64)>,
+    /// Offset of the first blob without nodes in a type-sorted file, from which on the
+    /// file holds only ways and relations.
+    ways_offset: Option<u64>,
+    blobs: Vec<NodeBlob>,
+}
+
+struct NodeBlob {
+    offset: u64,
+    length: u64,
+    tiles: Box<[(u32, u32)]>,
+}
+
+impl BlobIndex {
+    pub fn new(zoom: u8) -> Self {
+        Self {
+            zoom,
+            header: None,
+            ways_offset: None,
+            blobs: Vec::new(),
+        }
+    }
+
+    pub fn zoom(&self) -> u8 {
+        self.zoom
+    }
+
+    pub fn set_header(&mut self, offset: u64, length: u64) {
+        self.header = Some((offset, length));
+    }
+
+    pub fn set_ways_offset(&mut self, offset: u64) {
+        self.ways_offset.get_or_insert(offset);
+    }
+
+    /// Record a node blob and the tiles (at the index zoom) of its nodes.
+    pub fn push(&mut self, offset: u64, length: u64, mut tiles: Vec<(u32, u32)>) {
+        tiles.sort_unstable();
+        tiles.dedup();
+        self.blobs.push(NodeBlob {
+            offset,
+            length,
+            tiles: tiles.into_boxed_slice(),
+        });
+    }
+
+    /// The byte ranges of each shard, adjacent blobs merged into one range.
+    fn ranges(&self, shards: &[Shard]) -> BTreeMap<String, Vec<[u64; 2]>> {
+        // Shards at or above the index zoom sit inside one index tile; coarser shards
+        // cover many, so those are found from each tile's ancestors instead.
+        let mut fine: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        let mut coarse: HashMap<(u8, u32, u32), usize> = HashMap::new();
+        for (idx, shard) in shards.iter().enumerate() {
+            match shard.zoom.checked_sub(self.zoom) {
+                Some(shift) => fine
+                    .entry((shard.x >> shift, shard.y >> shift))
+                    .or_default()
+                    .push(idx),
+                None => {
+                    coarse.insert((shard.zoom, shard.x, shard.y), idx);
+                }
+            }
+        }
+        let mut coarse_zooms: Vec<u8> = coarse.keys().map(|&(zoom, _, _)| zoom).collect();
+        coarse_zooms.sort_unstable();
+        coarse_zooms.dedup();
+
+        let mut blobs: Vec<Vec<usize>> = vec![Vec::new(); shards.len()];
+        for (blob_idx, blob) in self.blobs.iter().enumerate() {
+            for &(x, y) in blob.tiles.iter() {
+                let ancestors = coarse_zooms.iter().filter_map(|&zoom| {
+                    let shift = self.zoom - zoom;
+                    coarse.get(&(zoom, x >> shift, y >> shift)).copied()
+                });
+                let within = fine.get(&(x, y)).into_iter().flatten().copied();
+                for shard in within.chain(ancestors) {
+                    // Blobs are visited in order, so repeats are adjacent.
+                    if blobs[shard].last() != Some(&blob_idx) {
+                        blobs[shard].push(blob_idx);
+                    }
+                }
+            }
+        }
+
+        shards
+            .iter()
+            .zip(blobs)
+            .map(|(shard, blobs)| {
+                let mut ranges: Vec<[u64; 2]> = Vec::new();
+                for blob in blobs.into_iter().map(|idx| &self.blobs[idx]) {
+                    match ranges.last_mut() {
+                        Some(range) if range[0] + range[1] == blob.offset => {
+                            range[1] += blob.length;
+                        }
+                        _ => ranges.push([blob.offset, blob.length]),
+                    }
+                }
+                (format!("{}-{}-{}", shard.zoom, shard.x, shard.y), ranges)
+            })
+            .collect()
+    }
+}
+
+/// The published index.
+#[derive(Serialize)]
+struct Published<'a> {
+    /// File name of the indexed PBF.
+    file: &'a str,
+    bytes: u64,
+    index_zoom: u8,
+    /// `[offset, length]` of the header blob, which every extract needs first.
+    header: Option<[u64; 2]>,
+    ways_offset: Option<u64>,
+    /// `[offset, length]` ranges holding each shard's nodes, by shard ID.
+    shards: BTreeMap<String, Vec<[u64; 2]>>,
+}
+
+/// Map the index onto `shards` and write it to `store`.
+pub fn write(
+    index: &BlobIndex,
+    shards: &[Shard],
+    file: &str,
+    file_bytes: u64,
+    store: &Store,
+    summary: &mut Summary,
+) -> Result<()> {
+    let published = Published {
+        file,
+        bytes: file_bytes,
+        index_zoom: index.zoom,
+        header: index.header.map(|(offset, length)| [offset, length]),
+        ways_offset: index.ways_offset,
+        shards: index.ranges(shards),
+    };
+    let json = serde_json::to_vec(&published)?;
+    let (bytes, sha256) = (json.len() as u64, format!("{:x}", Sha256::digest(&json)));
+    store.put(json)?;
+    summary.add_artifact("blob_index", store.to_string(), bytes, sha256);
+    info!(
+        destination = %store,
+        blobs = index.blobs.len(),
+        "Wrote the blob index of {} node blobs to {store}.",
+        index.blobs.len()
+    );
+    Ok(())
+}
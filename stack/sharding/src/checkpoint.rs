@@ -0,0 +1,167 @@
+//! Periodic scan checkpoints so an interrupted planet scan can pick up where it stopped.
+//!
+//! A checkpoint is the byte offset of the next unprocessed PBF blob plus the partial
+//! histogram for everything before it. Layout: magic, a little-endian u32 length, a JSON
+//! header describing the scan, then the partial counts in the `histogram` encoding.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::histogram;
+use crate::scan::ScanResult;
+use crate::store::Store;
+
+const MAGIC: &[u8; 8] = b"OSMCKPT\0";
+
+/// Identifies the scan a checkpoint belongs to; a checkpoint is only resumed if everything
+/// but `offset` matches the current run.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Position {
+    input: String,
+    input_len: u64,
+    max_zoom: u8,
+    sample: Option<f64>,
+    /// Byte offset of the first blob not yet reflected in the partial counts.
+    pub offset: u64,
+}
+
+impl Position {
+    pub fn new(input: &Path, max_zoom: u8, sample: Option<f64>) -> Result<Self> {
+        let input_len = fs::metadata(input)
+            .with_context(|| format!("unable to stat {}", input.display()))?
+            .len();
+        Ok(Self {
+            input: input.display().to_string(),
+            input_len,
+            max_zoom,
+            sample,
+            offset: 0,
+        })
+    }
+
+    fn same_scan(&self, other: &Position) -> bool {
+        Position {
+            offset: 0,
+            ..self.clone()
+        } == Position {
+            offset: 0,
+            ..other.clone()
+        }
+    }
+}
+
+/// Saves, loads and clears checkpoints for one scan.
+pub struct Checkpointer {
+    store: Store,
+    interval: Duration,
+    resume: bool,
+    last_save: Cell<Instant>,
+}
+
+impl Checkpointer {
+    /// `location` is a local path or an object store URI (see [`Store::open`]).
+    pub fn open(location: &str, interval: Duration, resume: bool) -> Result<Self> {
+        Ok(Self {
+            store: Store::open(location, "scan.checkpoint")?,
+            interval,
+            resume,
+            last_save: Cell::new(Instant::now()),
+        })
+    }
+
+    /// The partial scan to continue from, if `--resume` was given and a checkpoint for the
+    /// same input and parameters exists.
+    pub fn resume_from(&self, position: &Position) -> Result<Option<(u64, ScanResult)>> {
+        if !self.resume {
+            return Ok(None);
+        }
+        let Some(bytes) = self.load()? else {
+            info!(
+                checkpoint = %self.describe(),
+                "No checkpoint found at {}, starting from the beginning.",
+                self.describe()
+            );
+            return Ok(None);
+        };
+
+        let (saved, scan) = decode(&bytes)
+            .with_context(|| format!("unable to read checkpoint {}", self.describe()))?;
+        if !saved.same_scan(position) {
+            warn!(
+                checkpoint = %self.describe(),
+                "Checkpoint at {} belongs to a different input or parameters, ignoring it.",
+                self.describe()
+            );
+            return Ok(None);
+        }
+
+        info!(
+            offset = saved.offset,
+            input_len = saved.input_len,
+            nodes = scan.node_total,
+            "Resuming from checkpoint at byte {} of {} ({} nodes already counted).",
+            saved.offset,
+            saved.input_len,
+            scan.node_total
+        );
+        Ok(Some((saved.offset, scan)))
+    }
+
+    /// Whether the interval has elapsed since the last checkpoint.
+    pub fn due(&self) -> bool {
+        self.last_save.get().elapsed() >= self.interval
+    }
+
+    /// Save a checkpoint of `scan`, which covers everything before `position.offset`.
+    pub fn save(&self, position: &Position, scan: &ScanResult) -> Result<()> {
+        let bytes = encode(position, scan)?;
+        self.store.put(bytes)?;
+        info!(
+            offset = position.offset,
+            input_len = position.input_len,
+            "Checkpoint saved at byte {} of {}.",
+            position.offset,
+            position.input_len
+        );
+        self.last_save.set(Instant::now());
+        Ok(())
+    }
+
+    /// Remove the checkpoint once the scan has completed.
+    pub fn clear(&self) -> Result<()> {
+        self.store.delete()
+    }
+
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        self.store.get()
+    }
+
+    fn describe(&self) -> String {
+        self.store.to_string()
+    }
+}
+
+fn encode(position: &Position, scan: &ScanResult) -> Result<Vec<u8>> {
+    let header = serde_json::to_vec(position)?;
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + header.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&u32::try_from(header.len())?.to_le_bytes());
+    bytes.extend_from_slice(&header);
+    histogram::encode(scan, &mut bytes)?;
+    Ok(bytes)
+}
+
+fn decode(bytes: &[u8]) -> Result<(Position, ScanResult)> {
+    let rest = bytes.strip_prefix(MAGIC).context("not a scan checkpoint")?;
+    let (len, rest) = rest.split_at_checked(4).context("truncated checkpoint")?;
+    let len = u32::from_le_bytes(len.try_into()?) as usize;
+    let (header, counts) = rest.split_at_checked(len).context("truncated checkpoint")?;
+    let position = serde_json::from_slice(header)?;
+    let scan = histogram::decode(counts)?;
+    Ok((position, scan))
+}
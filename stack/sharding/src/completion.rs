@@ -0,0 +1,955 @@
+//! Completion of a run's shards: `extract-pois` writes a `<shard_id>.done` marker per shard
+//! once its POIs and statistics are written, with the time it took and the checksums of
+//! what it wrote, and `finalize` writes the run's `_SUCCESS`, after an aggregated
+//! `completion.json`, only once every shard of the manifest has a marker, so downstream jobs
+//! can wait on one object for the run to be complete. A shard that fails gets a
+//! `<shard_id>.failed` marker with its error instead, until it is done, and `status` counts
+//! the shards done, failed and pending from both. While a shard is extracted, its worker
+//! rewrites a `<shard_id>.heartbeat` next to them every `--heartbeat-interval`, with how
+//! much of its input it has read, so `status` tells the shards running from those whose
+//! worker went quiet; the heartbeat is deleted once the shard is done or failed.
+//!
+//! A shard that keeps failing is dead-lettered: once it has failed `--dead-letter-after`
+//! times, its failure, with the number of attempts and where their logs are, is also
+//! written under `failures/`, and `finalize` writes a `failures.json` report of every shard
+//! not done rather than a `_SUCCESS`.
+//!
+//! A shard split at runtime gets a `<shard_id>.delegated` marker naming its child shards,
+//! and is done once they are: `status` and `finalize` count the children in its place.
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::extract::read_manifest;
+use crate::store::Store;
+use crate::{keys, logging, summary, task};
+
+/// Shard IDs listed in a message about some.
+const MISSING_LISTED: usize = 10;
+
+/// Where a run's markers are.
+#[derive(Args, Debug)]
+pub struct MarkerArgs {
+    /// Manifest of the run's shards, in any format `extract` reads: a local path or a URI.
+    #[arg(long, env = "MANIFEST")]
+    manifest: String,
+
+    /// Where `extract-pois` wrote the run's POIs and markers, as its `--output`.
+    #[arg(short, long, env = "POI_OUTPUT")]
+    output: String,
+
+    /// Key of each shard's marker under `--output`, as `extract-pois` was given it.
+    #[arg(
+        long,
+        env = "STATUS_KEY_TEMPLATE",
+        default_value = "runs/{run_id}/status/{name}"
+    )]
+    status_key_template: String,
+
+    /// Key of each dead-lettered shard's failure under `--output`, as `extract-pois` was
+    /// given it.
+    #[arg(
+        long,
+        env = "FAILURE_KEY_TEMPLATE",
+        default_value = "runs/{run_id}/failures/{name}"
+    )]
+    failure_key_template: String,
+}
+
+#[derive(Args, Debug)]
+pub struct FinalizeArgs {
+    #[command(flatten)]
+    markers: MarkerArgs,
+
+    /// Key of `_SUCCESS` and `completion.json`, or of `failures.json`, under `--output`,
+    /// with the placeholders of `--s3-key-template`.
+    #[arg(
+        long,
+        env = "COMPLETION_KEY_TEMPLATE",
+        default_value = "runs/{run_id}/{name}"
+    )]
+    completion_key_template: String,
+}
+
+#[derive(Args, Debug)]
+pub struct StatusArgs {
+    #[command(flatten)]
+    markers: MarkerArgs,
+
+    /// Print the progress as JSON.
+    #[arg(long)]
+    json: bool,
+
+    /// Slowest done shards to list.
+    #[arg(long, default_value_t = 5)]
+    slowest: usize,
+
+    /// Seconds without a heartbeat after which a shard's worker is reported stuck; keep it
+    /// a few of the workers' `--heartbeat-interval`.
+    #[arg(long, default_value_t = 300)]
+    stale_after: u64,
+}
+
+/// What a shard's marker says: when it ran, and what it wrote.
+#[derive(Deserialize, Serialize)]
+pub struct Marker {
+    pub shard_id: String,
+    pub run_id: Option<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub seconds: f64,
+    /// SHA-256 of the input the shard was extracted from: its extract, or the whole input.
+    #[serde(default)]
+    pub input_sha256: Option<String>,
+    pub pois: u64,
+    pub outputs: Vec<Output>,
+}
+
+/// An object a shard wrote.
+#[derive(Deserialize, Serialize)]
+pub struct Output {
+    pub location: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+/// What a failed shard's marker, or its dead-letter entry, says.
+#[derive(Deserialize, Serialize)]
+pub struct Failure {
+    pub shard_id: String,
+    pub run_id: Option<String>,
+    #[serde(default)]
+    pub first_failed_at: Option<String>,
+    pub failed_at: String,
+    /// Attempts that failed so far.
+    #[serde(default)]
+    pub attempts: u32,
+    /// The error of the last attempt.
+    pub error: String,
+    /// Where the last attempt's logs are, when the worker knows.
+    #[serde(default)]
+    pub logs: Option<String>,
+}
+
+/// What a shard's heartbeat says: how far its worker got, and when it last said so.
+#[derive(Deserialize, Serialize)]
+pub struct Heartbeat {
+    pub shard_id: String,
+    pub run_id: Option<String>,
+    pub started_at: String,
+    pub updated_at: String,
+    /// Share of the input read, when it can be told.
+    pub percent: Option<f64>,
+    /// Where the worker's logs are, when it knows.
+    pub logs: Option<String>,
+}
+
+/// Writes the heartbeats of the shards being extracted until it is dropped, once they are
+/// marked done or are to be marked failed, and then deletes them.
+pub struct Heartbeats {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<Vec<(String, Store)>>>,
+}
+
+impl Heartbeats {
+    /// Write a heartbeat for each of `shard_ids` now and then every `interval`, none if it
+    /// is zero, with how far `position` is through the `total` bytes of the input.
+    pub fn start(
+        output: &str,
+        template: &str,
+        shard_ids: &[&str],
+        interval: Duration,
+        position: Option<File>,
+        total: u64,
+    ) -> Result<Self> {
+        if interval.is_zero() {
+            return Ok(Self {
+                stop: None,
+                thread: None,
+            });
+        }
+        let stores = shard_ids
+            .iter()
+            .map(|shard_id| {
+                Ok((
+                    shard_id.to_string(),
+                    marker_store(output, template, shard_id, "heartbeat")?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let started_at = logging::timestamp();
+        let logs = logs();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || loop {
+            let percent = position
+                .as_ref()
+                .and_then(|mut file| file.stream_position().ok())
+                .filter(|_| total > 0)
+                .map(|read| (read as f64 / total as f64 * 1e3).round().min(1e3) / 10.0);
+            for (shard_id, store) in &stores {
+                let heartbeat = Heartbeat {
+                    shard_id: shard_id.clone(),
+                    run_id: keys::run_id().map(str::to_string),
+                    started_at: started_at.clone(),
+                    updated_at: logging::timestamp(),
+                    percent,
+                    logs: logs.clone(),
+                };
+                let result = serde_json::to_vec(&heartbeat)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| store.put(bytes));
+                if let Err(err) = result {
+                    warn!("unable to write the heartbeat of shard {shard_id}: {err:#}");
+                }
+            }
+            if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                continue;
+            }
+            break stores;
+        });
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Heartbeats {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        let Some(Ok(stores)) = self.thread.take().map(JoinHandle::join) else {
+            return;
+        };
+        for (shard_id, store) in stores {
+            if let Err(err) = store.delete() {
+                warn!("unable to delete the heartbeat of shard {shard_id}: {err:#}");
+            }
+        }
+    }
+}
+
+/// What the marker of a shard split into child shards says.
+#[derive(Deserialize, Serialize)]
+pub struct Delegation {
+    pub shard_id: String,
+    pub run_id: Option<String>,
+    pub delegated_at: String,
+    /// Size of the shard's extract, which was found too big.
+    pub extract_bytes: u64,
+    pub children: Vec<String>,
+}
+
+/// Where a shard's failure is dead-lettered: after `after` attempts, under `output` at
+/// `template`.
+pub struct DeadLetter<'a> {
+    pub template: &'a str,
+    pub after: u32,
+}
+
+/// SHA-256 of a shard's input, hashed on a background thread from when the input is known,
+/// so a shard only waits for it to compare with the marker of an earlier attempt.
+pub struct InputHash {
+    thread: Mutex<Option<JoinHandle<Result<String>>>>,
+    sha256: OnceLock<String>,
+}
+
+impl InputHash {
+    pub fn spawn(path: &Path) -> Self {
+        Self {
+            thread: Mutex::new(Some(summary::spawn_sha256(path))),
+            sha256: OnceLock::new(),
+        }
+    }
+
+    /// The hash, once the thread is done.
+    pub fn get(&self) -> Result<&str> {
+        let mut thread = self.thread.lock().expect("input hash lock poisoned");
+        if let Some(thread) = thread.take() {
+            let sha256 = thread
+                .join()
+                .map_err(|_| anyhow!("checksum thread panicked"))??;
+            let _ = self.sha256.set(sha256);
+        }
+        self.sha256
+            .get()
+            .map(String::as_str)
+            .context("the input could not be hashed")
+    }
+}
+
+/// Upload the file at `path` as `store`, returning it as an output of the shard.
+pub fn put(store: &Store, path: &Path) -> Result<Output> {
+    let sha256 = summary::sha256_file(path)?;
+    store.put_hashed_file(path, None, &sha256)?;
+    task::record_output(store.to_string());
+    Ok(Output {
+        location: store.to_string(),
+        sha256,
+        bytes: path.metadata()?.len(),
+    })
+}
+
+/// The marker of shard `shard_id` under `output`, with `extension` (`done` or `failed`).
+fn marker_store(output: &str, template: &str, shard_id: &str, extension: &str) -> Result<Store> {
+    Store::open_in(output, template, &format!("{shard_id}.{extension}"))
+}
+
+/// Write a shard's marker, the last object of the shard, unless another worker marked the
+/// shard done from the same input meanwhile: the marker is only created where there is
+/// none, and only replaces one of another input, or any with `force`.
+pub fn write_marker(output: &str, template: &str, marker: &Marker, force: bool) -> Result<()> {
+    let store = marker_store(output, template, &marker.shard_id, "done")?;
+    let bytes = serde_json::to_vec_pretty(marker)?;
+    if force {
+        store.put(bytes)?;
+    } else if !store.put_new(bytes.clone())? {
+        let existing = read_marker(output, template, &marker.shard_id)?;
+        if existing.is_some_and(|existing| existing.input_sha256 == marker.input_sha256) {
+            info!(
+                shard_id = %marker.shard_id,
+                "Shard {} was marked done at {store} meanwhile, by another worker.",
+                marker.shard_id
+            );
+            return Ok(());
+        }
+        store.put(bytes)?;
+    }
+    info!(
+        shard_id = %marker.shard_id,
+        destination = %store,
+        "Marked shard {} done at {store}.",
+        marker.shard_id
+    );
+    Ok(())
+}
+
+/// Write the marker of a shard delegated to its children.
+pub fn write_delegation(output: &str, template: &str, delegation: &Delegation) -> Result<()> {
+    let store = marker_store(output, template, &delegation.shard_id, "delegated")?;
+    store.put(serde_json::to_vec_pretty(delegation)?)?;
+    info!(
+        shard_id = %delegation.shard_id,
+        destination = %store,
+        "Marked shard {} delegated to {} at {store}.",
+        delegation.shard_id,
+        delegation.children.join(", ")
+    );
+    Ok(())
+}
+
+/// Write the marker of a shard that failed with `error`, counting the attempts of its
+/// earlier marker, and dead-letter the shard once it has failed `dead_letter.after` times.
+pub fn write_failure(
+    output: &str,
+    template: &str,
+    dead_letter: &DeadLetter,
+    shard_id: &str,
+    error: &anyhow::Error,
+) -> Result<()> {
+    let previous = read_failure(output, template, shard_id).unwrap_or_else(|err| {
+        warn!("unable to read the earlier failures of shard {shard_id}: {err:#}");
+        None
+    });
+    let failed_at = logging::timestamp();
+    let failure = Failure {
+        shard_id: shard_id.to_string(),
+        run_id: keys::run_id().map(str::to_string),
+        first_failed_at: previous
+            .as_ref()
+            .and_then(|previous| previous.first_failed_at.clone())
+            .or_else(|| Some(failed_at.clone())),
+        failed_at,
+        attempts: previous.map_or(0, |previous| previous.attempts.max(1)) + 1,
+        error: format!("{error:#}"),
+        logs: logs(),
+    };
+    let store = marker_store(output, template, shard_id, "failed")?;
+    let bytes = serde_json::to_vec_pretty(&failure)?;
+    store.put(bytes.clone())?;
+    info!(
+        shard_id,
+        attempts = failure.attempts,
+        destination = %store,
+        "Marked shard {shard_id} failed at {store} (attempt {}).",
+        failure.attempts
+    );
+
+    if failure.attempts >= dead_letter.after {
+        let entry = dead_letter_store(output, dead_letter.template, shard_id)?;
+        entry.put(bytes)?;
+        warn!(
+            shard_id,
+            attempts = failure.attempts,
+            destination = %entry,
+            "Shard {shard_id} failed {} times; dead-lettered it at {entry}.",
+            failure.attempts
+        );
+    }
+    Ok(())
+}
+
+/// The dead-letter entry of shard `shard_id` under `output`, named apart from the shard's
+/// statistics, which a local output keeps in the same directory.
+fn dead_letter_store(output: &str, template: &str, shard_id: &str) -> Result<Store> {
+    Store::open_in(output, template, &format!("{shard_id}.dead-letter.json"))
+}
+
+/// Where this attempt's logs are: the CloudWatch log stream of a Lambda function, or the
+/// Batch job, whose log stream `aws batch describe-jobs` gives.
+fn logs() -> Option<String> {
+    let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+    if let (Some(group), Some(stream)) = (
+        var("AWS_LAMBDA_LOG_GROUP_NAME"),
+        var("AWS_LAMBDA_LOG_STREAM_NAME"),
+    ) {
+        return Some(format!("cloudwatch:{group}:{stream}"));
+    }
+    var("AWS_BATCH_JOB_ID").map(|job_id| format!("batch:{job_id}"))
+}
+
+/// The marker of shard `shard_id`, if it has one of this run.
+pub fn read_marker(output: &str, template: &str, shard_id: &str) -> Result<Option<Marker>> {
+    read(
+        &marker_store(output, template, shard_id, "done")?,
+        |marker: &Marker| marker.run_id.as_deref(),
+    )
+}
+
+/// The failure marker of shard `shard_id`, if it has one of this run.
+pub fn read_failure(output: &str, template: &str, shard_id: &str) -> Result<Option<Failure>> {
+    read(
+        &marker_store(output, template, shard_id, "failed")?,
+        |failure: &Failure| failure.run_id.as_deref(),
+    )
+}
+
+/// The delegation marker of shard `shard_id`, if it has one of this run.
+fn read_delegation(output: &str, template: &str, shard_id: &str) -> Result<Option<Delegation>> {
+    read(
+        &marker_store(output, template, shard_id, "delegated")?,
+        |delegation: &Delegation| delegation.run_id.as_deref(),
+    )
+}
+
+/// The heartbeat of shard `shard_id`, if it has one of this run.
+fn read_heartbeat(output: &str, template: &str, shard_id: &str) -> Result<Option<Heartbeat>> {
+    read(
+        &marker_store(output, template, shard_id, "heartbeat")?,
+        |heartbeat: &Heartbeat| heartbeat.run_id.as_deref(),
+    )
+}
+
+/// The dead-letter entry of shard `shard_id`, if it has one of this run.
+fn read_dead_letter(output: &str, template: &str, shard_id: &str) -> Result<Option<Failure>> {
+    read(
+        &dead_letter_store(output, template, shard_id)?,
+        |failure: &Failure| failure.run_id.as_deref(),
+    )
+}
+
+/// A marker, unless it is missing or of another run than `--run-id`.
+fn read<T: DeserializeOwned>(
+    store: &Store,
+    run_of: impl Fn(&T) -> Option<&str>,
+) -> Result<Option<T>> {
+    let Some(bytes) = store.get()? else {
+        return Ok(None);
+    };
+    let marker: T =
+        serde_json::from_slice(&bytes).with_context(|| format!("invalid marker {store}"))?;
+    if let (Some(marked), Some(run_id)) = (run_of(&marker), keys::run_id()) {
+        if marked != run_id {
+            warn!("Ignoring {store}, the marker of run {marked}, not {run_id}.");
+            return Ok(None);
+        }
+    }
+    Ok(Some(marker))
+}
+
+/// Where a shard of the manifest is.
+enum State {
+    Done(Marker),
+    Failed(Failure),
+    Pending,
+}
+
+/// Each shard of the manifest, by its markers, with the shards delegated to their children
+/// in their place.
+fn read_states(args: &MarkerArgs) -> Result<Vec<(String, State)>> {
+    let shards = read_manifest(&args.manifest)?;
+    info!(
+        shards = shards.len(),
+        "Checking the markers of the {} shard(s) of {}...",
+        shards.len(),
+        args.manifest
+    );
+    let states: Vec<Vec<(String, State)>> = shards
+        .into_par_iter()
+        .map(|shard| read_state(args, shard.id))
+        .collect::<Result<_>>()?;
+    Ok(states.into_iter().flatten().collect())
+}
+
+/// The state of a shard, or those of the shards it was delegated to.
+fn read_state(args: &MarkerArgs, shard_id: String) -> Result<Vec<(String, State)>> {
+    let (output, template) = (&args.output, &args.status_key_template);
+    if let Some(marker) = read_marker(output, template, &shard_id)? {
+        return Ok(vec![(shard_id, State::Done(marker))]);
+    }
+    if let Some(delegation) = read_delegation(output, template, &shard_id)? {
+        let children: Vec<Vec<(String, State)>> = delegation
+            .children
+            .into_par_iter()
+            .map(|child| read_state(args, child))
+            .collect::<Result<_>>()?;
+        return Ok(children.into_iter().flatten().collect());
+    }
+    let state = match read_failure(output, template, &shard_id)? {
+        Some(failure) => State::Failed(failure),
+        None => State::Pending,
+    };
+    Ok(vec![(shard_id, state)])
+}
+
+/// The first of `shard_ids`, for a message.
+fn list_some(shard_ids: &[&str]) -> String {
+    let listed = shard_ids[..shard_ids.len().min(MISSING_LISTED)].join(", ");
+    match shard_ids.len() > MISSING_LISTED {
+        true => format!("{listed}, ..."),
+        false => listed,
+    }
+}
+
+pub fn finalize(args: &FinalizeArgs) -> Result<()> {
+    let states = read_states(&args.markers)?;
+    let shards = states.len();
+    let missing: Vec<&str> = states
+        .iter()
+        .filter(|(_, state)| !matches!(state, State::Done(_)))
+        .map(|(shard_id, _)| shard_id.as_str())
+        .collect();
+    if !missing.is_empty() {
+        let report = report_failures(args, &states)?;
+        bail!(
+            "{} of {shards} shards are not done ({}); not writing _SUCCESS, see {report}",
+            missing.len(),
+            list_some(&missing)
+        );
+    }
+
+    let markers: Vec<Marker> = states
+        .into_iter()
+        .filter_map(|(_, state)| match state {
+            State::Done(marker) => Some(marker),
+            _ => None,
+        })
+        .collect();
+    let pois: u64 = markers.iter().map(|marker| marker.pois).sum();
+    let outputs: usize = markers.iter().map(|marker| marker.outputs.len()).sum();
+    let bytes: u64 = markers
+        .iter()
+        .flat_map(|marker| &marker.outputs)
+        .map(|output| output.bytes)
+        .sum();
+    let shard_seconds: f64 = markers.iter().map(|marker| marker.seconds).sum();
+    let slowest = markers
+        .iter()
+        .max_by(|a, b| a.seconds.total_cmp(&b.seconds));
+    let report = json!({
+        "run_id": keys::run_id(),
+        "manifest": args.markers.manifest,
+        "shards": markers.len(),
+        "pois": pois,
+        "outputs": outputs,
+        "bytes": bytes,
+        "started_at": markers.iter().map(|marker| &marker.started_at).min(),
+        "finished_at": markers.iter().map(|marker| &marker.finished_at).max(),
+        "shard_seconds": (shard_seconds * 1e3).round() / 1e3,
+        "slowest_shard": slowest.map(|marker| json!({
+            "shard_id": marker.shard_id,
+            "seconds": marker.seconds,
+        })),
+        "finalized_at": logging::timestamp(),
+    });
+
+    let completion = Store::open_in(
+        &args.markers.output,
+        &args.completion_key_template,
+        "completion.json",
+    )?;
+    completion.put(serde_json::to_vec_pretty(&report)?)?;
+    task::record_output(completion.to_string());
+    // Last, so a `_SUCCESS` always has its report next to it.
+    let success = Store::open_in(
+        &args.markers.output,
+        &args.completion_key_template,
+        "_SUCCESS",
+    )?;
+    success.put(Vec::new())?;
+    task::record_output(success.to_string());
+    info!(
+        shards = markers.len(),
+        pois,
+        destination = %success,
+        "All {} shard(s) are done, with {pois} POIs: wrote {completion} and {success}.",
+        markers.len()
+    );
+    Ok(())
+}
+
+/// Write `failures.json`, the report of the shards of `states` not done, returning where.
+fn report_failures(args: &FinalizeArgs, states: &[(String, State)]) -> Result<Store> {
+    let markers = &args.markers;
+    let shards: Vec<serde_json::Value> = states
+        .par_iter()
+        .filter(|(_, state)| !matches!(state, State::Done(_)))
+        .map(|(shard_id, state)| {
+            let dead_letter =
+                read_dead_letter(&markers.output, &markers.failure_key_template, shard_id)?;
+            let failure = match state {
+                State::Failed(failure) => Some(failure),
+                _ => dead_letter.as_ref(),
+            };
+            Ok(json!({
+                "shard_id": shard_id,
+                "state": if failure.is_some() { "failed" } else { "pending" },
+                "dead_lettered": dead_letter.is_some(),
+                "attempts": failure.map_or(0, |failure| failure.attempts.max(1)),
+                "first_failed_at": failure.and_then(|failure| failure.first_failed_at.as_ref()),
+                "failed_at": failure.map(|failure| &failure.failed_at),
+                "error": failure.map(|failure| &failure.error),
+                "logs": failure.and_then(|failure| failure.logs.as_ref()),
+            }))
+        })
+        .collect::<Result<_>>()?;
+    let count = |key: &str, value: serde_json::Value| {
+        shards.iter().filter(|shard| shard[key] == value).count()
+    };
+    let report = json!({
+        "run_id": keys::run_id(),
+        "manifest": markers.manifest,
+        "shards": states.len(),
+        "done": states.len() - shards.len(),
+        "failed": count("state", json!("failed")),
+        "pending": count("state", json!("pending")),
+        "dead_lettered": count("dead_lettered", json!(true)),
+        "not_done": shards,
+        "reported_at": logging::timestamp(),
+    });
+    let store = Store::open_in(
+        &markers.output,
+        &args.completion_key_template,
+        "failures.json",
+    )?;
+    store.put(serde_json::to_vec_pretty(&report)?)?;
+    task::record_output(store.to_string());
+    warn!(
+        failed = report["failed"].as_u64(),
+        pending = report["pending"].as_u64(),
+        dead_lettered = report["dead_lettered"].as_u64(),
+        destination = %store,
+        "Reported the {} shard(s) not done at {store}.",
+        shards.len()
+    );
+    Ok(store)
+}
+
+pub fn status(args: &StatusArgs) -> Result<()> {
+    let states = read_states(&args.markers)?;
+    let mut done: Vec<&Marker> = Vec::new();
+    let mut failed: Vec<&Failure> = Vec::new();
+    let mut pending: Vec<&str> = Vec::new();
+    for (shard_id, state) in &states {
+        match state {
+            State::Done(marker) => done.push(marker),
+            State::Failed(failure) => failed.push(failure),
+            State::Pending => pending.push(shard_id),
+        }
+    }
+    let shards = states.len();
+    let pois: u64 = done.iter().map(|marker| marker.pois).sum();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+
+    // Shards not done with a heartbeat since they last failed, if they did: running, or
+    // stuck once their worker has been quiet for `--stale-after`.
+    let markers = &args.markers;
+    let heartbeats = states
+        .par_iter()
+        .map(|(shard_id, state)| {
+            let failed_at = match state {
+                State::Done(_) => return Ok(None),
+                State::Failed(failure) => Some(&failure.failed_at),
+                State::Pending => None,
+            };
+            let heartbeat =
+                read_heartbeat(&markers.output, &markers.status_key_template, shard_id)?;
+            Ok(heartbeat.filter(|heartbeat| {
+                failed_at.is_none_or(|failed_at| &heartbeat.updated_at > failed_at)
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let quiet = |heartbeat: &Heartbeat| {
+        logging::parse_rfc3339(&heartbeat.updated_at).map_or(0, |updated| (now - updated).max(0))
+    };
+    let (running, stuck): (Vec<&Heartbeat>, Vec<&Heartbeat>) = heartbeats
+        .iter()
+        .flatten()
+        .partition(|heartbeat| quiet(heartbeat) <= args.stale_after as i64);
+
+    // Throughput over the time since the first shard started, up to the last finish once
+    // every shard has been tried.
+    let started_at = done.iter().map(|marker| &marker.started_at).min();
+    let finished_at = done.iter().map(|marker| &marker.finished_at).max();
+    let until = match (pending.is_empty(), finished_at) {
+        (true, Some(finished_at)) => logging::parse_rfc3339(finished_at),
+        _ => Some(now),
+    };
+    let elapsed = started_at
+        .and_then(|started_at| logging::parse_rfc3339(started_at))
+        .zip(until)
+        .map(|(started, until)| (until - started).max(1) as f64);
+    let shards_per_hour = elapsed.map(|elapsed| done.len() as f64 / elapsed * 3600.0);
+    let remaining = shards - done.len();
+    let eta_seconds = shards_per_hour
+        .filter(|&rate| rate > 0.0 && remaining > 0)
+        .map(|rate| (remaining as f64 / rate * 3600.0).round() as i64);
+
+    done.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+    let slowest = &done[..done.len().min(args.slowest)];
+    let report = json!({
+        "run_id": keys::run_id(),
+        "manifest": args.markers.manifest,
+        "shards": shards,
+        "done": done.len(),
+        "failed": failed.len(),
+        "pending": pending.len(),
+        "running": running.len(),
+        "stuck": stuck.len(),
+        "percent_done": (done.len() as f64 / shards.max(1) as f64 * 1e3).round() / 10.0,
+        "pois": pois,
+        "started_at": started_at,
+        "elapsed_seconds": elapsed,
+        "shards_per_hour": shards_per_hour.map(|rate| (rate * 10.0).round() / 10.0),
+        "eta_seconds": eta_seconds,
+        "eta_at": eta_seconds.map(|eta| logging::format_rfc3339(now + eta, None)),
+        "slowest": slowest
+            .iter()
+            .map(|marker| json!({
+                "shard_id": marker.shard_id,
+                "seconds": marker.seconds,
+                "pois": marker.pois,
+            }))
+            .collect::<Vec<_>>(),
+        "failures": failed
+            .iter()
+            .map(|failure| json!({
+                "shard_id": failure.shard_id,
+                "failed_at": failure.failed_at,
+                "attempts": failure.attempts.max(1),
+                "error": failure.error,
+            }))
+            .collect::<Vec<_>>(),
+        "running_shards": running
+            .iter()
+            .map(|heartbeat| json!({
+                "shard_id": heartbeat.shard_id,
+                "percent": heartbeat.percent,
+                "started_at": heartbeat.started_at,
+                "updated_at": heartbeat.updated_at,
+            }))
+            .collect::<Vec<_>>(),
+        "stuck_shards": stuck
+            .iter()
+            .map(|heartbeat| json!({
+                "shard_id": heartbeat.shard_id,
+                "percent": heartbeat.percent,
+                "updated_at": heartbeat.updated_at,
+                "quiet_seconds": quiet(heartbeat),
+                "logs": heartbeat.logs,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    let mut stdout = std::io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut stdout, &report)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+
+    writeln!(
+        stdout,
+        "{} of {shards} shards done ({}%), {} failed, {} pending; {pois} POIs",
+        done.len(),
+        report["percent_done"],
+        failed.len(),
+        pending.len()
+    )?;
+    if let (Some(rate), Some(elapsed)) = (shards_per_hour, elapsed) {
+        writeln!(
+            stdout,
+            "{rate:.1} shards/hour over {} since {}",
+            format_seconds(elapsed),
+            started_at.map_or("", String::as_str)
+        )?;
+    }
+    match eta_seconds {
+        Some(eta) => writeln!(
+            stdout,
+            "ETA {} (in {})",
+            logging::format_rfc3339(now + eta, None),
+            format_seconds(eta as f64)
+        )?,
+        None if remaining == 0 => writeln!(stdout, "Every shard is done.")?,
+        None => {}
+    }
+    if !slowest.is_empty() {
+        writeln!(stdout, "Slowest shards:")?;
+        for marker in slowest {
+            writeln!(
+                stdout,
+                "  {}\t{}\t{} POIs",
+                marker.shard_id,
+                format_seconds(marker.seconds),
+                marker.pois
+            )?;
+        }
+    }
+    if !failed.is_empty() {
+        writeln!(stdout, "Failed shards:")?;
+        for failure in &failed[..failed.len().min(MISSING_LISTED)] {
+            writeln!(
+                stdout,
+                "  {}\t{} attempt(s)\t{}",
+                failure.shard_id,
+                failure.attempts.max(1),
+                failure.error
+            )?;
+        }
+        if failed.len() > MISSING_LISTED {
+            writeln!(stdout, "  ... and {} more", failed.len() - MISSING_LISTED)?;
+        }
+    }
+    if !running.is_empty() {
+        let listed: Vec<String> = running
+            .iter()
+            .map(|heartbeat| match heartbeat.percent {
+                Some(percent) => format!("{} ({percent}%)", heartbeat.shard_id),
+                None => heartbeat.shard_id.clone(),
+            })
+            .collect();
+        let listed: Vec<&str> = listed.iter().map(String::as_str).collect();
+        writeln!(stdout, "Running: {}", list_some(&listed))?;
+    }
+    if !stuck.is_empty() {
+        writeln!(
+            stdout,
+            "Stuck, without a heartbeat for over {}:",
+            format_seconds(args.stale_after as f64)
+        )?;
+        for heartbeat in &stuck[..stuck.len().min(MISSING_LISTED)] {
+            writeln!(
+                stdout,
+                "  {}\tlast at {}{}\t{}",
+                heartbeat.shard_id,
+                heartbeat.updated_at,
+                heartbeat
+                    .percent
+                    .map_or(String::new(), |percent| format!(", {percent}%")),
+                heartbeat.logs.as_deref().unwrap_or_default()
+            )?;
+        }
+        if stuck.len() > MISSING_LISTED {
+            writeln!(stdout, "  ... and {} more", stuck.len() - MISSING_LISTED)?;
+        }
+    }
+    if !pending.is_empty() {
+        writeln!(stdout, "Pending: {}", list_some(&pending))?;
+    }
+    Ok(())
+}
+
+/// `seconds` as hours, minutes and seconds, such as `1h 02m 05s`.
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s:02}s"),
+        (h, m, s) => format!("{h}h {m:02}m {s:02}s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finalize_reports_dead_letters_next_to_local_stats() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir
+            .path()
+            .to_str()
+            .expect("UTF-8 temporary path")
+            .to_string();
+        let manifest = dir.path().join("manifest.csv");
+        std::fs::write(&manifest, "shard_id\n0-0-0\n1-0-0\n")?;
+        // Statistics go next to the markers of a local output, with no template.
+        let stats = dir.path().join("0-0-0.json");
+        std::fs::write(&stats, r#"{"shard_id": "0-0-0", "pois": 0}"#)?;
+        std::fs::write(
+            dir.path().join("1-0-0.json"),
+            r#"{"shard_id": "1-0-0", "pois": 3}"#,
+        )?;
+
+        let markers = MarkerArgs {
+            manifest: manifest.to_string_lossy().into_owned(),
+            output: output.clone(),
+            status_key_template: "runs/{run_id}/status/{name}".to_string(),
+            failure_key_template: "runs/{run_id}/failures/{name}".to_string(),
+        };
+        let dead_letter = DeadLetter {
+            template: &markers.failure_key_template,
+            after: 1,
+        };
+        write_failure(
+            &output,
+            &markers.status_key_template,
+            &dead_letter,
+            "0-0-0",
+            &anyhow!("no space left"),
+        )?;
+        assert_eq!(
+            std::fs::read_to_string(&stats)?,
+            r#"{"shard_id": "0-0-0", "pois": 0}"#
+        );
+
+        let args = FinalizeArgs {
+            markers,
+            completion_key_template: "runs/{run_id}/{name}".to_string(),
+        };
+        let err = finalize(&args).expect_err("a shard is not done");
+        assert!(format!("{err:#}").contains("2 of 2 shards are not done"));
+        let report: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("failures.json"))?)?;
+        assert_eq!(report["failed"], 1);
+        assert_eq!(report["pending"], 1);
+        assert_eq!(report["dead_lettered"], 1);
+        assert_eq!(report["not_done"][0]["error"], "no space left");
+        assert!(!dir.path().join("_SUCCESS").exists());
+        Ok(())
+    }
+}
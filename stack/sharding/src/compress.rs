@@ -0,0 +1,112 @@
+//! Compression of the manifest written to stdout or a file.
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    /// Compressed by the `zstd` binary, which the container ships.
+    Zstd,
+}
+
+impl Compression {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+type Sink = io::BufWriter<Box<dyn Write>>;
+
+/// Stdout or a file, optionally through a compressor. Call [`Output::finish`] to flush the
+/// compressed stream and surface compressor failures.
+pub enum Output {
+    Plain(Sink),
+    Gzip(GzEncoder<Sink>),
+    Zstd { child: Child, stdin: ChildStdin },
+}
+
+impl Output {
+    /// Open `path` (created or truncated), or stdout when there is none.
+    pub fn open(path: Option<&Path>, compression: Option<Compression>) -> Result<Self> {
+        let file = path
+            .map(|path| {
+                File::create(path).with_context(|| format!("unable to create {}", path.display()))
+            })
+            .transpose()?;
+        if compression == Some(Compression::Zstd) {
+            // The child writes straight to the file or our stdout.
+            let stdout = match file {
+                Some(file) => Stdio::from(file),
+                None => Stdio::inherit(),
+            };
+            let mut child = Command::new("zstd")
+                .args(["-q", "-c"])
+                .stdin(Stdio::piped())
+                .stdout(stdout)
+                .spawn()
+                .context("unable to run zstd to compress the manifest")?;
+            let stdin = child.stdin.take().context("zstd has no stdin")?;
+            return Ok(Output::Zstd { child, stdin });
+        }
+        let sink: Box<dyn Write> = match file {
+            Some(file) => Box::new(file),
+            None => Box::new(io::stdout()),
+        };
+        let sink = io::BufWriter::with_capacity(1 << 20, sink);
+        Ok(match compression {
+            Some(Compression::Gzip) => {
+                Output::Gzip(GzEncoder::new(sink, flate2::Compression::default()))
+            }
+            _ => Output::Plain(sink),
+        })
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Output::Plain(mut out) => out.flush()?,
+            Output::Gzip(encoder) => encoder.finish()?.flush()?,
+            Output::Zstd { mut child, stdin } => {
+                drop(stdin);
+                let status = child.wait()?;
+                if !status.success() {
+                    bail!("zstd exited with {status}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(out) => out.write(buf),
+            Output::Gzip(out) => out.write(buf),
+            Output::Zstd { stdin, .. } => stdin.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(out) => out.flush(),
+            Output::Gzip(out) => out.flush(),
+            Output::Zstd { stdin, .. } => stdin.flush(),
+        }
+    }
+}
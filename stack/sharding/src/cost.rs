@@ -0,0 +1,308 @@
+//! `plan-cost`: what a run of `extract-pois` workers over a manifest will take and cost,
+//! from the node count the sharder wrote for each shard, before any worker starts.
+//!
+//! A shard's extract is estimated at `--bytes-per-node` per node and its work at
+//! `--nodes-per-second` per vCPU, plus `--shard-overhead` seconds to start, fetch and
+//! upload. With `--lambda-memory`, the shards whose extract fits the function, at about
+//! five times its size in memory, and that finish within Lambda's 15 minutes, go to Lambda
+//! as `extract-pois` run from the queue would send them; the others to Batch instances.
+//! Calibrate the rates from an earlier run's markers, which have each shard's seconds.
+
+use anyhow::{bail, Result};
+use clap::Args;
+use serde_json::json;
+use std::io::Write;
+use tracing::info;
+
+use crate::extract::read_manifest;
+use crate::progress::format_bytes;
+use crate::store::COPY_RANGE;
+
+/// Memory a shard takes per byte of its extract, as measured for the Lambda sizing.
+const MEMORY_PER_EXTRACT_BYTE: u64 = 5;
+/// Memory of a Lambda function with one full vCPU, in MB.
+const LAMBDA_MB_PER_VCPU: f64 = 1769.0;
+/// Longest a Lambda invocation runs, in seconds.
+const LAMBDA_TIMEOUT: f64 = 900.0;
+/// PUT requests per shard: its extract, POIs, statistics and marker.
+const PUTS_PER_SHARD: u64 = 4;
+
+#[derive(Args, Debug)]
+pub struct PlanCostArgs {
+    /// Manifest to estimate the run of, as written by the sharder with node counts:
+    /// GeoJSON, GeoJSON lines, CSV, TSV, KML, KMZ or quadkeys, a local path or a URI.
+    #[arg(long, env = "MANIFEST")]
+    manifest: String,
+
+    /// Bytes of extract per node of a shard, about the input's size over its node count.
+    #[arg(long, env = "COST_BYTES_PER_NODE", default_value_t = 10.0)]
+    bytes_per_node: f64,
+
+    /// Nodes a worker reads per second and vCPU.
+    #[arg(long, env = "COST_NODES_PER_SECOND", default_value_t = 1_000_000)]
+    nodes_per_second: u64,
+
+    /// Seconds per shard besides reading its nodes: starting, fetching and uploading.
+    #[arg(long, env = "COST_SHARD_OVERHEAD", default_value_t = 20.0)]
+    shard_overhead: f64,
+
+    /// vCPUs of a Batch instance.
+    #[arg(long, env = "COST_INSTANCE_VCPUS", default_value_t = 2)]
+    instance_vcpus: u32,
+
+    /// Dollars per Batch instance-hour; the default is an on-demand m5.large.
+    #[arg(long, env = "COST_INSTANCE_PRICE", default_value_t = 0.096)]
+    instance_price: f64,
+
+    /// Memory of the Lambda function in MB, to send the shards that fit it to Lambda
+    /// rather than Batch.
+    #[arg(long, env = "COST_LAMBDA_MEMORY")]
+    lambda_memory: Option<u64>,
+
+    /// Dollars per Lambda GB-second.
+    #[arg(
+        long,
+        env = "COST_LAMBDA_GB_SECOND_PRICE",
+        default_value_t = 0.000_016_666_7
+    )]
+    lambda_gb_second_price: f64,
+
+    /// Dollars per million Lambda requests.
+    #[arg(long, env = "COST_LAMBDA_REQUEST_PRICE", default_value_t = 0.20)]
+    lambda_request_price: f64,
+
+    /// Dollars per thousand S3 PUT requests.
+    #[arg(long, env = "COST_S3_PUT_PRICE", default_value_t = 0.005)]
+    s3_put_price: f64,
+
+    /// Dollars per thousand S3 GET requests.
+    #[arg(long, env = "COST_S3_GET_PRICE", default_value_t = 0.0004)]
+    s3_get_price: f64,
+
+    /// Dollars per GB the workers read from S3: nothing in the bucket's region.
+    #[arg(long, env = "COST_TRANSFER_PRICE", default_value_t = 0.0)]
+    transfer_price: f64,
+
+    /// Print the estimate as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+/// What one shard is estimated to take.
+struct Estimate<'a> {
+    shard_id: &'a str,
+    nodes: u64,
+    bytes: u64,
+    cpu_seconds: f64,
+    /// Seconds from start to upload, on the instance or function it runs on.
+    seconds: f64,
+    lambda: bool,
+}
+
+pub fn plan_cost(args: &PlanCostArgs) -> Result<()> {
+    let shards = read_manifest(&args.manifest)?;
+    let without: Vec<&str> = shards
+        .iter()
+        .filter(|shard| shard.node_count.is_none())
+        .map(|shard| shard.id.as_str())
+        .collect();
+    if !without.is_empty() {
+        bail!(
+            "{} of the {} shards of {} have no node count; estimate from a manifest the \
+             sharder wrote as GeoJSON, CSV, TSV, KML, KMZ or quadkeys",
+            without.len(),
+            shards.len(),
+            args.manifest
+        );
+    }
+    if args.nodes_per_second == 0 || args.instance_vcpus == 0 {
+        bail!("--nodes-per-second and --instance-vcpus must be positive");
+    }
+
+    let rate = args.nodes_per_second as f64;
+    let lambda_vcpus = args
+        .lambda_memory
+        .map(|memory| memory as f64 / LAMBDA_MB_PER_VCPU);
+    let estimates: Vec<Estimate> = shards
+        .iter()
+        .map(|shard| {
+            let nodes = shard.node_count.unwrap_or_default();
+            let bytes = (nodes as f64 * args.bytes_per_node).round() as u64;
+            let cpu_seconds = nodes as f64 / rate;
+            let lambda_seconds =
+                lambda_vcpus.map(|vcpus| args.shard_overhead + cpu_seconds / vcpus);
+            let lambda = match (args.lambda_memory, lambda_seconds) {
+                (Some(memory), Some(seconds)) => {
+                    bytes * MEMORY_PER_EXTRACT_BYTE <= memory << 20 && seconds <= LAMBDA_TIMEOUT
+                }
+                _ => false,
+            };
+            let seconds = match lambda_seconds.filter(|_| lambda) {
+                Some(seconds) => seconds,
+                None => args.shard_overhead + cpu_seconds / f64::from(args.instance_vcpus),
+            };
+            Estimate {
+                shard_id: &shard.id,
+                nodes,
+                bytes,
+                cpu_seconds,
+                seconds,
+                lambda,
+            }
+        })
+        .collect();
+
+    let (on_lambda, on_batch): (Vec<&Estimate>, Vec<&Estimate>) =
+        estimates.iter().partition(|estimate| estimate.lambda);
+    let nodes: u64 = estimates.iter().map(|estimate| estimate.nodes).sum();
+    let bytes: u64 = estimates.iter().map(|estimate| estimate.bytes).sum();
+    let cpu_hours = estimates
+        .iter()
+        .map(|estimate| estimate.cpu_seconds)
+        .sum::<f64>()
+        / 3600.0;
+
+    let instance_hours = on_batch
+        .iter()
+        .map(|estimate| estimate.seconds)
+        .sum::<f64>()
+        / 3600.0;
+    let batch_cost = instance_hours * args.instance_price;
+    let gb_seconds = on_lambda
+        .iter()
+        .map(|estimate| estimate.seconds)
+        .sum::<f64>()
+        * args.lambda_memory.unwrap_or_default() as f64
+        / 1024.0;
+    let lambda_cost = gb_seconds * args.lambda_gb_second_price
+        + on_lambda.len() as f64 / 1e6 * args.lambda_request_price;
+
+    // A GET for the shard's marker, then its extract a range at a time.
+    let puts = PUTS_PER_SHARD * estimates.len() as u64;
+    let gets: u64 = estimates
+        .iter()
+        .map(|estimate| 1 + estimate.bytes.div_ceil(COPY_RANGE).max(1))
+        .sum();
+    let s3_cost = puts as f64 / 1e3 * args.s3_put_price + gets as f64 / 1e3 * args.s3_get_price;
+    let transfer_gb = bytes as f64 / f64::from(1u32 << 30);
+    let transfer_cost = transfer_gb * args.transfer_price;
+    let total = batch_cost + lambda_cost + s3_cost + transfer_cost;
+
+    let largest = estimates.iter().max_by_key(|estimate| estimate.nodes);
+    let slowest = estimates
+        .iter()
+        .max_by(|a, b| a.seconds.total_cmp(&b.seconds));
+    let (Some(largest), Some(slowest)) = (largest, slowest) else {
+        bail!("{} has no shards", args.manifest);
+    };
+    let dollars = |cost: f64| (cost * 100.0).round() / 100.0;
+    let report = json!({
+        "manifest": args.manifest,
+        "shards": estimates.len(),
+        "nodes": nodes,
+        "extract_bytes": bytes,
+        "cpu_hours": (cpu_hours * 100.0).round() / 100.0,
+        "batch": {
+            "shards": on_batch.len(),
+            "instance_vcpus": args.instance_vcpus,
+            "instance_hours": (instance_hours * 100.0).round() / 100.0,
+            "cost": dollars(batch_cost),
+        },
+        "lambda": {
+            "shards": on_lambda.len(),
+            "memory_mb": args.lambda_memory,
+            "gb_seconds": gb_seconds.round(),
+            "requests": on_lambda.len(),
+            "cost": dollars(lambda_cost),
+        },
+        "s3": {
+            "put_requests": puts,
+            "get_requests": gets,
+            "cost": dollars(s3_cost),
+        },
+        "transfer": {
+            "bytes": bytes,
+            "cost": dollars(transfer_cost),
+        },
+        "largest_shard": {
+            "shard_id": largest.shard_id,
+            "nodes": largest.nodes,
+            "extract_bytes": largest.bytes,
+            "memory_bytes": largest.bytes * MEMORY_PER_EXTRACT_BYTE,
+        },
+        "slowest_shard": {
+            "shard_id": slowest.shard_id,
+            "seconds": slowest.seconds.round(),
+        },
+        "cost": dollars(total),
+        "parameters": {
+            "bytes_per_node": args.bytes_per_node,
+            "nodes_per_second": args.nodes_per_second,
+            "shard_overhead": args.shard_overhead,
+            "instance_price": args.instance_price,
+            "lambda_gb_second_price": args.lambda_gb_second_price,
+            "lambda_request_price": args.lambda_request_price,
+            "s3_put_price": args.s3_put_price,
+            "s3_get_price": args.s3_get_price,
+            "transfer_price": args.transfer_price,
+        },
+    });
+    info!(
+        shards = estimates.len(),
+        cpu_hours,
+        cost = total,
+        "Estimated the run of the {} shard(s) of {}.",
+        estimates.len(),
+        args.manifest
+    );
+
+    let mut stdout = std::io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut stdout, &report)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+    writeln!(
+        stdout,
+        "{} shards, {nodes} nodes, {} of extracts; {cpu_hours:.1} CPU-hours",
+        estimates.len(),
+        format_bytes(bytes)
+    )?;
+    writeln!(
+        stdout,
+        "Batch: {} shards, {instance_hours:.1} instance-hours of {} vCPUs\t${batch_cost:.2}",
+        on_batch.len(),
+        args.instance_vcpus
+    )?;
+    if let Some(memory) = args.lambda_memory {
+        writeln!(
+            stdout,
+            "Lambda: {} shards, {gb_seconds:.0} GB-seconds at {memory} MB\t${lambda_cost:.2}",
+            on_lambda.len()
+        )?;
+    }
+    writeln!(
+        stdout,
+        "S3: {puts} PUT and {gets} GET requests\t${s3_cost:.2}"
+    )?;
+    writeln!(
+        stdout,
+        "Transfer: {}\t${transfer_cost:.2}",
+        format_bytes(bytes)
+    )?;
+    writeln!(stdout, "Total\t${total:.2}")?;
+    writeln!(
+        stdout,
+        "Largest shard: {} ({} nodes, {} extract, about {} of memory)",
+        largest.shard_id,
+        largest.nodes,
+        format_bytes(largest.bytes),
+        format_bytes(largest.bytes * MEMORY_PER_EXTRACT_BYTE)
+    )?;
+    writeln!(
+        stdout,
+        "Slowest shard: {} (about {:.0} s)",
+        slowest.shard_id, slowest.seconds
+    )?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! CSV and TSV manifest export, one row per shard with the polygon as WKT.
+
+use anyhow::Result;
+use std::io::Write;
+
+use crate::Feature;
+
+const COLUMNS: [&str; 6] = ["shard_id", "z", "x", "y", "node_count", "wkt"];
+
+/// Write a header row and one row per feature, separated by `delimiter`. Fields containing
+/// the delimiter or quotes are quoted as in RFC 4180; TSV fields never need it.
+pub fn write(out: &mut impl Write, features: &[Feature], delimiter: char) -> Result<()> {
+    writeln!(out, "{}", COLUMNS.join(&delimiter.to_string()))?;
+    for feature in features {
+        let props = &feature.properties;
+        let fields = [
+            props.shard_id.clone(),
+            props.z.to_string(),
+            props.x.to_string(),
+            props.y.to_string(),
+            props.node_count.to_string(),
+            wkt_polygon(&feature.geometry.coordinates),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| quote(field, delimiter)).collect();
+        writeln!(out, "{}", row.join(&delimiter.to_string()))?;
+    }
+    Ok(())
+}
+
+/// A field as written in a row, quoted if it contains the delimiter, quotes or a line break.
+pub(crate) fn quote(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn wkt_polygon(rings: &[Vec<[f64; 2]>]) -> String {
+    let rings: Vec<String> = rings
+        .iter()
+        .map(|ring| {
+            let positions: Vec<String> = ring.iter().map(|[x, y]| format!("{x} {y}")).collect();
+            format!("({})", positions.join(", "))
+        })
+        .collect();
+    format!("POLYGON ({})", rings.join(", "))
+}
@@ -0,0 +1,483 @@
+//! `extract` subcommand: cut one `.osm.pbf` per shard of a manifest out of the planet, so
+//! downstream jobs read a small regional file instead of the whole planet.
+//!
+//! The input is read one to three times per pass, depending on the [`Strategy`], and must be
+//! sorted by type, then ID, as planet dumps are. A node goes to the shard whose tile
+//! contains it; a way goes to every shard holding one of its nodes, and a relation to every
+//! shard holding one of its node or way members. Each pass keeps the node and way IDs of its
+//! shards in sorted lists (16 bytes per node), which `--shards-per-pass` bounds.
+
+mod pbf;
+mod strategy;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use osmpbf::{BlobDecode, BlobReader};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use tracing::{info, info_span};
+
+use crate::input::{self, InputFormat, OsmElement};
+use crate::kml;
+use crate::store::Store;
+use crate::tally::BBox;
+use crate::task;
+use crate::tile_bbox;
+use pbf::{Header, PbfWriter};
+use strategy::{Selection, Strategy, Tiles};
+
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+    /// Manifest to cut extracts for, as written by the sharder in GeoJSON, GeoJSON lines,
+    /// CSV, TSV, KML, KMZ or quadkeys format, optionally compressed: a local path or a URI.
+    #[arg(long, env = "MANIFEST")]
+    manifest: String,
+
+    /// Planet (or other OSM file) to cut the extracts from, sorted by type, then ID.
+    #[arg(env = "OSM_FILE")]
+    input: PathBuf,
+
+    /// Input encoding; `auto` picks it by extension.
+    #[arg(long, env = "INPUT_FORMAT", value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    /// Where to write the extracts: a local directory, or an object store bucket or prefix
+    /// URI under which each extract is keyed by `--extract-key-template`.
+    #[arg(short, long, env = "EXTRACT_OUTPUT")]
+    output: String,
+
+    /// Key of each extract under `--output`, with the placeholders of `--s3-key-template`;
+    /// `{name}` is `<shard_id>.osm.pbf`.
+    #[arg(
+        long,
+        env = "EXTRACT_KEY_TEMPLATE",
+        default_value = "runs/{run_id}/extracts/{name}"
+    )]
+    extract_key_template: String,
+
+    /// Only cut these shards (by shard ID, e.g. `12-2048-1361`) instead of every shard in
+    /// the manifest.
+    #[arg(long, env = "SHARDS", value_delimiter = ',')]
+    shards: Vec<String>,
+
+    /// Extracts cut per pass over the input. Fewer passes read the input fewer times; each
+    /// shard in a pass holds its node and way IDs in memory and keeps an output file open.
+    #[arg(long, env = "SHARDS_PER_PASS", default_value_t = 256)]
+    shards_per_pass: usize,
+
+    /// Which elements an extract gets besides the nodes inside its shard.
+    #[arg(long, env = "EXTRACT_STRATEGY", value_enum, default_value_t = Strategy::CompleteWays)]
+    strategy: Strategy,
+}
+
+/// One shard of the manifest.
+pub(crate) struct Shard {
+    pub(crate) id: String,
+    zoom: u8,
+    x: u32,
+    y: u32,
+    /// Nodes the sharder counted in the shard, when the manifest has them.
+    pub(crate) node_count: Option<u64>,
+}
+
+pub fn run(args: &ExtractArgs) -> Result<()> {
+    let mut shards = read_manifest(&args.manifest)?;
+    if !args.shards.is_empty() {
+        for id in &args.shards {
+            if !shards.iter().any(|shard| &shard.id == id) {
+                bail!("shard {id} is not in {}", args.manifest);
+            }
+        }
+        shards.retain(|shard| args.shards.contains(&shard.id));
+    }
+    let format = args.input_format.resolve(&args.input)?;
+    let header = match format {
+        InputFormat::Pbf => input_header(&args.input)?,
+        _ => Header::default(),
+    };
+
+    let per_pass = args.shards_per_pass.max(1);
+    let passes = shards.len().div_ceil(per_pass);
+    info!(
+        extracts = shards.len(),
+        passes,
+        strategy = ?args.strategy,
+        "Cutting {} extracts from {} in {passes} pass(es) with the {} strategy...",
+        shards.len(),
+        args.input.display(),
+        args.strategy.to_possible_value().expect("no skipped variants").get_name()
+    );
+    for (pass, batch) in shards.chunks(per_pass).enumerate() {
+        let _pass = info_span!("extract_pass", pass = pass + 1).entered();
+        info!(
+            "Pass {}/{passes}: cutting {} extracts...",
+            pass + 1,
+            batch.len()
+        );
+        let outputs = batch
+            .iter()
+            .map(|shard| {
+                let name = format!("{}.osm.pbf", shard.id);
+                Store::open_in(&args.output, &args.extract_key_template, &name)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        extract_pass(&args.input, format, &header, batch, &outputs, args.strategy)?;
+    }
+    Ok(())
+}
+
+/// Cut `input`, the extract of tile `zoom/x/y`, into the extracts of its four children at
+/// the next zoom in one pass, publishing them under `output` keyed by `template`, and
+/// return their shard IDs.
+pub(crate) fn split(
+    input: &Path,
+    (zoom, x, y): (u8, u32, u32),
+    output: &str,
+    template: &str,
+) -> Result<Vec<String>> {
+    let children = (0..4)
+        .map(|child| shard(zoom + 1, 2 * x + (child & 1), 2 * y + (child >> 1)))
+        .collect::<Result<Vec<_>>>()?;
+    let outputs = children
+        .iter()
+        .map(|child| Store::open_in(output, template, &format!("{}.osm.pbf", child.id)))
+        .collect::<Result<Vec<_>>>()?;
+    let header = input_header(input)?;
+    extract_pass(
+        input,
+        InputFormat::Pbf,
+        &header,
+        &children,
+        &outputs,
+        Strategy::CompleteWays,
+    )?;
+    Ok(children.into_iter().map(|child| child.id).collect())
+}
+
+/// The extract of one shard while it is written.
+struct Extract<'a> {
+    shard: &'a Shard,
+    file: NamedTempFile,
+    writer: PbfWriter<BufWriter<File>>,
+    /// Nodes, ways and relations written.
+    counts: [u64; 3],
+}
+
+/// Stream the input, writing every element to the extracts of `shards` it belongs to, then
+/// publish the extracts to `outputs`.
+fn extract_pass(
+    input: &Path,
+    format: InputFormat,
+    header: &Header,
+    shards: &[Shard],
+    outputs: &[Store],
+    strategy: Strategy,
+) -> Result<()> {
+    let tiles = Tiles::new(shards);
+    // `simple` selects as it writes; the others select ahead.
+    let mut selection = match strategy {
+        Strategy::Simple => Selection::default(),
+        _ => strategy::select(input, format, &tiles, strategy)?,
+    };
+    let selected = strategy != Strategy::Simple;
+
+    let mut extracts = shards
+        .iter()
+        .map(|shard| {
+            let (west, south, east, north) = tile_bbox(shard.zoom, shard.x, shard.y);
+            let header = Header {
+                bbox: Some(BBox {
+                    west,
+                    south,
+                    east,
+                    north,
+                }),
+                ..header.clone()
+            };
+            let file = NamedTempFile::new()?;
+            let writer = PbfWriter::new(BufWriter::new(file.reopen()?), &header)?;
+            Ok(Extract {
+                shard,
+                file,
+                writer,
+                counts: [0; 3],
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut targets: Vec<u32> = Vec::new();
+    stream(input, format, &mut |element| {
+        match element {
+            OsmElement::Node(node) if selected => {
+                targets.clear();
+                targets.extend(selection.nodes.get(node.id));
+            }
+            OsmElement::Node(node) => {
+                targets.clear();
+                targets.extend(tiles.locate(node.lon, node.lat));
+                for &shard in &targets {
+                    selection.nodes.push(node.id, shard);
+                }
+            }
+            OsmElement::Way(way) if selected => {
+                targets.clear();
+                targets.extend(selection.ways.get(way.id));
+            }
+            OsmElement::Way(way) => {
+                selection.way_targets(way, &mut targets);
+                for &shard in &targets {
+                    selection.ways.push(way.id, shard);
+                }
+            }
+            OsmElement::Relation(relation) => selection.relation_targets(relation, &mut targets),
+        }
+
+        for &target in &targets {
+            let extract = &mut extracts[target as usize];
+            match element {
+                OsmElement::Node(node) => {
+                    extract.counts[0] += 1;
+                    extract.writer.node(node)?;
+                }
+                OsmElement::Way(way) => {
+                    extract.counts[1] += 1;
+                    extract.writer.way(way)?;
+                }
+                OsmElement::Relation(relation) => {
+                    extract.counts[2] += 1;
+                    extract.writer.relation(relation)?;
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    for (extract, store) in extracts.into_iter().zip(outputs) {
+        extract.writer.finish()?;
+        store.put_file(extract.file.path(), None)?;
+        task::record_output(store.to_string());
+        let [node_count, way_count, relation_count] = extract.counts;
+        info!(
+            shard_id = %extract.shard.id,
+            destination = %store,
+            nodes = node_count,
+            ways = way_count,
+            relations = relation_count,
+            "Wrote extract {} to {store}: {node_count} nodes, {way_count} ways, \
+             {relation_count} relations.",
+            extract.shard.id
+        );
+    }
+    Ok(())
+}
+
+/// Feed every element of the input to `f`, checking that it is sorted by type, then ID.
+fn stream(
+    input: &Path,
+    format: InputFormat,
+    f: &mut dyn FnMut(&OsmElement) -> Result<()>,
+) -> Result<()> {
+    let mut last = (0, i64::MIN);
+    input::open_source(input, format)?.for_each_element(&mut |element| {
+        let (kind, id) = match &element {
+            OsmElement::Node(node) => (0, node.id),
+            OsmElement::Way(way) => (1, way.id),
+            OsmElement::Relation(relation) => (2, relation.id),
+        };
+        if (kind, id) <= last {
+            bail!(
+                "{} is not sorted by type, then ID (at {} {id}); sort it first, e.g. with \
+                 `osmium sort`",
+                input.display(),
+                ["node", "way", "relation"][kind]
+            );
+        }
+        last = (kind, id);
+        f(&element)
+    })
+}
+
+/// Bounding box and replication state from the header of a PBF input.
+fn input_header(path: &Path) -> Result<Header> {
+    let mut reader = BlobReader::from_path(path)
+        .with_context(|| format!("unable to open {}", path.display()))?;
+    let Some(blob) = reader.next().transpose()? else {
+        return Ok(Header::default());
+    };
+    let BlobDecode::OsmHeader(header) = blob.decode()? else {
+        return Ok(Header::default());
+    };
+    Ok(Header {
+        bbox: None,
+        replication_timestamp: header.osmosis_replication_timestamp(),
+        replication_sequence_number: header.osmosis_replication_sequence_number(),
+        replication_base_url: header.osmosis_replication_base_url().map(str::to_string),
+    })
+}
+
+/// Read the shards of a manifest in any of the sharder's text formats, or KMZ.
+pub(crate) fn read_manifest(location: &str) -> Result<Vec<Shard>> {
+    let store = Store::open(location, "manifest.json")?;
+    let bytes = match &store {
+        Store::Local(path) => read_decompressed(path)?,
+        _ => {
+            let Some(bytes) = store.get()? else {
+                bail!("manifest {store} does not exist");
+            };
+            let mut file = NamedTempFile::new()?;
+            std::io::Write::write_all(&mut file, &bytes)?;
+            read_decompressed(file.path())?
+        }
+    };
+    let shards = parse_manifest(&bytes).with_context(|| format!("invalid manifest {store}"))?;
+    if shards.is_empty() {
+        bail!("manifest {store} has no shards");
+    }
+    Ok(shards)
+}
+
+fn read_decompressed(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    input::open_decompressed(path)?
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("unable to read {}", path.display()))?;
+    Ok(bytes)
+}
+
+/// Shards of a FeatureCollection, GeoJSON lines, a KML or KMZ document, or lines whose
+/// first (comma or tab separated) field is a shard ID or quadkey.
+fn parse_manifest(bytes: &[u8]) -> Result<Vec<Shard>> {
+    let unreadable = match bytes {
+        [b'f', b'g', b'b', ..] => Some("FlatGeobuf"),
+        [b'P', b'A', b'R', b'1', ..] => Some("GeoParquet"),
+        _ => None,
+    };
+    if let Some(format) = unreadable {
+        bail!(
+            "{format} manifests cannot be read back; write the run's manifest as GeoJSON, \
+             GeoJSON lines, CSV, TSV, KML, KMZ or quadkeys"
+        );
+    }
+    if bytes.starts_with(b"PK") {
+        return parse_kml(std::str::from_utf8(&kml::unzip(bytes)?)?);
+    }
+    let text = std::str::from_utf8(bytes).context("not a text manifest")?;
+    match text.trim_start().chars().next() {
+        Some('<') => return parse_kml(text),
+        Some('{') => return parse_geojson(text),
+        _ => {}
+    }
+    // Lines are not trimmed as a whole: a quadkeys line of the zoom 0 shard starts with its
+    // empty key.
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split([',', '\t']).map(str::trim).collect::<Vec<_>>())
+        .filter(|fields| fields[0] != "shard_id")
+        .map(|fields| {
+            let field = fields[0];
+            // CSV and TSV rows have the node count after `z`, `x` and `y`, quadkeys next
+            // to the key.
+            let (shard, count) = match parse_shard_id(field) {
+                Some(shard) => (shard, fields.get(4)),
+                None => (
+                    parse_quadkey(field)
+                        .with_context(|| format!("{field} is neither a shard ID nor a quadkey"))?,
+                    fields.get(1),
+                ),
+            };
+            Ok(Shard {
+                node_count: count.and_then(|count| count.parse().ok()),
+                ..shard
+            })
+        })
+        .collect()
+}
+
+/// Shards of a FeatureCollection, or of GeoJSON lines, one of which may be the whole text.
+fn parse_geojson(text: &str) -> Result<Vec<Shard>> {
+    let features = match serde_json::from_str::<Value>(text) {
+        Ok(Value::Object(mut collection))
+            if collection.get("type").and_then(Value::as_str) == Some("FeatureCollection") =>
+        {
+            match collection.remove("features") {
+                Some(Value::Array(features)) => features,
+                _ => bail!("FeatureCollection without features"),
+            }
+        }
+        // The sequence of a single shard.
+        Ok(feature @ Value::Object(_)) => vec![feature],
+        _ => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+    features
+        .iter()
+        .map(|feature| {
+            let property = |name: &str| {
+                feature["properties"][name]
+                    .as_u64()
+                    .with_context(|| format!("feature without a numeric {name} property"))
+            };
+            tile_shard(
+                [property("z")?, property("x")?, property("y")?],
+                feature["properties"]["node_count"].as_u64(),
+            )
+        })
+        .collect()
+}
+
+/// Shards of the placemarks of a KML document.
+fn parse_kml(document: &str) -> Result<Vec<Shard>> {
+    kml::read(document)?
+        .into_iter()
+        .map(|[zoom, x, y, node_count]| tile_shard([zoom, x, y], Some(node_count)))
+        .collect()
+}
+
+/// The shard of tile `[zoom, x, y]`, as read from a manifest.
+fn tile_shard([zoom, x, y]: [u64; 3], node_count: Option<u64>) -> Result<Shard> {
+    Ok(Shard {
+        node_count,
+        ..shard(zoom.try_into()?, x.try_into()?, y.try_into()?)?
+    })
+}
+
+fn shard(zoom: u8, x: u32, y: u32) -> Result<Shard> {
+    if zoom > 31 || x >= 1 << zoom || y >= 1 << zoom {
+        bail!("tile {zoom}/{x}/{y} does not exist");
+    }
+    Ok(Shard {
+        id: format!("{zoom}-{x}-{y}"),
+        zoom,
+        x,
+        y,
+        node_count: None,
+    })
+}
+
+/// `z-x-y`.
+fn parse_shard_id(id: &str) -> Option<Shard> {
+    let mut parts = id.split('-');
+    let (zoom, x, y) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    shard(zoom.parse().ok()?, x.parse().ok()?, y.parse().ok()?).ok()
+}
+
+/// Inverse of the quadkeys manifest's keys.
+fn parse_quadkey(quadkey: &str) -> Option<Shard> {
+    let zoom = u8::try_from(quadkey.len()).ok()?;
+    let (mut x, mut y) = (0u32, 0u32);
+    for digit in quadkey.chars() {
+        let digit = digit.to_digit(4)?;
+        x = (x << 1) | (digit & 1);
+        y = (y << 1) | (digit >> 1);
+    }
+    shard(zoom, x, y).ok()
+}
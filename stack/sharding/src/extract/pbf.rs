@@ -0,0 +1,311 @@
+//! Minimal `.osm.pbf` writer: dense nodes, ways and relations with their tags, in blocks of
+//! one element type, zlib-compressed. Elements carry no metadata (version, timestamp,
+//! changeset, user), which is all a downstream extract needs.
+
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use hashbrown::HashMap;
+use std::io::Write;
+
+use crate::input::{MemberType, OsmNode, OsmRelation, OsmWay};
+use crate::tally::BBox;
+
+/// Elements per block; the format recommends at most 8000.
+const BLOCK_ELEMENTS: usize = 8000;
+/// Flush a block early once its encoded elements reach this many bytes, well under the
+/// 32 MiB limit on an uncompressed blob.
+const BLOCK_BYTES: usize = 8 << 20;
+
+/// What the header block of a written file says. The replication state is copied from the
+/// input, so extracts keep their planet's provenance.
+#[derive(Clone, Default)]
+pub struct Header {
+    pub bbox: Option<BBox>,
+    /// Seconds since the epoch.
+    pub replication_timestamp: Option<i64>,
+    pub replication_sequence_number: Option<i64>,
+    pub replication_base_url: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Node,
+    Way,
+    Relation,
+}
+
+/// Writes elements in the order given; callers keep them sorted by type, then ID.
+pub struct PbfWriter<W: Write> {
+    out: W,
+    block: Block,
+}
+
+impl<W: Write> PbfWriter<W> {
+    pub fn new(mut out: W, header: &Header) -> Result<Self> {
+        write_blob(&mut out, "OSMHeader", &header_block(header))?;
+        Ok(Self {
+            out,
+            block: Block::default(),
+        })
+    }
+
+    pub fn node(&mut self, node: &OsmNode) -> Result<()> {
+        self.start(Kind::Node)?;
+        let block = &mut self.block;
+        block.ids.push(node.id);
+        block.lats.push(decimicro(node.lat));
+        block.lons.push(decimicro(node.lon));
+        for (key, value) in &node.tags {
+            let (key, value) = (block.string(key), block.string(value));
+            block.keys_vals.extend([key, value]);
+        }
+        block.keys_vals.push(0);
+        Ok(())
+    }
+
+    pub fn way(&mut self, way: &OsmWay) -> Result<()> {
+        self.start(Kind::Way)?;
+        let block = &mut self.block;
+        let mut message = Vec::new();
+        field_varint(&mut message, 1, way.id as u64);
+        block.tags(&mut message, &way.tags);
+        field_packed(&mut message, 8, deltas(way.refs.iter().copied()));
+        field_bytes(&mut block.elements, 3, &message);
+        Ok(())
+    }
+
+    pub fn relation(&mut self, relation: &OsmRelation) -> Result<()> {
+        self.start(Kind::Relation)?;
+        let block = &mut self.block;
+        let mut message = Vec::new();
+        field_varint(&mut message, 1, relation.id as u64);
+        block.tags(&mut message, &relation.tags);
+        let roles: Vec<u64> = relation
+            .members
+            .iter()
+            .map(|member| u64::from(block.string(&member.role)))
+            .collect();
+        field_packed(&mut message, 8, roles);
+        field_packed(
+            &mut message,
+            9,
+            deltas(relation.members.iter().map(|member| member.id)),
+        );
+        let types = relation
+            .members
+            .iter()
+            .map(|member| match member.member_type {
+                MemberType::Node => 0,
+                MemberType::Way => 1,
+                MemberType::Relation => 2,
+            });
+        field_packed(&mut message, 10, types);
+        field_bytes(&mut block.elements, 4, &message);
+        Ok(())
+    }
+
+    /// Flush the last block and hand back the output.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Make room for one more element of `kind`, flushing the current block when it is full
+    /// or holds another type.
+    fn start(&mut self, kind: Kind) -> Result<()> {
+        let block = &self.block;
+        if block.kind.is_some_and(|current| current != kind)
+            || block.count >= BLOCK_ELEMENTS
+            || block.elements.len() + block.keys_vals.len() * 4 >= BLOCK_BYTES
+        {
+            self.flush()?;
+        }
+        self.block.kind = Some(kind);
+        self.block.count += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let block = std::mem::take(&mut self.block);
+        let Some(kind) = block.kind else {
+            return Ok(());
+        };
+        let mut group = Vec::new();
+        match kind {
+            Kind::Node => {
+                let mut dense = Vec::new();
+                field_packed(&mut dense, 1, deltas(block.ids.iter().copied()));
+                field_packed(&mut dense, 8, deltas(block.lats.iter().copied()));
+                field_packed(&mut dense, 9, deltas(block.lons.iter().copied()));
+                if block.keys_vals.iter().any(|&string| string != 0) {
+                    field_packed(
+                        &mut dense,
+                        10,
+                        block.keys_vals.iter().map(|&s| u64::from(s)),
+                    );
+                }
+                field_bytes(&mut group, 2, &dense);
+            }
+            Kind::Way | Kind::Relation => group = block.elements,
+        }
+        let mut strings = Vec::new();
+        for string in &block.strings {
+            field_bytes(&mut strings, 1, string.as_bytes());
+        }
+        let mut primitive = Vec::new();
+        field_bytes(&mut primitive, 1, &strings);
+        field_bytes(&mut primitive, 2, &group);
+        write_blob(&mut self.out, "OSMData", &primitive)
+    }
+}
+
+/// The block being filled: its string table and its elements of one kind.
+struct Block {
+    kind: Option<Kind>,
+    count: usize,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    // Dense nodes, delta-encoded on flush.
+    ids: Vec<i64>,
+    lats: Vec<i64>,
+    lons: Vec<i64>,
+    keys_vals: Vec<u32>,
+    /// Encoded `Way` or `Relation` messages, each as a field of the primitive group.
+    elements: Vec<u8>,
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Self {
+            kind: None,
+            count: 0,
+            // Index 0 is reserved: it ends a dense node's tags.
+            strings: vec![String::new()],
+            string_ids: HashMap::new(),
+            ids: Vec::new(),
+            lats: Vec::new(),
+            lons: Vec::new(),
+            keys_vals: Vec::new(),
+            elements: Vec::new(),
+        }
+    }
+}
+
+impl Block {
+    fn string(&mut self, string: &str) -> u32 {
+        if let Some(&id) = self.string_ids.get(string) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(string.to_string());
+        self.string_ids.insert(string.to_string(), id);
+        id
+    }
+
+    /// `keys` and `vals` fields of a way or relation.
+    fn tags(&mut self, message: &mut Vec<u8>, tags: &[(String, String)]) {
+        let (keys, vals): (Vec<u64>, Vec<u64>) = tags
+            .iter()
+            .map(|(key, value)| (u64::from(self.string(key)), u64::from(self.string(value))))
+            .unzip();
+        if !keys.is_empty() {
+            field_packed(message, 2, keys);
+            field_packed(message, 3, vals);
+        }
+    }
+}
+
+fn header_block(header: &Header) -> Vec<u8> {
+    let mut block = Vec::new();
+    if let Some(bbox) = header.bbox {
+        let mut message = Vec::new();
+        for (field, degrees) in [
+            (1, bbox.west),
+            (2, bbox.east),
+            (3, bbox.north),
+            (4, bbox.south),
+        ] {
+            field_varint(&mut message, field, zigzag((degrees * 1e9).round() as i64));
+        }
+        field_bytes(&mut block, 1, &message);
+    }
+    field_bytes(&mut block, 4, b"OsmSchema-V0.6");
+    field_bytes(&mut block, 4, b"DenseNodes");
+    field_bytes(&mut block, 5, b"Sort.Type_then_ID");
+    let program = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+    field_bytes(&mut block, 16, program.as_bytes());
+    if let Some(timestamp) = header.replication_timestamp {
+        field_varint(&mut block, 32, timestamp as u64);
+    }
+    if let Some(sequence) = header.replication_sequence_number {
+        field_varint(&mut block, 33, sequence as u64);
+    }
+    if let Some(url) = &header.replication_base_url {
+        field_bytes(&mut block, 34, url.as_bytes());
+    }
+    block
+}
+
+/// Frame `data` as a zlib-compressed blob of `kind`.
+fn write_blob(out: &mut impl Write, kind: &str, data: &[u8]) -> Result<()> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    let mut blob = Vec::new();
+    field_varint(&mut blob, 2, data.len() as u64);
+    field_bytes(&mut blob, 3, &compressed);
+    let mut header = Vec::new();
+    field_bytes(&mut header, 1, kind.as_bytes());
+    field_varint(&mut header, 3, blob.len() as u64);
+    out.write_all(&(header.len() as u32).to_be_bytes())?;
+    out.write_all(&header)?;
+    out.write_all(&blob)?;
+    Ok(())
+}
+
+/// Degrees in the default 100-nanodegree granularity.
+fn decimicro(degrees: f64) -> i64 {
+    (degrees * 1e7).round() as i64
+}
+
+/// Zigzag-encoded differences between consecutive values, for packed `sint64` fields.
+fn deltas(values: impl Iterator<Item = i64>) -> impl Iterator<Item = u64> {
+    let mut previous = 0;
+    values.map(move |value| {
+        let delta = value.wrapping_sub(previous);
+        previous = value;
+        zigzag(delta)
+    })
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn field_varint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    varint(buf, u64::from(field << 3));
+    varint(buf, value);
+}
+
+fn field_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    varint(buf, u64::from((field << 3) | 2));
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn field_packed(buf: &mut Vec<u8>, field: u32, values: impl IntoIterator<Item = u64>) {
+    let mut packed = Vec::new();
+    for value in values {
+        varint(&mut packed, value);
+    }
+    field_bytes(buf, field, &packed);
+}
@@ -0,0 +1,193 @@
+//! Which elements go to which extract, following osmium's extract strategies.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use hashbrown::HashMap;
+use std::path::Path;
+use tracing::info;
+
+use super::{stream, Shard};
+use crate::input::{InputFormat, MemberType, OsmElement, OsmRelation, OsmWay};
+use crate::lon_lat_to_tile;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Strategy {
+    /// Nodes inside the shard, and the ways and relations referencing them. Ways crossing
+    /// the shard's edge lose their nodes outside it. One read of the input.
+    Simple,
+    /// Like `simple`, but every way keeps all its nodes, wherever they are. Two reads.
+    CompleteWays,
+    /// Like `complete-ways`, but multipolygon relations also keep all their ways, so no
+    /// area is torn. Up to three reads.
+    Smart,
+}
+
+/// Finds the shard of a location among shards of any zoom.
+pub struct Tiles {
+    lookup: HashMap<(u8, u32, u32), u32>,
+    zooms: Vec<u8>,
+}
+
+impl Tiles {
+    pub fn new(shards: &[Shard]) -> Self {
+        let mut lookup = HashMap::new();
+        for (idx, shard) in shards.iter().enumerate() {
+            lookup.insert((shard.zoom, shard.x, shard.y), idx as u32);
+        }
+        let mut zooms: Vec<u8> = shards.iter().map(|shard| shard.zoom).collect();
+        zooms.sort_unstable();
+        zooms.dedup();
+        Self { lookup, zooms }
+    }
+
+    pub fn locate(&self, lon: f64, lat: f64) -> Option<u32> {
+        self.zooms.iter().find_map(|&zoom| {
+            let (x, y) = lon_lat_to_tile(lon, lat, zoom)?;
+            self.lookup.get(&(zoom, x, y)).copied()
+        })
+    }
+}
+
+/// Node or way IDs with a shard they go to, in ID order once finished. An element can go to
+/// several shards, so an ID can appear several times.
+#[derive(Default)]
+pub struct IdIndex {
+    entries: Vec<(i64, u32)>,
+}
+
+impl IdIndex {
+    pub fn push(&mut self, id: i64, shard: u32) {
+        self.entries.push((id, shard));
+    }
+
+    /// Shards `id` goes to.
+    pub fn get(&self, id: i64) -> impl Iterator<Item = u32> + '_ {
+        let start = self.entries.partition_point(|&(other, _)| other < id);
+        self.entries[start..]
+            .iter()
+            .take_while(move |&&(other, _)| other == id)
+            .map(|&(_, shard)| shard)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn append(&mut self, other: IdIndex) {
+        self.entries.extend(other.entries);
+    }
+
+    /// Sort and deduplicate entries pushed out of order.
+    fn finish(&mut self) {
+        self.entries.sort_unstable();
+        self.entries.dedup();
+    }
+}
+
+/// The nodes and ways of each extract.
+#[derive(Default)]
+pub struct Selection {
+    pub nodes: IdIndex,
+    pub ways: IdIndex,
+}
+
+impl Selection {
+    /// Shards holding one of the way's nodes.
+    pub fn way_targets(&self, way: &OsmWay, targets: &mut Vec<u32>) {
+        targets.clear();
+        for &node in &way.refs {
+            targets.extend(self.nodes.get(node));
+        }
+        targets.sort_unstable();
+        targets.dedup();
+    }
+
+    /// Shards holding one of the relation's node or way members.
+    pub fn relation_targets(&self, relation: &OsmRelation, targets: &mut Vec<u32>) {
+        targets.clear();
+        for member in &relation.members {
+            match member.member_type {
+                MemberType::Node => targets.extend(self.nodes.get(member.id)),
+                MemberType::Way => targets.extend(self.ways.get(member.id)),
+                MemberType::Relation => {}
+            }
+        }
+        targets.sort_unstable();
+        targets.dedup();
+    }
+}
+
+/// Read the input ahead of writing to select the complete ways (and, with `smart`, the
+/// complete multipolygons) of each extract. Not needed for `simple`, which selects while it
+/// writes.
+pub fn select(
+    input: &Path,
+    format: InputFormat,
+    tiles: &Tiles,
+    strategy: Strategy,
+) -> Result<Selection> {
+    let mut selection = Selection::default();
+    // Nodes of the selected ways, and ways of the selected multipolygons, by shard.
+    let mut way_nodes = IdIndex::default();
+    let mut area_ways = IdIndex::default();
+    let mut targets = Vec::new();
+    stream(input, format, &mut |element| {
+        match element {
+            OsmElement::Node(node) => {
+                if let Some(shard) = tiles.locate(node.lon, node.lat) {
+                    selection.nodes.push(node.id, shard);
+                }
+            }
+            OsmElement::Way(way) => {
+                selection.way_targets(way, &mut targets);
+                for &shard in &targets {
+                    selection.ways.push(way.id, shard);
+                    for &node in &way.refs {
+                        way_nodes.push(node, shard);
+                    }
+                }
+            }
+            OsmElement::Relation(relation)
+                if strategy == Strategy::Smart && is_multipolygon(relation) =>
+            {
+                selection.relation_targets(relation, &mut targets);
+                for &shard in &targets {
+                    for member in &relation.members {
+                        if member.member_type == MemberType::Way {
+                            area_ways.push(member.id, shard);
+                        }
+                    }
+                }
+            }
+            OsmElement::Relation(_) => {}
+        }
+        Ok(())
+    })?;
+
+    if !area_ways.is_empty() {
+        info!("Reading the input again to complete multipolygons...");
+        area_ways.finish();
+        stream(input, format, &mut |element| {
+            if let OsmElement::Way(way) = element {
+                for shard in area_ways.get(way.id) {
+                    for &node in &way.refs {
+                        way_nodes.push(node, shard);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        selection.ways.append(area_ways);
+        selection.ways.finish();
+    }
+    selection.nodes.append(way_nodes);
+    selection.nodes.finish();
+    Ok(selection)
+}
+
+fn is_multipolygon(relation: &OsmRelation) -> bool {
+    relation
+        .tags
+        .iter()
+        .any(|(key, value)| key == "type" && value == "multipolygon")
+}
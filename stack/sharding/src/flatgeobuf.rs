@@ -0,0 +1,470 @@
+//! FlatGeobuf writer (<https://flatgeobuf.org>), for the manifest and for POIs.
+//!
+//! The file is the magic bytes, a size-prefixed FlatBuffers header, a packed Hilbert R-tree
+//! over the feature bounding boxes and the size-prefixed features. The index lets readers
+//! fetch only the features intersecting a region with HTTP range requests. There is no
+//! FlatBuffers crate available to us, so the few tables the format needs are encoded by hand.
+
+use anyhow::{bail, Result};
+use std::io::Write;
+
+use crate::Feature;
+
+const MAGIC: [u8; 8] = [0x66, 0x67, 0x62, 0x03, 0x66, 0x67, 0x62, 0x00];
+/// Children per R-tree node; 16 is the FlatGeobuf default.
+const INDEX_NODE_SIZE: u16 = 16;
+
+pub const GEOMETRY_TYPE_POINT: u8 = 1;
+const GEOMETRY_TYPE_POLYGON: u8 = 3;
+const COLUMN_TYPE_UBYTE: u8 = 1;
+const COLUMN_TYPE_UINT: u8 = 6;
+pub const COLUMN_TYPE_LONG: u8 = 7;
+const COLUMN_TYPE_ULONG: u8 = 8;
+pub const COLUMN_TYPE_STRING: u8 = 11;
+pub const COLUMN_TYPE_JSON: u8 = 12;
+
+/// A column of the header; features leave out the values of a nullable column that are
+/// null.
+pub struct Column {
+    pub name: &'static str,
+    pub column_type: u8,
+    pub nullable: bool,
+}
+
+/// What the header says of the features.
+pub struct Layer<'a> {
+    pub name: &'a str,
+    pub geometry_type: u8,
+    /// In the order their indexes are written in feature properties.
+    pub columns: &'a [Column],
+    /// The header's free-form metadata string.
+    pub metadata: Option<&'a str>,
+}
+
+const fn column(name: &'static str, column_type: u8) -> Column {
+    Column {
+        name,
+        column_type,
+        nullable: false,
+    }
+}
+
+const COLUMNS: [Column; 5] = [
+    column("shard_id", COLUMN_TYPE_STRING),
+    column("z", COLUMN_TYPE_UBYTE),
+    column("x", COLUMN_TYPE_UINT),
+    column("y", COLUMN_TYPE_UINT),
+    column("node_count", COLUMN_TYPE_ULONG),
+];
+
+// Field ids from the FlatGeobuf schema (header.fbs and feature.fbs).
+const HEADER_NAME: u16 = 0;
+const HEADER_ENVELOPE: u16 = 1;
+const HEADER_GEOMETRY_TYPE: u16 = 2;
+const HEADER_COLUMNS: u16 = 7;
+const HEADER_FEATURES_COUNT: u16 = 8;
+const HEADER_INDEX_NODE_SIZE: u16 = 9;
+const HEADER_CRS: u16 = 10;
+const HEADER_METADATA: u16 = 13;
+const COLUMN_NAME: u16 = 0;
+const COLUMN_TYPE: u16 = 1;
+const COLUMN_NULLABLE: u16 = 7;
+const CRS_ORG: u16 = 0;
+const CRS_CODE: u16 = 1;
+const GEOMETRY_XY: u16 = 1;
+const FEATURE_GEOMETRY: u16 = 0;
+const FEATURE_PROPERTIES: u16 = 1;
+
+pub type Bounds = [f64; 4];
+
+/// Write `features` (single-ring polygons) as FlatGeobuf with a spatial index. `metadata`
+/// goes into the header's free-form metadata string.
+pub fn write(out: &mut impl Write, features: &[Feature], metadata: Option<&str>) -> Result<()> {
+    let mut items = Vec::with_capacity(features.len());
+    let mut encoded = Vec::with_capacity(features.len());
+    for feature in features {
+        let Some(ring) = feature.geometry.coordinates.first() else {
+            bail!("shard {} has no exterior ring", feature.properties.shard_id);
+        };
+        let bytes = encode_shard(feature);
+        items.push((ring_bounds(ring), bytes.len() as u64));
+        encoded.push(bytes);
+    }
+    let layer = Layer {
+        name: "shards",
+        geometry_type: GEOMETRY_TYPE_POLYGON,
+        columns: &COLUMNS,
+        metadata,
+    };
+    write_indexed(out, &layer, &items, |idx, out| {
+        Ok(out.write_all(&encoded[idx])?)
+    })
+}
+
+/// Write FlatGeobuf with a spatial index, given the bounds and encoded size of each feature.
+/// `feature` writes the encoded feature of an index; it is called in file order, sorted
+/// along a Hilbert curve, so the features can be kept anywhere until then.
+pub fn write_indexed<W: Write>(
+    out: &mut W,
+    layer: &Layer,
+    items: &[(Bounds, u64)],
+    mut feature: impl FnMut(usize, &mut W) -> Result<()>,
+) -> Result<()> {
+    let extent = items
+        .iter()
+        .fold(EMPTY_BOUNDS, |acc, (bounds, _)| expand(acc, *bounds));
+
+    // The index is only useful when features sharing a node are close together.
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    order.sort_by_cached_key(|&idx| std::cmp::Reverse(hilbert_value(&items[idx].0, &extent)));
+
+    out.write_all(&MAGIC)?;
+    out.write_all(&encode_header(layer, items.len() as u64, &extent))?;
+    if !items.is_empty() {
+        let mut offset = 0u64;
+        let leaves = order.iter().map(|&idx| {
+            let (bounds, size) = items[idx];
+            let leaf = (bounds, offset);
+            offset += size;
+            leaf
+        });
+        for (bounds, offset) in packed_rtree(leaves.collect()) {
+            for value in bounds {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&offset.to_le_bytes())?;
+        }
+    }
+    for idx in order {
+        feature(idx, out)?;
+    }
+    Ok(())
+}
+
+fn encode_header(layer: &Layer, features_count: u64, extent: &Bounds) -> Vec<u8> {
+    let mut fbb = Builder::default();
+    let columns: Vec<u32> = layer
+        .columns
+        .iter()
+        .map(|column| {
+            let name = fbb.create_string(column.name);
+            fbb.start_table();
+            fbb.add_offset(COLUMN_NAME, name);
+            fbb.add_u8(COLUMN_TYPE, column.column_type);
+            fbb.add_u8(COLUMN_NULLABLE, u8::from(column.nullable));
+            fbb.end_table()
+        })
+        .collect();
+    let columns = fbb.create_offset_vector(&columns);
+    let org = fbb.create_string("EPSG");
+    fbb.start_table();
+    fbb.add_offset(CRS_ORG, org);
+    fbb.add_i32(CRS_CODE, 4326);
+    let crs = fbb.end_table();
+    let name = fbb.create_string(layer.name);
+    let envelope = (features_count > 0).then(|| fbb.create_f64_vector(extent));
+    let metadata = layer.metadata.map(|metadata| fbb.create_string(metadata));
+
+    fbb.start_table();
+    fbb.add_u64(HEADER_FEATURES_COUNT, features_count);
+    fbb.add_offset(HEADER_NAME, name);
+    if let Some(envelope) = envelope {
+        fbb.add_offset(HEADER_ENVELOPE, envelope);
+    }
+    fbb.add_offset(HEADER_COLUMNS, columns);
+    fbb.add_offset(HEADER_CRS, crs);
+    if let Some(metadata) = metadata {
+        fbb.add_offset(HEADER_METADATA, metadata);
+    }
+    fbb.add_u16(HEADER_INDEX_NODE_SIZE, INDEX_NODE_SIZE);
+    fbb.add_u8(HEADER_GEOMETRY_TYPE, layer.geometry_type);
+    let header = fbb.end_table();
+    fbb.finish_size_prefixed(header)
+}
+
+fn encode_shard(feature: &Feature) -> Vec<u8> {
+    let props = &feature.properties;
+    let mut properties = Vec::new();
+    properties.extend_from_slice(&0u16.to_le_bytes());
+    properties.extend_from_slice(&(props.shard_id.len() as u32).to_le_bytes());
+    properties.extend_from_slice(props.shard_id.as_bytes());
+    properties.extend_from_slice(&1u16.to_le_bytes());
+    properties.push(props.z);
+    properties.extend_from_slice(&2u16.to_le_bytes());
+    properties.extend_from_slice(&props.x.to_le_bytes());
+    properties.extend_from_slice(&3u16.to_le_bytes());
+    properties.extend_from_slice(&props.y.to_le_bytes());
+    properties.extend_from_slice(&4u16.to_le_bytes());
+    properties.extend_from_slice(&props.node_count.to_le_bytes());
+
+    // A single ring needs no `ends`; the geometry type comes from the header.
+    let xy: Vec<f64> = feature
+        .geometry
+        .coordinates
+        .iter()
+        .flatten()
+        .flat_map(|&[x, y]| [x, y])
+        .collect();
+    encode_feature(&xy, &properties)
+}
+
+/// A size-prefixed feature of a point or single-part geometry's coordinates and encoded
+/// properties (each a `u16` column index and its value).
+pub fn encode_feature(xy: &[f64], properties: &[u8]) -> Vec<u8> {
+    let mut fbb = Builder::default();
+    let xy = fbb.create_f64_vector(xy);
+    fbb.start_table();
+    fbb.add_offset(GEOMETRY_XY, xy);
+    let geometry = fbb.end_table();
+    let properties = fbb.create_u8_vector(properties);
+    fbb.start_table();
+    fbb.add_offset(FEATURE_GEOMETRY, geometry);
+    fbb.add_offset(FEATURE_PROPERTIES, properties);
+    let root = fbb.end_table();
+    fbb.finish_size_prefixed(root)
+}
+
+const EMPTY_BOUNDS: Bounds = [
+    f64::INFINITY,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    f64::NEG_INFINITY,
+];
+
+fn expand(a: Bounds, b: Bounds) -> Bounds {
+    [
+        a[0].min(b[0]),
+        a[1].min(b[1]),
+        a[2].max(b[2]),
+        a[3].max(b[3]),
+    ]
+}
+
+fn ring_bounds(ring: &[[f64; 2]]) -> Bounds {
+    ring.iter()
+        .fold(EMPTY_BOUNDS, |acc, &[x, y]| expand(acc, [x, y, x, y]))
+}
+
+/// Nodes of a packed Hilbert R-tree over `leaves` (bounds and feature byte offset), root
+/// first and leaves last. Internal nodes point at their first child by node index.
+fn packed_rtree(leaves: Vec<(Bounds, u64)>) -> Vec<(Bounds, u64)> {
+    let node_size = usize::from(INDEX_NODE_SIZE);
+    // Node counts per level, leaves first.
+    let mut level_sizes = vec![leaves.len()];
+    let mut n = leaves.len();
+    loop {
+        n = n.div_ceil(node_size);
+        level_sizes.push(n);
+        if n == 1 {
+            break;
+        }
+    }
+    let total: usize = level_sizes.iter().sum();
+    let mut level_starts = Vec::with_capacity(level_sizes.len());
+    let mut end = total;
+    for &size in &level_sizes {
+        end -= size;
+        level_starts.push(end);
+    }
+
+    let mut nodes = vec![(EMPTY_BOUNDS, 0u64); total];
+    nodes[level_starts[0]..].copy_from_slice(&leaves);
+    for level in 0..level_sizes.len() - 1 {
+        let (start, end) = (
+            level_starts[level],
+            level_starts[level] + level_sizes[level],
+        );
+        let parents = level_starts[level + 1]..;
+        for (parent, first) in parents.zip((start..end).step_by(node_size)) {
+            let bounds = nodes[first..end.min(first + node_size)]
+                .iter()
+                .fold(EMPTY_BOUNDS, |acc, (bounds, _)| expand(acc, *bounds));
+            nodes[parent] = (bounds, first as u64);
+        }
+    }
+    nodes
+}
+
+/// Position of the centre of `bounds` on a 16-bit Hilbert curve across `extent`.
+fn hilbert_value(bounds: &Bounds, extent: &Bounds) -> u32 {
+    const MAX: f64 = 65_535.0;
+    let scale = |value: f64, min: f64, max: f64| {
+        if max > min {
+            (MAX * (value - min) / (max - min)).floor() as u32
+        } else {
+            0
+        }
+    };
+    let x = scale((bounds[0] + bounds[2]) / 2.0, extent[0], extent[2]);
+    let y = scale((bounds[1] + bounds[3]) / 2.0, extent[1], extent[3]);
+    hilbert(x, y)
+}
+
+/// Hilbert index of `(x, y)` in a 2^16 grid, as used by the FlatGeobuf reference writers.
+fn hilbert(x: u32, y: u32) -> u32 {
+    let mut a = x ^ y;
+    let mut b = 0xFFFF ^ a;
+    let mut c = 0xFFFF ^ (x | y);
+    let mut d = x & (y ^ 0xFFFF);
+
+    let mut aa = a | (b >> 1);
+    let mut bb = (a >> 1) ^ a;
+    let mut cc = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+    let mut dd = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+    for shift in [2, 4] {
+        (a, b, c, d) = (aa, bb, cc, dd);
+        aa = (a & (a >> shift)) ^ (b & (b >> shift));
+        bb = (a & (b >> shift)) ^ (b & ((a ^ b) >> shift));
+        cc ^= (a & (c >> shift)) ^ (b & (d >> shift));
+        dd ^= (b & (c >> shift)) ^ ((a ^ b) & (d >> shift));
+    }
+
+    (a, b, c, d) = (aa, bb, cc, dd);
+    cc ^= (a & (c >> 8)) ^ (b & (d >> 8));
+    dd ^= (b & (c >> 8)) ^ ((a ^ b) & (d >> 8));
+
+    a = cc ^ (cc >> 1);
+    b = dd ^ (dd >> 1);
+
+    let interleave = |mut v: u32| {
+        v = (v | (v << 8)) & 0x00FF_00FF;
+        v = (v | (v << 4)) & 0x0F0F_0F0F;
+        v = (v | (v << 2)) & 0x3333_3333;
+        (v | (v << 1)) & 0x5555_5555
+    };
+    let i0 = x ^ y;
+    let i1 = b | (0xFFFF ^ (i0 | a));
+    (interleave(i1) << 1) | interleave(i0)
+}
+
+/// Minimal FlatBuffers builder. Like the reference implementation it builds back to front,
+/// so every object is written before the tables referring to it and offsets point forward.
+/// Offsets returned are distances from the end of the buffer.
+#[derive(Default)]
+struct Builder {
+    /// The tail of the finished buffer.
+    buf: Vec<u8>,
+    /// Fields of the table being built: field id and offset from the end.
+    fields: Vec<(u16, u32)>,
+    table_start: u32,
+}
+
+impl Builder {
+    fn len(&self) -> u32 {
+        self.buf.len() as u32
+    }
+
+    fn prepend(&mut self, bytes: &[u8]) {
+        self.buf.splice(0..0, bytes.iter().copied());
+    }
+
+    /// Pad so that an object of `size` bytes written next starts `align`-aligned. The
+    /// finished buffer is a multiple of 8 bytes long, so alignment from the end holds
+    /// from the start too.
+    fn align(&mut self, size: usize, align: usize) {
+        let padding = (align - (self.buf.len() + size) % align) % align;
+        self.prepend(&vec![0; padding]);
+    }
+
+    fn create_string(&mut self, value: &str) -> u32 {
+        self.align(value.len() + 1, 4);
+        self.prepend(&[0]);
+        self.prepend(value.as_bytes());
+        self.prepend(&(value.len() as u32).to_le_bytes());
+        self.len()
+    }
+
+    fn create_u8_vector(&mut self, values: &[u8]) -> u32 {
+        self.align(values.len(), 4);
+        self.prepend(values);
+        self.prepend(&(values.len() as u32).to_le_bytes());
+        self.len()
+    }
+
+    fn create_f64_vector(&mut self, values: &[f64]) -> u32 {
+        self.align(values.len() * 8, 8);
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.prepend(&bytes);
+        self.prepend(&(values.len() as u32).to_le_bytes());
+        self.len()
+    }
+
+    fn create_offset_vector(&mut self, targets: &[u32]) -> u32 {
+        self.align(targets.len() * 4, 4);
+        for &target in targets.iter().rev() {
+            let offset = self.len() + 4 - target;
+            self.prepend(&offset.to_le_bytes());
+        }
+        self.prepend(&(targets.len() as u32).to_le_bytes());
+        self.len()
+    }
+
+    fn start_table(&mut self) {
+        self.fields.clear();
+        self.table_start = self.len();
+    }
+
+    fn add_scalar(&mut self, field: u16, bytes: &[u8]) {
+        self.align(bytes.len(), bytes.len());
+        self.prepend(bytes);
+        self.fields.push((field, self.len()));
+    }
+
+    fn add_u8(&mut self, field: u16, value: u8) {
+        self.add_scalar(field, &[value]);
+    }
+
+    fn add_u16(&mut self, field: u16, value: u16) {
+        self.add_scalar(field, &value.to_le_bytes());
+    }
+
+    fn add_i32(&mut self, field: u16, value: i32) {
+        self.add_scalar(field, &value.to_le_bytes());
+    }
+
+    fn add_u64(&mut self, field: u16, value: u64) {
+        self.add_scalar(field, &value.to_le_bytes());
+    }
+
+    fn add_offset(&mut self, field: u16, target: u32) {
+        self.align(4, 4);
+        let offset = self.len() + 4 - target;
+        self.add_scalar(field, &offset.to_le_bytes());
+    }
+
+    /// Write the vtable and the table's offset to it; returns the table's offset.
+    fn end_table(&mut self) -> u32 {
+        self.align(4, 4);
+        self.prepend(&[0; 4]);
+        let table = self.len();
+
+        let slots = self.fields.iter().map(|&(id, _)| id + 1).max().unwrap_or(0);
+        let mut vtable = vec![0u16; 2 + usize::from(slots)];
+        vtable[0] = (vtable.len() * 2) as u16;
+        vtable[1] = (table - self.table_start) as u16;
+        for &(id, offset) in &self.fields {
+            vtable[2 + usize::from(id)] = (table - offset) as u16;
+        }
+        let bytes: Vec<u8> = vtable.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.prepend(&bytes);
+
+        // The vtable sits right before the table: soffset = table position - vtable position.
+        let soffset = (self.len() - table) as i32;
+        let at = (self.len() - table) as usize;
+        self.buf[at..at + 4].copy_from_slice(&soffset.to_le_bytes());
+        self.fields.clear();
+        table
+    }
+
+    /// Finish with `root` as the root table, prefixed by the buffer size as FlatGeobuf
+    /// expects.
+    fn finish_size_prefixed(mut self, root: u32) -> Vec<u8> {
+        self.align(8, 8);
+        let offset = self.len() + 4 - root;
+        self.prepend(&offset.to_le_bytes());
+        let size = self.len();
+        self.prepend(&size.to_le_bytes());
+        self.buf
+    }
+}
@@ -0,0 +1,82 @@
+//! Ring normalization and validity checks for the GeoJSON output (RFC 7946 section 3.1.6).
+
+use anyhow::{bail, Result};
+
+/// Close `ring` if its last position differs from the first and make it counterclockwise,
+/// as RFC 7946 requires of exterior rings.
+pub fn normalize_exterior(ring: &mut Vec<[f64; 2]>) {
+    if let (Some(&first), Some(&last)) = (ring.first(), ring.last()) {
+        if first != last {
+            ring.push(first);
+        }
+    }
+    if signed_area(ring) < 0.0 {
+        ring.reverse();
+    }
+}
+
+/// Check that `ring` is a closed, non-degenerate, simple linear ring with positions in
+/// longitude/latitude range.
+pub fn validate_ring(ring: &[[f64; 2]]) -> Result<()> {
+    if ring.len() < 4 {
+        bail!("ring has {} positions, at least 4 are required", ring.len());
+    }
+    if ring.first() != ring.last() {
+        bail!("ring is not closed");
+    }
+    if let Some([lon, lat]) = ring
+        .iter()
+        .find(|[lon, lat]| !((-180.0..=180.0).contains(lon) && (-90.0..=90.0).contains(lat)))
+    {
+        bail!("position [{lon}, {lat}] is outside longitude/latitude range");
+    }
+    if signed_area(ring) == 0.0 {
+        bail!("ring has zero area (coordinate precision too low for the tile size?)");
+    }
+
+    let edges = ring.len() - 1;
+    for i in 0..edges {
+        // Adjacent edges share an endpoint, as do the first and last edge.
+        for j in i + 2..edges {
+            if i == 0 && j == edges - 1 {
+                continue;
+            }
+            if segments_intersect(ring[i], ring[i + 1], ring[j], ring[j + 1]) {
+                bail!("ring intersects itself between positions {i} and {j}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Shoelace area; positive when the ring is counterclockwise.
+fn signed_area(ring: &[[f64; 2]]) -> f64 {
+    ring.windows(2)
+        .map(|pair| pair[0][0] * pair[1][1] - pair[1][0] * pair[0][1])
+        .sum::<f64>()
+        / 2.0
+}
+
+fn segments_intersect(a: [f64; 2], b: [f64; 2], c: [f64; 2], d: [f64; 2]) -> bool {
+    let (d1, d2) = (orientation(c, d, a), orientation(c, d, b));
+    let (d3, d4) = (orientation(a, b, c), orientation(a, b, d));
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(c, d, a))
+        || (d2 == 0.0 && on_segment(c, d, b))
+        || (d3 == 0.0 && on_segment(a, b, c))
+        || (d4 == 0.0 && on_segment(a, b, d))
+}
+
+fn orientation(a: [f64; 2], b: [f64; 2], p: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Whether `p`, collinear with `a` and `b`, lies within their bounding box.
+fn on_segment(a: [f64; 2], b: [f64; 2], p: [f64; 2]) -> bool {
+    p[0] >= a[0].min(b[0])
+        && p[0] <= a[0].max(b[0])
+        && p[1] >= a[1].min(b[1])
+        && p[1] <= a[1].max(b[1])
+}
@@ -0,0 +1,533 @@
+//! GeoParquet writer (<https://geoparquet.org>, version 1.1), for the manifest and for POIs.
+//!
+//! Row groups of uncompressed, PLAIN-encoded columns, with geometries as WKB and the `geo`
+//! key in the file metadata. That is all Athena, DuckDB and GDAL need, and it keeps us off
+//! a Parquet dependency: the footer is Thrift compact protocol, encoded by hand below.
+
+use anyhow::Result;
+use serde_json::json;
+use std::io::Write;
+
+use crate::Feature;
+
+const MAGIC: &[u8; 4] = b"PAR1";
+
+// Parquet physical types, converted types and encodings (parquet.thrift).
+pub const TYPE_INT32: i32 = 1;
+pub const TYPE_INT64: i32 = 2;
+pub const TYPE_DOUBLE: i32 = 5;
+pub const TYPE_BYTE_ARRAY: i32 = 6;
+pub const CONVERTED_UTF8: i32 = 0;
+const CONVERTED_UINT_8: i32 = 11;
+const CONVERTED_UINT_32: i32 = 13;
+const CONVERTED_UINT_64: i32 = 14;
+const REPETITION_REQUIRED: i32 = 0;
+const REPETITION_OPTIONAL: i32 = 1;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_DATA: i32 = 0;
+
+/// A column's schema: its path (`["bbox", "xmin"]` for a field of a struct), physical
+/// type, converted type, integer bit width (0 for strings and binary, and signed integers
+/// have none) and whether it may be null.
+pub struct Column {
+    pub path: &'static [&'static str],
+    pub physical: i32,
+    pub converted: Option<i32>,
+    pub bits: i8,
+    pub optional: bool,
+}
+
+const COLUMNS: [Column; 6] = [
+    Column {
+        path: &["shard_id"],
+        physical: TYPE_BYTE_ARRAY,
+        converted: Some(CONVERTED_UTF8),
+        bits: 0,
+        optional: false,
+    },
+    Column {
+        path: &["z"],
+        physical: TYPE_INT32,
+        converted: Some(CONVERTED_UINT_8),
+        bits: 8,
+        optional: false,
+    },
+    Column {
+        path: &["x"],
+        physical: TYPE_INT32,
+        converted: Some(CONVERTED_UINT_32),
+        bits: 32,
+        optional: false,
+    },
+    Column {
+        path: &["y"],
+        physical: TYPE_INT32,
+        converted: Some(CONVERTED_UINT_32),
+        bits: 32,
+        optional: false,
+    },
+    Column {
+        path: &["node_count"],
+        physical: TYPE_INT64,
+        converted: Some(CONVERTED_UINT_64),
+        bits: 64,
+        optional: false,
+    },
+    Column {
+        path: &["geometry"],
+        physical: TYPE_BYTE_ARRAY,
+        converted: None,
+        bits: 0,
+        optional: false,
+    },
+];
+
+/// Where a column chunk ended up in the file.
+struct Chunk {
+    offset: u64,
+    size: u64,
+}
+
+struct RowGroup {
+    rows: i64,
+    chunks: Vec<Chunk>,
+}
+
+/// One column's values in the row group being filled, PLAIN-encoded.
+#[derive(Default)]
+pub struct Values {
+    plain: Vec<u8>,
+    /// Whether each row has a value, the definition levels of an optional column.
+    present: Vec<bool>,
+}
+
+impl Values {
+    pub fn i32(&mut self, value: i32) {
+        self.plain.extend_from_slice(&value.to_le_bytes());
+        self.present.push(true);
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.plain.extend_from_slice(&value.to_le_bytes());
+        self.present.push(true);
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.plain.extend_from_slice(&value.to_le_bytes());
+        self.present.push(true);
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.plain
+            .extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.plain.extend_from_slice(value);
+        self.present.push(true);
+    }
+
+    /// A missing value, in an optional column.
+    pub fn null(&mut self) {
+        self.present.push(false);
+    }
+
+    /// Definition levels in the RLE/bit-packed hybrid encoding, as RLE runs of bit width 1,
+    /// with their length first as in a v1 data page.
+    fn levels(&self) -> Vec<u8> {
+        let mut runs = Compact::default();
+        let mut rows = self.present.iter().peekable();
+        while let Some(&present) = rows.next() {
+            let mut len = 1u64;
+            while rows.next_if_eq(&&present).is_some() {
+                len += 1;
+            }
+            runs.varint(len << 1);
+            runs.buf.push(u8::from(present));
+        }
+        let mut levels = (runs.buf.len() as u32).to_le_bytes().to_vec();
+        levels.extend_from_slice(&runs.buf);
+        levels
+    }
+}
+
+/// Writes a Parquet file a row group at a time, so a table never needs to be held in memory
+/// whole.
+pub struct Writer<W: Write> {
+    out: W,
+    columns: &'static [Column],
+    position: u64,
+    row_groups: Vec<RowGroup>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut out: W, columns: &'static [Column]) -> Result<Self> {
+        out.write_all(MAGIC)?;
+        Ok(Self {
+            out,
+            columns,
+            position: MAGIC.len() as u64,
+            row_groups: Vec::new(),
+        })
+    }
+
+    /// Write a row group of `values`, one per column and all with the same rows, emptying
+    /// them for the next.
+    pub fn row_group(&mut self, values: &mut [Values]) -> Result<()> {
+        let rows = values.first().map_or(0, |values| values.present.len());
+        let mut chunks = Vec::with_capacity(self.columns.len());
+        for (column, values) in self.columns.iter().zip(values.iter_mut()) {
+            let levels = if column.optional {
+                values.levels()
+            } else {
+                Vec::new()
+            };
+            let size = levels.len() + values.plain.len();
+            let mut header = Compact::default();
+            header.begin();
+            header.i32(1, PAGE_DATA);
+            header.i32(2, size as i32);
+            header.i32(3, size as i32);
+            header.begin_struct(5);
+            header.i32(1, rows as i32);
+            header.i32(2, ENCODING_PLAIN);
+            header.i32(3, ENCODING_RLE);
+            header.i32(4, ENCODING_RLE);
+            header.end_struct();
+            header.end();
+
+            self.out.write_all(&header.buf)?;
+            self.out.write_all(&levels)?;
+            self.out.write_all(&values.plain)?;
+            let size = (header.buf.len() + size) as u64;
+            chunks.push(Chunk {
+                offset: self.position,
+                size,
+            });
+            self.position += size;
+            *values = Values::default();
+        }
+        self.row_groups.push(RowGroup {
+            rows: rows as i64,
+            chunks,
+        });
+        Ok(())
+    }
+
+    /// Write the footer with `key_values` as the file metadata, handing back the output.
+    pub fn finish(mut self, key_values: &[(&str, String)]) -> Result<W> {
+        let footer = self.file_metadata(key_values);
+        self.out.write_all(&footer)?;
+        self.out.write_all(&(footer.len() as u32).to_le_bytes())?;
+        self.out.write_all(MAGIC)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Thrift-encoded `FileMetaData`.
+    fn file_metadata(&self, key_values: &[(&str, String)]) -> Vec<u8> {
+        let mut meta = Compact::default();
+        meta.begin();
+        meta.i32(1, 1);
+
+        // The schema depth first: the root, then each top-level field followed by the
+        // fields of a struct. The fields of a struct are adjacent columns.
+        let top_level = |idx: usize| {
+            idx == 0 || self.columns[idx].path.first() != self.columns[idx - 1].path.first()
+        };
+        let fields = (0..self.columns.len())
+            .filter(|&idx| top_level(idx))
+            .count();
+        let structs = (0..self.columns.len())
+            .filter(|&idx| top_level(idx) && self.columns[idx].path.len() > 1)
+            .count();
+        meta.list(2, Compact::STRUCT, 1 + self.columns.len() + structs);
+        meta.begin();
+        meta.binary(4, b"schema");
+        meta.i32(5, fields as i32);
+        meta.end();
+        for (idx, column) in self.columns.iter().enumerate() {
+            if let [parent, ..] = column.path {
+                if top_level(idx) && column.path.len() > 1 {
+                    let children = self.columns[idx..]
+                        .iter()
+                        .take_while(|other| other.path.first() == Some(parent))
+                        .count();
+                    meta.begin();
+                    meta.i32(3, REPETITION_REQUIRED);
+                    meta.binary(4, parent.as_bytes());
+                    meta.i32(5, children as i32);
+                    meta.end();
+                }
+            }
+            meta.begin();
+            meta.i32(1, column.physical);
+            meta.i32(
+                3,
+                if column.optional {
+                    REPETITION_OPTIONAL
+                } else {
+                    REPETITION_REQUIRED
+                },
+            );
+            let name = column.path.last().expect("column without a name");
+            meta.binary(4, name.as_bytes());
+            if let Some(converted) = column.converted {
+                meta.i32(6, converted);
+                // LogicalType union: STRING (1) or INTEGER (10).
+                meta.begin_struct(10);
+                if column.bits == 0 {
+                    meta.begin_struct(1);
+                } else {
+                    meta.begin_struct(10);
+                    meta.i8(1, column.bits);
+                    meta.bool(2, false);
+                }
+                meta.end_struct();
+                meta.end_struct();
+            }
+            meta.end();
+        }
+
+        let num_rows: i64 = self.row_groups.iter().map(|group| group.rows).sum();
+        meta.i64(3, num_rows);
+
+        meta.list(4, Compact::STRUCT, self.row_groups.len());
+        for group in &self.row_groups {
+            let total: u64 = group.chunks.iter().map(|chunk| chunk.size).sum();
+            meta.begin();
+            meta.list(1, Compact::STRUCT, group.chunks.len());
+            for (column, chunk) in self.columns.iter().zip(&group.chunks) {
+                meta.begin();
+                meta.i64(2, chunk.offset as i64);
+                meta.begin_struct(3);
+                meta.i32(1, column.physical);
+                if column.optional {
+                    meta.list(2, Compact::I32, 2);
+                    meta.list_i32(ENCODING_PLAIN);
+                    meta.list_i32(ENCODING_RLE);
+                } else {
+                    meta.list(2, Compact::I32, 1);
+                    meta.list_i32(ENCODING_PLAIN);
+                }
+                meta.list(3, Compact::BINARY, column.path.len());
+                for name in column.path {
+                    meta.list_binary(name.as_bytes());
+                }
+                meta.i32(4, CODEC_UNCOMPRESSED);
+                meta.i64(5, group.rows);
+                meta.i64(6, chunk.size as i64);
+                meta.i64(7, chunk.size as i64);
+                meta.i64(9, chunk.offset as i64);
+                meta.end_struct();
+                meta.end();
+            }
+            meta.i64(2, total as i64);
+            meta.i64(3, group.rows);
+            meta.end();
+        }
+
+        meta.list(5, Compact::STRUCT, key_values.len());
+        for (key, value) in key_values {
+            meta.begin();
+            meta.binary(1, key.as_bytes());
+            meta.binary(2, value.as_bytes());
+            meta.end();
+        }
+        meta.binary(
+            6,
+            concat!("osm-planet-sharding ", env!("CARGO_PKG_VERSION")).as_bytes(),
+        );
+        meta.end();
+        meta.buf
+    }
+}
+
+/// Write the manifest's `features` (single-ring polygons) as GeoParquet, in one row group.
+/// `metadata` is stored under the `osm_sharding` key of the file metadata.
+pub fn write(out: &mut impl Write, features: &[Feature], metadata: Option<&str>) -> Result<()> {
+    let mut bbox = [
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    ];
+    for &[x, y] in features
+        .iter()
+        .flat_map(|f| f.geometry.coordinates.iter().flatten())
+    {
+        bbox = [
+            bbox[0].min(x),
+            bbox[1].min(y),
+            bbox[2].max(x),
+            bbox[3].max(y),
+        ];
+    }
+
+    let mut values: Vec<Values> = COLUMNS.iter().map(|_| Values::default()).collect();
+    for feature in features {
+        let props = &feature.properties;
+        values[0].bytes(props.shard_id.as_bytes());
+        values[1].i32(i32::from(props.z));
+        // Unsigned values are stored in the signed physical type of the same width.
+        values[2].i32(props.x as i32);
+        values[3].i32(props.y as i32);
+        values[4].i64(props.node_count as i64);
+        values[5].bytes(&wkb_polygon(&feature.geometry.coordinates));
+    }
+    let mut writer = Writer::new(out, &COLUMNS)?;
+    writer.row_group(&mut values)?;
+
+    let mut geo = json!({
+        "version": "1.1.0",
+        "primary_column": "geometry",
+        "columns": {
+            "geometry": {
+                "encoding": "WKB",
+                "geometry_types": ["Polygon"],
+            }
+        }
+    });
+    if !features.is_empty() {
+        geo["columns"]["geometry"]["bbox"] = json!(bbox);
+    }
+    let mut key_values = vec![("geo", geo.to_string())];
+    if let Some(metadata) = metadata {
+        key_values.push(("osm_sharding", metadata.to_string()));
+    }
+    writer.finish(&key_values)?;
+    Ok(())
+}
+
+/// Little-endian WKB Polygon.
+fn wkb_polygon(rings: &[Vec<[f64; 2]>]) -> Vec<u8> {
+    let mut wkb = vec![1];
+    wkb.extend_from_slice(&3u32.to_le_bytes());
+    wkb.extend_from_slice(&(rings.len() as u32).to_le_bytes());
+    for ring in rings {
+        wkb.extend_from_slice(&(ring.len() as u32).to_le_bytes());
+        for &[x, y] in ring {
+            wkb.extend_from_slice(&x.to_le_bytes());
+            wkb.extend_from_slice(&y.to_le_bytes());
+        }
+    }
+    wkb
+}
+
+/// Little-endian WKB Point.
+pub fn wkb_point(x: f64, y: f64) -> Vec<u8> {
+    let mut wkb = vec![1];
+    wkb.extend_from_slice(&1u32.to_le_bytes());
+    wkb.extend_from_slice(&x.to_le_bytes());
+    wkb.extend_from_slice(&y.to_le_bytes());
+    wkb
+}
+
+/// Thrift compact protocol writer, covering the types the Parquet footer uses.
+#[derive(Default)]
+struct Compact {
+    buf: Vec<u8>,
+    /// Last field id written in each open struct, for delta-encoded field headers.
+    last_ids: Vec<i16>,
+}
+
+impl Compact {
+    const BOOL_TRUE: u8 = 1;
+    const BOOL_FALSE: u8 = 2;
+    const BYTE: u8 = 3;
+    const I32: u8 = 5;
+    const I64: u8 = 6;
+    const BINARY: u8 = 8;
+    const LIST: u8 = 9;
+    const STRUCT: u8 = 12;
+
+    /// Open a struct that is a list element or the top-level message.
+    fn begin(&mut self) {
+        self.last_ids.push(0);
+    }
+
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last_ids.pop();
+    }
+
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, Self::STRUCT);
+        self.begin();
+    }
+
+    fn end_struct(&mut self) {
+        self.end();
+    }
+
+    fn field(&mut self, id: i16, field_type: u8) {
+        let last = self.last_ids.last_mut().expect("field outside a struct");
+        let delta = id - *last;
+        if (1..=15).contains(&delta) {
+            self.buf.push(((delta as u8) << 4) | field_type);
+        } else {
+            self.buf.push(field_type);
+            self.varint(((id << 1) ^ (id >> 15)) as u16 as u64);
+        }
+        *self.last_ids.last_mut().expect("field outside a struct") = id;
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn bool(&mut self, id: i16, value: bool) {
+        self.field(
+            id,
+            if value {
+                Self::BOOL_TRUE
+            } else {
+                Self::BOOL_FALSE
+            },
+        );
+    }
+
+    fn i8(&mut self, id: i16, value: i8) {
+        self.field(id, Self::BYTE);
+        self.buf.push(value as u8);
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, Self::I32);
+        self.list_i32(value);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, Self::I64);
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, Self::BINARY);
+        self.list_binary(value);
+    }
+
+    fn list(&mut self, id: i16, element_type: u8, len: usize) {
+        self.field(id, Self::LIST);
+        if len < 15 {
+            self.buf.push(((len as u8) << 4) | element_type);
+        } else {
+            self.buf.push(0xF0 | element_type);
+            self.varint(len as u64);
+        }
+    }
+
+    /// An i32 list element (also the body of an i32 field).
+    fn list_i32(&mut self, value: i32) {
+        self.varint(((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    /// A binary list element (also the body of a binary field).
+    fn list_binary(&mut self, value: &[u8]) {
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+}
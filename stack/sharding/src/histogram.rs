@@ -0,0 +1,324 @@
+//! Compact on-disk form of the hierarchical count histogram.
+//!
+//! Only the max-zoom counts are stored (coarser levels are re-derived on load), sorted by
+//! tile and delta/varint encoded, then gzip compressed. A planet histogram at z14 is a few
+//! tens of MB instead of the 100+ GB PBF it summarizes.
+//!
+//! Version 1 sorts cells by `(x, y)` and stores the cell count up front. Version 2 sorts
+//! them by Morton key ([`crate::tile_key`]) and ends with a zero-count marker instead, so
+//! it can be written from, and read back as, a stream without holding every cell in memory.
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hashbrown::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use tracing::info;
+
+use crate::scan::ScanResult;
+use crate::{key_tile, tile_key};
+
+const MAGIC: &[u8; 8] = b"OSMHIST\0";
+const FORMAT_VERSION: u8 = 2;
+
+/// Scan-wide values stored ahead of the cells.
+#[derive(Clone, Copy, Debug)]
+pub struct Header {
+    pub max_zoom: u8,
+    pub sample: Option<f64>,
+    pub node_total: u64,
+    pub duplicate_total: u64,
+}
+
+impl Header {
+    pub fn of(scan: &ScanResult) -> Self {
+        Self {
+            max_zoom: (scan.counts.len() - 1) as u8,
+            sample: scan.sample,
+            node_total: scan.node_total,
+            duplicate_total: scan.duplicate_total,
+        }
+    }
+}
+
+/// `(Morton key, count)` cells in increasing key order.
+pub type KeyedCells = Box<dyn Iterator<Item = Result<(u64, u64)>>>;
+
+/// Incremental writer for cells arriving in increasing Morton key order.
+pub struct Writer<W: Write> {
+    out: GzEncoder<W>,
+    prev: u64,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W, header: &Header) -> Result<Self> {
+        let mut out = GzEncoder::new(out, Compression::default());
+        out.write_all(MAGIC)?;
+        out.write_all(&[FORMAT_VERSION, header.max_zoom])?;
+        out.write_all(&header.sample.unwrap_or(0.0).to_le_bytes())?;
+        write_varint(&mut out, header.node_total)?;
+        write_varint(&mut out, header.duplicate_total)?;
+        Ok(Self { out, prev: 0 })
+    }
+
+    /// Append one cell. Keys must be strictly increasing; zero counts are skipped.
+    pub fn push(&mut self, key: u64, count: u64) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        write_varint(&mut self.out, key - self.prev)?;
+        write_varint(&mut self.out, count)?;
+        self.prev = key;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        write_varint(&mut self.out, 0)?;
+        write_varint(&mut self.out, 0)?;
+        Ok(self.out.finish()?)
+    }
+}
+
+/// Start a histogram file to be filled through [`Writer::push`].
+pub fn create(path: &Path, header: &Header) -> Result<Writer<BufWriter<File>>> {
+    let file =
+        File::create(path).with_context(|| format!("unable to create {}", path.display()))?;
+    Writer::new(BufWriter::new(file), header)
+}
+
+/// Write the max-zoom level of `scan` to `path`.
+pub fn write(path: &Path, scan: &ScanResult) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("unable to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    encode(scan, &mut out)?;
+    out.flush()?;
+    info!(path = %path.display(), "Wrote count histogram to {}.", path.display());
+    Ok(())
+}
+
+/// Load a histogram written by [`write`] and rebuild every zoom level.
+pub fn read(path: &Path) -> Result<ScanResult> {
+    let file = File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+    let scan = decode(BufReader::new(file))
+        .with_context(|| format!("unable to load count histogram {}", path.display()))?;
+
+    let max_zoom = scan.counts.len() - 1;
+    info!(
+        path = %path.display(),
+        nodes = scan.node_total,
+        tiles = scan.counts[max_zoom].len(),
+        max_zoom,
+        "Loaded count histogram from {} ({} nodes in {} max-zoom tiles, z{}).",
+        path.display(),
+        scan.node_total,
+        scan.counts[max_zoom].len(),
+        max_zoom
+    );
+    Ok(scan)
+}
+
+/// Open a histogram as a stream of `(Morton key, count)` max-zoom cells in key order.
+/// Version 1 files are not stored in that order and are sorted in memory first.
+pub fn stream(path: &Path) -> Result<(Header, KeyedCells)> {
+    let file = File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+    let cells = Cells::new(BufReader::new(file))
+        .with_context(|| format!("unable to load count histogram {}", path.display()))?;
+    let header = cells.header;
+    let sorted = cells.version == FORMAT_VERSION;
+
+    let keyed = cells.map(|cell| cell.map(|((x, y), count)| (tile_key(x, y), count)));
+    if sorted {
+        return Ok((header, Box::new(keyed)));
+    }
+
+    let mut keyed = keyed.collect::<Result<Vec<_>>>()?;
+    keyed.sort_unstable_by_key(|&(key, _)| key);
+    Ok((header, Box::new(keyed.into_iter().map(Ok))))
+}
+
+/// Serialize the max-zoom level of `scan` in the compressed histogram format.
+pub fn encode(scan: &ScanResult, out: impl Write) -> Result<()> {
+    let header = Header::of(scan);
+    let mut cells: Vec<(u64, u64)> = scan.counts[usize::from(header.max_zoom)]
+        .iter()
+        .map(|(&(x, y), &count)| (tile_key(x, y), count))
+        .collect();
+    cells.sort_unstable_by_key(|&(key, _)| key);
+
+    let mut writer = Writer::new(out, &header)?;
+    for (key, count) in cells {
+        writer.push(key, count)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Inverse of [`encode`].
+pub fn decode(input: impl Read) -> Result<ScanResult> {
+    let cells = Cells::new(input)?;
+    let header = cells.header;
+    let leaves = cells.collect::<Result<HashMap<_, _>>>()?;
+
+    Ok(ScanResult {
+        counts: aggregate_levels(leaves, header.max_zoom),
+        node_total: header.node_total,
+        duplicate_total: header.duplicate_total,
+        sample: header.sample,
+        spilled: None,
+        blob_index: None,
+    })
+}
+
+/// Derive every coarser zoom level from the max-zoom counts.
+pub fn aggregate_levels(
+    leaves: HashMap<(u32, u32), u64>,
+    max_zoom: u8,
+) -> Vec<HashMap<(u32, u32), u64>> {
+    let mut levels = vec![leaves];
+    for _ in 0..max_zoom {
+        let child = levels.last().expect("at least one level");
+        let mut parent = HashMap::with_capacity(child.len() / 2);
+        for (&(x, y), &count) in child {
+            *parent.entry((x >> 1, y >> 1)).or_insert(0) += count;
+        }
+        levels.push(parent);
+    }
+    levels.reverse();
+    levels
+}
+
+/// Decoder over the cells of either format version, in file order.
+struct Cells<R: Read> {
+    input: GzDecoder<R>,
+    header: Header,
+    version: u8,
+    /// Cells left to read in a version 1 file.
+    remaining: u64,
+    tile: (u32, u32),
+    key: u64,
+    done: bool,
+}
+
+impl<R: Read> Cells<R> {
+    fn new(input: R) -> Result<Self> {
+        let mut input = GzDecoder::new(input);
+
+        let mut magic = [0u8; 8];
+        input
+            .read_exact(&mut magic)
+            .context("not a count histogram")?;
+        if &magic != MAGIC {
+            bail!("not a count histogram");
+        }
+
+        let mut header = [0u8; 2];
+        input.read_exact(&mut header)?;
+        let [version, max_zoom] = header;
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            bail!("unsupported histogram version {version}");
+        }
+
+        let mut sample = [0u8; 8];
+        input.read_exact(&mut sample)?;
+        let sample = f64::from_le_bytes(sample);
+        let node_total = read_varint(&mut input)?;
+        let duplicate_total = read_varint(&mut input)?;
+        let remaining = if version == 1 {
+            read_varint(&mut input)?
+        } else {
+            0
+        };
+
+        Ok(Self {
+            input,
+            header: Header {
+                max_zoom,
+                sample: (sample > 0.0).then_some(sample),
+                node_total,
+                duplicate_total,
+            },
+            version,
+            remaining,
+            tile: (0, 0),
+            key: 0,
+            done: false,
+        })
+    }
+
+    fn next_v1(&mut self) -> Result<Option<((u32, u32), u64)>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let dx = u32::try_from(read_varint(&mut self.input)?)?;
+        let y_value = u32::try_from(read_varint(&mut self.input)?)?;
+        let count = read_varint(&mut self.input)?;
+        let (x, y) = self.tile;
+        self.tile = (x + dx, if dx == 0 { y + y_value } else { y_value });
+        Ok(Some((self.tile, count)))
+    }
+
+    fn next_v2(&mut self) -> Result<Option<((u32, u32), u64)>> {
+        let delta = read_varint(&mut self.input)?;
+        let count = read_varint(&mut self.input)?;
+        if count == 0 {
+            return Ok(None);
+        }
+        self.key += delta;
+        Ok(Some((key_tile(self.key), count)))
+    }
+}
+
+impl<R: Read> Iterator for Cells<R> {
+    type Item = Result<((u32, u32), u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let cell = if self.version == 1 {
+            self.next_v1()
+        } else {
+            self.next_v2()
+        };
+        match cell {
+            Ok(Some(cell)) => Some(Ok(cell)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+pub(crate) fn write_varint(out: &mut impl Write, mut value: u64) -> Result<()> {
+    while value >= 0x80 {
+        out.write_all(&[(value as u8) | 0x80])?;
+        value >>= 7;
+    }
+    out.write_all(&[value as u8])?;
+    Ok(())
+}
+
+pub(crate) fn read_varint(input: &mut impl Read) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        input
+            .read_exact(&mut byte)
+            .context("truncated count histogram")?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("malformed varint in count histogram")
+}
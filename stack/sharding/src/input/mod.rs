@@ -0,0 +1,199 @@
+//! Input formats. PBF keeps its parallel block-based fast path in the scanner; every
+//! format can also be streamed element by element through [`ElementSource`].
+
+mod o5m;
+mod pbf;
+mod xml;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+pub use o5m::O5mSource;
+pub use pbf::PbfSource;
+pub use xml::XmlSource;
+
+/// On-disk OSM encodings we can read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Pick the format from the file extension.
+    Auto,
+    /// Protocol Buffer Binary Format (`.osm.pbf`).
+    Pbf,
+    /// OSM XML (`.osm`, `.osm.xml`), optionally gzip, bzip2 or zstd compressed.
+    Xml,
+    /// o5m (`.o5m`) or o5c change files (`.o5c`).
+    O5m,
+}
+
+impl InputFormat {
+    /// Resolve `Auto` by looking at the file name, ignoring any compression suffix.
+    pub fn resolve(self, path: &Path) -> Result<InputFormat> {
+        if self != InputFormat::Auto {
+            return Ok(self);
+        }
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let name = name
+            .strip_suffix(".gz")
+            .or_else(|| name.strip_suffix(".bz2"))
+            .or_else(|| name.strip_suffix(".zst"))
+            .unwrap_or(&name);
+
+        if name.ends_with(".pbf") {
+            Ok(InputFormat::Pbf)
+        } else if name.ends_with(".osm") || name.ends_with(".xml") {
+            Ok(InputFormat::Xml)
+        } else if name.ends_with(".o5m") || name.ends_with(".o5c") {
+            Ok(InputFormat::O5m)
+        } else {
+            bail!(
+                "cannot detect the format of {}; pass --input-format",
+                path.display()
+            )
+        }
+    }
+}
+
+/// Owned OSM element produced by the streaming readers.
+#[derive(Debug, Clone)]
+pub enum OsmElement {
+    Node(OsmNode),
+    Way(OsmWay),
+    Relation(OsmRelation),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OsmNode {
+    pub id: i64,
+    pub lat: f64,
+    pub lon: f64,
+    pub tags: Vec<(String, String)>,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OsmWay {
+    pub id: i64,
+    pub refs: Vec<i64>,
+    pub tags: Vec<(String, String)>,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OsmRelation {
+    pub id: i64,
+    pub members: Vec<Member>,
+    pub tags: Vec<(String, String)>,
+    pub meta: Meta,
+}
+
+/// Edit metadata of an element, as far as the input has it.
+#[derive(Debug, Clone, Default)]
+pub struct Meta {
+    pub version: Option<u32>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: Option<i64>,
+    pub changeset: Option<i64>,
+    pub uid: Option<i64>,
+    pub user: Option<String>,
+}
+
+/// One relation member reference.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub member_type: MemberType,
+    pub id: i64,
+    pub role: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberType {
+    Node,
+    Way,
+    Relation,
+}
+
+/// A sequential reader of OSM elements, independent of the on-disk encoding.
+pub trait ElementSource {
+    /// Feed every element, in file order, to `f`. Stops at the first error.
+    fn for_each_element(&mut self, f: &mut dyn FnMut(OsmElement) -> Result<()>) -> Result<()>;
+
+    /// A handle on the file the elements are read from, sharing its offset, to tell how
+    /// far through it the source is; `None` when it is read through a decompressor.
+    fn position(&self) -> Option<File> {
+        None
+    }
+}
+
+/// Open `path` as a streaming element source of the given (resolved) format.
+pub fn open_source(path: &Path, format: InputFormat) -> Result<Box<dyn ElementSource>> {
+    match format.resolve(path)? {
+        InputFormat::Pbf => Ok(Box::new(PbfSource::open(path)?)),
+        InputFormat::Xml => Ok(Box::new(XmlSource::new(open_decompressed(path)?))),
+        InputFormat::O5m => Ok(Box::new(O5mSource::new(open_decompressed(path)?))),
+        InputFormat::Auto => unreachable!("resolve never returns Auto"),
+    }
+}
+
+/// Open a file and transparently undo gzip, bzip2 or zstd compression, detected by magic
+/// bytes.
+pub fn open_decompressed(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+    let mut reader = BufReader::with_capacity(1 << 20, file);
+    let magic = reader.fill_buf()?;
+
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))));
+    }
+    // No bzip2 or zstd crate in the dependency set; the container ships both binaries.
+    let program = if magic.starts_with(b"BZh") {
+        "bzip2"
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        "zstd"
+    } else {
+        return Ok(Box::new(reader));
+    };
+    let mut child = Command::new(program)
+        .arg("-dc")
+        .arg(path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("unable to run {program} to decompress input"))?;
+    let stdout = child
+        .stdout
+        .take()
+        .with_context(|| format!("{program} produced no stdout"))?;
+    Ok(Box::new(BufReader::with_capacity(
+        1 << 20,
+        ChildReader { child, stdout },
+    )))
+}
+
+/// Stdout of a decompression process; surfaces a non-zero exit status at end of stream.
+struct ChildReader {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for ChildReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "decompressor exited with {status}"
+                )));
+            }
+        }
+        Ok(n)
+    }
+}
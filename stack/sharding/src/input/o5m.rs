@@ -0,0 +1,380 @@
+//! Streaming o5m / o5c reader.
+//!
+//! o5m is a delta-coded binary format: every dataset starts with a type byte and (for
+//! types below 0xf0) a length, ids/coordinates are deltas against running counters, and
+//! strings are either inline or back-references into a 15000-entry circular table.
+
+use anyhow::{bail, Context, Result};
+use std::io::BufRead;
+
+use super::{ElementSource, Member, MemberType, Meta, OsmElement, OsmNode, OsmRelation, OsmWay};
+
+const DATASET_NODE: u8 = 0x10;
+const DATASET_WAY: u8 = 0x11;
+const DATASET_RELATION: u8 = 0x12;
+const DATASET_END: u8 = 0xfe;
+const DATASET_RESET: u8 = 0xff;
+
+const STRING_TABLE_SIZE: usize = 15_000;
+/// Inline strings (or pairs) longer than this are not added to the string table.
+const STRING_TABLE_MAX_LEN: usize = 250;
+
+/// Element source over (already decompressed) o5m or o5c data. Deleted objects in o5c
+/// change files carry no payload and are skipped.
+pub struct O5mSource {
+    reader: Box<dyn BufRead + Send>,
+}
+
+impl O5mSource {
+    pub fn new(reader: Box<dyn BufRead + Send>) -> Self {
+        Self { reader }
+    }
+}
+
+/// Delta-coding state, cleared by every reset dataset.
+#[derive(Default)]
+struct Counters {
+    node_id: i64,
+    way_id: i64,
+    relation_id: i64,
+    timestamp: i64,
+    changeset: i64,
+    lon: i64,
+    lat: i64,
+    way_ref: i64,
+    member_refs: [i64; 3],
+}
+
+/// Circular table of recently seen inline strings.
+struct StringTable {
+    entries: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        Self {
+            entries: vec![Vec::new(); STRING_TABLE_SIZE],
+            next: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.iter_mut().for_each(Vec::clear);
+        self.next = 0;
+    }
+
+    fn push(&mut self, entry: Vec<u8>) {
+        self.entries[self.next] = entry;
+        self.next = (self.next + 1) % STRING_TABLE_SIZE;
+    }
+
+    fn lookup(&self, back: u64) -> Result<&[u8]> {
+        let back = usize::try_from(back)
+            .ok()
+            .filter(|back| (1..=STRING_TABLE_SIZE).contains(back))
+            .with_context(|| format!("invalid o5m string reference {back}"))?;
+        Ok(&self.entries[(self.next + STRING_TABLE_SIZE - back) % STRING_TABLE_SIZE])
+    }
+}
+
+impl ElementSource for O5mSource {
+    fn for_each_element(&mut self, f: &mut dyn FnMut(OsmElement) -> Result<()>) -> Result<()> {
+        let mut counters = Counters::default();
+        let mut strings = StringTable::new();
+        let mut data = Vec::new();
+
+        loop {
+            let mut kind = [0u8; 1];
+            if self.reader.read(&mut kind)? == 0 {
+                return Ok(());
+            }
+            match kind[0] {
+                DATASET_END => return Ok(()),
+                DATASET_RESET => {
+                    counters = Counters::default();
+                    strings.clear();
+                    continue;
+                }
+                // Remaining single-byte datasets carry no payload.
+                0xf0..=0xfd => continue,
+                _ => {}
+            }
+
+            let len = read_uvarint(&mut self.reader)?;
+            data.clear();
+            data.resize(usize::try_from(len)?, 0);
+            self.reader
+                .read_exact(&mut data)
+                .context("truncated o5m dataset")?;
+
+            let mut cursor = Cursor {
+                data: &data,
+                pos: 0,
+            };
+            let element = match kind[0] {
+                DATASET_NODE => read_node(&mut cursor, &mut counters, &mut strings)?,
+                DATASET_WAY => read_way(&mut cursor, &mut counters, &mut strings)?,
+                DATASET_RELATION => read_relation(&mut cursor, &mut counters, &mut strings)?,
+                // Header, bounding box, file timestamp, sync and jump datasets.
+                _ => None,
+            };
+            if let Some(element) = element {
+                f(element)?;
+            }
+        }
+    }
+}
+
+fn read_node(
+    cursor: &mut Cursor<'_>,
+    counters: &mut Counters,
+    strings: &mut StringTable,
+) -> Result<Option<OsmElement>> {
+    counters.node_id += cursor.svarint()?;
+    let id = counters.node_id;
+    let meta = read_version_info(cursor, counters, strings)?;
+    if cursor.at_end() {
+        return Ok(None);
+    }
+
+    counters.lon += cursor.svarint()?;
+    counters.lat += cursor.svarint()?;
+    let tags = read_tags(cursor, strings)?;
+
+    Ok(Some(OsmElement::Node(OsmNode {
+        id,
+        lon: counters.lon as f64 / 1e7,
+        lat: counters.lat as f64 / 1e7,
+        tags,
+        meta,
+    })))
+}
+
+fn read_way(
+    cursor: &mut Cursor<'_>,
+    counters: &mut Counters,
+    strings: &mut StringTable,
+) -> Result<Option<OsmElement>> {
+    counters.way_id += cursor.svarint()?;
+    let id = counters.way_id;
+    let meta = read_version_info(cursor, counters, strings)?;
+    if cursor.at_end() {
+        return Ok(None);
+    }
+
+    let refs_end = cursor.section_end()?;
+    let mut refs = Vec::new();
+    while cursor.pos < refs_end {
+        counters.way_ref += cursor.svarint()?;
+        refs.push(counters.way_ref);
+    }
+    let tags = read_tags(cursor, strings)?;
+
+    Ok(Some(OsmElement::Way(OsmWay {
+        id,
+        refs,
+        tags,
+        meta,
+    })))
+}
+
+fn read_relation(
+    cursor: &mut Cursor<'_>,
+    counters: &mut Counters,
+    strings: &mut StringTable,
+) -> Result<Option<OsmElement>> {
+    counters.relation_id += cursor.svarint()?;
+    let id = counters.relation_id;
+    let meta = read_version_info(cursor, counters, strings)?;
+    if cursor.at_end() {
+        return Ok(None);
+    }
+
+    let members_end = cursor.section_end()?;
+    let mut members = Vec::new();
+    while cursor.pos < members_end {
+        let delta = cursor.svarint()?;
+        let type_and_role = cursor.string(strings)?;
+        let (&type_byte, role) = type_and_role
+            .split_first()
+            .context("empty o5m relation member type")?;
+        let (member_type, slot) = match type_byte {
+            b'0' => (MemberType::Node, 0),
+            b'1' => (MemberType::Way, 1),
+            b'2' => (MemberType::Relation, 2),
+            other => bail!("unknown o5m member type {:?}", other as char),
+        };
+        counters.member_refs[slot] += delta;
+        members.push(Member {
+            member_type,
+            id: counters.member_refs[slot],
+            role: String::from_utf8_lossy(role).into_owned(),
+        });
+    }
+    let tags = read_tags(cursor, strings)?;
+
+    Ok(Some(OsmElement::Relation(OsmRelation {
+        id,
+        members,
+        tags,
+        meta,
+    })))
+}
+
+/// Read the version/timestamp/changeset/author block, keeping the delta counters and
+/// string table in sync.
+fn read_version_info(
+    cursor: &mut Cursor<'_>,
+    counters: &mut Counters,
+    strings: &mut StringTable,
+) -> Result<Meta> {
+    let mut meta = Meta::default();
+    if cursor.at_end() {
+        return Ok(meta);
+    }
+    let version = cursor.uvarint()?;
+    if version == 0 {
+        return Ok(meta);
+    }
+    meta.version = u32::try_from(version).ok();
+
+    let timestamp_delta = cursor.svarint()?;
+    counters.timestamp += timestamp_delta;
+    if counters.timestamp != 0 {
+        counters.changeset += cursor.svarint()?;
+        // The author is a uid, varint-encoded in the first string, and a user name.
+        let (uid, user) = cursor.pair(strings)?;
+        meta.timestamp = Some(counters.timestamp);
+        meta.changeset = Some(counters.changeset);
+        let uid = Cursor { data: &uid, pos: 0 }.uvarint().ok();
+        meta.uid = uid
+            .and_then(|uid| i64::try_from(uid).ok())
+            .filter(|&uid| uid != 0);
+        meta.user =
+            Some(String::from_utf8_lossy(&user).into_owned()).filter(|user| !user.is_empty());
+    }
+    Ok(meta)
+}
+
+fn read_tags(cursor: &mut Cursor<'_>, strings: &mut StringTable) -> Result<Vec<(String, String)>> {
+    let mut tags = Vec::new();
+    while !cursor.at_end() {
+        let (key, value) = cursor.pair(strings)?;
+        tags.push((
+            String::from_utf8_lossy(&key).into_owned(),
+            String::from_utf8_lossy(&value).into_owned(),
+        ));
+    }
+    Ok(tags)
+}
+
+/// Read position inside one dataset.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self.data.get(self.pos).context("o5m dataset ended early")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn uvarint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("o5m varint is too long")
+    }
+
+    fn svarint(&mut self) -> Result<i64> {
+        let raw = self.uvarint()?;
+        Ok(((raw >> 1) as i64) ^ -((raw & 1) as i64))
+    }
+
+    /// Length-prefixed subsection (way refs, relation members); returns its end offset.
+    fn section_end(&mut self) -> Result<usize> {
+        let len = usize::try_from(self.uvarint()?)?;
+        let end = self.pos + len;
+        if end > self.data.len() {
+            bail!("o5m reference section overruns its dataset");
+        }
+        Ok(end)
+    }
+
+    fn zero_terminated(&mut self) -> Result<Vec<u8>> {
+        let rest = &self.data[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .context("unterminated o5m string")?;
+        self.pos += len + 1;
+        Ok(rest[..len].to_vec())
+    }
+
+    /// A single string, inline or from the table.
+    fn string(&mut self, strings: &mut StringTable) -> Result<Vec<u8>> {
+        let reference = self.uvarint()?;
+        if reference != 0 {
+            return Ok(strings.lookup(reference)?.to_vec());
+        }
+        let value = self.zero_terminated()?;
+        if value.len() <= STRING_TABLE_MAX_LEN {
+            strings.push(value.clone());
+        }
+        Ok(value)
+    }
+
+    /// A key/value (or uid/user) pair, inline or from the table.
+    fn pair(&mut self, strings: &mut StringTable) -> Result<(Vec<u8>, Vec<u8>)> {
+        let reference = self.uvarint()?;
+        let entry = if reference != 0 {
+            strings.lookup(reference)?.to_vec()
+        } else {
+            let start = self.pos;
+            self.zero_terminated()?;
+            self.zero_terminated()?;
+            let entry = self.data[start..self.pos].to_vec();
+            if entry.len() - 2 <= STRING_TABLE_MAX_LEN {
+                strings.push(entry.clone());
+            }
+            entry
+        };
+
+        let split = entry
+            .iter()
+            .position(|&b| b == 0)
+            .context("malformed o5m string pair")?;
+        let value_end = entry.len().saturating_sub(1).max(split + 1);
+        Ok((
+            entry[..split].to_vec(),
+            entry[split + 1..value_end].to_vec(),
+        ))
+    }
+}
+
+fn read_uvarint(reader: &mut dyn BufRead) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        reader
+            .read_exact(&mut byte)
+            .context("truncated o5m length")?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("o5m length is too long")
+}
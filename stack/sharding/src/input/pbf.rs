@@ -0,0 +1,109 @@
+//! Sequential PBF element source built on `osmpbf::BlobReader`.
+
+use anyhow::{Context, Result};
+use osmpbf::{BlobDecode, BlobReader, Element, Info, RelMemberType};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use super::{ElementSource, Member, MemberType, Meta, OsmElement, OsmNode, OsmRelation, OsmWay};
+
+/// Streams a `.osm.pbf` file as owned elements. The scanner does not use this (it works on
+/// raw blocks in parallel); it exists for consumers that need tags and references.
+pub struct PbfSource {
+    file: File,
+}
+
+impl PbfSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+        Ok(Self { file })
+    }
+}
+
+impl ElementSource for PbfSource {
+    fn for_each_element(&mut self, f: &mut dyn FnMut(OsmElement) -> Result<()>) -> Result<()> {
+        let reader = BlobReader::new(BufReader::new(self.file.try_clone()?));
+        // Blob by blob, so the first error stops the read rather than the rest of the file
+        // being decoded for nothing.
+        for blob in reader {
+            let BlobDecode::OsmData(block) = blob?.decode()? else {
+                continue;
+            };
+            for element in block.elements() {
+                f(convert(&element))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> Option<File> {
+        self.file.try_clone().ok()
+    }
+}
+
+/// Copy a borrowed `osmpbf` element into the shared owned representation.
+pub fn convert(element: &Element<'_>) -> OsmElement {
+    match element {
+        Element::Node(node) => OsmElement::Node(OsmNode {
+            id: node.id(),
+            lat: node.lat(),
+            lon: node.lon(),
+            tags: owned_tags(node.tags()),
+            meta: meta(&node.info()),
+        }),
+        Element::DenseNode(node) => OsmElement::Node(OsmNode {
+            id: node.id(),
+            lat: node.lat(),
+            lon: node.lon(),
+            tags: owned_tags(node.tags()),
+            meta: node.info().map_or_else(Meta::default, |info| Meta {
+                version: u32::try_from(info.version()).ok(),
+                timestamp: Some(info.milli_timestamp() / 1000),
+                changeset: Some(info.changeset()),
+                uid: Some(info.uid().into()),
+                user: info.user().ok().map(str::to_string),
+            }),
+        }),
+        Element::Way(way) => OsmElement::Way(OsmWay {
+            id: way.id(),
+            refs: way.refs().collect(),
+            tags: owned_tags(way.tags()),
+            meta: meta(&way.info()),
+        }),
+        Element::Relation(relation) => OsmElement::Relation(OsmRelation {
+            id: relation.id(),
+            members: relation
+                .members()
+                .map(|member| Member {
+                    member_type: match member.member_type {
+                        RelMemberType::Node => MemberType::Node,
+                        RelMemberType::Way => MemberType::Way,
+                        RelMemberType::Relation => MemberType::Relation,
+                    },
+                    id: member.member_id,
+                    role: member.role().unwrap_or_default().to_string(),
+                })
+                .collect(),
+            tags: owned_tags(relation.tags()),
+            meta: meta(&relation.info()),
+        }),
+    }
+}
+
+fn meta(info: &Info<'_>) -> Meta {
+    Meta {
+        version: info
+            .version()
+            .and_then(|version| u32::try_from(version).ok()),
+        timestamp: info.milli_timestamp().map(|millis| millis / 1000),
+        changeset: info.changeset(),
+        uid: info.uid().map(i64::from),
+        user: info.user().and_then(Result::ok).map(str::to_string),
+    }
+}
+
+fn owned_tags<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(String, String)> {
+    tags.map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
@@ -0,0 +1,295 @@
+//! Streaming OSM XML reader.
+//!
+//! OSM XML is a flat, attribute-only dialect, so rather than building a DOM we read one
+//! tag at a time from a `BufRead` and assemble elements as their closing tags arrive.
+
+use anyhow::{bail, Context, Result};
+use std::io::BufRead;
+
+use super::{ElementSource, Member, MemberType, Meta, OsmElement, OsmNode, OsmRelation, OsmWay};
+use crate::logging;
+
+/// Element source over (already decompressed) OSM XML.
+pub struct XmlSource {
+    reader: Box<dyn BufRead + Send>,
+}
+
+impl XmlSource {
+    pub fn new(reader: Box<dyn BufRead + Send>) -> Self {
+        Self { reader }
+    }
+}
+
+/// One start, end or self-closing tag.
+struct Tag {
+    name: String,
+    attrs: Vec<(String, String)>,
+    closing: bool,
+    self_closing: bool,
+}
+
+impl Tag {
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The edit metadata in the attributes; invalid values count as missing.
+    fn meta(&self) -> Meta {
+        Meta {
+            version: self.attr("version").and_then(|raw| raw.parse().ok()),
+            timestamp: self.attr("timestamp").and_then(logging::parse_rfc3339),
+            changeset: self.attr("changeset").and_then(|raw| raw.parse().ok()),
+            uid: self.attr("uid").and_then(|raw| raw.parse().ok()),
+            user: self.attr("user").map(str::to_string),
+        }
+    }
+
+    fn parse_attr<T: std::str::FromStr>(&self, key: &str) -> Result<T> {
+        let raw = self
+            .attr(key)
+            .with_context(|| format!("<{}> is missing the '{key}' attribute", self.name))?;
+        raw.parse()
+            .ok()
+            .with_context(|| format!("<{}> has an invalid '{key}' value: {raw}", self.name))
+    }
+}
+
+impl ElementSource for XmlSource {
+    fn for_each_element(&mut self, f: &mut dyn FnMut(OsmElement) -> Result<()>) -> Result<()> {
+        let mut current: Option<OsmElement> = None;
+        let mut buf = Vec::new();
+
+        while let Some(tag) = next_tag(&mut self.reader, &mut buf)? {
+            if tag.closing {
+                if matches!(tag.name.as_str(), "node" | "way" | "relation") {
+                    if let Some(element) = current.take() {
+                        f(element)?;
+                    }
+                }
+                continue;
+            }
+
+            let element = match tag.name.as_str() {
+                "node" => Some(OsmElement::Node(OsmNode {
+                    id: tag.parse_attr("id")?,
+                    lat: tag.parse_attr("lat")?,
+                    lon: tag.parse_attr("lon")?,
+                    tags: Vec::new(),
+                    meta: tag.meta(),
+                })),
+                "way" => Some(OsmElement::Way(OsmWay {
+                    id: tag.parse_attr("id")?,
+                    meta: tag.meta(),
+                    ..OsmWay::default()
+                })),
+                "relation" => Some(OsmElement::Relation(OsmRelation {
+                    id: tag.parse_attr("id")?,
+                    meta: tag.meta(),
+                    ..OsmRelation::default()
+                })),
+                "tag" => {
+                    let key = tag.attr("k").unwrap_or_default().to_string();
+                    let value = tag.attr("v").unwrap_or_default().to_string();
+                    match current.as_mut() {
+                        Some(OsmElement::Node(node)) => node.tags.push((key, value)),
+                        Some(OsmElement::Way(way)) => way.tags.push((key, value)),
+                        Some(OsmElement::Relation(relation)) => relation.tags.push((key, value)),
+                        None => {}
+                    }
+                    None
+                }
+                "nd" => {
+                    if let Some(OsmElement::Way(way)) = current.as_mut() {
+                        way.refs.push(tag.parse_attr("ref")?);
+                    }
+                    None
+                }
+                "member" => {
+                    if let Some(OsmElement::Relation(relation)) = current.as_mut() {
+                        let member_type = match tag.attr("type") {
+                            Some("node") => MemberType::Node,
+                            Some("way") => MemberType::Way,
+                            Some("relation") => MemberType::Relation,
+                            other => bail!("unknown relation member type {other:?}"),
+                        };
+                        relation.members.push(Member {
+                            member_type,
+                            id: tag.parse_attr("ref")?,
+                            role: tag.attr("role").unwrap_or_default().to_string(),
+                        });
+                    }
+                    None
+                }
+                _ => None,
+            };
+
+            if let Some(element) = element {
+                if tag.self_closing {
+                    f(element)?;
+                } else {
+                    current = Some(element);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the next tag, skipping text, comments, processing instructions and doctypes.
+fn next_tag(reader: &mut dyn BufRead, buf: &mut Vec<u8>) -> Result<Option<Tag>> {
+    loop {
+        buf.clear();
+        // Skip character data up to the next '<'.
+        if reader.read_until(b'<', buf)? == 0 || buf.last() != Some(&b'<') {
+            return Ok(None);
+        }
+
+        buf.clear();
+        read_tag_body(reader, buf)?;
+
+        if buf.starts_with(b"!--") {
+            while !buf.ends_with(b"--") {
+                buf.push(b'>');
+                if reader.read_until(b'>', buf)? == 0 {
+                    bail!("unterminated XML comment");
+                }
+                buf.pop();
+            }
+            continue;
+        }
+        if buf.starts_with(b"?") || buf.starts_with(b"!") {
+            continue;
+        }
+
+        let body = std::str::from_utf8(buf).context("XML tag is not valid UTF-8")?;
+        return parse_tag(body).map(Some);
+    }
+}
+
+/// Read up to the closing '>' of a tag, honouring quoted attribute values.
+fn read_tag_body(reader: &mut dyn BufRead, buf: &mut Vec<u8>) -> Result<()> {
+    let mut quote: Option<u8> = None;
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            bail!("unexpected end of XML input inside a tag");
+        }
+
+        let mut consumed = 0;
+        let mut done = false;
+        for &byte in available {
+            consumed += 1;
+            match (quote, byte) {
+                (Some(q), b) if b == q => quote = None,
+                (Some(_), _) => {}
+                (None, b'"' | b'\'') => quote = Some(byte),
+                (None, b'>') => {
+                    done = true;
+                    break;
+                }
+                _ => {}
+            }
+            if !done {
+                buf.push(byte);
+            }
+        }
+        reader.consume(consumed);
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+fn parse_tag(body: &str) -> Result<Tag> {
+    let (closing, body) = match body.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (false, body),
+    };
+    let (self_closing, body) = match body.trim_end().strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, body),
+    };
+
+    let body = body.trim();
+    let name_end = body
+        .find(|c: char| c.is_ascii_whitespace())
+        .unwrap_or(body.len());
+    let name = body[..name_end].to_string();
+    let mut rest = &body[name_end..];
+    let mut attrs = Vec::new();
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let eq = rest
+            .find('=')
+            .with_context(|| format!("malformed attribute in <{name}>"))?;
+        let key = rest[..eq].trim().to_string();
+        let after = rest[eq + 1..].trim_start();
+        let quote = after
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .with_context(|| format!("unquoted attribute '{key}' in <{name}>"))?;
+        let value_end = after[1..]
+            .find(quote)
+            .with_context(|| format!("unterminated attribute '{key}' in <{name}>"))?;
+        attrs.push((key, unescape(&after[1..1 + value_end])));
+        rest = &after[value_end + 2..];
+    }
+
+    Ok(Tag {
+        name,
+        attrs,
+        closing,
+        self_closing,
+    })
+}
+
+/// Decode the predefined XML entities and numeric character references.
+fn unescape(raw: &str) -> String {
+    if !raw.contains('&') {
+        return raw.to_string();
+    }
+
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(ch) => {
+                out.push(ch);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,68 @@
+//! Graceful termination of workers: a spot reclaim or a Batch job being stopped sends
+//! SIGTERM, then kills the worker some seconds later. Once it comes, a worker takes no more
+//! shards and drops the shard it is reading, before writing any of its objects, while one
+//! whose objects are being written is finished; its queued message is returned for another
+//! worker to take at once, and the worker exits with [`EXIT_CODE`] rather than 1, for a
+//! retry strategy to tell the two apart. A second SIGTERM kills it as usual.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code of a worker stopped by SIGTERM, as the shell reports one killed by it.
+pub const EXIT_CODE: i32 = 143;
+
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+/// The error of work stopped by SIGTERM.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("interrupted by SIGTERM")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Note SIGTERM instead of dying of it.
+#[cfg(target_os = "linux")]
+pub fn install() -> std::io::Result<()> {
+    extern "C" fn terminate(_signal: libc::c_int) {
+        TERMINATED.store(true, Ordering::Relaxed);
+    }
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe; the action
+    // is zeroed but for it and its flags, so no signal is blocked while it runs.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = terminate as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART | libc::SA_RESETHAND;
+        if libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn install() -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Whether SIGTERM came.
+pub fn requested() -> bool {
+    TERMINATED.load(Ordering::Relaxed)
+}
+
+/// Fail with [`Interrupted`] once SIGTERM came.
+pub fn check() -> anyhow::Result<()> {
+    match requested() {
+        true => Err(Interrupted.into()),
+        false => Ok(()),
+    }
+}
+
+/// Whether `err` is, or was caused by, SIGTERM.
+pub fn is_interrupted(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<Interrupted>())
+}
@@ -0,0 +1,119 @@
+//! Object keys for what the sharder uploads, laid out by `--s3-key-template`.
+
+use anyhow::{bail, Result};
+use clap::Args;
+use std::sync::OnceLock;
+
+use crate::logging;
+
+#[derive(Args, Debug, Clone)]
+pub struct KeyArgs {
+    /// Key for objects written to a location that names only a bucket or a prefix ending in
+    /// `/`, e.g. `s3://bucket` or `gs://bucket/osm/`. Placeholders: `{run_id}`, `{date}`
+    /// (UTC, YYYY-MM-DD), `{grid}`, `{format}` and `{name}`, the object's file name such as
+    /// `manifest.json`. Placeholders in full location URIs are expanded too.
+    #[arg(
+        long,
+        env = "S3_KEY_TEMPLATE",
+        default_value = "runs/{run_id}/shards/{name}",
+        global = true
+    )]
+    s3_key_template: String,
+}
+
+/// Values substituted into the template.
+pub struct Vars {
+    pub run_id: Option<String>,
+    pub grid: &'static str,
+    /// Manifest format of the run.
+    pub format: &'static str,
+}
+
+struct Keys {
+    template: String,
+    vars: Vars,
+    date: String,
+}
+
+static KEYS: OnceLock<Keys> = OnceLock::new();
+
+/// Set the template and its values for the rest of the run.
+pub fn configure(args: &KeyArgs, vars: Vars) {
+    let _ = KEYS.set(Keys {
+        template: args.s3_key_template.clone(),
+        vars,
+        date: logging::timestamp()[..10].to_string(),
+    });
+}
+
+/// The run ID given with `--run-id`, if any.
+pub fn run_id() -> Option<&'static str> {
+    KEYS.get()?.vars.run_id.as_deref()
+}
+
+/// Expand the placeholders in `location`, then, for an object store URI naming only a
+/// bucket or a prefix, append the templated key for the object called `name`.
+pub fn location(location: &str, name: &str) -> Result<String> {
+    match KEYS.get() {
+        Some(keys) => location_with(location, &keys.template, name),
+        None => Ok(location.to_string()),
+    }
+}
+
+/// Expand the placeholders in `prefix`, a location under which objects are found, without
+/// appending a key to it.
+pub fn expand(prefix: &str) -> Result<String> {
+    match KEYS.get() {
+        Some(keys) => keys.expand(prefix, ""),
+        None => Ok(prefix.to_string()),
+    }
+}
+
+/// [`location`] with another key template than `--s3-key-template`.
+pub fn location_with(location: &str, template: &str, name: &str) -> Result<String> {
+    let Some(keys) = KEYS.get() else {
+        return Ok(location.to_string());
+    };
+    let mut location = keys.expand(location, name)?;
+    let Some((scheme, rest)) = location.split_once("://") else {
+        return Ok(location);
+    };
+    if scheme == "file" {
+        return Ok(location);
+    }
+    if !rest.contains('/') {
+        location.push('/');
+    }
+    if location.ends_with('/') {
+        location.push_str(&keys.expand(template, name)?);
+    }
+    Ok(location)
+}
+
+impl Keys {
+    fn expand(&self, template: &str, name: &str) -> Result<String> {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            let Some(len) = rest[start..].find('}') else {
+                bail!("unclosed placeholder in {template}");
+            };
+            let placeholder = &rest[start + 1..start + len];
+            match placeholder {
+                "run_id" => match &self.vars.run_id {
+                    Some(run_id) => expanded.push_str(run_id),
+                    None => bail!("{template} uses {{run_id}}, but no --run-id (RUN_ID) was given"),
+                },
+                "date" => expanded.push_str(&self.date),
+                "grid" => expanded.push_str(self.vars.grid),
+                "format" => expanded.push_str(self.vars.format),
+                "name" => expanded.push_str(name),
+                _ => bail!("unknown placeholder {{{placeholder}}} in {template}"),
+            }
+            rest = &rest[start + len + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
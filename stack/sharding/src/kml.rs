@@ -0,0 +1,190 @@
+//! KML and KMZ manifest export, for reviewing coverage in Google Earth, and the reading
+//! of those manifests back.
+
+use anyhow::{bail, Context, Result};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::io::{Read, Write};
+
+use crate::Feature;
+
+/// Write `features` as a KML document, with each shard's node count in its balloon and
+/// shards over `max_nodes` drawn in red. With `kmz`, the document is zipped as `doc.kml`.
+pub fn write(out: &mut impl Write, features: &[Feature], max_nodes: u64, kmz: bool) -> Result<()> {
+    let document = document(features, max_nodes);
+    if kmz {
+        write_zip(out, "doc.kml", document.as_bytes())
+    } else {
+        Ok(out.write_all(document.as_bytes())?)
+    }
+}
+
+/// The `z`, `x`, `y` and `node_count` of each placemark of a document [`write`] wrote.
+pub fn read(document: &str) -> Result<Vec<[u64; 4]>> {
+    document
+        .split("<Placemark>")
+        .skip(1)
+        .map(|placemark| {
+            let data = |name: &str| -> Result<u64> {
+                let value = placemark
+                    .split_once(&format!("<Data name=\"{name}\"><value>"))
+                    .and_then(|(_, rest)| rest.split_once("</value>"))
+                    .map(|(value, _)| value.trim())
+                    .with_context(|| format!("placemark without a {name} value"))?;
+                value
+                    .parse()
+                    .with_context(|| format!("invalid {name} value {value}"))
+            };
+            Ok([data("z")?, data("x")?, data("y")?, data("node_count")?])
+        })
+        .collect()
+}
+
+/// The document of a KMZ archive, as [`write_zip`] stores it: the first file, deflated.
+pub fn unzip(kmz: &[u8]) -> Result<Vec<u8>> {
+    let field = |offset: usize, len: usize| {
+        kmz.get(offset..offset + len)
+            .context("truncated KMZ archive")
+    };
+    let u16_at =
+        |offset| -> Result<usize> { Ok(u16::from_le_bytes(field(offset, 2)?.try_into()?).into()) };
+    let u32_at = |offset| -> Result<usize> {
+        Ok(u32::from_le_bytes(field(offset, 4)?.try_into()?).try_into()?)
+    };
+    if field(0, 4)? != 0x0403_4b50u32.to_le_bytes() {
+        bail!("not a KMZ archive");
+    }
+    // Local header: method at 8, compressed size at 18, name and extra field lengths at 26.
+    let start = 30 + u16_at(26)? + u16_at(28)?;
+    let data = field(start, u32_at(18)?)?;
+    let mut document = Vec::with_capacity(u32_at(22)?);
+    match u16_at(8)? {
+        0 => document.extend_from_slice(data),
+        8 => {
+            DeflateDecoder::new(data).read_to_end(&mut document)?;
+        }
+        method => bail!("unsupported KMZ compression method {method}"),
+    }
+    Ok(document)
+}
+
+fn document(features: &[Feature], max_nodes: u64) -> String {
+    let mut kml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n",
+        "<Document>\n",
+        "<name>Shards</name>\n",
+        "<Style id=\"shard\"><LineStyle><color>ff333333</color><width>1</width></LineStyle>",
+        "<PolyStyle><color>4d00a5ff</color></PolyStyle></Style>\n",
+        "<Style id=\"oversized\"><LineStyle><color>ff0000cc</color><width>2</width></LineStyle>",
+        "<PolyStyle><color>800000ff</color></PolyStyle></Style>\n",
+    ));
+    for feature in features {
+        let props = &feature.properties;
+        let style = if props.node_count > max_nodes {
+            "oversized"
+        } else {
+            "shard"
+        };
+        let rings: Vec<String> = feature
+            .geometry
+            .coordinates
+            .iter()
+            .map(|ring| {
+                let positions: Vec<String> = ring.iter().map(|[x, y]| format!("{x},{y}")).collect();
+                positions.join(" ")
+            })
+            .collect();
+        let Some((outer, inner)) = rings.split_first() else {
+            continue;
+        };
+        kml.push_str(&format!(
+            "<Placemark><name>{}</name><styleUrl>#{style}</styleUrl>\
+             <description>{}</description>\
+             <ExtendedData>\
+             <Data name=\"z\"><value>{}</value></Data>\
+             <Data name=\"x\"><value>{}</value></Data>\
+             <Data name=\"y\"><value>{}</value></Data>\
+             <Data name=\"node_count\"><value>{}</value></Data>\
+             </ExtendedData>\
+             <Polygon><tessellate>1</tessellate>\
+             <outerBoundaryIs><LinearRing><coordinates>{outer}</coordinates></LinearRing></outerBoundaryIs>",
+            escape(&props.shard_id),
+            escape(&format!(
+                "<b>{}</b><br/>z/x/y: {}/{}/{}<br/>nodes: {}",
+                props.shard_id, props.z, props.x, props.y, props.node_count
+            )),
+            props.z,
+            props.x,
+            props.y,
+            props.node_count,
+        ));
+        for ring in inner {
+            kml.push_str(&format!(
+                "<innerBoundaryIs><LinearRing><coordinates>{ring}</coordinates></LinearRing></innerBoundaryIs>"
+            ));
+        }
+        kml.push_str("</Polygon></Placemark>\n");
+    }
+    kml.push_str("</Document>\n</kml>\n");
+    kml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Zip archive holding one deflated file. Timestamps are fixed at the DOS epoch so the same
+/// plan always gives the same bytes.
+fn write_zip(out: &mut impl Write, name: &str, contents: &[u8]) -> Result<()> {
+    const DOS_DATE: u16 = (1 << 5) | 1;
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(contents)?;
+    let compressed = encoder.finish()?;
+    let mut crc = Crc::new();
+    crc.update(contents);
+
+    // Fields shared by the local and central headers, from "version needed" on.
+    let mut common = Vec::new();
+    common.extend_from_slice(&20u16.to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+    common.extend_from_slice(&8u16.to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+    common.extend_from_slice(&DOS_DATE.to_le_bytes());
+    common.extend_from_slice(&crc.sum().to_le_bytes());
+    common.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    common.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+
+    let mut local = Vec::new();
+    local.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+    local.extend_from_slice(&common);
+    local.extend_from_slice(name.as_bytes());
+
+    let mut central = Vec::new();
+    central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+    central.extend_from_slice(&20u16.to_le_bytes());
+    central.extend_from_slice(&common);
+    // Comment length, disk number, internal and external attributes, local header offset.
+    central.extend_from_slice(&[0; 14]);
+    central.extend_from_slice(name.as_bytes());
+
+    let mut end = Vec::new();
+    end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    end.extend_from_slice(&[0; 4]);
+    end.extend_from_slice(&1u16.to_le_bytes());
+    end.extend_from_slice(&1u16.to_le_bytes());
+    end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    end.extend_from_slice(&((local.len() + compressed.len()) as u32).to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+
+    for part in [&local, &compressed, &central, &end] {
+        out.write_all(part)?;
+    }
+    Ok(())
+}
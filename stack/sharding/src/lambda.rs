@@ -0,0 +1,143 @@
+//! Lambda handler for small shards: built with the `lambda` feature, `extract-pois` run as
+//! a function's bootstrap takes its shards from the invocations of the Lambda Runtime API,
+//! whose address Lambda sets as `AWS_LAMBDA_RUNTIME_API`, instead of `--shard`. Big shards
+//! are still left to Batch.
+//!
+//! An invocation is an SQS event of the messages `enqueue` sends, whose shards are extracted
+//! one after the other; the records whose shard failed are returned as `batchItemFailures`,
+//! for an event source mapping with `ReportBatchItemFailures`, so only they go back to the
+//! queue. An event naming a shard itself, `{"shard_id": ...}`, fails the invocation when its
+//! shard fails.
+//!
+//! A function has little memory but a `/tmp` disk: with `--extracts` a shard is read from
+//! its own extract, copied to `/tmp` a range at a time and streamed from there, and
+//! `--max-extract-size` fails the shards too big for the function's memory before they are
+//! read.
+//!
+//! The Runtime API is plain HTTP on the function's host, spoken like the metrics exporters.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use tracing::{info, info_span, warn};
+
+use crate::queue;
+use crate::task;
+
+/// Version of the Runtime API, the first segment of its paths.
+const VERSION: &str = "2018-06-01";
+
+/// Serve the invocations of the Runtime API at `api`, extracting each shard with `extract`,
+/// until the function is shut down.
+pub fn serve(api: &str, mut extract: impl FnMut(&str) -> Result<()>) -> Result<()> {
+    let memory = std::env::var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE").unwrap_or_default();
+    info!("Taking shards from the Lambda Runtime API at {api} ({memory} MB of memory)...");
+    loop {
+        let (head, event) = request(api, "GET", "runtime/invocation/next", None)?;
+        let request_id = header(&head, "Lambda-Runtime-Aws-Request-Id")
+            .context("invocation without a request ID")?;
+        let _invocation = info_span!("invocation", request_id).entered();
+        let (outcome, response) = match handle(request_id, &event, &mut extract) {
+            Ok(response) => ("response", response),
+            Err(err) => {
+                warn!("Invocation {request_id} failed: {err:#}");
+                let error = json!({"errorType": task::ERROR, "errorMessage": format!("{err:#}")});
+                ("error", error)
+            }
+        };
+        let path = format!("runtime/invocation/{request_id}/{outcome}");
+        request(api, "POST", &path, Some(&response))?;
+    }
+}
+
+/// Extract the shards of an invocation's event, returning the function's response.
+fn handle(
+    request_id: &str,
+    event: &[u8],
+    extract: &mut impl FnMut(&str) -> Result<()>,
+) -> Result<Value> {
+    let event: Value = serde_json::from_slice(event).context("invocation event is not JSON")?;
+    let Some(records) = event["Records"].as_array() else {
+        let shard_id = queue::shard_id(request_id, &event)?;
+        let _shard = info_span!("invoked_shard", shard = %shard_id).entered();
+        info!("Invoked for shard {shard_id}.");
+        extract(shard_id)?;
+        return Ok(json!({ "shard_id": shard_id }));
+    };
+
+    let mut failures = Vec::new();
+    for record in records {
+        let message_id = record["messageId"].as_str().unwrap_or_default();
+        let body = record["body"]
+            .as_str()
+            .and_then(|body| serde_json::from_str(body).ok())
+            .unwrap_or_default();
+        let attempt = record["attributes"]["ApproximateReceiveCount"]
+            .as_str()
+            .and_then(|count| count.parse::<u32>().ok())
+            .unwrap_or(1);
+        let result = queue::shard_id(message_id, &body).and_then(|shard_id| {
+            let _shard = info_span!("queued_shard", shard = %shard_id).entered();
+            info!(attempt, "Received shard {shard_id} (attempt {attempt}).");
+            extract(shard_id)
+        });
+        if let Err(err) = result {
+            warn!("Message {message_id} failed, returning it to the queue: {err:#}");
+            failures.push(json!({ "itemIdentifier": message_id }));
+        }
+    }
+    info!(
+        records = records.len(),
+        failed = failures.len(),
+        "Extracted {} of {} queued shard(s).",
+        records.len() - failures.len(),
+        records.len()
+    );
+    Ok(json!({ "batchItemFailures": failures }))
+}
+
+/// HTTP/1.1 request to the Runtime API, returning the response's status line and headers,
+/// and its body; fails unless the status is 2xx. The next invocation's is answered only once
+/// there is one, so there is no timeout.
+fn request(api: &str, method: &str, path: &str, body: Option<&Value>) -> Result<(String, Vec<u8>)> {
+    let mut stream = TcpStream::connect(api)
+        .with_context(|| format!("unable to connect to the Lambda Runtime API at {api}"))?;
+    let body = body.map(Value::to_string).unwrap_or_default();
+    write!(
+        stream,
+        "{method} /{VERSION}/{path} HTTP/1.1\r\nHost: {api}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let Some(end) = response.windows(4).position(|bytes| bytes == b"\r\n\r\n") else {
+        bail!("malformed response from the Lambda Runtime API to {method} {path}");
+    };
+    let head = String::from_utf8_lossy(&response[..end]).into_owned();
+    let body = response.split_off(end + 4);
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .with_context(|| format!("malformed response from the Lambda Runtime API to {path}"))?;
+    if !(200..300).contains(&status) {
+        bail!(
+            "Lambda Runtime API {method} {path}: HTTP {status}: {}",
+            String::from_utf8_lossy(&body).trim()
+        );
+    }
+    Ok((head, body))
+}
+
+/// The value of the header called `name`.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
@@ -0,0 +1,284 @@
+//! Minimal `tracing` subscriber writing log events to stderr, as plain text or JSON lines.
+//!
+//! Events at info level and above are written. Spans mark the phases of a run; when one
+//! closes its duration is logged, so JSON logs carry per-phase timings as `elapsed_ms`.
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use hashbrown::HashMap;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+use crate::metrics;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable messages.
+    Text,
+    /// One JSON object per line, with structured fields.
+    Json,
+}
+
+/// Install the stderr logger as the global subscriber.
+pub fn init(format: LogFormat) {
+    let logger = Logger {
+        format,
+        next_id: AtomicU64::new(1),
+        spans: Mutex::new(HashMap::new()),
+    };
+    // Only fails if a subscriber is already installed, which leaves logging working.
+    let _ = tracing::subscriber::set_global_default(logger);
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Map<String, Value>,
+    started: Instant,
+    refs: usize,
+}
+
+struct Logger {
+    format: LogFormat,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last.
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Logger {
+    fn write(&self, level: &Level, target: &str, message: &str, mut fields: Map<String, Value>) {
+        let line = match self.format {
+            LogFormat::Text => match *level {
+                Level::ERROR => format!("Error: {message}"),
+                Level::WARN => format!("Warning: {message}"),
+                _ => message.to_string(),
+            },
+            LogFormat::Json => {
+                let mut record = Map::new();
+                record.insert("timestamp".into(), timestamp().into());
+                record.insert("level".into(), level.as_str().into());
+                record.insert("target".into(), target.into());
+                record.insert("message".into(), message.into());
+                let spans = self.entered_names();
+                if !spans.is_empty() {
+                    record.insert("spans".into(), spans.into());
+                }
+                record.append(&mut fields);
+                Value::Object(record).to_string()
+            }
+        };
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+
+    fn entered_names(&self) -> Vec<Value> {
+        let spans = self.spans.lock().expect("log span lock poisoned");
+        ENTERED.with(|entered| {
+            entered
+                .borrow()
+                .iter()
+                .filter_map(|id| spans.get(id).map(|span| span.name.into()))
+                .collect()
+        })
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= Level::INFO
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = Map::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        self.spans.lock().expect("log span lock poisoned").insert(
+            id,
+            SpanData {
+                name: attrs.metadata().name(),
+                fields,
+                started: Instant::now(),
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = self
+            .spans
+            .lock()
+            .expect("log span lock poisoned")
+            .get_mut(&span.into_u64())
+        {
+            values.record(&mut FieldVisitor(&mut span.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Map::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let message = match fields.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        let metadata = event.metadata();
+        self.write(metadata.level(), metadata.target(), &message, fields);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(pos) = entered.iter().rposition(|&id| id == span.into_u64()) {
+                entered.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self
+            .spans
+            .lock()
+            .expect("log span lock poisoned")
+            .get_mut(&span.into_u64())
+        {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let closed = {
+            let mut spans = self.spans.lock().expect("log span lock poisoned");
+            match spans.get_mut(&span.into_u64()) {
+                Some(data) if data.refs > 1 => {
+                    data.refs -= 1;
+                    None
+                }
+                Some(_) => spans.remove(&span.into_u64()),
+                None => None,
+            }
+        };
+        let Some(mut data) = closed else {
+            return false;
+        };
+
+        let elapsed = data.started.elapsed();
+        metrics::record_phase(data.name, elapsed.as_secs_f64());
+        data.fields.insert("span".into(), data.name.into());
+        data.fields
+            .insert("elapsed_ms".into(), (elapsed.as_millis() as u64).into());
+        self.write(
+            &Level::INFO,
+            env!("CARGO_CRATE_NAME"),
+            &format!("Finished {} in {:.1}s.", data.name, elapsed.as_secs_f64()),
+            data.fields,
+        );
+        true
+    }
+}
+
+/// Collects the fields of an event or span into a JSON object.
+struct FieldVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+/// Current UTC time as RFC 3339 with millisecond precision.
+pub(crate) fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format_rfc3339(now.as_secs() as i64, Some(now.subsec_millis()))
+}
+
+/// Parse an RFC 3339 UTC timestamp, as OSM writes them (`2024-05-01T12:00:00Z`), into
+/// seconds since the Unix epoch; fractions of a second, as in [`timestamp`], are dropped.
+pub(crate) fn parse_rfc3339(raw: &str) -> Option<i64> {
+    let raw = raw.strip_suffix('Z')?;
+    let (date, time) = raw.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let time = time.split_once('.').map_or(time, |(seconds, _)| seconds);
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since the epoch from the civil date, the inverse of `format_rfc3339`.
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp, with milliseconds if
+/// given.
+pub(crate) fn format_rfc3339(secs: i64, millis: Option<u32>) -> String {
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let fraction = millis.map_or_else(String::new, |millis| format!(".{millis:03}"));
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}{fraction}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
@@ -1,36 +1,538 @@
+mod batch;
+mod blob_index;
+mod checkpoint;
+mod completion;
+mod compress;
+mod cost;
+mod delimited;
+mod extract;
+mod flatgeobuf;
+mod geometry;
+mod geoparquet;
+mod histogram;
+mod input;
+mod interrupt;
+mod keys;
+mod kml;
+#[cfg(feature = "lambda")]
+mod lambda;
+mod logging;
+mod metrics;
+mod migration;
+mod node_cache;
+mod node_set;
+mod notify;
+mod pmtiles;
+mod pois;
+mod preview;
+mod progress;
+mod publish;
+mod queue;
+mod registry;
+mod runs;
+mod s3;
+mod scan;
+mod spill;
+mod sqlite;
+mod stats;
+mod store;
+mod stream_plan;
+mod summary;
+mod tally;
+mod task;
+mod threads;
+
 use anyhow::{bail, Context, Result};
-use clap::Parser;
+use checkpoint::Checkpointer;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use hashbrown::HashMap;
-use osmpbf::{Element, ElementReader};
+use input::InputFormat;
+use logging::LogFormat;
+use metrics::{Counter, MetricsArgs};
+use progress::ProgressFormat;
+use scan::{ScanOptions, ScanResult};
 use serde::Serialize;
+use spill::{Spilled, Spiller};
 use std::f64::consts::PI;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use stream_plan::StreamPlanner;
+use summary::{InputFile, Summary};
+use tally::BBox;
+use threads::CpuList;
+use tracing::{error, info, info_span, warn};
 
 /// CLI parameters - all can be set via environment variables.
+///
+/// Without a subcommand the tool scans and plans in one go. `scan` and `plan` split the
+/// expensive PBF pass from shard planning so thresholds can be re-tuned without rescanning.
 #[derive(Parser, Debug)]
 #[command(
     author,
     version,
-    about = "Shard an OSM planet file into quadtree tiles"
+    about = "Shard an OSM planet file into quadtree tiles",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
-struct Args {
-    /// Path to the .osm.pbf file to scan.
-    #[arg(env = "OSM_FILE")]
-    osm_file: PathBuf,
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    scan: ScanArgs,
+
+    /// Also persist the count histogram here, for later `plan` runs.
+    #[arg(long, env = "SAVE_COUNTS")]
+    save_counts: Option<PathBuf>,
+
+    /// Also publish the byte ranges of the input's blobs holding each shard's nodes here,
+    /// as JSON, so extract workers can fetch just their shard's blobs. Needs a single PBF
+    /// input.
+    #[arg(long, env = "BLOB_INDEX")]
+    blob_index: Option<String>,
+
+    /// Zoom at which the blob index notes the tiles of each blob. Higher is more precise for
+    /// small shards and takes more memory during the scan.
+    #[arg(
+        long,
+        env = "BLOB_INDEX_ZOOM",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u8).range(0..=16)
+    )]
+    blob_index_zoom: u8,
+
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    /// Log output on stderr: readable text, or JSON lines with structured fields.
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    /// Pipeline run identifier, in object keys, shard markers and exported metrics.
+    #[arg(long, env = "RUN_ID", global = true)]
+    run_id: Option<String>,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    #[command(flatten)]
+    upload: s3::UploadArgs,
+
+    #[command(flatten)]
+    keys: keys::KeyArgs,
+
+    #[command(flatten)]
+    task: task::TaskArgs,
+}
+
+impl Cli {
+    /// Where the run is reported at exit, for the commands that shard; managing past runs,
+    /// extracting, estimating costs, enqueueing, submitting, finalizing, showing progress,
+    /// merging, caching nodes, writing boundaries, tiling and moving search aliases are not
+    /// reported.
+    fn report(&self) -> Option<&ReportArgs> {
+        match &self.command {
+            None => Some(&self.report),
+            Some(Command::Scan { report, .. } | Command::Plan { report, .. }) => Some(report),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Where a sharding run is reported at exit: its metrics, its event and its registry item.
+#[derive(Args, Debug)]
+struct ReportArgs {
+    #[command(flatten)]
+    metrics: MetricsArgs,
+
+    #[command(flatten)]
+    notify: notify::NotifyArgs,
+
+    #[command(flatten)]
+    registry: registry::RegistryArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Scan the input(s) and write the hierarchical count histogram to a file.
+    Scan {
+        #[command(flatten)]
+        scan: ScanArgs,
+
+        /// Destination of the gzip-compressed count histogram.
+        #[arg(short, long, env = "COUNTS_FILE")]
+        output: PathBuf,
+
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// Build the shard manifest from a histogram written by `scan`.
+    Plan {
+        /// Count histogram written by `scan` (or `--save-counts`).
+        #[arg(env = "COUNTS_FILE")]
+        counts: PathBuf,
+
+        /// Plan at a coarser max zoom than the histogram was scanned at.
+        #[arg(short, long, env = "MAX_ZOOM")]
+        max_zoom: Option<u8>,
+
+        /// Stream the histogram through the planner instead of loading every zoom level;
+        /// planning then needs almost no memory, whatever the limit.
+        #[arg(long, env = "MEMORY_LIMIT", value_parser = spill::parse_byte_size)]
+        memory_limit: Option<u64>,
+
+        #[command(flatten)]
+        plan: PlanArgs,
+
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// List, show or prune the runs uploaded to a bucket.
+    Runs(runs::RunsArgs),
+    /// Cut one `.osm.pbf` extract per shard of a manifest out of the input.
+    Extract(extract::ExtractArgs),
+    /// Estimate the CPU-hours, S3 requests, transfer and dollars of extracting the POIs of
+    /// a manifest's shards, from their node counts.
+    PlanCost(cost::PlanCostArgs),
+    /// Send a message per shard of a manifest to an SQS queue, for `extract-pois
+    /// --queue-url` workers to take.
+    Enqueue(queue::EnqueueArgs),
+    /// Write the POIs of one or more shards as GeoJSON.
+    ExtractPois(Box<pois::PoisArgs>),
+    /// Submit an AWS Batch array job with an `extract-pois` child per shard of a manifest.
+    Submit(batch::SubmitArgs),
+    /// Write a run's `_SUCCESS`, with a report of its shards, once every shard of its
+    /// manifest has the marker `extract-pois` writes when it is done.
+    Finalize(completion::FinalizeArgs),
+    /// Show a run's progress from the markers of its shards: how many are done, failed and
+    /// pending, the throughput, ETA and the slowest shards.
+    Status(completion::StatusArgs),
+    /// Combine the POIs `extract-pois` wrote per shard into one GeoParquet file and one
+    /// PMTiles archive, each POI once.
+    Merge(pois::merge::MergeArgs),
+    /// Write the location of every node to a flat file that `extract-pois` can read way
+    /// and relation nodes from.
+    NodeCache(node_cache::NodeCacheArgs),
+    /// Write the administrative boundaries of the input as GeoJSON, for `extract-pois` to
+    /// place POIs in countries, regions and cities with.
+    AdminBoundaries(pois::admin::AdminBoundariesArgs),
+    /// Cut merged POIs into a vector tileset, with rules for which categories show from
+    /// which zoom.
+    Tile(pois::tile::TileArgs),
+    /// Point an OpenSearch alias at a run's POI index, once every shard is loaded into it.
+    OpensearchAlias(pois::opensearch::OpensearchAliasArgs),
+}
+
+/// Options controlling the PBF scan.
+#[derive(Args, Debug)]
+struct ScanArgs {
+    /// Path(s) to the .osm.pbf files to scan. Several files (or a glob such as
+    /// `extracts/*.osm.pbf`) are merged into one histogram, skipping nodes repeated across files.
+    #[arg(env = "OSM_FILE", required = true, num_args = 1.., value_delimiter = ',')]
+    osm_files: Vec<PathBuf>,
+
+    /// Input encoding; `auto` picks PBF, o5m/o5c or (optionally gz/bz2-compressed) XML by
+    /// extension.
+    #[arg(long, env = "INPUT_FORMAT", value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
 
     /// Highest Web Mercator zoom level to consider when splitting tiles. optional, default is 20.
     #[arg(short, long, env = "MAX_ZOOM", default_value = "20")]
     max_zoom: u8,
 
+    /// Only count a deterministic fraction of nodes (selected by hashing the node ID) and
+    /// scale the counts back up. Useful for quick previews of a parameter set.
+    #[arg(long, env = "SAMPLE_FRACTION", value_parser = parse_fraction)]
+    sample: Option<f64>,
+
+    /// Periodically save scan progress here (a local path or `s3://`, `gs://` or `az://` URI) so an
+    /// interrupted scan can continue with `--resume`. Single PBF inputs only.
+    #[arg(long, env = "SCAN_CHECKPOINT")]
+    checkpoint: Option<String>,
+
+    /// Seconds between checkpoints.
+    #[arg(long, env = "CHECKPOINT_INTERVAL", default_value = "300")]
+    checkpoint_interval: u64,
+
+    /// Continue from the `--checkpoint` file if one exists for the same input and options.
+    #[arg(long, env = "RESUME", requires = "checkpoint")]
+    resume: bool,
+
+    /// Worker threads for the parallel PBF scan (default: one per core, or per `--cpus`).
+    #[arg(long, env = "SCAN_THREADS")]
+    threads: Option<usize>,
+
+    /// Pin scan worker threads to these CPUs, e.g. `0-3,8` (Linux only).
+    #[arg(long, env = "SCAN_CPUS")]
+    cpus: Option<CpuList>,
+
+    /// Cap the memory used by the count maps (e.g. `8G`); beyond it, counts are spilled to
+    /// sorted temporary files and merged at the end. Needed for z16+ on a planet.
+    #[arg(long, env = "MEMORY_LIMIT", value_parser = spill::parse_byte_size)]
+    memory_limit: Option<u64>,
+
+    /// Directory for spill files (default: the system temp directory).
+    #[arg(long, env = "SPILL_DIR")]
+    spill_dir: Option<PathBuf>,
+
+    /// Extent of the input as `west,south,east,north`. Max-zoom tiles inside it are counted
+    /// in a dense array instead of a hash map; PBF header bounding boxes are used otherwise.
+    #[arg(long, env = "BBOX", allow_hyphen_values = true)]
+    bbox: Option<BBox>,
+
+    /// Progress output on stderr while scanning.
+    #[arg(long, env = "PROGRESS", value_enum, default_value_t = ProgressFormat::Text)]
+    progress: ProgressFormat,
+
+    /// Seconds between progress updates.
+    #[arg(long, env = "PROGRESS_INTERVAL", default_value = "5")]
+    progress_interval: u64,
+}
+
+/// Options controlling shard planning and manifest output.
+#[derive(Args, Debug)]
+struct PlanArgs {
     /// Maximum number of nodes allowed per shard before splitting.
-    #[arg(short, long, env = "MAX_NODES_PER_SHARD", default_value = "1000000")]
+    #[arg(
+        short = 'n',
+        long,
+        env = "MAX_NODES_PER_SHARD",
+        default_value = "1000000"
+    )]
     max_nodes: u64,
+
+    /// Write the manifest here instead of stdout: a local path, or a `file://`, `s3://`,
+    /// `gs://` or `az://` URI.
+    #[arg(short, long, env = "MANIFEST_OUTPUT")]
+    output: Option<String>,
+
+    /// After writing `--output`, also upload the manifest under a content-addressed key and
+    /// then write a JSON pointer to it (with the run ID and checksum) here, e.g.
+    /// `s3://bucket/runs/latest.json`.
+    #[arg(long, env = "LATEST_POINTER", requires = "output")]
+    latest_pointer: Option<String>,
+
+    /// Print presigned GET URLs for the manifest and summary, valid for this many seconds
+    /// (at most 604800, seven days), as one JSON object on stdout, and add them to the run
+    /// event. Requires `--output`, so the URLs never mix with the manifest. Only `s3://`
+    /// locations can be presigned.
+    #[arg(
+        long,
+        env = "PRESIGN_EXPIRY",
+        requires = "output",
+        value_parser = clap::value_parser!(u64).range(1..=604_800)
+    )]
+    presign_expiry: Option<u64>,
+
+    /// Manifest schema version.
+    #[arg(long, env = "MANIFEST_SCHEMA", value_enum, default_value_t = ManifestSchema::LATEST)]
+    schema: ManifestSchema,
+
+    /// Manifest file format.
+    #[arg(long, env = "MANIFEST_FORMAT", value_enum, default_value_t = ManifestFormat::Geojson)]
+    format: ManifestFormat,
+
+    /// Compress the manifest.
+    #[arg(long, env = "MANIFEST_COMPRESSION", value_enum)]
+    compress: Option<compress::Compression>,
+
+    /// Also write the manifest in this (older) schema version plus a field-level comparison
+    /// report, so downstream consumers can migrate without a flag day.
+    #[arg(long, env = "DUAL_OUTPUT_SCHEMA", value_enum)]
+    dual_output_schema: Option<ManifestSchema>,
+
+    /// Number of runs to keep writing the dual output for, counted in `--migration-state`.
+    #[arg(long, env = "DUAL_OUTPUT_RUNS", default_value = "5")]
+    dual_output_runs: u32,
+
+    /// Directory receiving the legacy manifest and the comparison report.
+    #[arg(long, env = "DUAL_OUTPUT_DIR", default_value = ".")]
+    dual_output_dir: PathBuf,
+
+    /// JSON file tracking how many dual-output runs have been written (created if missing).
+    #[arg(long, env = "MIGRATION_STATE")]
+    migration_state: Option<PathBuf>,
+
+    /// Also write a machine-readable run summary (parameters, input checksums, totals, shard
+    /// size statistics, oversized tiles, artifacts, timings) here: a local path, or a URI
+    /// like `--output`.
+    #[arg(long, env = "SUMMARY_PATH")]
+    summary: Option<String>,
+
+    /// Report the shard size distribution (buckets, coefficient of variation, Gini).
+    #[arg(long, env = "SHARD_STATS")]
+    stats: bool,
+
+    /// Decimal places kept in manifest coordinates; 6 is about 10 cm.
+    #[arg(
+        long,
+        env = "COORDINATE_PRECISION",
+        default_value_t = DEFAULT_PRECISION,
+        value_parser = clap::value_parser!(u8).range(0..=15)
+    )]
+    precision: u8,
+
+    /// Write the manifest on a single line instead of pretty-printed.
+    #[arg(long, env = "COMPACT_OUTPUT")]
+    compact: bool,
+
+    /// Check every shard polygon is a valid RFC 7946 ring before writing the manifest.
+    #[arg(long, env = "VALIDATE_GEOMETRY")]
+    validate: bool,
+
+    /// Also write the shard polygons as vector tiles to this PMTiles archive, for viewing
+    /// the plan in MapLibre.
+    #[arg(long, env = "SHARDS_PMTILES")]
+    pmtiles: Option<PathBuf>,
+
+    /// Highest zoom level in the PMTiles archive; viewers overzoom past it.
+    #[arg(
+        long,
+        env = "PMTILES_MAX_ZOOM",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u8).range(0..=14)
+    )]
+    pmtiles_max_zoom: u8,
+
+    /// Also write a self-contained HTML map of the shards, coloured by node count.
+    #[arg(long, env = "SHARDS_PREVIEW")]
+    preview: Option<PathBuf>,
 }
 
-/// Aggregated counts for every resolution plus the total number of nodes we saw.
-struct ScanResult {
-    counts: Vec<HashMap<(u32, u32), u64>>,
-    node_total: u64,
+/// File formats the manifest can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ManifestFormat {
+    /// One GeoJSON FeatureCollection.
+    Geojson,
+    /// Newline-delimited GeoJSON features (GeoJSONSeq without record separators, i.e.
+    /// NDJSON), which can be streamed and split. Collection-level members are left out.
+    Geojsonseq,
+    /// FlatGeobuf with a spatial index, for reading only the shards in a region with HTTP
+    /// range requests. Collection-level members go into the header metadata.
+    Flatgeobuf,
+    /// GeoParquet with WKB geometry, for Athena and DuckDB. Collection-level members go
+    /// into the `osm_sharding` file metadata key.
+    Geoparquet,
+    /// Comma-separated rows with the polygon as WKT, for spreadsheets and SQL imports.
+    Csv,
+    /// Tab-separated rows with the polygon as WKT.
+    Tsv,
+    /// KML document, for Google Earth.
+    Kml,
+    /// Zipped KML.
+    Kmz,
+    /// One Bing-style quadkey and node count per line, tab-separated.
+    Quadkeys,
+}
+
+impl ManifestFormat {
+    fn name(self) -> &'static str {
+        match self {
+            ManifestFormat::Geojson => "GeoJSON",
+            ManifestFormat::Geojsonseq => "GeoJSONSeq",
+            ManifestFormat::Flatgeobuf => "FlatGeobuf",
+            ManifestFormat::Geoparquet => "GeoParquet",
+            ManifestFormat::Csv => "CSV",
+            ManifestFormat::Tsv => "TSV",
+            ManifestFormat::Kml => "KML",
+            ManifestFormat::Kmz => "KMZ",
+            ManifestFormat::Quadkeys => "quadkeys",
+        }
+    }
+
+    /// The `--format` value, for `{format}` in key templates.
+    fn id(self) -> &'static str {
+        match self {
+            ManifestFormat::Geojson => "geojson",
+            ManifestFormat::Geojsonseq => "geojsonseq",
+            ManifestFormat::Flatgeobuf => "flatgeobuf",
+            ManifestFormat::Geoparquet => "geoparquet",
+            ManifestFormat::Csv => "csv",
+            ManifestFormat::Tsv => "tsv",
+            ManifestFormat::Kml => "kml",
+            ManifestFormat::Kmz => "kmz",
+            ManifestFormat::Quadkeys => "quadkeys",
+        }
+    }
+
+    /// File extension of the manifest when it is uploaded under a templated key.
+    fn extension(self) -> &'static str {
+        match self {
+            ManifestFormat::Geojson => "json",
+            ManifestFormat::Geojsonseq => "geojsonl",
+            ManifestFormat::Flatgeobuf => "fgb",
+            ManifestFormat::Geoparquet => "parquet",
+            ManifestFormat::Csv => "csv",
+            ManifestFormat::Tsv | ManifestFormat::Quadkeys => "tsv",
+            ManifestFormat::Kml => "kml",
+            ManifestFormat::Kmz => "kmz",
+        }
+    }
+}
+
+/// Versions of the manifest layout. Bump `LATEST` whenever property names or structure change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ManifestSchema {
+    /// FeatureCollection with `shard_id`, `z`, `x`, `y` and `node_count` properties.
+    V1,
+    /// V1 plus top-level `schema_version`, `parameters` and `metadata` members.
+    V2,
+}
+
+impl ManifestSchema {
+    const LATEST: ManifestSchema = ManifestSchema::V2;
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ManifestSchema::V1 => "v1",
+            ManifestSchema::V2 => "v2",
+        }
+    }
+
+    fn version(self) -> u32 {
+        match self {
+            ManifestSchema::V1 => 1,
+            ManifestSchema::V2 => 2,
+        }
+    }
+}
+
+/// Cell system the shards are drawn from.
+const GRID: &str = "web-mercator-quadtree";
+
+/// The settings a plan was produced with, recorded in the manifest and the run summary.
+#[derive(Clone, Serialize)]
+struct Parameters {
+    /// Cell system the shards are drawn from.
+    grid: &'static str,
+    max_zoom: u8,
+    max_nodes: u64,
+    sample: Option<f64>,
+    /// `[west, south, east, north]` given with `--bbox`.
+    bbox: Option<[f64; 4]>,
+    schema: &'static str,
+    /// Decimal places kept in coordinates.
+    precision: u8,
+    tool_version: &'static str,
+    /// Commit the binary was built from, when the build provided `GIT_SHA`.
+    git_sha: Option<&'static str>,
+}
+
+impl Parameters {
+    fn new(max_zoom: u8, sample: Option<f64>, bbox: Option<BBox>, args: &PlanArgs) -> Self {
+        Self {
+            grid: GRID,
+            max_zoom,
+            max_nodes: args.max_nodes,
+            sample,
+            bbox: bbox.map(|bbox| [bbox.west, bbox.south, bbox.east, bbox.north]),
+            schema: args.schema.as_str(),
+            precision: args.precision,
+            tool_version: env!("CARGO_PKG_VERSION"),
+            git_sha: option_env!("GIT_SHA").filter(|sha| !sha.is_empty()),
+        }
+    }
 }
 
 /// One shard entry combining the cell index with its aggregated count.
@@ -44,12 +546,25 @@ struct Shard {
 
 /// GeoJSON FeatureCollection wrapper used for serialization.
 #[derive(Serialize)]
-struct FeatureCollection {
+struct FeatureCollection<'a> {
     #[serde(rename = "type")]
     feature_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema_version: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<&'a Parameters>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a ManifestMetadata>,
     features: Vec<Feature>,
 }
 
+/// Top-level manifest metadata: which input files, and so which planet state, the shards
+/// were computed from.
+#[derive(Serialize)]
+struct ManifestMetadata {
+    inputs: Vec<InputFile>,
+}
+
 /// GeoJSON Feature with the handful of properties we need.
 #[derive(Serialize)]
 struct Feature {
@@ -78,100 +593,581 @@ struct Geometry {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-    if !args.osm_file.exists() {
-        bail!("file does not exist: {}", args.osm_file.display());
+async fn main() {
+    let cli = Cli::parse();
+    logging::init(cli.log_format);
+    s3::configure(&cli.upload);
+    let format = match &cli.command {
+        Some(Command::Plan { plan, .. }) => plan.format,
+        _ => cli.plan.format,
+    };
+    keys::configure(
+        &cli.keys,
+        keys::Vars {
+            run_id: cli.run_id.clone(),
+            grid: GRID,
+            format: format.id(),
+        },
+    );
+
+    let mut summary = Summary::start();
+    let heartbeat = task::Heartbeat::start(&cli.task);
+    let result = run(&cli, &mut summary);
+    drop(heartbeat);
+    if let Some(report) = cli.report() {
+        metrics::export(&report.metrics);
+        notify::send(&report.notify, result.as_ref().err());
+        registry::record(&report.registry, &mut summary, result.as_ref().err());
+    }
+    task::report(&cli.task, result.as_ref().err());
+    if let Err(err) = result {
+        error!("{err:#}");
+        if interrupt::is_interrupted(&err) {
+            std::process::exit(interrupt::EXIT_CODE);
+        }
+        std::process::exit(1);
     }
+}
 
-    eprintln!(
-        "Scanning {} (max zoom = {})...",
-        args.osm_file.display(),
-        args.max_zoom
-    );
-    let scan = scan_osm(&args.osm_file, args.max_zoom)?;
-    eprintln!(
-        "Scan complete.  {} nodes in {} populated max-zoom tiles.",
-        scan.node_total,
-        scan.counts[usize::from(args.max_zoom)].len()
+fn run(cli: &Cli, summary: &mut Summary) -> Result<()> {
+    match &cli.command {
+        None => {
+            let parameters =
+                Parameters::new(cli.scan.max_zoom, cli.scan.sample, cli.scan.bbox, &cli.plan);
+            summary.set_parameters(parameters.clone());
+            let index_zoom = cli.blob_index.as_ref().map(|_| cli.blob_index_zoom);
+            let (mut scan, inputs) = run_scan(&cli.scan, true, index_zoom)?;
+            summary.set_inputs(inputs.clone());
+            let metadata = ManifestMetadata { inputs };
+            summary.set_totals(&histogram::Header::of(&scan));
+            let shards = if let Some(spilled) = scan.spilled.take() {
+                let header = histogram::Header::of(&scan);
+                let mut planner =
+                    StreamPlanner::new(header.max_zoom, header.max_zoom, cli.plan.max_nodes);
+                let plan_span = info_span!("plan").entered();
+                info!(
+                    max_nodes = cli.plan.max_nodes,
+                    "Building shards (max nodes per shard = {})...", cli.plan.max_nodes
+                );
+                drain_spilled(
+                    &header,
+                    spilled,
+                    cli.save_counts.as_deref(),
+                    Some(&mut planner),
+                )?;
+                let shards = planner.finish();
+                drop(plan_span);
+                shards
+            } else {
+                if let Some(path) = &cli.save_counts {
+                    histogram::write(path, &scan)?;
+                }
+                run_plan(&scan, cli.scan.max_zoom, &cli.plan)
+            };
+            if let Some(path) = &cli.save_counts {
+                summary.add_file("counts", path);
+            }
+            let manifest = write_manifests(
+                &shards,
+                scan.sample,
+                &cli.plan,
+                &parameters,
+                Some(&metadata),
+                summary,
+            )?;
+            if let (Some(location), Some(index)) = (&cli.blob_index, &scan.blob_index) {
+                let input = &metadata.inputs[0];
+                let file = input.path.file_name().unwrap_or_default().to_string_lossy();
+                let store = store::Store::open(location, "blob-index.json")?;
+                blob_index::write(index, &shards, &file, input.bytes, &store, summary)?;
+            }
+            let summary = finish_summary(summary, &shards, &cli.plan)?;
+            presign(&cli.plan, manifest, summary)
+        }
+        Some(Command::Scan { scan, output, .. }) => {
+            let (mut result, _) = run_scan(scan, false, None)?;
+            match result.spilled.take() {
+                Some(spilled) => {
+                    drain_spilled(&histogram::Header::of(&result), spilled, Some(output), None)
+                }
+                None => histogram::write(output, &result),
+            }
+        }
+        Some(Command::Plan {
+            counts,
+            max_zoom,
+            memory_limit,
+            plan,
+            ..
+        }) => {
+            summary.set_counts(counts);
+            let (shards, max_zoom, sample) = if memory_limit.is_some() {
+                let (shards, max_zoom, header) = plan_streaming(counts, *max_zoom, plan)?;
+                summary.set_totals(&header);
+                (shards, max_zoom, header.sample)
+            } else {
+                let scan = histogram::read(counts)?;
+                let scanned_zoom = (scan.counts.len() - 1) as u8;
+                let max_zoom = max_zoom.unwrap_or(scanned_zoom);
+                if max_zoom > scanned_zoom {
+                    bail!("histogram only goes to zoom {scanned_zoom}, cannot plan at {max_zoom}");
+                }
+                summary.set_totals(&histogram::Header::of(&scan));
+                (run_plan(&scan, max_zoom, plan), max_zoom, scan.sample)
+            };
+            let parameters = Parameters::new(max_zoom, sample, None, plan);
+            summary.set_parameters(parameters.clone());
+            let manifest = write_manifests(&shards, sample, plan, &parameters, None, summary)?;
+            let summary = finish_summary(summary, &shards, plan)?;
+            presign(plan, manifest, summary)
+        }
+        Some(Command::Runs(args)) => runs::run(args),
+        Some(Command::Extract(args)) => extract::run(args),
+        Some(Command::PlanCost(args)) => cost::plan_cost(args),
+        Some(Command::Enqueue(args)) => queue::enqueue(args),
+        Some(Command::ExtractPois(args)) => pois::run(args),
+        Some(Command::Submit(args)) => batch::submit(args),
+        Some(Command::Finalize(args)) => completion::finalize(args),
+        Some(Command::Status(args)) => completion::status(args),
+        Some(Command::Merge(args)) => pois::merge::run(args),
+        Some(Command::NodeCache(args)) => node_cache::build(args),
+        Some(Command::AdminBoundaries(args)) => pois::admin::build(args),
+        Some(Command::Tile(args)) => pois::tile::run(args),
+        Some(Command::OpensearchAlias(args)) => pois::opensearch::swap_alias(args),
+    }
+}
+
+/// Record the plan in the run summary and write it, if one was requested, returning where.
+fn finish_summary(
+    summary: &mut Summary,
+    shards: &[Shard],
+    args: &PlanArgs,
+) -> Result<Option<store::Store>> {
+    summary.set_shards(shards, args.max_nodes);
+    let Some(location) = &args.summary else {
+        return Ok(None);
+    };
+    let store = store::Store::open(location, "summary.json")?;
+    summary.write(&store)?;
+    Ok(Some(store))
+}
+
+/// With `--presign-expiry`, share the published manifest and summary through presigned URLs.
+fn presign(
+    args: &PlanArgs,
+    manifest: Option<store::Store>,
+    summary: Option<store::Store>,
+) -> Result<()> {
+    let Some(expiry) = args.presign_expiry else {
+        return Ok(());
+    };
+    // `--presign-expiry` requires `--output`, but never mix the URLs into a manifest on stdout.
+    let stdout_free = manifest.is_some();
+    let artifacts = [("manifest", manifest), ("summary", summary)];
+    let artifacts: Vec<_> = artifacts
+        .iter()
+        .filter_map(|(kind, store)| Some((*kind, store.as_ref()?)))
+        .collect();
+    publish::presign(&artifacts, Duration::from_secs(expiry), stdout_free)
+}
+
+/// Scan all inputs and report what we found. With `checksums`, the inputs are hashed on
+/// background threads while they are scanned; with `blob_index_zoom`, the input's node
+/// blobs are indexed.
+fn run_scan(
+    args: &ScanArgs,
+    checksums: bool,
+    blob_index_zoom: Option<u8>,
+) -> Result<(ScanResult, Vec<InputFile>)> {
+    let osm_files = expand_inputs(&args.osm_files)?;
+    threads::configure(args.threads, args.cpus.as_ref())?;
+    let hashers: Vec<_> = osm_files
+        .iter()
+        .map(|path| checksums.then(|| summary::spawn_sha256(path)))
+        .collect();
+    let checkpoint = args
+        .checkpoint
+        .as_deref()
+        .map(|location| {
+            Checkpointer::open(
+                location,
+                Duration::from_secs(args.checkpoint_interval),
+                args.resume,
+            )
+        })
+        .transpose()?;
+    let spiller = args
+        .memory_limit
+        .map(|limit| Spiller::new(limit, args.spill_dir.as_deref()));
+
+    let _scan = info_span!("scan", files = osm_files.len()).entered();
+    let scan = scan::scan_osm(
+        &osm_files,
+        &ScanOptions {
+            format: args.input_format,
+            max_zoom: args.max_zoom,
+            sample: args.sample,
+            checkpoint: checkpoint.as_ref(),
+            spill: spiller.as_ref(),
+            bbox: args.bbox,
+            progress: args.progress,
+            progress_interval: Duration::from_secs(args.progress_interval),
+            blob_index_zoom,
+        },
+    )?;
+    metrics::add(Counter::NodesScanned, scan.node_total);
+    if scan.spilled.is_some() {
+        info!(
+            nodes = scan.node_total,
+            "Scan complete.  {}{} nodes, counts spilled to disk.",
+            if args.sample.is_some() { "~" } else { "" },
+            scan.node_total
+        );
+    } else {
+        info!(
+            nodes = scan.node_total,
+            tiles = scan.counts[usize::from(args.max_zoom)].len(),
+            "Scan complete.  {}{} nodes in {} populated max-zoom tiles.",
+            if args.sample.is_some() { "~" } else { "" },
+            scan.node_total,
+            scan.counts[usize::from(args.max_zoom)].len()
+        );
+    }
+    if scan.duplicate_total > 0 {
+        info!(
+            duplicates = scan.duplicate_total,
+            "Skipped {} nodes repeated across input files.", scan.duplicate_total
+        );
+    }
+
+    let mut inputs = Vec::with_capacity(osm_files.len());
+    for (path, hasher) in osm_files.into_iter().zip(hashers) {
+        let sha256 = match hasher {
+            Some(hasher) => Some(
+                hasher
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("checksum thread panicked"))??,
+            ),
+            None => None,
+        };
+        let header = match args.input_format.resolve(&path)? {
+            InputFormat::Pbf => Some(scan::source_metadata(&path)?),
+            _ => None,
+        };
+        inputs.push(InputFile {
+            bytes: std::fs::metadata(&path)?.len(),
+            path,
+            sha256,
+            header,
+        });
+    }
+    Ok((scan, inputs))
+}
+
+/// Turn a histogram into shards.
+fn run_plan(scan: &ScanResult, max_zoom: u8, args: &PlanArgs) -> Vec<Shard> {
+    let plan = info_span!("plan").entered();
+    info!(
+        max_nodes = args.max_nodes,
+        "Building shards (max nodes per shard = {})...", args.max_nodes
     );
+    let shards = build_shards(&scan.counts, max_zoom, args.max_nodes);
+    drop(plan);
+    shards
+}
 
-    eprintln!(
-        "Building shards (max nodes per shard = {})...",
+/// Plan straight from a histogram stream, without rebuilding the zoom levels in memory.
+/// Returns the shards with the zoom they were planned at and the histogram header.
+fn plan_streaming(
+    counts: &Path,
+    max_zoom: Option<u8>,
+    args: &PlanArgs,
+) -> Result<(Vec<Shard>, u8, histogram::Header)> {
+    let (header, cells) = histogram::stream(counts)?;
+    let max_zoom = max_zoom.unwrap_or(header.max_zoom);
+    if max_zoom > header.max_zoom {
+        bail!(
+            "histogram only goes to zoom {}, cannot plan at {max_zoom}",
+            header.max_zoom
+        );
+    }
+
+    let plan = info_span!("plan").entered();
+    info!(
+        counts = %counts.display(),
+        max_nodes = args.max_nodes,
+        "Building shards from {} (max nodes per shard = {})...",
+        counts.display(),
         args.max_nodes
     );
-    let shards = build_shards(&scan.counts, args.max_zoom, args.max_nodes);
-    eprintln!("Generated {} shards.", shards.len());
+    let mut planner = StreamPlanner::new(header.max_zoom, max_zoom, args.max_nodes);
+    for cell in cells {
+        let (key, count) = cell?;
+        planner.push(key, count);
+    }
+    let shards = planner.finish();
+    drop(plan);
+    Ok((shards, max_zoom, header))
+}
 
-    // Generate GeoJSON, print to stdout.
-    let geojson = generate_geojson(&shards)?;
-    eprintln!("Writing GeoJSON to stdout...");
-    println!("{}", geojson);
+/// Merge spilled counts once, writing them to a histogram file and/or feeding a planner.
+fn drain_spilled(
+    header: &histogram::Header,
+    spilled: Spilled,
+    counts_path: Option<&Path>,
+    mut planner: Option<&mut StreamPlanner>,
+) -> Result<()> {
+    let mut writer = counts_path
+        .map(|path| histogram::create(path, header))
+        .transpose()?;
+
+    for cell in spilled.merge()? {
+        let (key, count) = cell?;
+        if let Some(writer) = &mut writer {
+            writer.push(key, count)?;
+        }
+        if let Some(planner) = planner.as_deref_mut() {
+            planner.push(key, count);
+        }
+    }
 
+    if let (Some(writer), Some(path)) = (writer, counts_path) {
+        writer.finish()?.flush()?;
+        info!(path = %path.display(), "Wrote count histogram to {}.", path.display());
+    }
     Ok(())
 }
 
-/// Stream the PBF in parallel, map every node to its ZXY cell, and keep tallies for each zoom level.
-fn scan_osm(path: &Path, max_zoom: u8) -> Result<ScanResult> {
-    let reader = ElementReader::from_path(path)
-        .with_context(|| format!("unable to open {}", path.display()))?;
-
-    let max_zoom_usize = usize::from(max_zoom);
+/// Write the manifest for `shards` to stdout or `--output`, plus the dual output when enabled,
+/// recording what was written in `summary`. Returns the `--output` store.
+fn write_manifests(
+    shards: &[Shard],
+    sample: Option<f64>,
+    args: &PlanArgs,
+    parameters: &Parameters,
+    metadata: Option<&ManifestMetadata>,
+    summary: &mut Summary,
+) -> Result<Option<store::Store>> {
+    let _write = info_span!("write").entered();
+    // Features are ordered by z/x/y whatever order planning produced them in.
+    let mut shards = shards.to_vec();
+    shards.sort_unstable_by_key(|shard| (shard.zoom, shard.x, shard.y));
+    let shards = shards.as_slice();
+    metrics::add(Counter::ShardsGenerated, shards.len() as u64);
+    info!(shards = shards.len(), "Generated {} shards.", shards.len());
+    if args.stats {
+        if let Some(distribution) = stats::Distribution::of(shards, args.max_nodes) {
+            distribution.report(args.max_nodes);
+        }
+    }
+    if let Some(path) = &args.pmtiles {
+        pmtiles::write(path, shards, args.pmtiles_max_zoom)?;
+        summary.add_file("pmtiles", path);
+    }
+    if let Some(path) = &args.preview {
+        let features = shard_features(shards, args.precision, args.validate)?;
+        preview::write(path, &features, args.max_nodes)?;
+        summary.add_file("preview", path);
+    }
+    if let Some(fraction) = sample {
+        info!(
+            sample = fraction,
+            "Note: node counts are estimates from a {:.4}% sample.",
+            fraction * 100.0
+        );
+    }
 
-    // Use par_map_reduce for parallel processing of PBF blocks
-    let (counts, node_total) = reader.par_map_reduce(
-        // Map function: process each element and return local counts
-        |element| {
-            let mut local_counts: Vec<HashMap<(u32, u32), u64>> =
-                (0..=max_zoom).map(|_| HashMap::new()).collect();
-            let mut local_total = 0u64;
+    if let Some(legacy_schema) = args.dual_output_schema {
+        let dual = migration::DualOutput {
+            legacy_schema,
+            schema: args.schema,
+            max_runs: args.dual_output_runs,
+            dir: &args.dual_output_dir,
+            state_path: args.migration_state.as_deref(),
+        };
+        if dual.begin_run()? {
+            // The comparison is always between FeatureCollections, whatever `--format` is.
+            let legacy = generate_geojson(shards, legacy_schema, args, parameters, metadata)?;
+            let current = generate_geojson(shards, args.schema, args, parameters, metadata)?;
+            dual.write(&legacy, &current)?;
+        }
+    }
 
-            let (lat, lon) = match element {
-                Element::DenseNode(node) => (node.lat(), node.lon()),
-                Element::Node(node) => (node.lat(), node.lon()),
-                _ => return (local_counts, local_total),
+    let name = match args.compress {
+        Some(compression) => format!(
+            "manifest.{}.{}",
+            args.format.extension(),
+            compression.extension()
+        ),
+        None => format!("manifest.{}", args.format.extension()),
+    };
+    let store = args
+        .output
+        .as_deref()
+        .map(|output| store::Store::open(output, &name))
+        .transpose()?;
+    let destination = match &store {
+        Some(store) => store.to_string(),
+        None => "stdout".to_string(),
+    };
+    match args.compress {
+        Some(compression) => info!(
+            "Writing {} to {destination}, {}-compressed...",
+            args.format.name(),
+            compression.name()
+        ),
+        None => info!("Writing {} to {destination}...", args.format.name()),
+    }
+    // The manifest is staged in a temporary file and published once complete.
+    let staged = store
+        .as_ref()
+        .map(|_| tempfile::NamedTempFile::new())
+        .transpose()?;
+    let mut out = compress::Output::open(staged.as_ref().map(|file| file.path()), args.compress)?;
+    match args.format {
+        ManifestFormat::Geojson => {
+            let geojson = generate_geojson(shards, args.schema, args, parameters, metadata)?;
+            writeln!(out, "{geojson}")?;
+        }
+        ManifestFormat::Geojsonseq => {
+            for feature in shard_features(shards, args.precision, args.validate)? {
+                serde_json::to_writer(&mut out, &feature)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        ManifestFormat::Flatgeobuf => {
+            let features = shard_features(shards, args.precision, args.validate)?;
+            let header = manifest_header(args.schema, parameters, metadata)?;
+            flatgeobuf::write(&mut out, &features, header.as_deref())?;
+        }
+        ManifestFormat::Geoparquet => {
+            let features = shard_features(shards, args.precision, args.validate)?;
+            let header = manifest_header(args.schema, parameters, metadata)?;
+            geoparquet::write(&mut out, &features, header.as_deref())?;
+        }
+        ManifestFormat::Csv | ManifestFormat::Tsv => {
+            let features = shard_features(shards, args.precision, args.validate)?;
+            let delimiter = if args.format == ManifestFormat::Csv {
+                ','
+            } else {
+                '\t'
             };
-
-            if !(lat.is_finite() && lon.is_finite()) {
-                return (local_counts, local_total);
+            delimited::write(&mut out, &features, delimiter)?;
+        }
+        ManifestFormat::Kml | ManifestFormat::Kmz => {
+            let features = shard_features(shards, args.precision, args.validate)?;
+            let kmz = args.format == ManifestFormat::Kmz;
+            kml::write(&mut out, &features, args.max_nodes, kmz)?;
+        }
+        ManifestFormat::Quadkeys => {
+            for shard in shards {
+                writeln!(
+                    out,
+                    "{}\t{}",
+                    quadkey(shard.zoom, shard.x, shard.y),
+                    shard.node_count
+                )?;
             }
+        }
+    }
+    out.finish()?;
+    if let (Some(store), Some(staged)) = (&store, &staged) {
+        let sha256 = publish::publish(
+            store,
+            staged.path(),
+            &name,
+            args.compress.map(compress::Compression::name),
+            args.latest_pointer.as_deref(),
+        )?;
+        let bytes = staged.as_file().metadata()?.len();
+        summary.add_artifact("manifest", store.to_string(), bytes, sha256);
+    }
+    if let Some(store) = &store {
+        task::record_output(store.to_string());
+    }
+    let nodes = shards.iter().map(|shard| shard.node_count).sum();
+    notify::record_manifest(
+        store.as_ref().map(store::Store::to_string),
+        shards.len() as u64,
+        nodes,
+    );
 
-            if let Some((mut x, mut y)) = lon_lat_to_tile(lon, lat, max_zoom) {
-                *local_counts[max_zoom_usize].entry((x, y)).or_insert(0) += 1;
+    Ok(store)
+}
 
-                // Bubble up to parent zoom levels by shifting.
-                for zoom in (0..max_zoom).rev() {
-                    x >>= 1;
-                    y >>= 1;
-                    *local_counts[usize::from(zoom)].entry((x, y)).or_insert(0) += 1;
-                }
+/// Resolve the input arguments into concrete files, expanding `*`/`?` wildcards in file names.
+fn expand_inputs(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
 
-                local_total = 1;
+    for input in inputs {
+        let name = input
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !name.contains(['*', '?']) {
+            if !input.exists() {
+                bail!("file does not exist: {}", input.display());
             }
+            files.push(input.clone());
+            continue;
+        }
 
-            (local_counts, local_total)
-        },
-        // Identity function: create empty state
-        || {
-            (
-                (0..=max_zoom).map(|_| HashMap::new()).collect::<Vec<_>>(),
-                0u64,
-            )
-        },
-        // Reduce function: merge two results
-        |mut acc, item| {
-            // Merge counts from item into accumulator
-            for (res_idx, item_map) in item.0.into_iter().enumerate() {
-                for (cell, count) in item_map {
-                    *acc.0[res_idx].entry(cell).or_insert(0) += count;
-                }
-            }
-            acc.1 += item.1;
-            acc
-        },
-    )?;
+        let dir = match input.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut matched: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .with_context(|| format!("unable to list {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|candidate| wildcard_match(&name, &candidate.to_string_lossy()))
+            })
+            .collect();
+        if matched.is_empty() {
+            bail!("no files match {}", input.display());
+        }
+        matched.sort();
+        files.extend(matched);
+    }
+
+    files.dedup();
+    Ok(files)
+}
+
+/// Shell-style matching of a file name against a pattern with `*` and `?` wildcards.
+fn wildcard_match(pattern: &str, candidate: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    let (mut p, mut c) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while c < candidate.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == candidate[c]) {
+            p += 1;
+            c += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, c));
+            p += 1;
+        } else if let Some((star_p, star_c)) = backtrack {
+            p = star_p + 1;
+            c = star_c + 1;
+            backtrack = Some((star_p, star_c + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
 
-    Ok(ScanResult { counts, node_total })
+/// Parse a sampling fraction in (0, 1].
+fn parse_fraction(raw: &str) -> Result<f64, String> {
+    let value: f64 = raw.parse().map_err(|err| format!("{err}"))?;
+    if value > 0.0 && value <= 1.0 {
+        Ok(value)
+    } else {
+        Err(format!("{value} is not in (0, 1]"))
+    }
 }
 
 /// Translate the hierarchical counts into the final set of shards.
@@ -184,7 +1180,7 @@ fn build_shards(counts: &[HashMap<(u32, u32), u64>], max_zoom: u8, max_nodes: u6
     }
 
     // Start splitting from every populated zoom-0 tile.
-    if let Some(root_counts) = counts.get(0) {
+    if let Some(root_counts) = counts.first() {
         for (&(x, y), _) in root_counts.iter() {
             subdivide(
                 0,
@@ -199,26 +1195,43 @@ fn build_shards(counts: &[HashMap<(u32, u32), u64>], max_zoom: u8, max_nodes: u6
         }
     }
 
-    if !oversized.is_empty() {
-        eprintln!(
-            "Warning: {} tiles at max zoom exceeded the node threshold (showing up to 5):",
-            oversized.len()
+    report_oversized(&oversized, max_nodes);
+    shards
+}
+
+/// Warn about max-zoom tiles that could not be split below the threshold.
+fn report_oversized(oversized: &[Shard], max_nodes: u64) {
+    metrics::add(Counter::OversizedCells, oversized.len() as u64);
+    if oversized.is_empty() {
+        return;
+    }
+    warn!(
+        oversized = oversized.len(),
+        "{} tiles at max zoom exceeded the node threshold (showing up to 5):",
+        oversized.len()
+    );
+    for shard in oversized.iter().take(5) {
+        warn!(
+            zoom = shard.zoom,
+            x = shard.x,
+            y = shard.y,
+            nodes = shard.node_count,
+            max_nodes,
+            "tile z/x/y {}/{}/{} has {} nodes (max {})",
+            shard.zoom,
+            shard.x,
+            shard.y,
+            shard.node_count,
+            max_nodes
         );
-        for shard in oversized.iter().take(5) {
-            eprintln!(
-                "  z/x/y {}/{}/{} -> {} nodes (max {})",
-                shard.zoom, shard.x, shard.y, shard.node_count, max_nodes
-            );
-        }
-        if oversized.len() > 5 {
-            eprintln!("  ... and {} more", oversized.len() - 5);
-        }
     }
-
-    shards
+    if oversized.len() > 5 {
+        info!("  ... and {} more", oversized.len() - 5);
+    }
 }
 
 /// Recursively split a cell until it satisfies the node constraint or we hit max resolution.
+#[allow(clippy::too_many_arguments)]
 fn subdivide(
     zoom: u8,
     x: u32,
@@ -276,13 +1289,80 @@ fn subdivide(
     }
 }
 
-/// Convert the shard list into a GeoJSON string.
-fn generate_geojson(shards: &[Shard]) -> Result<String> {
+/// Convert the shard list into a GeoJSON string using the requested schema version.
+fn generate_geojson(
+    shards: &[Shard],
+    schema: ManifestSchema,
+    args: &PlanArgs,
+    parameters: &Parameters,
+    metadata: Option<&ManifestMetadata>,
+) -> Result<String> {
+    let features = shard_features(shards, args.precision, args.validate)?;
+    let collection = generate_collection(schema, parameters, metadata, features);
+    if args.compact {
+        Ok(serde_json::to_string(&collection)?)
+    } else {
+        Ok(serde_json::to_string_pretty(&collection)?)
+    }
+}
+
+fn generate_collection<'a>(
+    schema: ManifestSchema,
+    parameters: &'a Parameters,
+    metadata: Option<&'a ManifestMetadata>,
+    features: Vec<Feature>,
+) -> FeatureCollection<'a> {
+    match schema {
+        // Original manifest layout: one Polygon feature per shard with ZXY properties.
+        ManifestSchema::V1 => FeatureCollection {
+            feature_type: "FeatureCollection",
+            schema_version: None,
+            parameters: None,
+            metadata: None,
+            features,
+        },
+        ManifestSchema::V2 => FeatureCollection {
+            feature_type: "FeatureCollection",
+            schema_version: Some(schema.version()),
+            parameters: Some(parameters),
+            metadata,
+            features,
+        },
+    }
+}
+
+/// The collection-level members of a manifest as a JSON object, for formats that store them
+/// apart from the features; `None` when the schema has none.
+fn manifest_header(
+    schema: ManifestSchema,
+    parameters: &Parameters,
+    metadata: Option<&ManifestMetadata>,
+) -> Result<Option<String>> {
+    let collection = generate_collection(schema, parameters, metadata, Vec::new());
+    let mut header = serde_json::to_value(&collection)?;
+    if let Some(header) = header.as_object_mut() {
+        header.remove("type");
+        header.remove("features");
+        if header.is_empty() {
+            return Ok(None);
+        }
+    }
+    Ok(Some(header.to_string()))
+}
+
+/// One Polygon feature per shard with ZXY properties, coordinates rounded to `decimals`.
+/// With `validate`, a ring that is not a valid RFC 7946 exterior ring fails the run.
+fn shard_features(shards: &[Shard], decimals: u8, validate: bool) -> Result<Vec<Feature>> {
     let mut features = Vec::with_capacity(shards.len());
 
     for shard in shards {
-        let ring = tile_ring(shard.zoom, shard.x, shard.y);
         let shard_id = format!("{}-{}-{}", shard.zoom, shard.x, shard.y);
+        let mut ring = tile_ring(shard.zoom, shard.x, shard.y, decimals);
+        geometry::normalize_exterior(&mut ring);
+        if validate {
+            geometry::validate_ring(&ring)
+                .with_context(|| format!("invalid geometry for shard {shard_id}"))?;
+        }
         features.push(Feature {
             feature_type: "Feature",
             properties: Properties {
@@ -298,17 +1378,24 @@ fn generate_geojson(shards: &[Shard]) -> Result<String> {
             },
         });
     }
+    Ok(features)
+}
 
-    let collection = FeatureCollection {
-        feature_type: "FeatureCollection",
-        features,
-    };
+// Web Mercator tile utilities
 
-    Ok(serde_json::to_string_pretty(&collection)?)
+/// Bing Maps quadkey of a tile: one base-4 digit per zoom level, most significant first.
+fn quadkey(zoom: u8, x: u32, y: u32) -> String {
+    (1..=zoom)
+        .rev()
+        .map(|level| {
+            let mask = 1 << (level - 1);
+            let digit = u8::from(x & mask != 0) + 2 * u8::from(y & mask != 0);
+            char::from(b'0' + digit)
+        })
+        .collect()
 }
 
-// Web Mercator tile utilities
-fn lon_lat_to_tile(lon: f64, lat: f64, zoom: u8) -> Option<(u32, u32)> {
+pub(crate) fn lon_lat_to_tile(lon: f64, lat: f64, zoom: u8) -> Option<(u32, u32)> {
     if !(lon.is_finite() && lat.is_finite()) {
         return None;
     }
@@ -330,7 +1417,36 @@ fn lon_lat_to_tile(lon: f64, lat: f64, zoom: u8) -> Option<(u32, u32)> {
     Some((x, y))
 }
 
-fn tile_bbox(zoom: u8, x: u32, y: u32) -> (f64, f64, f64, f64) {
+/// Morton (Z-order) key of a tile: x bits in even, y bits in odd positions. Sorting by key
+/// keeps every tile's descendants contiguous, and `key >> 2` is the parent's key.
+pub(crate) fn tile_key(x: u32, y: u32) -> u64 {
+    spread_bits(x) | (spread_bits(y) << 1)
+}
+
+/// Inverse of [`tile_key`].
+pub(crate) fn key_tile(key: u64) -> (u32, u32) {
+    (compact_bits(key), compact_bits(key >> 1))
+}
+
+fn spread_bits(value: u32) -> u64 {
+    let mut v = u64::from(value);
+    v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+    v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+    (v | (v << 1)) & 0x5555_5555_5555_5555
+}
+
+fn compact_bits(key: u64) -> u32 {
+    let mut v = key & 0x5555_5555_5555_5555;
+    v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+    v = (v | (v >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v >> 4)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v >> 8)) & 0x0000_ffff_0000_ffff;
+    ((v | (v >> 16)) & 0x0000_0000_ffff_ffff) as u32
+}
+
+pub(crate) fn tile_bbox(zoom: u8, x: u32, y: u32) -> (f64, f64, f64, f64) {
     let n = 2u32.pow(u32::from(zoom)) as f64;
     let west = (f64::from(x) / n) * 360.0 - 180.0;
     let east = (f64::from(x + 1) / n) * 360.0 - 180.0;
@@ -342,8 +1458,22 @@ fn tile_bbox(zoom: u8, x: u32, y: u32) -> (f64, f64, f64, f64) {
     (west, south, east, north)
 }
 
-fn tile_ring(zoom: u8, x: u32, y: u32) -> Vec<[f64; 2]> {
+/// Default decimal places kept in manifest coordinates (about 0.1 mm). Rounding hides
+/// last-bit differences between math libraries, so identical inputs give byte-identical
+/// manifests.
+const DEFAULT_PRECISION: u8 = 9;
+
+fn round_coordinate(value: f64, decimals: u8) -> f64 {
+    let scale = 10f64.powi(i32::from(decimals));
+    (value * scale).round() / scale
+}
+
+/// Exterior ring of a tile. Web Mercator tiles span `[-180, 180]` without wrapping, so a
+/// ring never crosses the antimeridian and a plain Polygon is always valid RFC 7946.
+fn tile_ring(zoom: u8, x: u32, y: u32, decimals: u8) -> Vec<[f64; 2]> {
     let (west, south, east, north) = tile_bbox(zoom, x, y);
+    let [west, south, east, north] =
+        [west, south, east, north].map(|value| round_coordinate(value, decimals));
     vec![
         [west, south],
         [east, south],
@@ -352,3 +1482,122 @@ fn tile_ring(zoom: u8, x: u32, y: u32) -> Vec<[f64; 2]> {
         [west, south],
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shard ids and node counts read back from a manifest, in shard order.
+    type ReadBack = Result<Vec<(String, u64)>>;
+
+    /// Write `shards` with the sharder's own writer in each manifest format, then read them
+    /// back as the workers do.
+    fn round_trip(shards: &[Shard]) -> Result<Vec<(ManifestFormat, ReadBack)>> {
+        let dir = tempfile::tempdir()?;
+        let mut read = Vec::new();
+        for format in ManifestFormat::value_variants() {
+            let name = format.to_possible_value().expect("no skipped format");
+            let output = dir.path().join(format!("manifest.{}", format.extension()));
+            let output = output.to_str().expect("UTF-8 temporary path");
+            let cli = Cli::try_parse_from([
+                "osm-planet-sharding",
+                "--format",
+                name.get_name(),
+                "--output",
+                output,
+                "planet.osm.pbf",
+            ])?;
+            let parameters = Parameters::new(12, None, None, &cli.plan);
+            write_manifests(
+                shards,
+                None,
+                &cli.plan,
+                &parameters,
+                None,
+                &mut Summary::start(),
+            )?;
+            let shards = extract::read_manifest(output).map(|shards| {
+                shards
+                    .into_iter()
+                    .map(|shard| (shard.id, shard.node_count.unwrap_or_default()))
+                    .collect()
+            });
+            read.push((*format, shards));
+        }
+        Ok(read)
+    }
+
+    fn assert_round_trips(shards: &[Shard], expected: &[(&str, u64)]) -> Result<()> {
+        for (format, read) in round_trip(shards)? {
+            match format {
+                // Binary formats are for other tools; the workers refuse them plainly.
+                ManifestFormat::Flatgeobuf | ManifestFormat::Geoparquet => {
+                    let err = read.expect_err("a binary manifest is not read back");
+                    assert!(
+                        format!("{err:#}").contains("cannot be read back"),
+                        "{format:?}: {err:#}"
+                    );
+                }
+                _ => {
+                    let read = read.with_context(|| format!("{format:?}"))?;
+                    let expected: Vec<(String, u64)> = expected
+                        .iter()
+                        .map(|&(id, node_count)| (id.to_string(), node_count))
+                        .collect();
+                    assert_eq!(read, expected, "{format:?}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn presigning_requires_the_manifest_off_stdout() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(
+                ["osm-planet-sharding", "--presign-expiry", "60"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+        let err = parse(&["planet.osm.pbf"]).expect_err("the manifest would go to stdout");
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+        assert!(parse(&["--output", "s3://bucket/runs/r1/", "planet.osm.pbf"]).is_ok());
+    }
+
+    #[test]
+    fn manifests_of_one_root_shard_round_trip() -> Result<()> {
+        let shards = [Shard {
+            zoom: 0,
+            x: 0,
+            y: 0,
+            node_count: 6,
+        }];
+        assert_round_trips(&shards, &[("0-0-0", 6)])
+    }
+
+    #[test]
+    fn manifests_of_many_shards_round_trip() -> Result<()> {
+        let shards = [
+            Shard {
+                zoom: 3,
+                x: 5,
+                y: 2,
+                node_count: 0,
+            },
+            Shard {
+                zoom: 1,
+                x: 0,
+                y: 1,
+                node_count: 1200,
+            },
+            Shard {
+                zoom: 2,
+                x: 3,
+                y: 0,
+                node_count: 7,
+            },
+        ];
+        assert_round_trips(&shards, &[("1-0-1", 1200), ("2-3-0", 7), ("3-5-2", 0)])
+    }
+}
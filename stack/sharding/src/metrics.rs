@@ -0,0 +1,339 @@
+//! Run metrics, pushed at exit to a Prometheus pushgateway and/or an OTLP/HTTP collector,
+//! or printed as a CloudWatch Embedded Metric Format record.
+//!
+//! Counters are process-wide atomics bumped from wherever the work happens; phase durations
+//! come from the logging spans as they close. Both exporters speak plain HTTP, which is what
+//! the pushgateway and the collector sidecar listen on inside the VPC.
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::keys;
+
+const PREFIX: &str = "osm_sharding";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MetricsFormat {
+    /// A CloudWatch Embedded Metric Format record on stderr, picked up from the job's logs.
+    CloudwatchEmf,
+}
+
+#[derive(Args, Debug)]
+pub struct MetricsArgs {
+    /// Print the run metrics at exit in this format.
+    #[arg(long, env = "METRICS", value_enum)]
+    metrics: Option<MetricsFormat>,
+
+    /// CloudWatch namespace for `--metrics cloudwatch-emf`.
+    #[arg(long, env = "METRICS_NAMESPACE", default_value = "OSM/Sharding")]
+    metrics_namespace: String,
+
+    /// Prometheus pushgateway to push run metrics to at exit, e.g. http://pushgateway:9091.
+    #[arg(long, env = "PUSHGATEWAY_URL")]
+    pushgateway: Option<String>,
+
+    /// OTLP/HTTP collector to send run metrics to at exit, e.g. http://otel-collector:4318.
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    /// Job name for exported metrics.
+    #[arg(long, env = "METRICS_JOB", default_value = "osm-sharding")]
+    metrics_job: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Counter {
+    NodesScanned,
+    BlobsProcessed,
+    ShardsGenerated,
+    OversizedCells,
+    S3UploadBytes,
+}
+
+impl Counter {
+    const ALL: [Counter; 5] = [
+        Counter::NodesScanned,
+        Counter::BlobsProcessed,
+        Counter::ShardsGenerated,
+        Counter::OversizedCells,
+        Counter::S3UploadBytes,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Counter::NodesScanned => "nodes_scanned",
+            Counter::BlobsProcessed => "blobs_processed",
+            Counter::ShardsGenerated => "shards_generated",
+            Counter::OversizedCells => "oversized_cells",
+            Counter::S3UploadBytes => "s3_upload_bytes",
+        }
+    }
+
+    /// CloudWatch metric name and unit.
+    fn cloudwatch(self) -> (&'static str, &'static str) {
+        match self {
+            Counter::NodesScanned => ("NodesScanned", "Count"),
+            Counter::BlobsProcessed => ("BlobsProcessed", "Count"),
+            Counter::ShardsGenerated => ("ShardsGenerated", "Count"),
+            Counter::OversizedCells => ("OversizedCells", "Count"),
+            Counter::S3UploadBytes => ("S3UploadBytes", "Bytes"),
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Counter::NodesScanned => "Nodes counted by the scan (scaled up when sampling).",
+            Counter::BlobsProcessed => "PBF blobs decoded by the scan.",
+            Counter::ShardsGenerated => "Shards in the generated plan.",
+            Counter::OversizedCells => "Max-zoom tiles still over the node threshold.",
+            Counter::S3UploadBytes => "Bytes uploaded to S3.",
+        }
+    }
+}
+
+static COUNTERS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+static PHASES: Mutex<Vec<(&'static str, f64)>> = Mutex::new(Vec::new());
+
+/// Add `value` to a counter.
+pub fn add(counter: Counter, value: u64) {
+    COUNTERS[counter as usize].fetch_add(value, Ordering::Relaxed);
+}
+
+pub fn get(counter: Counter) -> u64 {
+    COUNTERS[counter as usize].load(Ordering::Relaxed)
+}
+
+/// Record how long a phase of the run took.
+pub fn record_phase(phase: &'static str, seconds: f64) {
+    PHASES
+        .lock()
+        .expect("metrics phase lock poisoned")
+        .push((phase, seconds));
+}
+
+/// Phase durations recorded so far, summed per phase, in first-seen order.
+pub fn phases() -> Vec<(&'static str, f64)> {
+    let mut totals: Vec<(&'static str, f64)> = Vec::new();
+    for &(phase, seconds) in PHASES.lock().expect("metrics phase lock poisoned").iter() {
+        match totals.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += seconds,
+            None => totals.push((phase, seconds)),
+        }
+    }
+    totals
+}
+
+/// Send the metrics to every configured exporter. Failures are logged, not returned, so a
+/// monitoring outage never fails a run.
+pub fn export(args: &MetricsArgs) {
+    if args.metrics == Some(MetricsFormat::CloudwatchEmf) {
+        eprintln!("{}", emf_record(args));
+    }
+    if let Some(url) = &args.pushgateway {
+        match push_prometheus(url, args) {
+            Ok(()) => info!(pushgateway = %url, "Pushed metrics to {url}."),
+            Err(err) => warn!(pushgateway = %url, "unable to push metrics to {url}: {err:#}"),
+        }
+    }
+    if let Some(endpoint) = &args.otlp_endpoint {
+        match push_otlp(endpoint, args) {
+            Ok(()) => info!(otlp_endpoint = %endpoint, "Sent metrics to {endpoint}."),
+            Err(err) => {
+                warn!(otlp_endpoint = %endpoint, "unable to send metrics to {endpoint}: {err:#}")
+            }
+        }
+    }
+}
+
+/// Replace this job's metric group on the pushgateway with the current values.
+fn push_prometheus(url: &str, args: &MetricsArgs) -> Result<()> {
+    let mut body = String::new();
+    for counter in Counter::ALL {
+        let name = format!("{PREFIX}_{}_total", counter.name());
+        body.push_str(&format!("# HELP {name} {}\n", counter.help()));
+        body.push_str(&format!("# TYPE {name} counter\n"));
+        body.push_str(&format!("{name} {}\n", get(counter)));
+    }
+    let name = format!("{PREFIX}_phase_duration_seconds");
+    body.push_str(&format!(
+        "# HELP {name} Wall-clock time spent in each phase.\n"
+    ));
+    body.push_str(&format!("# TYPE {name} gauge\n"));
+    for (phase, seconds) in phases() {
+        body.push_str(&format!("{name}{{phase=\"{phase}\"}} {seconds}\n"));
+    }
+
+    let mut path = format!("/metrics/job/{}", args.metrics_job);
+    if let Some(run_id) = keys::run_id() {
+        path.push_str(&format!("/run_id/{run_id}"));
+    }
+    http_send(
+        "PUT",
+        &format!("{}{path}", url.trim_end_matches('/')),
+        "text/plain; version=0.0.4",
+        body.as_bytes(),
+    )
+}
+
+/// One EMF record with every counter and a `<Phase>Duration` metric per phase, dimensioned
+/// by job. The run id is a plain property so it stays searchable without adding a metric
+/// per run.
+fn emf_record(args: &MetricsArgs) -> Value {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let mut record = serde_json::Map::new();
+    let mut definitions = Vec::new();
+
+    for counter in Counter::ALL {
+        let (name, unit) = counter.cloudwatch();
+        definitions.push(json!({"Name": name, "Unit": unit}));
+        record.insert(name.into(), get(counter).into());
+    }
+    for (phase, seconds) in phases() {
+        let name = format!("{}Duration", camel_case(phase));
+        definitions.push(json!({"Name": name, "Unit": "Seconds"}));
+        record.insert(name, seconds.into());
+    }
+
+    record.insert("Job".into(), args.metrics_job.clone().into());
+    if let Some(run_id) = keys::run_id() {
+        record.insert("RunId".into(), run_id.into());
+    }
+    record.insert(
+        "_aws".into(),
+        json!({
+            "Timestamp": timestamp,
+            "CloudWatchMetrics": [{
+                "Namespace": args.metrics_namespace,
+                "Dimensions": [["Job"]],
+                "Metrics": definitions,
+            }],
+        }),
+    );
+    Value::Object(record)
+}
+
+/// `scan_file` -> `ScanFile`.
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map_or_else(String::new, |first| {
+                first.to_ascii_uppercase().to_string() + chars.as_str()
+            })
+        })
+        .collect()
+}
+
+/// Send the metrics as an OTLP/HTTP JSON export request.
+fn push_otlp(endpoint: &str, args: &MetricsArgs) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string();
+    let attribute = |key: &str, value: &str| json!({"key": key, "value": {"stringValue": value}});
+
+    let mut metrics: Vec<Value> = Counter::ALL
+        .iter()
+        .map(|&counter| {
+            json!({
+                "name": format!("{PREFIX}.{}", counter.name()),
+                "description": counter.help(),
+                "sum": {
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                    "dataPoints": [{"asInt": get(counter).to_string(), "timeUnixNano": now}],
+                },
+            })
+        })
+        .collect();
+    metrics.push(json!({
+        "name": format!("{PREFIX}.phase_duration"),
+        "description": "Wall-clock time spent in each phase.",
+        "unit": "s",
+        "gauge": {
+            "dataPoints": phases()
+                .into_iter()
+                .map(|(phase, seconds)| json!({
+                    "asDouble": seconds,
+                    "timeUnixNano": now,
+                    "attributes": [attribute("phase", phase)],
+                }))
+                .collect::<Vec<_>>(),
+        },
+    }));
+
+    let mut resource = vec![attribute("service.name", &args.metrics_job)];
+    if let Some(run_id) = keys::run_id() {
+        resource.push(attribute("run_id", run_id));
+    }
+    let request = json!({
+        "resourceMetrics": [{
+            "resource": {"attributes": resource},
+            "scopeMetrics": [{
+                "scope": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+                "metrics": metrics,
+            }],
+        }],
+    });
+    http_send(
+        "POST",
+        &format!("{}/v1/metrics", endpoint.trim_end_matches('/')),
+        "application/json",
+        request.to_string().as_bytes(),
+    )
+}
+
+/// Minimal HTTP/1.1 request over plain TCP; fails unless the response status is 2xx.
+fn http_send(method: &str, url: &str, content_type: &str, body: &[u8]) -> Result<()> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        bail!("only http:// endpoints are supported, got {url}");
+    };
+    let (host, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+
+    let mut stream =
+        TcpStream::connect(&address).with_context(|| format!("unable to connect to {address}"))?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .with_context(|| format!("malformed HTTP response from {address}"))?;
+    if !(200..300).contains(&status) {
+        bail!(
+            "HTTP {status}: {}",
+            response.split("\r\n\r\n").nth(1).unwrap_or("").trim()
+        );
+    }
+    Ok(())
+}
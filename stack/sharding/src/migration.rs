@@ -0,0 +1,271 @@
+//! Dual-output mode used while migrating between manifest schema versions.
+//!
+//! For a configurable number of runs the sharder writes the manifest in both the legacy
+//! and the current schema, plus a field-level report describing what changed between them.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use crate::ManifestSchema;
+
+/// Settings for one dual-output run.
+pub struct DualOutput<'a> {
+    pub legacy_schema: ManifestSchema,
+    pub schema: ManifestSchema,
+    pub max_runs: u32,
+    pub dir: &'a Path,
+    pub state_path: Option<&'a Path>,
+}
+
+/// Persisted run counter; resets whenever the schema pair changes.
+#[derive(Serialize, Deserialize, Default)]
+struct MigrationState {
+    legacy_schema: String,
+    schema: String,
+    runs_written: u32,
+}
+
+/// Field-level comparison between the legacy and the current manifest.
+#[derive(Serialize)]
+struct ComparisonReport {
+    legacy_schema: &'static str,
+    schema: &'static str,
+    legacy_feature_count: usize,
+    feature_count: usize,
+    unmatched_legacy_features: usize,
+    unmatched_features: usize,
+    top_level: Vec<FieldDiff>,
+    features: Vec<FieldDiff>,
+}
+
+/// How a single field differs between the two outputs.
+#[derive(Serialize)]
+struct FieldDiff {
+    field: String,
+    status: &'static str,
+    features_differing: u64,
+}
+
+/// Presence/equality tallies for one field path.
+#[derive(Default)]
+struct FieldTally {
+    legacy_only: u64,
+    current_only: u64,
+    equal: u64,
+    differing: u64,
+}
+
+impl DualOutput<'_> {
+    /// Check the run counter and decide whether this run still needs the legacy output.
+    pub fn begin_run(&self) -> Result<bool> {
+        let state = self.load_state()?;
+        if state.runs_written >= self.max_runs {
+            info!(
+                schema = self.legacy_schema.as_str(),
+                runs_written = state.runs_written,
+                "Dual output for schema {} already written for {} runs; skipping.",
+                self.legacy_schema.as_str(),
+                state.runs_written
+            );
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Write the legacy manifest and the comparison report, then bump the run counter.
+    pub fn write(&self, legacy: &str, current: &str) -> Result<()> {
+        fs::create_dir_all(self.dir)
+            .with_context(|| format!("unable to create {}", self.dir.display()))?;
+
+        let legacy_path = self
+            .dir
+            .join(format!("manifest.{}.json", self.legacy_schema.as_str()));
+        fs::write(&legacy_path, legacy)
+            .with_context(|| format!("unable to write {}", legacy_path.display()))?;
+
+        let report = self.compare(legacy, current)?;
+        let report_path = self.dir.join("migration-report.json");
+        fs::write(&report_path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("unable to write {}", report_path.display()))?;
+
+        info!(
+            schema = self.legacy_schema.as_str(),
+            manifest = %legacy_path.display(),
+            report = %report_path.display(),
+            "Wrote legacy {} manifest to {} and comparison report to {}.",
+            self.legacy_schema.as_str(),
+            legacy_path.display(),
+            report_path.display()
+        );
+
+        let mut state = self.load_state()?;
+        state.runs_written += 1;
+        self.save_state(&state)
+    }
+
+    fn load_state(&self) -> Result<MigrationState> {
+        let fresh = MigrationState {
+            legacy_schema: self.legacy_schema.as_str().to_string(),
+            schema: self.schema.as_str().to_string(),
+            runs_written: 0,
+        };
+        let Some(path) = self.state_path else {
+            return Ok(fresh);
+        };
+        if !path.exists() {
+            return Ok(fresh);
+        }
+
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        let state: MigrationState = serde_json::from_str(&raw)
+            .with_context(|| format!("invalid migration state in {}", path.display()))?;
+        if state.legacy_schema != fresh.legacy_schema || state.schema != fresh.schema {
+            return Ok(fresh);
+        }
+        Ok(state)
+    }
+
+    fn save_state(&self, state: &MigrationState) -> Result<()> {
+        let Some(path) = self.state_path else {
+            return Ok(());
+        };
+        fs::write(path, serde_json::to_string_pretty(state)?)
+            .with_context(|| format!("unable to write {}", path.display()))
+    }
+
+    fn compare(&self, legacy: &str, current: &str) -> Result<ComparisonReport> {
+        let legacy: Value = serde_json::from_str(legacy)?;
+        let current: Value = serde_json::from_str(current)?;
+
+        let legacy_features = keyed_features(&legacy);
+        let current_features = keyed_features(&current);
+
+        let mut tallies: BTreeMap<String, FieldTally> = BTreeMap::new();
+        let mut unmatched_legacy_features = 0;
+        for (key, legacy_fields) in &legacy_features {
+            let Some(current_fields) = current_features.get(key) else {
+                unmatched_legacy_features += 1;
+                continue;
+            };
+            tally_fields(&mut tallies, legacy_fields, current_fields);
+        }
+        let unmatched_features = current_features
+            .keys()
+            .filter(|key| !legacy_features.contains_key(*key))
+            .count();
+
+        let mut top_tallies: BTreeMap<String, FieldTally> = BTreeMap::new();
+        tally_fields(
+            &mut top_tallies,
+            &top_level_fields(&legacy),
+            &top_level_fields(&current),
+        );
+
+        Ok(ComparisonReport {
+            legacy_schema: self.legacy_schema.as_str(),
+            schema: self.schema.as_str(),
+            legacy_feature_count: legacy_features.len(),
+            feature_count: current_features.len(),
+            unmatched_legacy_features,
+            unmatched_features,
+            top_level: summarize(top_tallies),
+            features: summarize(tallies),
+        })
+    }
+}
+
+/// Index features by `shard_id`, falling back to their position, and flatten their fields.
+fn keyed_features(collection: &Value) -> BTreeMap<String, BTreeMap<String, Value>> {
+    let mut keyed = BTreeMap::new();
+    let Some(features) = collection.get("features").and_then(Value::as_array) else {
+        return keyed;
+    };
+
+    for (idx, feature) in features.iter().enumerate() {
+        let key = feature
+            .pointer("/properties/shard_id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("#{idx}"));
+
+        let mut fields = BTreeMap::new();
+        if let Some(object) = feature.as_object() {
+            for (name, value) in object {
+                match (name.as_str(), value.as_object()) {
+                    ("properties", Some(properties)) => {
+                        for (prop, prop_value) in properties {
+                            fields.insert(format!("properties.{prop}"), prop_value.clone());
+                        }
+                    }
+                    _ => {
+                        fields.insert(name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        keyed.insert(key, fields);
+    }
+    keyed
+}
+
+/// Every top-level member of the collection except the feature array itself.
+fn top_level_fields(collection: &Value) -> BTreeMap<String, Value> {
+    collection
+        .as_object()
+        .map(|object| {
+            object
+                .iter()
+                .filter(|(name, _)| name.as_str() != "features")
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn tally_fields(
+    tallies: &mut BTreeMap<String, FieldTally>,
+    legacy: &BTreeMap<String, Value>,
+    current: &BTreeMap<String, Value>,
+) {
+    for (field, legacy_value) in legacy {
+        let tally = tallies.entry(field.clone()).or_default();
+        match current.get(field) {
+            Some(value) if value == legacy_value => tally.equal += 1,
+            Some(_) => tally.differing += 1,
+            None => tally.legacy_only += 1,
+        }
+    }
+    for field in current.keys() {
+        if !legacy.contains_key(field) {
+            tallies.entry(field.clone()).or_default().current_only += 1;
+        }
+    }
+}
+
+fn summarize(tallies: BTreeMap<String, FieldTally>) -> Vec<FieldDiff> {
+    tallies
+        .into_iter()
+        .map(|(field, tally)| {
+            let in_legacy = tally.legacy_only + tally.equal + tally.differing > 0;
+            let in_current = tally.current_only + tally.equal + tally.differing > 0;
+            let features_differing = tally.legacy_only + tally.current_only + tally.differing;
+            let status = match (in_legacy, in_current) {
+                (true, false) => "removed",
+                (false, true) => "added",
+                _ if features_differing > 0 => "changed",
+                _ => "unchanged",
+            };
+            FieldDiff {
+                field,
+                status,
+                features_differing,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,219 @@
+//! `node-cache` subcommand and reader: the location of every node of the input in one flat
+//! file indexed by node ID, in the manner of osm2pgsql's flat nodes, so `extract-pois`
+//! workers can place the nodes of ways and relations without reading the planet for them.
+//! The cache is built once per planet and shared: a worker memory-maps a local copy, or
+//! fetches the pages it needs from the object store with ranged reads.
+//!
+//! Node `id` sits at byte `8 * id`: longitude then latitude, each in 100 nanodegrees plus
+//! [`OFFSET`] as a little-endian `u32`. All-zero bytes, which fill the unused IDs (as holes
+//! on file systems with sparse files), are no node. A planet takes 8 bytes per node ID up to
+//! its highest.
+
+use anyhow::{Context, Result};
+use clap::Args;
+use hashbrown::HashMap;
+use memmap2::Mmap;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use tracing::{info, info_span, warn};
+
+use crate::input::{self, InputFormat, OsmElement};
+use crate::store::Store;
+use crate::task;
+
+/// Added to coordinates so that no location is stored as zero.
+const OFFSET: i64 = 1_800_000_001;
+/// Bytes per node.
+const ENTRY: u64 = 8;
+/// Largest run of consecutive nodes written at once while building.
+const RUN_BYTES: usize = 1 << 20;
+/// Nodes fetched per ranged read of a remote cache, 64 KiB.
+const PAGE_NODES: u64 = 8192;
+/// Pages of a remote cache kept in memory, 256 MiB.
+const MAX_PAGES: usize = 4096;
+/// Object name for `--s3-key-template` when the cache location is a bucket or prefix.
+const NAME: &str = "nodes.cache";
+
+#[derive(Args, Debug)]
+pub struct NodeCacheArgs {
+    /// Planet (or extract) whose node locations to cache.
+    #[arg(env = "OSM_FILE")]
+    input: PathBuf,
+
+    /// Input encoding; `auto` picks it by extension.
+    #[arg(long, env = "INPUT_FORMAT", value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    /// Where to write the cache: a local path or an object store URI. A bucket or prefix
+    /// gets its key from `--s3-key-template`, with `{name}` `nodes.cache`.
+    #[arg(short, long, env = "NODE_CACHE")]
+    output: String,
+}
+
+pub fn build(args: &NodeCacheArgs) -> Result<()> {
+    let format = args.input_format.resolve(&args.input)?;
+    let store = Store::open(&args.output, NAME)?;
+    // Built next to a local destination, so it can be renamed into place when complete.
+    let tmp = match &store {
+        Store::Local(path) => {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            let dir = dir.unwrap_or(Path::new("."));
+            fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create {}", dir.display()))?;
+            NamedTempFile::new_in(dir)?
+        }
+        _ => NamedTempFile::new()?,
+    };
+
+    let _build = info_span!("node_cache").entered();
+    info!("Caching the node locations of {}...", args.input.display());
+    let mut writer = CacheWriter {
+        file: tmp.reopen()?,
+        start: 0,
+        run: Vec::with_capacity(RUN_BYTES),
+    };
+    let (mut nodes, mut negative) = (0u64, 0u64);
+    input::open_source(&args.input, format)?.for_each_element(&mut |element| {
+        if let OsmElement::Node(node) = element {
+            match u64::try_from(node.id) {
+                Ok(id) => {
+                    writer.push(id, node.lon, node.lat)?;
+                    nodes += 1;
+                }
+                Err(_) => negative += 1,
+            }
+        }
+        Ok(())
+    })?;
+    writer.flush()?;
+    writer.file.sync_all()?;
+    if negative > 0 {
+        warn!(
+            nodes = negative,
+            "Left {negative} nodes with negative IDs out of the cache."
+        );
+    }
+
+    let bytes = tmp.as_file().metadata()?.len();
+    match &store {
+        Store::Local(path) => {
+            tmp.persist(path)
+                .with_context(|| format!("unable to write {}", path.display()))?;
+        }
+        _ => store.put_file(tmp.path(), None)?,
+    }
+    task::record_output(store.to_string());
+    info!(
+        destination = %store,
+        nodes,
+        bytes,
+        "Wrote the locations of {nodes} nodes to {store} ({bytes} bytes)."
+    );
+    Ok(())
+}
+
+/// Writes runs of consecutive node IDs with one seek each; sorted input is one long run
+/// per block of nodes, unsorted input still works, just with more seeks.
+struct CacheWriter {
+    file: File,
+    /// ID of the first node in `run`.
+    start: u64,
+    run: Vec<u8>,
+}
+
+impl CacheWriter {
+    fn push(&mut self, id: u64, lon: f64, lat: f64) -> Result<()> {
+        if id != self.start + self.run.len() as u64 / ENTRY || self.run.len() >= RUN_BYTES {
+            self.flush()?;
+            self.start = id;
+        }
+        self.run.extend_from_slice(&encode(lon).to_le_bytes());
+        self.run.extend_from_slice(&encode(lat).to_le_bytes());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.run.is_empty() {
+            self.file.seek(SeekFrom::Start(self.start * ENTRY))?;
+            self.file.write_all(&self.run)?;
+            self.run.clear();
+        }
+        Ok(())
+    }
+}
+
+fn encode(degrees: f64) -> u32 {
+    ((degrees * 1e7).round() as i64 + OFFSET) as u32
+}
+
+/// A node cache being read.
+pub struct NodeCache {
+    source: Source,
+    /// Pages fetched from a remote cache, by page number.
+    pages: HashMap<u64, Box<[u8]>>,
+}
+
+enum Source {
+    Mapped(Mmap),
+    Remote(Store),
+}
+
+impl NodeCache {
+    /// Open the cache at a local path, which is memory-mapped, or an object store URI, which
+    /// is read a page at a time.
+    pub fn open(location: &str) -> Result<Self> {
+        let source = match Store::open(location, NAME)? {
+            Store::Local(path) => {
+                let file = File::open(&path)
+                    .with_context(|| format!("unable to open node cache {}", path.display()))?;
+                // SAFETY: the cache is only read, and is not expected to change while it is
+                // mapped; a cache rebuilt in place would be renamed over, not rewritten.
+                Source::Mapped(unsafe { Mmap::map(&file)? })
+            }
+            store => Source::Remote(store),
+        };
+        Ok(Self {
+            source,
+            pages: HashMap::new(),
+        })
+    }
+
+    /// The location of a node in 100 nanodegrees, `None` if the cache does not have it.
+    pub fn get(&mut self, id: i64) -> Result<Option<[i32; 2]>> {
+        let Ok(id) = u64::try_from(id) else {
+            return Ok(None);
+        };
+        let (bytes, offset) = match &self.source {
+            Source::Mapped(map) => (&map[..], id * ENTRY),
+            Source::Remote(store) => {
+                let page = id / PAGE_NODES;
+                if !self.pages.contains_key(&page) {
+                    if self.pages.len() >= MAX_PAGES {
+                        self.pages.clear();
+                    }
+                    let bytes = store.get_range(page * PAGE_NODES * ENTRY, PAGE_NODES * ENTRY)?;
+                    self.pages.insert(page, bytes.into_boxed_slice());
+                }
+                (&self.pages[&page][..], id % PAGE_NODES * ENTRY)
+            }
+        };
+        let Some(entry) = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| bytes.get(offset..offset + ENTRY as usize))
+        else {
+            return Ok(None);
+        };
+        let lon = u32::from_le_bytes(entry[..4].try_into()?);
+        let lat = u32::from_le_bytes(entry[4..].try_into()?);
+        if lon == 0 && lat == 0 {
+            return Ok(None);
+        }
+        Ok(Some([decode(lon), decode(lat)]))
+    }
+}
+
+fn decode(stored: u32) -> i32 {
+    (i64::from(stored) - OFFSET) as i32
+}
@@ -0,0 +1,42 @@
+//! Concurrent set of OSM node IDs used to skip nodes repeated across overlapping extracts.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Node IDs covered by one lazily-allocated page (8M IDs = 1 MiB of bits).
+const PAGE_BITS: u32 = 23;
+const WORDS_PER_PAGE: usize = 1 << (PAGE_BITS - 6);
+/// Highest supported node ID is `2^40`; the page table itself stays around 1 MiB.
+const MAX_PAGES: usize = 1 << (40 - PAGE_BITS);
+
+/// Paged bitmap keyed by node ID. Pages are allocated on first touch, so regional
+/// extracts only pay for the ID ranges they actually contain.
+pub struct NodeIdSet {
+    pages: Vec<OnceLock<Box<[AtomicU64]>>>,
+}
+
+impl NodeIdSet {
+    pub fn new() -> Self {
+        Self {
+            pages: (0..MAX_PAGES).map(|_| OnceLock::new()).collect(),
+        }
+    }
+
+    /// Record `id` and report whether it was new. Negative (unsaved) IDs and IDs beyond
+    /// the supported range are never treated as duplicates.
+    pub fn insert(&self, id: i64) -> bool {
+        let Ok(id) = u64::try_from(id) else {
+            return true;
+        };
+        let page_idx = (id >> PAGE_BITS) as usize;
+        let Some(slot) = self.pages.get(page_idx) else {
+            return true;
+        };
+
+        let page = slot.get_or_init(|| (0..WORDS_PER_PAGE).map(|_| AtomicU64::new(0)).collect());
+        let bit = id & ((1 << PAGE_BITS) - 1);
+        let mask = 1u64 << (bit & 63);
+        let previous = page[(bit >> 6) as usize].fetch_or(mask, Ordering::Relaxed);
+        previous & mask == 0
+    }
+}
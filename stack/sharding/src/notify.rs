@@ -0,0 +1,175 @@
+//! Run events, published at exit to an SNS topic and/or an EventBridge bus so a pipeline
+//! can react to a finished run instead of polling for its manifest.
+//!
+//! Like the GCS and Azure stores, this goes through a CLI, here `aws`, which the image
+//! already ships; credentials and region come from the environment. The event is the same
+//! JSON document on both: the run, whether it succeeded, the published manifest and its
+//! shard and node totals, presigned URLs if any, or the error.
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::store::run_cli;
+use crate::{keys, logging};
+
+/// `source` of the published events.
+const SOURCE: &str = "osm-planet-sharding";
+
+#[derive(Args, Debug)]
+pub struct NotifyArgs {
+    /// SNS topic to publish the run's success or failure event to at exit.
+    #[arg(long, env = "NOTIFY_TOPIC_ARN")]
+    notify_topic_arn: Option<String>,
+
+    /// EventBridge bus (name or ARN) to put the run's success or failure event on at exit.
+    #[arg(long, env = "NOTIFY_EVENT_BUS")]
+    notify_event_bus: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Event<'a> {
+    source: &'static str,
+    run_id: Option<&'static str>,
+    /// `succeeded` or `failed`.
+    status: &'static str,
+    /// Where the manifest was published, if it was written to `--output`.
+    manifest: Option<&'a str>,
+    shards: u64,
+    nodes: u64,
+    /// Presigned URLs by artifact, with `--presign-expiry`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    urls: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    finished_at: String,
+}
+
+/// The manifest, recorded once it is written.
+struct Manifest {
+    location: Option<String>,
+    shards: u64,
+    nodes: u64,
+}
+
+static MANIFEST: Mutex<Option<Manifest>> = Mutex::new(None);
+static URLS: Mutex<Option<Value>> = Mutex::new(None);
+
+/// Record the written manifest: its `--output` location (`None` for stdout) and its shard
+/// and node totals.
+pub fn record_manifest(location: Option<String>, shards: u64, nodes: u64) {
+    *MANIFEST.lock().expect("notify lock poisoned") = Some(Manifest {
+        location,
+        shards,
+        nodes,
+    });
+}
+
+/// Record the presigned URLs of the published artifacts.
+pub fn record_urls(urls: Value) {
+    *URLS.lock().expect("notify lock poisoned") = Some(urls);
+}
+
+/// Send the run's event to every configured target. Failures are logged, not returned, so
+/// a notification outage never changes a run's outcome.
+pub fn send(args: &NotifyArgs, error: Option<&anyhow::Error>) {
+    if args.notify_topic_arn.is_none() && args.notify_event_bus.is_none() {
+        return;
+    }
+    let manifest = MANIFEST.lock().expect("notify lock poisoned");
+    let event = Event {
+        source: SOURCE,
+        run_id: keys::run_id(),
+        status: if error.is_some() {
+            "failed"
+        } else {
+            "succeeded"
+        },
+        manifest: manifest.as_ref().and_then(|m| m.location.as_deref()),
+        shards: manifest.as_ref().map_or(0, |m| m.shards),
+        nodes: manifest.as_ref().map_or(0, |m| m.nodes),
+        urls: URLS.lock().expect("notify lock poisoned").clone(),
+        error: error.map(|err| format!("{err:#}")),
+        finished_at: logging::timestamp(),
+    };
+
+    if let Some(topic) = &args.notify_topic_arn {
+        match publish_sns(topic, &event) {
+            Ok(()) => info!(topic = %topic, "Published {} event to {topic}.", event.status),
+            Err(err) => warn!(topic = %topic, "unable to publish to {topic}: {err:#}"),
+        }
+    }
+    if let Some(bus) = &args.notify_event_bus {
+        match put_event(bus, &event) {
+            Ok(()) => info!(event_bus = %bus, "Put {} event on {bus}.", event.status),
+            Err(err) => warn!(event_bus = %bus, "unable to put event on {bus}: {err:#}"),
+        }
+    }
+}
+
+/// Publish to SNS, with the status as a message attribute for subscription filter policies.
+fn publish_sns(topic: &str, event: &Event) -> Result<()> {
+    let message = serde_json::to_string(event)?;
+    let attributes = serde_json::json!({
+        "status": {"DataType": "String", "StringValue": event.status},
+    })
+    .to_string();
+    let args = [
+        "sns",
+        "publish",
+        "--topic-arn",
+        topic,
+        "--message",
+        &message,
+        "--message-attributes",
+        &attributes,
+    ];
+    if let Err(stderr) = run_cli("aws", &args)? {
+        bail!("{stderr}");
+    }
+    Ok(())
+}
+
+/// Put the event on an EventBridge bus, with detail type `Sharding Run Succeeded` or
+/// `Sharding Run Failed`.
+fn put_event(bus: &str, event: &Event) -> Result<()> {
+    let detail_type = if event.error.is_some() {
+        "Sharding Run Failed"
+    } else {
+        "Sharding Run Succeeded"
+    };
+    let entries = serde_json::json!([{
+        "Source": SOURCE,
+        "DetailType": detail_type,
+        "Detail": serde_json::to_string(event)?,
+        "EventBusName": bus,
+    }])
+    .to_string();
+    let args = [
+        "events",
+        "put-events",
+        "--output",
+        "json",
+        "--entries",
+        &entries,
+    ];
+    let stdout = match run_cli("aws", &args)? {
+        Ok(stdout) => stdout,
+        Err(stderr) => bail!("{stderr}"),
+    };
+    // PutEvents succeeds as a call even when it rejects the entry.
+    let response: serde_json::Value =
+        serde_json::from_slice(&stdout).context("unexpected put-events response")?;
+    if response["FailedEntryCount"].as_u64().unwrap_or(0) > 0 {
+        let entry = &response["Entries"][0];
+        bail!(
+            "{}: {}",
+            entry["ErrorCode"].as_str().unwrap_or("rejected"),
+            entry["ErrorMessage"].as_str().unwrap_or("")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,538 @@
+//! PMTiles archive of the shard polygons (<https://github.com/protomaps/PMTiles>, spec v3),
+//! for inspecting a shard plan in MapLibre from a static file host.
+//!
+//! Shards are Web Mercator tiles themselves, so cutting them into vector tiles is rectangle
+//! intersection. Tiles go up to a fixed zoom and viewers overzoom past it; at low zooms,
+//! shards smaller than one tile unit are left out. The Mapbox Vector Tile protobuf and the
+//! PMTiles directories are encoded by hand.
+//!
+//! The archive layout and a layer of points are shared with the merged POI tiles
+//! (see `merge`).
+
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hashbrown::HashMap;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::info;
+
+use crate::{tile_bbox, Shard};
+
+const LAYER: &str = "shards";
+pub const EXTENT: u32 = 4096;
+/// Tile units drawn outside each tile edge, so polygon borders are not clipped visibly.
+const BUFFER: f64 = 64.0;
+const HEADER_BYTES: usize = 127;
+/// The header and root directory must fit in the first 16 KiB, so readers can fetch both
+/// with one request.
+const ROOT_BYTES: usize = 16_384 - HEADER_BYTES;
+const COMPRESSION_GZIP: u8 = 2;
+const TILE_TYPE_MVT: u8 = 1;
+
+/// One directory entry: a run of `run_length` tile ids starting at `tile_id` sharing the
+/// same data, or a leaf directory when `run_length` is 0.
+#[derive(Clone, Copy)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u32,
+    run_length: u32,
+}
+
+/// The tiles of an archive, addressed in tile id order.
+#[derive(Default)]
+pub struct Directory {
+    entries: Vec<Entry>,
+    /// Tiles addressed, counting every tile of a run.
+    addressed: u64,
+}
+
+impl Directory {
+    /// Address tile `tile_id` to `length` bytes at `offset` of the tile data, as part of
+    /// the previous entry's run when it is the next tile with the same data.
+    pub fn push(&mut self, tile_id: u64, offset: u64, length: u32) {
+        self.addressed += 1;
+        match self.entries.last_mut() {
+            Some(last)
+                if last.offset == offset
+                    && last.tile_id + u64::from(last.run_length) == tile_id =>
+            {
+                last.run_length += 1;
+            }
+            _ => self.entries.push(Entry {
+                tile_id,
+                offset,
+                length,
+                run_length: 1,
+            }),
+        }
+    }
+
+    pub fn addressed(&self) -> u64 {
+        self.addressed
+    }
+}
+
+/// What an archive's header and metadata say about its tiles.
+pub struct Tileset {
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    /// West, south, east, north.
+    pub bounds: [f64; 4],
+    /// Distinct tile contents in the tile data.
+    pub contents: u64,
+    pub metadata: Value,
+}
+
+/// Write an archive of gzipped vector tiles: the header, the directories and metadata,
+/// then `data_len` bytes of tile data from `data`, in tile id order.
+pub fn write_archive<W: Write>(
+    out: &mut W,
+    directory: &Directory,
+    tileset: &Tileset,
+    data_len: u64,
+    data: impl FnOnce(&mut W) -> Result<()>,
+) -> Result<()> {
+    let (root, leaves) = build_directories(&directory.entries)?;
+    let metadata = gzip(tileset.metadata.to_string().as_bytes())?;
+    let bounds = tileset.bounds;
+    let e7 = |degrees: f64| ((degrees * 1e7).round() as i32).to_le_bytes();
+
+    let root_offset = HEADER_BYTES as u64;
+    let metadata_offset = root_offset + root.len() as u64;
+    let leaves_offset = metadata_offset + metadata.len() as u64;
+    let data_offset = leaves_offset + leaves.len() as u64;
+
+    let mut header = Vec::with_capacity(HEADER_BYTES);
+    header.extend_from_slice(b"PMTiles");
+    header.push(3);
+    for value in [
+        root_offset,
+        root.len() as u64,
+        metadata_offset,
+        metadata.len() as u64,
+        leaves_offset,
+        leaves.len() as u64,
+        data_offset,
+        data_len,
+        directory.addressed,
+        directory.entries.len() as u64,
+        tileset.contents,
+    ] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    // Clustered: tile data is in tile id order.
+    header.push(1);
+    header.push(COMPRESSION_GZIP);
+    header.push(COMPRESSION_GZIP);
+    header.push(TILE_TYPE_MVT);
+    header.push(tileset.min_zoom);
+    header.push(tileset.max_zoom);
+    for degrees in bounds {
+        header.extend_from_slice(&e7(degrees));
+    }
+    header.push(tileset.min_zoom);
+    header.extend_from_slice(&e7((bounds[0] + bounds[2]) / 2.0));
+    header.extend_from_slice(&e7((bounds[1] + bounds[3]) / 2.0));
+    debug_assert_eq!(header.len(), HEADER_BYTES);
+
+    for part in [&header, &root, &metadata, &leaves] {
+        out.write_all(part)?;
+    }
+    data(out)
+}
+
+/// Write the shards of a plan as vector tiles for zooms 0 to `max_zoom` into a PMTiles
+/// archive at `path`.
+pub fn write(path: &Path, shards: &[Shard], max_zoom: u8) -> Result<()> {
+    let mut tile_data: Vec<u8> = Vec::new();
+    let mut contents: HashMap<Vec<u8>, (u64, u32)> = HashMap::new();
+    let mut directory = Directory::default();
+
+    for zoom in 0..=max_zoom {
+        let mut tiles: Vec<(u64, u32, u32, Vec<&Shard>)> = tiles_at(zoom, shards)
+            .into_iter()
+            .map(|((x, y), shards)| (tile_id(zoom, x, y), x, y, shards))
+            .collect();
+        tiles.sort_unstable_by_key(|&(id, ..)| id);
+
+        for (id, x, y, tile_shards) in tiles {
+            let Some(tile) = encode_tile(zoom, x, y, &tile_shards) else {
+                continue;
+            };
+            let tile = gzip(&tile)?;
+            // Tiles inside one large shard are identical, so store each content once.
+            let (offset, length) = *contents.entry(tile).or_insert_with_key(|tile| {
+                let stored = (tile_data.len() as u64, tile.len() as u32);
+                tile_data.extend_from_slice(tile);
+                stored
+            });
+            directory.push(id, offset, length);
+        }
+    }
+
+    let bounds = shards
+        .iter()
+        .fold([180.0f64, 90.0, -180.0, -90.0], |acc, shard| {
+            let (west, south, east, north) = tile_bbox(shard.zoom, shard.x, shard.y);
+            [
+                acc[0].min(west),
+                acc[1].min(south),
+                acc[2].max(east),
+                acc[3].max(north),
+            ]
+        });
+    let tileset = Tileset {
+        min_zoom: 0,
+        max_zoom,
+        bounds,
+        contents: contents.len() as u64,
+        metadata: json!({
+            "name": "shards",
+            "description": "Shard plan of osm-planet-sharding",
+            "vector_layers": [{
+                "id": LAYER,
+                "minzoom": 0,
+                "maxzoom": max_zoom,
+                "fields": {
+                    "shard_id": "String",
+                    "z": "Number",
+                    "x": "Number",
+                    "y": "Number",
+                    "node_count": "Number",
+                },
+            }],
+        }),
+    };
+
+    let file =
+        File::create(path).with_context(|| format!("unable to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    write_archive(
+        &mut out,
+        &directory,
+        &tileset,
+        tile_data.len() as u64,
+        |out| Ok(out.write_all(&tile_data)?),
+    )?;
+    out.flush()
+        .with_context(|| format!("unable to write {}", path.display()))?;
+    info!(
+        path = %path.display(),
+        tiles = directory.addressed,
+        "Wrote {} vector tiles of the shard plan to {}.",
+        directory.addressed,
+        path.display()
+    );
+    Ok(())
+}
+
+/// The shards overlapping each tile at `zoom`.
+fn tiles_at(zoom: u8, shards: &[Shard]) -> HashMap<(u32, u32), Vec<&Shard>> {
+    let mut tiles: HashMap<(u32, u32), Vec<&Shard>> = HashMap::new();
+    for shard in shards {
+        if shard.zoom >= zoom {
+            let shift = shard.zoom - zoom;
+            tiles
+                .entry((shard.x >> shift, shard.y >> shift))
+                .or_default()
+                .push(shard);
+        } else {
+            let shift = zoom - shard.zoom;
+            for x in shard.x << shift..(shard.x + 1) << shift {
+                for y in shard.y << shift..(shard.y + 1) << shift {
+                    tiles.entry((x, y)).or_default().push(shard);
+                }
+            }
+        }
+    }
+    tiles
+}
+
+/// Mapbox Vector Tile with one layer of shard polygons, or `None` when every shard is too
+/// small to draw at this zoom.
+fn encode_tile(zoom: u8, x: u32, y: u32, shards: &[&Shard]) -> Option<Vec<u8>> {
+    const KEYS: [&str; 5] = ["shard_id", "z", "x", "y", "node_count"];
+    let mut values: Vec<Vec<u8>> = Vec::new();
+    let mut value_index: HashMap<u64, u32> = HashMap::new();
+    let mut features = Vec::new();
+
+    for shard in shards {
+        // Shard edges in this tile's coordinates, clamped to the buffer.
+        let scale = 2f64.powi(i32::from(zoom) - i32::from(shard.zoom));
+        let edge = |shard_edge: u32, tile_edge: u32| {
+            ((f64::from(shard_edge) * scale - f64::from(tile_edge)) * f64::from(EXTENT))
+                .clamp(-BUFFER, f64::from(EXTENT) + BUFFER)
+                .round() as i32
+        };
+        let (left, right) = (edge(shard.x, x), edge(shard.x + 1, x));
+        let (top, bottom) = (edge(shard.y, y), edge(shard.y + 1, y));
+        if left == right || top == bottom {
+            continue;
+        }
+
+        let mut tags = Vec::with_capacity(10);
+        let mut string = Vec::new();
+        field_bytes(
+            &mut string,
+            1,
+            format!("{}-{}-{}", shard.zoom, shard.x, shard.y).as_bytes(),
+        );
+        values.push(string);
+        tags.extend([0, values.len() as u32 - 1]);
+        for (key, value) in [
+            u64::from(shard.zoom),
+            u64::from(shard.x),
+            u64::from(shard.y),
+            shard.node_count,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let index = *value_index.entry(value).or_insert_with(|| {
+                let mut uint = Vec::new();
+                field_varint(&mut uint, 5, value);
+                values.push(uint);
+                values.len() as u32 - 1
+            });
+            tags.extend([key as u32 + 1, index]);
+        }
+
+        // Clockwise in screen coordinates, as the spec requires of exterior rings.
+        let geometry = [
+            command(1, 1),
+            zigzag(left),
+            zigzag(top),
+            command(2, 3),
+            zigzag(right - left),
+            zigzag(0),
+            zigzag(0),
+            zigzag(bottom - top),
+            zigzag(left - right),
+            zigzag(0),
+            command(7, 1),
+        ];
+
+        let mut feature = Vec::new();
+        field_varint(&mut feature, 1, features.len() as u64);
+        field_packed(&mut feature, 2, &tags);
+        field_varint(&mut feature, 3, 3);
+        field_packed(&mut feature, 4, &geometry);
+        features.push(feature);
+    }
+    if features.is_empty() {
+        return None;
+    }
+
+    let mut layer = Vec::new();
+    field_varint(&mut layer, 15, 2);
+    field_bytes(&mut layer, 1, LAYER.as_bytes());
+    for feature in &features {
+        field_bytes(&mut layer, 2, feature);
+    }
+    for key in KEYS {
+        field_bytes(&mut layer, 3, key.as_bytes());
+    }
+    for value in &values {
+        field_bytes(&mut layer, 4, value);
+    }
+    field_varint(&mut layer, 5, u64::from(EXTENT));
+
+    let mut tile = Vec::new();
+    field_bytes(&mut tile, 3, &layer);
+    Some(tile)
+}
+
+/// A vector tile with one layer of points with string properties, built a point at a time.
+pub struct PointLayer {
+    name: &'static str,
+    keys: &'static [&'static str],
+    values: Vec<Vec<u8>>,
+    value_index: HashMap<String, u32>,
+    features: Vec<Vec<u8>>,
+}
+
+impl PointLayer {
+    pub fn new(name: &'static str, keys: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            keys,
+            values: Vec::new(),
+            value_index: HashMap::new(),
+            features: Vec::new(),
+        }
+    }
+
+    /// Add a point at `x`, `y` in tile units, with the value of each of the layer's keys
+    /// that it has.
+    pub fn push(&mut self, x: i32, y: i32, properties: &[Option<&str>]) {
+        let mut tags = Vec::with_capacity(2 * properties.len());
+        for (key, value) in properties.iter().enumerate() {
+            let Some(value) = value else {
+                continue;
+            };
+            let index = match self.value_index.get(*value) {
+                Some(&index) => index,
+                None => {
+                    let mut string = Vec::new();
+                    field_bytes(&mut string, 1, value.as_bytes());
+                    self.values.push(string);
+                    let index = self.values.len() as u32 - 1;
+                    self.value_index.insert(value.to_string(), index);
+                    index
+                }
+            };
+            tags.extend([key as u32, index]);
+        }
+        let mut feature = Vec::new();
+        field_varint(&mut feature, 1, self.features.len() as u64);
+        field_packed(&mut feature, 2, &tags);
+        field_varint(&mut feature, 3, 1);
+        field_packed(&mut feature, 4, &[command(1, 1), zigzag(x), zigzag(y)]);
+        self.features.push(feature);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    /// The gzipped tile.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let mut layer = Vec::new();
+        field_varint(&mut layer, 15, 2);
+        field_bytes(&mut layer, 1, self.name.as_bytes());
+        for feature in &self.features {
+            field_bytes(&mut layer, 2, feature);
+        }
+        for key in self.keys {
+            field_bytes(&mut layer, 3, key.as_bytes());
+        }
+        for value in &self.values {
+            field_bytes(&mut layer, 4, value);
+        }
+        field_varint(&mut layer, 5, u64::from(EXTENT));
+
+        let mut tile = Vec::new();
+        field_bytes(&mut tile, 3, &layer);
+        gzip(&tile)
+    }
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn field_varint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    varint(buf, u64::from(field << 3));
+    varint(buf, value);
+}
+
+fn field_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    varint(buf, u64::from((field << 3) | 2));
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn field_packed(buf: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::new();
+    for &value in values {
+        varint(&mut packed, u64::from(value));
+    }
+    field_bytes(buf, field, &packed);
+}
+
+fn gzip(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+/// PMTiles tile id: tiles of all lower zooms first, then the position on the Hilbert curve.
+pub fn tile_id(zoom: u8, x: u32, y: u32) -> u64 {
+    let base = ((1u64 << (2 * u32::from(zoom))) - 1) / 3;
+    let (mut x, mut y) = (u64::from(x), u64::from(y));
+    let mut position = 0u64;
+    let mut s = (1u64 << zoom) >> 1;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        position += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s >>= 1;
+    }
+    base + position
+}
+
+/// Compressed directory, with entries delta-encoded column by column.
+fn encode_directory(entries: &[Entry]) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    varint(&mut buf, entries.len() as u64);
+    let mut last_id = 0;
+    for entry in entries {
+        varint(&mut buf, entry.tile_id - last_id);
+        last_id = entry.tile_id;
+    }
+    for entry in entries {
+        varint(&mut buf, u64::from(entry.run_length));
+    }
+    for entry in entries {
+        varint(&mut buf, u64::from(entry.length));
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        let contiguous =
+            i > 0 && entry.offset == entries[i - 1].offset + u64::from(entries[i - 1].length);
+        varint(&mut buf, if contiguous { 0 } else { entry.offset + 1 });
+    }
+    gzip(&buf)
+}
+
+/// Root directory and the concatenated leaf directories. Entries go straight into the root
+/// when they fit, otherwise into leaves grown until the root pointing at them fits.
+fn build_directories(entries: &[Entry]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let root = encode_directory(entries)?;
+    if root.len() <= ROOT_BYTES {
+        return Ok((root, Vec::new()));
+    }
+
+    let mut leaf_size = 4096;
+    loop {
+        let mut leaves = Vec::new();
+        let mut root_entries = Vec::new();
+        for chunk in entries.chunks(leaf_size) {
+            let leaf = encode_directory(chunk)?;
+            root_entries.push(Entry {
+                tile_id: chunk[0].tile_id,
+                offset: leaves.len() as u64,
+                length: leaf.len() as u32,
+                run_length: 0,
+            });
+            leaves.extend_from_slice(&leaf);
+        }
+        let root = encode_directory(&root_entries)?;
+        if root.len() <= ROOT_BYTES {
+            return Ok((root, leaves));
+        }
+        leaf_size += leaf_size / 5;
+    }
+}
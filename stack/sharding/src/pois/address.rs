@@ -0,0 +1,73 @@
+//! Structured addresses: each POI's `address` object, from its `addr:*` tags. A house that
+//! is a member of an `associatedStreet` relation, rather than tagged with `addr:street`,
+//! takes its street from the relation's `name`, and any other part it lacks from the
+//! relation's own `addr:*` tags.
+
+use serde_json::{json, Map, Value};
+
+use crate::input::{MemberType, OsmRelation};
+
+/// Fields of `address` and the tags they come from.
+const FIELDS: [(&str, &str); 5] = [
+    ("housenumber", "addr:housenumber"),
+    ("street", "addr:street"),
+    ("postcode", "addr:postcode"),
+    ("city", "addr:city"),
+    ("country", "addr:country"),
+];
+
+/// The `address` of an element, every field present and null when not tagged.
+pub fn address(tags: &[(String, String)]) -> Map<String, Value> {
+    FIELDS
+        .iter()
+        .map(|&(field, key)| (field.to_string(), json!(tag(tags, key))))
+        .collect()
+}
+
+/// Whether an address has a house number but no street, which an `associatedStreet`
+/// relation read later may supply.
+pub fn lacks_street(address: &Map<String, Value>) -> bool {
+    !address["housenumber"].is_null() && address["street"].is_null()
+}
+
+pub fn is_associated_street(relation: &OsmRelation) -> bool {
+    tag(&relation.tags, "type") == Some("associatedStreet")
+}
+
+/// The members of an `associatedStreet` relation whose addresses it completes, as kind
+/// (`node`, `way` or `relation`) and ID.
+pub fn houses(relation: &OsmRelation) -> impl Iterator<Item = (&'static str, i64)> + '_ {
+    relation
+        .members
+        .iter()
+        .filter(|member| member.role == "house")
+        .map(|member| {
+            let kind = match member.member_type {
+                MemberType::Node => "node",
+                MemberType::Way => "way",
+                MemberType::Relation => "relation",
+            };
+            (kind, member.id)
+        })
+}
+
+/// Fill the fields an address lacks from an `associatedStreet` relation.
+pub fn complete(address: &mut Map<String, Value>, relation: &OsmRelation) {
+    for (field, key) in FIELDS {
+        let value = match field {
+            "street" => tag(&relation.tags, "name").or_else(|| tag(&relation.tags, key)),
+            _ => tag(&relation.tags, key),
+        };
+        if let (Some(value), Some(slot)) = (value, address.get_mut(field)) {
+            if slot.is_null() {
+                *slot = json!(value);
+            }
+        }
+    }
+}
+
+fn tag<'a>(tags: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|(tag, _)| tag == key)
+        .map(|(_, value)| value.as_str())
+}
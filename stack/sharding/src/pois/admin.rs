@@ -0,0 +1,280 @@
+//! Admin areas: the `admin-boundaries` subcommand, which writes the administrative
+//! boundaries of the input as GeoJSON, and the reader with which `extract-pois
+//! --admin-boundaries` gives each POI the `country_code`, `region` and `city` around it.
+//!
+//! Boundaries are `boundary=administrative` relations, assembled from their member ways like
+//! multipolygons. They reach far beyond any shard, so the input is read three times: for the
+//! relations, for their ways, then for the ways' nodes (or the nodes come from a node
+//! cache). A boundaries file from elsewhere works too, as GeoJSON polygons with
+//! `admin_level`, `name` and, for countries, `country_code` or `ISO3166-1:alpha2`
+//! properties.
+
+use anyhow::{Context, Result};
+use clap::Args;
+use hashbrown::{HashMap, HashSet};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tempfile::NamedTempFile;
+use tracing::{info, info_span, warn};
+
+use super::shape::{self, Shape};
+use super::{decimicro, positions, rings, Locations};
+use crate::input::{self, InputFormat, OsmElement, OsmRelation};
+use crate::node_cache::NodeCache;
+use crate::store::Store;
+use crate::tally::BBox;
+use crate::task;
+
+/// Object name for `--s3-key-template` when the output is a bucket or prefix.
+const NAME: &str = "admin-boundaries.geojson";
+
+/// Tags that hold a country's ISO 3166-1 code, in order of preference.
+const COUNTRY_CODE_KEYS: [&str; 3] = ["country_code", "ISO3166-1:alpha2", "ISO3166-1"];
+
+#[derive(Args, Debug)]
+pub struct AdminBoundariesArgs {
+    /// Planet, or an extract, whose boundaries to write.
+    #[arg(env = "OSM_FILE")]
+    input: PathBuf,
+
+    /// Input encoding; `auto` picks it by extension.
+    #[arg(long, env = "INPUT_FORMAT", value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    /// Admin levels to write; 2 is countries.
+    #[arg(
+        long,
+        env = "ADMIN_LEVELS",
+        value_delimiter = ',',
+        default_value = "2,4,8"
+    )]
+    levels: Vec<u8>,
+
+    /// Where to write the boundaries: a local path or an object store URI. A bucket or
+    /// prefix gets its key from `--s3-key-template`, with `{name}` `admin-boundaries.geojson`.
+    #[arg(short, long, env = "ADMIN_BOUNDARIES")]
+    output: String,
+
+    /// Node cache written by `node-cache`, to place the boundaries' nodes from instead of
+    /// reading the input a third time.
+    #[arg(long, env = "NODE_CACHE")]
+    node_cache: Option<String>,
+}
+
+pub fn build(args: &AdminBoundariesArgs) -> Result<()> {
+    let format = args.input_format.resolve(&args.input)?;
+    let store = Store::open(&args.output, NAME)?;
+    if let Store::Local(path) = &store {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create {}", dir.display()))?;
+        }
+    }
+    let _build = info_span!("admin_boundaries").entered();
+    info!(
+        "Reading the administrative boundaries of {}...",
+        args.input.display()
+    );
+
+    // The boundary relations, then the nodes of each of their ways.
+    let mut relations: Vec<(u8, OsmRelation)> = Vec::new();
+    let mut ways: HashMap<i64, Vec<i64>> = HashMap::new();
+    input::open_source(&args.input, format)?.for_each_element(&mut |element| {
+        if let OsmElement::Relation(relation) = element {
+            if let Some(level) = boundary_level(&relation.tags, &args.levels) {
+                for member in &relation.members {
+                    if member.member_type == input::MemberType::Way {
+                        ways.insert(member.id, Vec::new());
+                    }
+                }
+                relations.push((level, relation));
+            }
+        }
+        Ok(())
+    })?;
+    info!(
+        relations = relations.len(),
+        ways = ways.len(),
+        "Found {} boundaries of {} ways.",
+        relations.len(),
+        ways.len()
+    );
+    input::open_source(&args.input, format)?.for_each_element(&mut |element| {
+        if let OsmElement::Way(way) = element {
+            if let Some(refs) = ways.get_mut(&way.id) {
+                *refs = way.refs;
+            }
+        }
+        Ok(())
+    })?;
+    let mut locations = match &args.node_cache {
+        Some(cache) => Locations::Cache(NodeCache::open(cache)?),
+        None => {
+            let needed: HashSet<i64> = ways.values().flatten().copied().collect();
+            let mut nodes = HashMap::with_capacity(needed.len());
+            input::open_source(&args.input, format)?.for_each_element(&mut |element| {
+                if let OsmElement::Node(node) = element {
+                    if needed.contains(&node.id) {
+                        nodes.insert(node.id, [decimicro(node.lon), decimicro(node.lat)]);
+                    }
+                }
+                Ok(())
+            })?;
+            Locations::Halo(nodes)
+        }
+    };
+
+    let file = NamedTempFile::new()?;
+    let mut out = BufWriter::new(file.reopen()?);
+    out.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[")?;
+    let (mut written, mut incomplete, mut empty) = (0u64, 0u64, 0u64);
+    for (level, relation) in &relations {
+        let area = rings::assemble(&relation.members, |way| match ways.get(&way) {
+            Some(refs) if !refs.is_empty() => positions(refs, &mut locations),
+            _ => Ok(None),
+        })?;
+        if area.polygons.is_empty() {
+            empty += 1;
+            continue;
+        }
+        incomplete += u64::from(!area.complete);
+        let tag = |key: &str| {
+            relation
+                .tags
+                .iter()
+                .find(|(tag, _)| tag == key)
+                .map(|(_, value)| value.as_str())
+        };
+        let country_code = COUNTRY_CODE_KEYS.iter().find_map(|key| tag(key));
+        let feature = json!({
+            "type": "Feature",
+            "id": format!("relation/{}", relation.id),
+            "geometry": {"type": "MultiPolygon", "coordinates": area.polygons},
+            "properties": {
+                "admin_level": level,
+                "name": tag("name"),
+                "country_code": country_code,
+            },
+        });
+        if written > 0 {
+            out.write_all(b",")?;
+        }
+        out.write_all(b"\n")?;
+        serde_json::to_writer(&mut out, &feature)?;
+        written += 1;
+    }
+    out.write_all(b"\n]}\n")?;
+    out.flush()?;
+    if incomplete > 0 || empty > 0 {
+        warn!(
+            incomplete,
+            empty,
+            "{incomplete} boundaries have member ways missing from the input or rings that do \
+             not close, and were written from the rings that did; {empty} had no ring at all \
+             and were left out."
+        );
+    }
+    store.put_file(file.path(), None)?;
+    task::record_output(store.to_string());
+    info!(
+        destination = %store,
+        boundaries = written,
+        "Wrote {written} boundaries to {store}."
+    );
+    Ok(())
+}
+
+/// The admin level of a boundary relation, if it is one of `levels`.
+fn boundary_level(tags: &[(String, String)], levels: &[u8]) -> Option<u8> {
+    let tag = |key: &str| {
+        tags.iter()
+            .find(|(tag, _)| tag == key)
+            .map(|(_, value)| value.as_str())
+    };
+    if tag("boundary") != Some("administrative")
+        || !matches!(tag("type"), Some("boundary" | "multipolygon"))
+    {
+        return None;
+    }
+    let level = tag("admin_level")?.trim().parse().ok()?;
+    levels.contains(&level).then_some(level)
+}
+
+/// The boundaries around the shards, to look up the admin areas of POIs in.
+pub struct AdminAreas {
+    boundaries: Vec<Boundary>,
+    region_level: u8,
+    city_level: u8,
+}
+
+impl AdminAreas {
+    /// Read the boundaries file at a local path or object store URI, keeping the
+    /// boundaries that reach into any of `within`.
+    pub fn read(location: &str, within: &[BBox], region_level: u8, city_level: u8) -> Result<Self> {
+        let levels = [2, region_level, city_level];
+        let mut boundaries = Vec::new();
+        for (properties, shape) in shape::read(location, "admin boundaries", within)? {
+            let level = match &properties["admin_level"] {
+                Value::Number(level) => level.as_u64(),
+                Value::String(level) => level.trim().parse().ok(),
+                _ => None,
+            };
+            let Some(level) = level.and_then(|level| u8::try_from(level).ok()) else {
+                continue;
+            };
+            if !levels.contains(&level) {
+                continue;
+            }
+            let text = |key: &str| properties[key].as_str().map(str::to_string);
+            boundaries.push(Boundary {
+                level,
+                name: text("name"),
+                country_code: COUNTRY_CODE_KEYS.iter().find_map(|key| text(key)),
+                shape,
+            });
+        }
+        info!(
+            boundaries = boundaries.len(),
+            "Placing POIs in {} admin boundaries around the shards.",
+            boundaries.len()
+        );
+        Ok(Self {
+            boundaries,
+            region_level,
+            city_level,
+        })
+    }
+
+    /// Add the `country_code`, `region` and `city` of a point to a POI's properties, each
+    /// null outside any boundary of its level. Of overlapping boundaries, the first in the
+    /// file wins.
+    pub fn insert(&self, [lon, lat]: [f64; 2], properties: &mut Map<String, Value>) {
+        let (mut country_code, mut region, mut city) = (None, None, None);
+        for boundary in &self.boundaries {
+            let slot = match boundary.level {
+                2 if country_code.is_none() => &mut country_code,
+                level if level == self.region_level && region.is_none() => &mut region,
+                level if level == self.city_level && city.is_none() => &mut city,
+                _ => continue,
+            };
+            if boundary.shape.contains(lon, lat) {
+                *slot = match boundary.level {
+                    2 => boundary.country_code.as_deref(),
+                    _ => boundary.name.as_deref(),
+                };
+            }
+        }
+        properties.insert("country_code".into(), json!(country_code));
+        properties.insert("region".into(), json!(region));
+        properties.insert("city".into(), json!(city));
+    }
+}
+
+struct Boundary {
+    level: u8,
+    name: Option<String>,
+    country_code: Option<String>,
+    shape: Shape,
+}
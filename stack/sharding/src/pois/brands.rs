@@ -0,0 +1,126 @@
+//! Brands from the Name Suggestion Index (NSI): a POI matches an NSI brand by its
+//! `brand:wikidata`, or by its `brand` or `name` among the brand's names together with the
+//! brand's category tag (`amenity=cafe` for `brands/amenity/cafe`), and gets the brand's
+//! NSI ID, name and Wikidata item. The data is NSI's `dist/nsi.json`; location sets are not
+//! checked, so of two brands with the same name and category the first listed wins.
+
+use anyhow::Result;
+use hashbrown::HashMap;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+#[derive(Deserialize)]
+struct NsiFile {
+    /// Sorted, so which of two clashing brands wins does not depend on hashing.
+    nsi: BTreeMap<String, NsiCategory>,
+}
+
+#[derive(Deserialize)]
+struct NsiCategory {
+    items: Vec<NsiItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NsiItem {
+    id: String,
+    display_name: String,
+    #[serde(default)]
+    match_names: Vec<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+struct Brand {
+    id: String,
+    name: String,
+    wikidata: Option<String>,
+}
+
+pub struct Brands {
+    brands: Vec<Brand>,
+    by_wikidata: HashMap<String, usize>,
+    /// By category key, value and simplified name.
+    by_name: HashMap<(String, String, String), usize>,
+}
+
+impl Brands {
+    pub fn parse(json: &str) -> Result<Self> {
+        let file: NsiFile = serde_json::from_str(json)?;
+        let mut brands = Self {
+            brands: Vec::new(),
+            by_wikidata: HashMap::new(),
+            by_name: HashMap::new(),
+        };
+        for (path, category) in file.nsi {
+            let mut parts = path.split('/');
+            let (Some("brands"), Some(key), Some(value)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            for item in category.items {
+                let idx = brands.brands.len();
+                let wikidata = item.tags.get("brand:wikidata").cloned();
+                if let Some(wikidata) = &wikidata {
+                    brands.by_wikidata.entry(wikidata.clone()).or_insert(idx);
+                }
+                let names = [&item.display_name]
+                    .into_iter()
+                    .chain(item.tags.get("brand"))
+                    .chain(item.tags.get("name"))
+                    .chain(&item.match_names);
+                for name in names {
+                    let name = simplify(name);
+                    let entry = (key.to_string(), value.to_string(), name);
+                    brands.by_name.entry(entry).or_insert(idx);
+                }
+                brands.brands.push(Brand {
+                    id: item.id,
+                    name: item.tags.get("brand").cloned().unwrap_or(item.display_name),
+                    wikidata,
+                });
+            }
+        }
+        Ok(brands)
+    }
+
+    pub fn len(&self) -> usize {
+        self.brands.len()
+    }
+
+    /// The `brand` property: the matching brand's `id`, `name` and `wikidata`, or null.
+    pub fn brand(&self, tags: &[(String, String)]) -> Value {
+        let tag = |key: &str| {
+            tags.iter()
+                .find(|(tag, _)| tag == key)
+                .map(|(_, value)| value.as_str())
+        };
+        let by_wikidata = || self.by_wikidata.get(tag("brand:wikidata")?).copied();
+        let by_name = || {
+            let names = [tag("brand"), tag("name")].into_iter().flatten();
+            names.map(simplify).find_map(|name| {
+                tags.iter().find_map(|(key, value)| {
+                    let entry = (key.clone(), value.clone(), name.clone());
+                    self.by_name.get(&entry).copied()
+                })
+            })
+        };
+        match by_wikidata().or_else(by_name) {
+            Some(idx) => {
+                let brand = &self.brands[idx];
+                json!({"id": brand.id, "name": brand.name, "wikidata": brand.wikidata})
+            }
+            None => Value::Null,
+        }
+    }
+}
+
+/// A name in lowercase letters and digits only, so `McDonald's` matches `mcdonalds`.
+fn simplify(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
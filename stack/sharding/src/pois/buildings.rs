@@ -0,0 +1,106 @@
+//! Building footprints for `--link-buildings`: a node POI inside a closed `building` way
+//! gets the way's ID and area as `building_ref` and `building_area`.
+//!
+//! Nodes come before ways in the input, so node POIs are held back, their points indexed
+//! on a grid, and each building way is matched against the points around it as it is read;
+//! buildings themselves are never kept. Of nested buildings, a POI takes the smallest.
+
+use hashbrown::HashMap;
+use serde_json::{json, Map, Value};
+
+use super::{rings, METERS_PER_DEGREE};
+
+/// Size of the grid cells node POIs are indexed in, in degrees: about 100 m.
+const CELL_DEGREES: f64 = 0.001;
+
+/// A held-back node POI: its index and point.
+type Poi = (usize, [f64; 2]);
+
+#[derive(Default)]
+pub struct Buildings {
+    /// Held-back node POIs by grid cell.
+    cells: HashMap<(i32, i32), Vec<Poi>>,
+    /// The smallest building found so far around each POI: its way ID and area.
+    found: HashMap<usize, (i64, f64)>,
+}
+
+impl Buildings {
+    /// Whether a way's tags make it a building.
+    pub fn is_building(tags: &[(String, String)]) -> bool {
+        tags.iter()
+            .any(|(key, value)| key == "building" && value != "no")
+    }
+
+    /// Add a node POI, by its index among those held back.
+    pub fn push(&mut self, idx: usize, point: [f64; 2]) {
+        self.cells
+            .entry(cell(point))
+            .or_default()
+            .push((idx, point));
+    }
+
+    /// Match a closed building way, from the positions of its nodes, to the POIs in it.
+    pub fn way(&mut self, id: i64, ring: &[[f64; 2]]) {
+        let (mut west, mut south) = (f64::INFINITY, f64::INFINITY);
+        let (mut east, mut north) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for &[lon, lat] in ring {
+            (west, south) = (west.min(lon), south.min(lat));
+            (east, north) = (east.max(lon), north.max(lat));
+        }
+        let (min, max) = (cell([west, south]), cell([east, north]));
+        let mut area = None;
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                let Some(pois) = self.cells.get(&(x, y)) else {
+                    continue;
+                };
+                for &(idx, [lon, lat]) in pois {
+                    let within = (west..=east).contains(&lon) && (south..=north).contains(&lat);
+                    if !within || !rings::contains(ring, [lon, lat]) {
+                        continue;
+                    }
+                    let area = *area.get_or_insert_with(|| area_m2(ring));
+                    let found = self.found.entry(idx).or_insert((id, area));
+                    if area < found.1 {
+                        *found = (id, area);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Add `building_ref` and `building_area` to a held-back node POI, null outside any
+    /// building.
+    pub fn insert(&self, idx: usize, properties: &mut Map<String, Value>) {
+        let found = self.found.get(&idx);
+        properties.insert(
+            "building_ref".into(),
+            json!(found.map(|(id, _)| format!("way/{id}"))),
+        );
+        properties.insert(
+            "building_area".into(),
+            json!(found.map(|(_, area)| (area * 10.0).round() / 10.0)),
+        );
+    }
+}
+
+fn cell([lon, lat]: [f64; 2]) -> (i32, i32) {
+    (
+        (lon / CELL_DEGREES).floor() as i32,
+        (lat / CELL_DEGREES).floor() as i32,
+    )
+}
+
+/// Area of a closed ring in square metres, with degrees scaled to metres at its latitude.
+fn area_m2(ring: &[[f64; 2]]) -> f64 {
+    // Relative to the first position, so small rings far from the origin keep precision.
+    let [x, y] = ring[0];
+    let degrees = ring
+        .windows(2)
+        .map(|pair| (pair[0][0] - x) * (pair[1][1] - y) - (pair[1][0] - x) * (pair[0][1] - y))
+        .sum::<f64>()
+        .abs()
+        / 2.0;
+    let lat = ring.iter().map(|position| position[1]).sum::<f64>() / ring.len() as f64;
+    degrees * METERS_PER_DEGREE * METERS_PER_DEGREE * lat.to_radians().cos()
+}
@@ -0,0 +1,280 @@
+//! Conflation for `merge --conflate-meters`: POIs of the same category within a distance
+//! of each other and with similar names are taken for one place mapped twice, most often
+//! as a node and as the building around it. Each cluster of them is kept as the POI with
+//! the most tags, with the IDs of the others as its `duplicates`; POIs without a name are
+//! never conflated.
+//!
+//! POIs are bucketed by H3 cell, each in the bucket of its own cell and, as a guest, in
+//! the buckets of the neighbouring cells it may be near. A cell is at least twice the
+//! distance across, so the POIs near one are in it or its ring of neighbours. The buckets
+//! are sorted on disk like the POIs themselves, so only the matches are held in memory.
+
+use anyhow::{bail, Result};
+use h3o::{CellIndex, LatLng, Resolution};
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::sort::Sorter;
+
+/// Bucket cells are this many resolutions coarser than the cells POIs are compared in.
+const BUCKET_LEVELS: u8 = 3;
+
+/// A POI as far as conflation is concerned.
+#[derive(Serialize, Deserialize)]
+struct Candidate {
+    /// In the bucket of its own cell, rather than as a guest.
+    home: bool,
+    key: u64,
+    id: String,
+    lon: f64,
+    lat: f64,
+    category: Option<String>,
+    /// Lowercase words of the name.
+    words: Vec<String>,
+    /// Tags the POI has.
+    tags: usize,
+}
+
+pub struct Conflator {
+    meters: f64,
+    similarity: f64,
+    /// Resolution of the cells POIs are compared in.
+    resolution: Resolution,
+    buckets: Sorter,
+}
+
+impl Conflator {
+    /// Conflate POIs within `meters` whose names are at least `similarity` (0 to 1) alike,
+    /// sorting with up to `memory_limit` bytes.
+    pub fn new(meters: f64, similarity: f64, memory_limit: u64) -> Result<Self> {
+        if !meters.is_finite() || meters <= 0.0 {
+            bail!("--conflate-meters must be positive");
+        }
+        // The finest resolution whose cells are at least twice `meters` across.
+        let resolution = Resolution::range(Resolution::Zero, Resolution::Fifteen)
+            .take_while(|resolution| resolution.edge_length_m() >= 2.0 * meters)
+            .last()
+            .unwrap_or(Resolution::Zero);
+        Ok(Self {
+            meters,
+            similarity,
+            resolution,
+            buckets: Sorter::new(memory_limit),
+        })
+    }
+
+    /// Add a POI, with its sort key by ID.
+    pub fn push(&mut self, key: u64, feature: &Value) -> Result<()> {
+        let properties = &feature["properties"];
+        let Some(name) = properties["name"].as_str() else {
+            return Ok(());
+        };
+        let coordinates = &feature["geometry"]["coordinates"];
+        let (Some(lon), Some(lat)) = (coordinates[0].as_f64(), coordinates[1].as_f64()) else {
+            return Ok(());
+        };
+        let Ok(point) = LatLng::new(lat, lon) else {
+            return Ok(());
+        };
+        let cell = point.to_cell(self.resolution);
+        let bucket_resolution =
+            Resolution::try_from(u8::from(self.resolution).saturating_sub(BUCKET_LEVELS))?;
+        let bucket = |cell: CellIndex| cell.parent(bucket_resolution).expect("coarser");
+        let home = bucket(cell);
+        let mut candidate = Candidate {
+            home: true,
+            key,
+            id: feature["id"].as_str().unwrap_or_default().to_string(),
+            lon,
+            lat,
+            category: properties["category"].as_str().map(str::to_string),
+            words: words(name),
+            tags: properties["tags"].as_object().map_or(0, |tags| {
+                tags.values().filter(|value| !value.is_null()).count()
+            }),
+        };
+        self.buckets
+            .push((u64::from(home), key), serde_json::to_vec(&candidate)?)?;
+        candidate.home = false;
+        let guests: HashSet<CellIndex> = cell
+            .grid_disk::<Vec<_>>(1)
+            .into_iter()
+            .map(bucket)
+            .filter(|&guest| guest != home)
+            .collect();
+        for guest in guests {
+            self.buckets
+                .push((u64::from(guest), key), serde_json::to_vec(&candidate)?)?;
+        }
+        Ok(())
+    }
+
+    /// Find the clusters.
+    pub fn finish(self) -> Result<Clusters> {
+        let mut links = UnionFind::default();
+        // Tags and ID of every POI in a cluster.
+        let mut members: HashMap<u64, (usize, String)> = HashMap::new();
+        let Self {
+            meters,
+            similarity: threshold,
+            resolution,
+            buckets,
+        } = self;
+        // Same category, near enough and alike enough.
+        let matches = |a: &Candidate, b: &Candidate| {
+            let distance = LatLng::new(a.lat, a.lon)
+                .and_then(|pa| Ok(pa.distance_m(LatLng::new(b.lat, b.lon)?)))
+                .unwrap_or(f64::INFINITY);
+            a.category == b.category
+                && distance <= meters
+                && similarity(&a.words, &b.words) >= threshold
+        };
+        let mut sorted = buckets.finish()?.peekable();
+        while let Some(record) = sorted.next() {
+            let ((bucket, _), record) = record?;
+            let mut candidates: Vec<Candidate> = vec![serde_json::from_slice(&record)?];
+            while sorted
+                .peek()
+                .is_some_and(|next| matches!(next, Ok(((next, _), _)) if *next == bucket))
+            {
+                let (_, record) = sorted.next().expect("peeked a record")?;
+                candidates.push(serde_json::from_slice(&record)?);
+            }
+
+            let mut cells: HashMap<CellIndex, Vec<usize>> = HashMap::new();
+            for (idx, candidate) in candidates.iter().enumerate() {
+                let point = LatLng::new(candidate.lat, candidate.lon)?;
+                cells
+                    .entry(point.to_cell(resolution))
+                    .or_default()
+                    .push(idx);
+            }
+            for a in candidates.iter().filter(|candidate| candidate.home) {
+                let point = LatLng::new(a.lat, a.lon)?;
+                for cell in point.to_cell(resolution).grid_disk::<Vec<_>>(1) {
+                    for &idx in cells.get(&cell).into_iter().flatten() {
+                        let b = &candidates[idx];
+                        // Pairs of POIs at home here are compared once, pairs with a guest
+                        // in the guest's bucket too.
+                        if (b.home && b.key <= a.key) || b.key == a.key {
+                            continue;
+                        }
+                        if matches(a, b) {
+                            links.union(a.key, b.key);
+                            for candidate in [a, b] {
+                                members
+                                    .entry(candidate.key)
+                                    .or_insert_with(|| (candidate.tags, candidate.id.clone()));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut clusters: HashMap<u64, Vec<u64>> = HashMap::new();
+        for &key in members.keys() {
+            clusters.entry(links.find(key)).or_default().push(key);
+        }
+        let mut result = Clusters::default();
+        for (_, mut keys) in clusters {
+            keys.sort_unstable();
+            // The most tags, then the lowest ID.
+            let canonical = *keys
+                .iter()
+                .max_by_key(|key| (members[*key].0, std::cmp::Reverse(**key)))
+                .expect("a cluster has members");
+            let duplicates = keys
+                .iter()
+                .filter(|&&key| key != canonical)
+                .map(|key| members[key].1.clone())
+                .collect();
+            result
+                .merged
+                .extend(keys.iter().filter(|&&key| key != canonical));
+            result.duplicates.insert(canonical, duplicates);
+        }
+        Ok(result)
+    }
+}
+
+/// The clusters found, by the sort keys of their POIs.
+#[derive(Default)]
+pub struct Clusters {
+    /// POIs conflated into another.
+    pub merged: HashSet<u64>,
+    /// IDs of the POIs conflated into each POI kept.
+    pub duplicates: HashMap<u64, Vec<String>>,
+}
+
+/// Clusters of POI keys, as the parent of each key that is not its cluster's root.
+#[derive(Default)]
+struct UnionFind {
+    parents: HashMap<u64, u64>,
+}
+
+impl UnionFind {
+    fn find(&mut self, key: u64) -> u64 {
+        let mut root = key;
+        while let Some(&parent) = self.parents.get(&root) {
+            root = parent;
+        }
+        // Point the path straight at the root.
+        let mut node = key;
+        while node != root {
+            let parent = self.parents[&node];
+            self.parents.insert(node, root);
+            node = parent;
+        }
+        root
+    }
+
+    fn union(&mut self, a: u64, b: u64) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents.insert(a.max(b), a.min(b));
+        }
+    }
+}
+
+/// The lowercase words of a name, without punctuation: `Joe's Café` is `joe`, `s`, `café`.
+fn words(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// How alike two names are, from 0 to 1: the better of one minus their edit distance
+/// relative to the longer one, and the share of their words they have in common.
+fn similarity(a: &[String], b: &[String]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let (joined_a, joined_b) = (a.join(" "), b.join(" "));
+    let (chars_a, chars_b): (Vec<char>, Vec<char>) =
+        (joined_a.chars().collect(), joined_b.chars().collect());
+    let longest = chars_a.len().max(chars_b.len());
+    let edits = 1.0 - levenshtein(&chars_a, &chars_b) as f64 / longest as f64;
+
+    let set_a: HashSet<&String> = a.iter().collect();
+    let set_b: HashSet<&String> = b.iter().collect();
+    let shared = set_a.intersection(&set_b).count();
+    let words = shared as f64 / (set_a.len() + set_b.len() - shared) as f64;
+    edits.max(words)
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,222 @@
+//! Contact details of POIs: the `phone`, `website` and `email` tags merged with their
+//! `contact:*` twins into one `contact` object of lists, each value cleaned up. Phone
+//! numbers become E.164 (`+4930123456`) where the country is known, from the number itself,
+//! the POI's `addr:country` or `--phone-country`; websites get a scheme, a lowercase host
+//! and no trailing slash after the host; emails lose `mailto:` and get a lowercase domain.
+//! Values that cannot be cleaned up are kept as they are.
+
+use serde_json::{json, Map, Value};
+use url::Url;
+
+/// Country calling codes by ISO 3166-1 code.
+const CALLING_CODES: &str = "AD376 AE971 AF93 AG1 AI1 AL355 AM374 AO244 AR54 AS1 AT43 AU61 \
+    AW297 AX358 AZ994 BA387 BB1 BD880 BE32 BF226 BG359 BH973 BI257 BJ229 BM1 BN673 BO591 \
+    BR55 BS1 BT975 BW267 BY375 BZ501 CA1 CD243 CF236 CG242 CH41 CI225 CK682 CL56 CM237 CN86 \
+    CO57 CR506 CU53 CV238 CW599 CY357 CZ420 DE49 DJ253 DK45 DM1 DO1 DZ213 EC593 EE372 EG20 \
+    ER291 ES34 ET251 FI358 FJ679 FM691 FO298 FR33 GA241 GB44 GD1 GE995 GF594 GG44 GH233 \
+    GI350 GL299 GM220 GN224 GP590 GQ240 GR30 GT502 GU1 GW245 GY592 HK852 HN504 HR385 HT509 \
+    HU36 ID62 IE353 IL972 IM44 IN91 IQ964 IR98 IS354 IT39 JE44 JM1 JO962 JP81 KE254 KG996 \
+    KH855 KI686 KM269 KN1 KP850 KR82 KW965 KY1 KZ7 LA856 LB961 LC1 LI423 LK94 LR231 LS266 \
+    LT370 LU352 LV371 LY218 MA212 MC377 MD373 ME382 MG261 MH692 MK389 ML223 MM95 MN976 \
+    MO853 MQ596 MR222 MS1 MT356 MU230 MV960 MW265 MX52 MY60 MZ258 NA264 NC687 NE227 NG234 \
+    NI505 NL31 NO47 NP977 NR674 NZ64 OM968 PA507 PE51 PF689 PG675 PH63 PK92 PL48 PM508 PR1 \
+    PS970 PT351 PW680 PY595 QA974 RE262 RO40 RS381 RU7 RW250 SA966 SB677 SC248 SD249 SE46 \
+    SG65 SI386 SK421 SL232 SM378 SN221 SO252 SR597 SS211 ST239 SV503 SY963 SZ268 TC1 TD235 \
+    TG228 TH66 TJ992 TL670 TM993 TN216 TO676 TR90 TT1 TV688 TW886 TZ255 UA380 UG256 US1 \
+    UY598 UZ998 VA39 VC1 VE58 VG1 VI1 VN84 VU678 WS685 XK383 YE967 YT262 ZA27 ZM260 ZW263";
+
+pub struct Contact {
+    /// Country of phone numbers written without a country code, for POIs without
+    /// `addr:country`.
+    pub phone_country: Option<String>,
+}
+
+impl Contact {
+    /// Add the `contact` property, with `phone`, `website` and `email` lists, empty when
+    /// the POI has none.
+    pub fn insert(&self, properties: &mut Map<String, Value>, tags: &[(String, String)]) {
+        let country = tags
+            .iter()
+            .find(|(key, _)| key == "addr:country")
+            .map(|(_, country)| country.as_str())
+            .or(self.phone_country.as_deref());
+        let phone = values(tags, "phone", |phone| normalize_phone(phone, country));
+        let website = values(tags, "website", normalize_url);
+        let email = values(tags, "email", normalize_email);
+        properties.insert(
+            "contact".into(),
+            json!({"phone": phone, "website": website, "email": email}),
+        );
+    }
+}
+
+/// The values of `key` and `contact:key`, split on `;`, cleaned up and without duplicates.
+fn values(tags: &[(String, String)], key: &str, normalize: impl Fn(&str) -> String) -> Vec<String> {
+    let prefixed = format!("contact:{key}");
+    let mut values: Vec<String> = Vec::new();
+    for wanted in [key, prefixed.as_str()] {
+        let raw = tags.iter().filter(|(tag, _)| tag == wanted);
+        for value in raw.flat_map(|(_, value)| value.split(';')) {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            let value = normalize(value);
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+    }
+    values
+}
+
+/// A phone number in E.164, or as it is when it has an extension, letters or no known
+/// country code.
+fn normalize_phone(raw: &str, country: Option<&str>) -> String {
+    let number = raw.strip_prefix("tel:").unwrap_or(raw).trim();
+    // `+49 (0)30 ...`: the trunk prefix in brackets is not dialled from abroad.
+    let number = number.replace("(0)", "");
+    let plain = number
+        .chars()
+        .all(|c| c.is_ascii_digit() || " -./()\u{a0}".contains(c) || c == '+');
+    let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+    if !plain || number.rfind('+').is_some_and(|idx| idx != 0) {
+        return raw.to_string();
+    }
+    let international = if number.starts_with('+') {
+        Some(digits)
+    } else if let Some(rest) = digits.strip_prefix("00") {
+        Some(rest.to_string())
+    } else {
+        country.and_then(|country| national(&digits, &country.to_ascii_uppercase()))
+    };
+    match international {
+        Some(digits) if (8..=15).contains(&digits.len()) => format!("+{digits}"),
+        _ => raw.to_string(),
+    }
+}
+
+/// The digits of a national number with its country code, without the trunk prefix.
+fn national(digits: &str, country: &str) -> Option<String> {
+    let code = CALLING_CODES.split_whitespace().find_map(|entry| {
+        entry
+            .strip_prefix(country)
+            .filter(|code| code.starts_with(|c: char| c.is_ascii_digit()))
+    })?;
+    let trunk = match (country, code) {
+        ("IT" | "SM" | "VA", _) => "",
+        ("RU" | "KZ" | "BY" | "LT", _) => "8",
+        ("HU", _) => "06",
+        (_, "1") => "1",
+        _ => "0",
+    };
+    let subscriber = match digits.strip_prefix(trunk) {
+        Some(rest) if !trunk.is_empty() => rest,
+        _ => digits,
+    };
+    Some(format!("{code}{subscriber}"))
+}
+
+/// A website with a scheme (`https` when it has none), a lowercase host and no slash after
+/// a bare host, or as it is when it is not an `http` or `https` URL, e.g. `mailto:` or `tel:`.
+fn normalize_url(raw: &str) -> String {
+    let url = match Url::parse(raw) {
+        // `example.com:8080` parses with `example.com` as its scheme; real schemes have no dot.
+        Ok(url) if !url.scheme().contains('.') => Ok(url),
+        Ok(_) | Err(url::ParseError::RelativeUrlWithoutBase) => {
+            Url::parse(&format!("https://{raw}"))
+        }
+        Err(err) => Err(err),
+    };
+    let Ok(url) = url else {
+        return raw.to_string();
+    };
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return raw.to_string();
+    }
+    let mut url = url.to_string();
+    if url.ends_with('/') && url.matches('/').count() == 3 {
+        url.pop();
+    }
+    url
+}
+
+/// An email address without `mailto:` and with a lowercase domain.
+fn normalize_email(raw: &str) -> String {
+    let email = raw.strip_prefix("mailto:").unwrap_or(raw).trim();
+    match email.rsplit_once('@') {
+        Some((local, domain)) if !local.is_empty() && domain.contains('.') => {
+            format!("{local}@{}", domain.to_lowercase())
+        }
+        _ => raw.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn websites_get_a_scheme_and_a_lowercase_host() {
+        assert_eq!(normalize_url("www.Example.com/"), "https://www.example.com");
+        assert_eq!(
+            normalize_url("Example.com/Menu"),
+            "https://example.com/Menu"
+        );
+        assert_eq!(
+            normalize_url("example.com:8080"),
+            "https://example.com:8080"
+        );
+        assert_eq!(normalize_url("HTTP://Example.com"), "http://example.com");
+        assert_eq!(
+            normalize_url("https://example.com/a/?q=1"),
+            "https://example.com/a/?q=1"
+        );
+    }
+
+    #[test]
+    fn other_schemes_are_kept_as_they_are() {
+        for raw in [
+            "mailto:info@example.com",
+            "tel:+4930123456",
+            "ftp://files.example.com",
+            "not a website",
+        ] {
+            assert_eq!(normalize_url(raw), raw);
+        }
+    }
+
+    #[test]
+    fn phone_numbers_become_e164_where_the_country_is_known() {
+        assert_eq!(normalize_phone("+49 (0)30 123456", None), "+4930123456");
+        assert_eq!(normalize_phone("0049 30 123456", None), "+4930123456");
+        assert_eq!(normalize_phone("tel:030/123456", Some("de")), "+4930123456");
+        assert_eq!(normalize_phone("06 1234 5678", Some("HU")), "+3612345678");
+        assert_eq!(normalize_phone("06 1234 5678", Some("IT")), "+390612345678");
+        assert_eq!(
+            normalize_phone("(555) 123-4567", Some("US")),
+            "+15551234567"
+        );
+        // No known country, an extension or letters: kept as it is.
+        assert_eq!(normalize_phone("030 123456", None), "030 123456");
+        assert_eq!(normalize_phone("030 123456", Some("XX")), "030 123456");
+        assert_eq!(
+            normalize_phone("+49 30 123456 ext 7", None),
+            "+49 30 123456 ext 7"
+        );
+        assert_eq!(normalize_phone("0800-FLOWERS", Some("US")), "0800-FLOWERS");
+        assert_eq!(normalize_phone("+49 12", None), "+49 12");
+    }
+
+    #[test]
+    fn emails_lose_mailto_and_get_a_lowercase_domain() {
+        assert_eq!(
+            normalize_email("mailto:Info@Example.COM"),
+            "Info@example.com"
+        );
+        assert_eq!(normalize_email("info@example.com"), "info@example.com");
+        assert_eq!(normalize_email("info@localhost"), "info@localhost");
+        assert_eq!(normalize_email("@example.com"), "@example.com");
+        assert_eq!(normalize_email("not an email"), "not an email");
+    }
+}
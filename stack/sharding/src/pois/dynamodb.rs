@@ -0,0 +1,267 @@
+//! DynamoDB POIs for `--sink dynamodb`: each POI an item keyed for nearby lookups, with
+//! the geohash of its point cut to `--dynamodb-geohash-precision` as partition key `pk` and
+//! its ID (`node/123`) as sort key `sk`, so the POIs around a point are the queries of its
+//! geohash cell and the cells next to it. Items also hold the full `geohash`, `lat`, `lon`
+//! and every non-null property, objects and arrays as maps and lists.
+//!
+//! Items are written with `BatchWriteItem` through `aws`, 25 at a time, so a POI extracted
+//! again is overwritten; POIs since deleted from OSM stay until the table is replaced.
+//! Items the table throttles come back unprocessed and are retried with a backoff, and
+//! `--dynamodb-max-wcu` paces the writes to stay under a share of the table's capacity.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tracing::warn;
+
+use super::output::Feature;
+use crate::registry::attribute;
+use crate::store::run_cli;
+
+/// Most items in a `BatchWriteItem` request.
+const BATCH: usize = 25;
+/// Precision of the `geohash` attribute: cells of about 5 × 5 metres.
+const GEOHASH_PRECISION: usize = 9;
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+/// Attempts of a batch, throttled requests and unprocessed items alike, before giving up.
+const MAX_ATTEMPTS: u32 = 10;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+/// Longest wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// Errors of a request DynamoDB turned away for capacity, which are worth retrying.
+const THROTTLED: [&str; 3] = [
+    "ProvisionedThroughputExceededException",
+    "ThrottlingException",
+    "RequestLimitExceeded",
+];
+
+/// A shard's put requests while they are written, one per line.
+pub struct Items {
+    file: NamedTempFile,
+    out: BufWriter<File>,
+    precision: usize,
+    count: u64,
+}
+
+impl Items {
+    pub fn new(precision: u8) -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let out = BufWriter::new(file.reopen()?);
+        Ok(Self {
+            file,
+            out,
+            precision: usize::from(precision),
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let coordinates = &feature.geometry["coordinates"];
+        let (Some(lon), Some(lat)) = (coordinates[0].as_f64(), coordinates[1].as_f64()) else {
+            return Ok(());
+        };
+        let geohash = geohash(lon, lat, GEOHASH_PRECISION);
+        let mut item: Map<String, Value> = feature
+            .properties
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key.clone(), attribute(value.clone())))
+            .collect();
+        item.insert("pk".into(), json!({"S": &geohash[..self.precision]}));
+        item.insert("sk".into(), json!({ "S": feature.id }));
+        item.insert("lat".into(), json!({"N": lat.to_string()}));
+        item.insert("lon".into(), json!({"N": lon.to_string()}));
+        item.insert("geohash".into(), json!({ "S": geohash }));
+        serde_json::to_writer(&mut self.out, &json!({"PutRequest": {"Item": item}}))?;
+        self.out.write_all(b"\n")?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        self.out.flush()?;
+        Ok((self.file, self.count))
+    }
+}
+
+/// Geohash of a point with `precision` characters.
+pub(super) fn geohash(lon: f64, lat: f64, precision: usize) -> String {
+    let (mut lon_range, mut lat_range) = ((-180.0, 180.0), (-90.0, 90.0));
+    let mut hash = String::with_capacity(precision);
+    let (mut bits, mut value, mut even) = (0, 0, true);
+    while hash.len() < precision {
+        let (range, coordinate) = match even {
+            true => (&mut lon_range, lon),
+            false => (&mut lat_range, lat),
+        };
+        let middle = (range.0 + range.1) / 2.0;
+        value <<= 1;
+        if coordinate >= middle {
+            value |= 1;
+            range.0 = middle;
+        } else {
+            range.1 = middle;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(char::from(GEOHASH_ALPHABET[value]));
+            (bits, value) = (0, 0);
+        }
+    }
+    hash
+}
+
+/// Writes shards' items to a table, pacing them under a write capacity ceiling across all
+/// of them.
+pub struct Loader {
+    table: String,
+    max_wcu: Option<u32>,
+    /// When the first batch was written.
+    started: Option<Instant>,
+    /// Write capacity units used so far, by the items' estimated sizes.
+    units: u64,
+}
+
+impl Loader {
+    /// Check that `table` exists with the `pk` and `sk` string keys, so a run fails before
+    /// reading its input rather than at its first shard.
+    pub fn new(table: &str, max_wcu: Option<u32>) -> Result<Self> {
+        let args = [
+            "dynamodb",
+            "describe-table",
+            "--output",
+            "json",
+            "--table-name",
+            table,
+        ];
+        let stdout = match run_cli("aws", &args)? {
+            Ok(stdout) => stdout,
+            Err(stderr) => bail!("unable to describe DynamoDB table {table}: {stderr}"),
+        };
+        let response: Value =
+            serde_json::from_slice(&stdout).context("unexpected describe-table response")?;
+        let description = &response["Table"];
+        let key = |name: &str, kind: &str| {
+            let schema = description["KeySchema"].as_array();
+            let in_schema = schema.is_some_and(|schema| {
+                schema.contains(&json!({"AttributeName": name, "KeyType": kind}))
+            });
+            let definitions = description["AttributeDefinitions"].as_array();
+            in_schema
+                && definitions.is_some_and(|definitions| {
+                    definitions.contains(&json!({"AttributeName": name, "AttributeType": "S"}))
+                })
+        };
+        if !key("pk", "HASH") || !key("sk", "RANGE") {
+            bail!(
+                "DynamoDB table {table} needs a string partition key pk and a string sort \
+                 key sk"
+            );
+        }
+        Ok(Self {
+            table: table.to_string(),
+            max_wcu,
+            started: None,
+            units: 0,
+        })
+    }
+
+    /// Write the items of a shard's file.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let mut batch = Vec::with_capacity(BATCH);
+        for line in BufReader::new(File::open(path)?).lines() {
+            batch.push(serde_json::from_str(&line?)?);
+            if batch.len() == BATCH {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        if !batch.is_empty() {
+            self.write_batch(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Write a batch, retrying what the table throttles until all of it is written.
+    fn write_batch(&mut self, mut requests: Vec<Value>) -> Result<()> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            self.pace(&requests);
+            let mut body = NamedTempFile::new()?;
+            let mut items = Map::new();
+            items.insert(self.table.clone(), Value::Array(requests));
+            serde_json::to_writer(&mut body, &items)?;
+            body.flush()?;
+            let path = format!("file://{}", body.path().display());
+            let args = [
+                "dynamodb",
+                "batch-write-item",
+                "--output",
+                "json",
+                "--request-items",
+                &path,
+            ];
+            match run_cli("aws", &args)? {
+                Ok(stdout) => {
+                    let mut response: Value = serde_json::from_slice(&stdout)
+                        .context("unexpected batch-write-item response")?;
+                    requests = match response["UnprocessedItems"][&self.table].take() {
+                        Value::Array(unprocessed) => unprocessed,
+                        _ => Vec::new(),
+                    };
+                    if requests.is_empty() {
+                        return Ok(());
+                    }
+                    if attempt == MAX_ATTEMPTS {
+                        bail!(
+                            "DynamoDB table {} still throttled {} items after {attempt} attempts",
+                            self.table,
+                            requests.len()
+                        );
+                    }
+                }
+                Err(stderr)
+                    if attempt < MAX_ATTEMPTS && THROTTLED.iter().any(|e| stderr.contains(e)) =>
+                {
+                    warn!(
+                        attempt,
+                        "Writes to DynamoDB table {} throttled (attempt {attempt} of \
+                         {MAX_ATTEMPTS}), retrying in {backoff:?}.",
+                        self.table
+                    );
+                    requests = match items.remove(&self.table) {
+                        Some(Value::Array(requests)) => requests,
+                        _ => unreachable!("the batch was just inserted"),
+                    };
+                }
+                Err(stderr) => bail!("unable to write to DynamoDB table {}: {stderr}", self.table),
+            }
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            attempt += 1;
+        }
+    }
+
+    /// Wait until writing `requests` keeps the average rate since the first write under
+    /// `--dynamodb-max-wcu`. Each item takes a unit per started KB, estimated on the high
+    /// side from its JSON, type descriptors included.
+    fn pace(&mut self, requests: &[Value]) {
+        let Some(max_wcu) = self.max_wcu else {
+            return;
+        };
+        self.units += requests
+            .iter()
+            .map(|request| (request.to_string().len() as u64).div_ceil(1024))
+            .sum::<u64>();
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let due = Duration::from_secs_f64(self.units as f64 / f64::from(max_wcu));
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Which tags each POI carries: a fixed list of fields, every one present (null when the
+//! element lacks it) so the output loads into a typed table, or with `--all-tags` every
+//! tag as it is.
+
+use anyhow::{bail, Result};
+use serde_json::{json, Map, Value};
+
+/// Fields written unless `--fields` says otherwise: what a POI is, what it is called, and
+/// how to reach it.
+pub const DEFAULT_FIELDS: &str = "name,brand,brand:wikidata,operator,amenity,shop,tourism,\
+    leisure,office,craft,healthcare,historic,emergency,cuisine,addr:housenumber,addr:street,\
+    addr:postcode,addr:city,addr:country,phone,website,email,opening_hours,wheelchair";
+
+pub enum Fields {
+    All,
+    Only(Vec<Field>),
+}
+
+pub enum Field {
+    Key(String),
+    /// `prefix:*`, any tags under the prefix; only those present are written.
+    Prefix(String),
+}
+
+impl Fields {
+    pub fn parse(fields: &[String]) -> Result<Self> {
+        let fields = fields
+            .iter()
+            .map(|field| field.trim())
+            .filter(|field| !field.is_empty())
+            .map(|field| match field.strip_suffix('*') {
+                Some(prefix) => Field::Prefix(prefix.to_string()),
+                None => Field::Key(field.to_string()),
+            })
+            .collect::<Vec<_>>();
+        if fields.is_empty() {
+            bail!("--fields selects no fields; use --all-tags for every tag");
+        }
+        Ok(Fields::Only(fields))
+    }
+
+    /// The `tags` object of a POI.
+    pub fn project(&self, tags: &[(String, String)]) -> Map<String, Value> {
+        let Fields::Only(fields) = self else {
+            return tags
+                .iter()
+                .map(|(key, value)| (key.clone(), json!(value)))
+                .collect();
+        };
+        let mut projected = Map::new();
+        for field in fields {
+            match field {
+                Field::Key(key) => {
+                    let value = tags.iter().find(|(tag, _)| tag == key);
+                    projected.insert(key.clone(), json!(value.map(|(_, value)| value)));
+                }
+                Field::Prefix(prefix) => projected.extend(
+                    tags.iter()
+                        .filter(|(key, _)| key.starts_with(prefix.as_str()))
+                        .map(|(key, value)| (key.clone(), json!(value))),
+                ),
+            }
+        }
+        projected
+    }
+}
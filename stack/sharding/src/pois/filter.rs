@@ -0,0 +1,363 @@
+//! Tag filters that select POIs, e.g. `amenity=* and not amenity=bench` or
+//! `tourism in (hotel, museum) or shop`.
+//!
+//! A term is `key` or `key=*` (any value), `key=value`, `key!=value` (the key with another
+//! value) or `key in (value, ...)`; terms combine with `and`, `or`, `not` and parentheses,
+//! `not` binding tightest and `or` loosest. Keys and values containing spaces or any of
+//! `()=!,"` go in double quotes, as do the words `and`, `or`, `not` and `in` when they are
+//! meant literally.
+
+use anyhow::{bail, Result};
+use std::fmt;
+
+/// How deep `not` and parentheses may nest, well within the parser's stack.
+const MAX_DEPTH: usize = 256;
+
+/// A parsed filter.
+pub enum Filter {
+    Has(String),
+    Is(String, String),
+    IsNot(String, String),
+    In(String, Vec<String>),
+    Not(Box<Filter>),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let filter = parser.or();
+        match filter {
+            Ok(filter) if parser.pos == tokens.len() => Ok(filter),
+            Ok(_) => bail!(
+                "invalid filter `{expression}`: unexpected {} where the filter should end",
+                tokens[parser.pos]
+            ),
+            Err(err) => bail!("invalid filter `{expression}`: {err}"),
+        }
+    }
+
+    /// The filter that `--tags` spells as a list of `key` and `key=value` terms.
+    pub fn any_of(terms: &[String]) -> Result<Self> {
+        let terms = terms
+            .iter()
+            .map(|term| term.trim())
+            .filter(|term| !term.is_empty())
+            .map(|term| match term.split_once('=') {
+                Some((key, value)) => Filter::Is(key.to_string(), value.to_string()),
+                None => Filter::Has(term.to_string()),
+            })
+            .collect::<Vec<_>>();
+        if terms.is_empty() {
+            bail!("--tags selects no tags");
+        }
+        Ok(Filter::Or(terms))
+    }
+
+    pub fn matches(&self, tags: &[(String, String)]) -> bool {
+        let value = |key: &str| {
+            tags.iter()
+                .find(|(tag, _)| tag == key)
+                .map(|(_, value)| value.as_str())
+        };
+        match self {
+            Filter::Has(key) => value(key).is_some(),
+            Filter::Is(key, wanted) => value(key) == Some(wanted.as_str()),
+            Filter::IsNot(key, unwanted) => value(key).is_some_and(|value| value != unwanted),
+            Filter::In(key, values) => {
+                value(key).is_some_and(|value| values.iter().any(|v| v == value))
+            }
+            Filter::Not(filter) => !filter.matches(tags),
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(tags)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(tags)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A bare word, which may be a keyword.
+    Word(String),
+    /// A double-quoted string, never a keyword.
+    Quoted(String),
+    Open,
+    Close,
+    Comma,
+    Equals,
+    NotEquals,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::Quoted(text) => write!(f, "\"{text}\""),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Equals => write!(f, "`=`"),
+            Token::NotEquals => write!(f, "`!=`"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' | '=' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    ',' => Token::Comma,
+                    _ => Token::Equals,
+                });
+            }
+            '!' => {
+                chars.next();
+                if chars.next() != Some('=') {
+                    bail!("invalid filter `{expression}`: `!` must be followed by `=`");
+                }
+                tokens.push(Token::NotEquals);
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                        None => bail!("invalid filter `{expression}`: unterminated quote"),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()=!,\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// How many `not`s and parentheses enclose `pos`.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// Consume `token` if it comes next.
+    fn next_is(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.pos) == Some(token);
+        self.pos += usize::from(found);
+        found
+    }
+
+    /// Consume the keyword `word` if it comes next.
+    fn keyword(&mut self, word: &str) -> bool {
+        self.next_is(&Token::Word(word.to_string()))
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<()> {
+        match self.tokens.get(self.pos) {
+            Some(next) if *next == token => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(next) => bail!("expected {what}, found {next}"),
+            None => bail!("expected {what} at the end"),
+        }
+    }
+
+    fn or(&mut self) -> Result<Filter> {
+        let mut filters = vec![self.and()?];
+        while self.keyword("or") {
+            filters.push(self.and()?);
+        }
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::Or(filters),
+        })
+    }
+
+    fn and(&mut self) -> Result<Filter> {
+        let mut filters = vec![self.unary()?];
+        while self.keyword("and") {
+            filters.push(self.unary()?);
+        }
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::And(filters),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Filter> {
+        if self.keyword("not") {
+            return Ok(Filter::Not(Box::new(self.nested(Self::unary)?)));
+        }
+        if self.next_is(&Token::Open) {
+            let filter = self.nested(Self::or)?;
+            self.expect(Token::Close, "`)`")?;
+            return Ok(filter);
+        }
+        self.term()
+    }
+
+    /// Parse with `parse` one level deeper, refusing filters nested past `MAX_DEPTH`.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Filter>) -> Result<Filter> {
+        if self.depth == MAX_DEPTH {
+            bail!("`not` and parentheses nest deeper than {MAX_DEPTH} levels");
+        }
+        self.depth += 1;
+        let filter = parse(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn term(&mut self) -> Result<Filter> {
+        let key = self.text("a tag key")?;
+        if self.next_is(&Token::Equals) {
+            if self.next_is(&Token::Word("*".into())) {
+                return Ok(Filter::Has(key));
+            }
+            return Ok(Filter::Is(key, self.text("a value after `=`")?));
+        }
+        if self.next_is(&Token::NotEquals) {
+            return Ok(Filter::IsNot(key, self.text("a value after `!=`")?));
+        }
+        if self.keyword("in") {
+            self.expect(Token::Open, "`(` after `in`")?;
+            let mut values = vec![self.text("a value")?];
+            while self.next_is(&Token::Comma) {
+                values.push(self.text("a value after `,`")?);
+            }
+            self.expect(Token::Close, "`)` after the values")?;
+            return Ok(Filter::In(key, values));
+        }
+        Ok(Filter::Has(key))
+    }
+
+    /// A key or value: a quoted string, or a word that is not a keyword.
+    fn text(&mut self, what: &str) -> Result<String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Quoted(text)) => {
+                self.pos += 1;
+                Ok(text.clone())
+            }
+            Some(Token::Word(word)) if !matches!(word.as_str(), "and" | "or" | "not" | "in") => {
+                self.pos += 1;
+                Ok(word.clone())
+            }
+            Some(next) => bail!("expected {what}, found {next}"),
+            None => bail!("expected {what} at the end"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn terms_match_their_tags() -> Result<()> {
+        let cafe = tags(&[("amenity", "cafe"), ("name", "Blue Door")]);
+        let bench = tags(&[("amenity", "bench")]);
+        let shop = tags(&[("shop", "bakery")]);
+        for (filter, matches) in [
+            ("amenity", [true, true, false]),
+            ("amenity=*", [true, true, false]),
+            ("amenity=cafe", [true, false, false]),
+            ("amenity!=cafe", [false, true, false]),
+            ("amenity in (bench, cafe)", [true, true, false]),
+            ("name=\"Blue Door\"", [true, false, false]),
+            ("\"not\"", [false, false, false]),
+        ] {
+            let filter = Filter::parse(filter)?;
+            let found = [&cafe, &bench, &shop].map(|tags| filter.matches(tags));
+            assert_eq!(found, matches);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn not_binds_tightest_and_or_loosest() -> Result<()> {
+        let cafe = tags(&[("amenity", "cafe")]);
+        let bench = tags(&[("amenity", "bench")]);
+        let shop = tags(&[("shop", "bakery")]);
+        for (filter, matches) in [
+            ("amenity and not amenity=bench or shop", [true, false, true]),
+            (
+                "amenity and not (amenity=bench or shop)",
+                [true, false, false],
+            ),
+            ("not amenity or amenity=cafe", [true, false, true]),
+            ("not not shop", [false, false, true]),
+        ] {
+            let filter = Filter::parse(filter)?;
+            let found = [&cafe, &bench, &shop].map(|tags| filter.matches(tags));
+            assert_eq!(found, matches);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_filters_are_refused() {
+        for filter in [
+            "",
+            "amenity=",
+            "amenity in ()",
+            "amenity in (cafe",
+            "(amenity",
+            "amenity shop",
+            "amenity and",
+            "amenity ! cafe",
+            "name=\"Blue",
+        ] {
+            assert!(Filter::parse(filter).is_err(), "{filter}");
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited_instead_of_overflowing_the_stack() -> Result<()> {
+        let nested = |depth: usize| format!("{}shop{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Filter::parse(&nested(MAX_DEPTH))?.matches(&tags(&[("shop", "bakery")])));
+        for filter in [
+            nested(MAX_DEPTH + 1),
+            nested(100_000),
+            "not ".repeat(200_000) + "shop",
+        ] {
+            let err = Filter::parse(&filter).err().expect("too deep");
+            assert!(err.to_string().contains("deeper than 256"), "{err}");
+        }
+        Ok(())
+    }
+}
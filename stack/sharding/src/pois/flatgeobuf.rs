@@ -0,0 +1,133 @@
+//! FlatGeobuf POIs for `--format flatgeobuf`: a shard's POIs as one `<shard_id>.fgb` with
+//! a spatial index, for GDAL and QGIS, or range requests straight to the object store.
+//!
+//! The index comes before the features and orders them, so features are encoded as they
+//! come into a spool file, and copied from it in index order once the shard is complete.
+//! Columns are the POI's `id`, `osm_id`, `source_type`, `name`, `category`, `subcategory`,
+//! and its `tags` and remaining `properties` as JSON.
+
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+use crate::flatgeobuf::{
+    encode_feature, write_indexed, Bounds, Column, Layer, COLUMN_TYPE_JSON, COLUMN_TYPE_LONG,
+    COLUMN_TYPE_STRING, GEOMETRY_TYPE_POINT,
+};
+
+const fn column(name: &'static str, column_type: u8, nullable: bool) -> Column {
+    Column {
+        name,
+        column_type,
+        nullable,
+    }
+}
+
+const COLUMNS: [Column; 8] = [
+    column("id", COLUMN_TYPE_STRING, false),
+    column("osm_id", COLUMN_TYPE_LONG, false),
+    column("source_type", COLUMN_TYPE_STRING, false),
+    column("name", COLUMN_TYPE_STRING, true),
+    column("category", COLUMN_TYPE_STRING, true),
+    column("subcategory", COLUMN_TYPE_STRING, true),
+    column("tags", COLUMN_TYPE_JSON, false),
+    column("properties", COLUMN_TYPE_JSON, false),
+];
+
+/// Properties with a column of their own.
+const OWN_COLUMNS: [&str; 6] = [
+    "osm_id",
+    "source_type",
+    "name",
+    "category",
+    "subcategory",
+    "tags",
+];
+
+/// A shard's POIs while they are written.
+pub struct Spool {
+    file: BufWriter<File>,
+    /// Bounds and encoded size of each feature, in spool order.
+    items: Vec<(Bounds, u64)>,
+    offsets: Vec<u64>,
+    position: u64,
+}
+
+impl Spool {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(tempfile::tempfile()?),
+            items: Vec::new(),
+            offsets: Vec::new(),
+            position: 0,
+        })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let properties = &feature.properties;
+        let mut encoded = Vec::new();
+        push_string(&mut encoded, 0, &feature.id);
+        encoded.extend_from_slice(&1u16.to_le_bytes());
+        let osm_id = properties["osm_id"].as_i64().unwrap_or_default();
+        encoded.extend_from_slice(&osm_id.to_le_bytes());
+        for (column, key) in [
+            (2, "source_type"),
+            (3, "name"),
+            (4, "category"),
+            (5, "subcategory"),
+        ] {
+            if let Some(Value::String(value)) = properties.get(key) {
+                push_string(&mut encoded, column, value);
+            }
+        }
+        push_string(&mut encoded, 6, &properties["tags"].to_string());
+        let rest: Map<String, Value> = properties
+            .iter()
+            .filter(|(key, _)| !OWN_COLUMNS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        push_string(&mut encoded, 7, &Value::Object(rest).to_string());
+
+        let coordinates = &feature.geometry["coordinates"];
+        let lon = coordinates[0].as_f64().unwrap_or_default();
+        let lat = coordinates[1].as_f64().unwrap_or_default();
+        let bytes = encode_feature(&[lon, lat], &encoded);
+        self.file.write_all(&bytes)?;
+        self.items.push(([lon, lat, lon, lat], bytes.len() as u64));
+        self.offsets.push(self.position);
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Write the shard's file, handing it back with the number of POIs in it.
+    pub fn finish(self) -> Result<(NamedTempFile, u64)> {
+        let mut spool = self.file.into_inner().map_err(|err| err.into_error())?;
+        let file = NamedTempFile::new()?;
+        let mut out = BufWriter::new(file.reopen()?);
+        let layer = Layer {
+            name: "pois",
+            geometry_type: GEOMETRY_TYPE_POINT,
+            columns: &COLUMNS,
+            metadata: None,
+        };
+        let mut buf = Vec::new();
+        write_indexed(&mut out, &layer, &self.items, |idx, out| {
+            buf.resize(self.items[idx].1 as usize, 0);
+            spool.seek(SeekFrom::Start(self.offsets[idx]))?;
+            spool.read_exact(&mut buf)?;
+            Ok(out.write_all(&buf)?)
+        })?;
+        out.flush()?;
+        Ok((file, self.items.len() as u64))
+    }
+}
+
+/// A string or JSON property: its column index, length and UTF-8 bytes.
+fn push_string(properties: &mut Vec<u8>, column: u16, value: &str) {
+    properties.extend_from_slice(&column.to_le_bytes());
+    properties.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    properties.extend_from_slice(value.as_bytes());
+}
@@ -0,0 +1,295 @@
+//! GeoPackage POIs for `--format gpkg` (<https://www.geopackage.org>, version 1.4): a
+//! shard's POIs as one `<shard_id>.gpkg` with a `pois` feature table, and with
+//! `--gpkg-category-layers` a `pois_<category>` view per category, listed as a layer of its
+//! own. Columns are those of FlatGeobuf, with `tags` and `properties` as JSON text. There is
+//! no spatial index; GDAL adds one with `SELECT CreateSpatialIndex('pois', 'geom')`.
+
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+use crate::geoparquet::wkb_point;
+use crate::logging;
+use crate::sqlite::{Database, Datum, Table};
+
+/// `GPKG` and version 1.4.0, as the header's application ID and user version.
+const APPLICATION_ID: u32 = 0x4750_4B47;
+const USER_VERSION: u32 = 10_400;
+
+const SRS_ID: i64 = 4326;
+const WGS84: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,\
+    298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\
+    \"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,\
+    AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]";
+
+// Table definitions from the specification.
+const SPATIAL_REF_SYS: &str = "CREATE TABLE gpkg_spatial_ref_sys (srs_name TEXT NOT NULL, \
+    srs_id INTEGER NOT NULL PRIMARY KEY, organization TEXT NOT NULL, \
+    organization_coordsys_id INTEGER NOT NULL, definition TEXT NOT NULL, description TEXT)";
+const CONTENTS: &str = "CREATE TABLE gpkg_contents (table_name TEXT NOT NULL PRIMARY KEY, \
+    data_type TEXT NOT NULL, identifier TEXT UNIQUE, description TEXT DEFAULT '', \
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')), \
+    min_x DOUBLE, min_y DOUBLE, max_x DOUBLE, max_y DOUBLE, srs_id INTEGER, \
+    CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id))";
+const GEOMETRY_COLUMNS: &str = "CREATE TABLE gpkg_geometry_columns (table_name TEXT NOT NULL, \
+    column_name TEXT NOT NULL, geometry_type_name TEXT NOT NULL, srs_id INTEGER NOT NULL, \
+    z TINYINT NOT NULL, m TINYINT NOT NULL, \
+    CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name), \
+    CONSTRAINT uk_gc_table_name UNIQUE (table_name), \
+    CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name), \
+    CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id))";
+const POIS: &str = "CREATE TABLE pois (fid INTEGER PRIMARY KEY NOT NULL, geom POINT, \
+    id TEXT NOT NULL, osm_id INTEGER NOT NULL, source_type TEXT NOT NULL, name TEXT, \
+    category TEXT, subcategory TEXT, tags TEXT NOT NULL, properties TEXT NOT NULL)";
+
+/// Properties with a column of their own.
+const OWN_COLUMNS: [&str; 6] = [
+    "osm_id",
+    "source_type",
+    "name",
+    "category",
+    "subcategory",
+    "tags",
+];
+
+type Bounds = [f64; 4];
+
+const EMPTY_BOUNDS: Bounds = [
+    f64::INFINITY,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    f64::NEG_INFINITY,
+];
+
+/// A shard's GeoPackage while it is written.
+pub struct Package {
+    file: NamedTempFile,
+    db: Database,
+    pois: Table,
+    count: u64,
+    bounds: Bounds,
+    /// Bounds of each category, for `--gpkg-category-layers`.
+    categories: Option<BTreeMap<String, Bounds>>,
+}
+
+impl Package {
+    pub fn new(category_layers: bool) -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let mut db = Database::create(file.reopen()?)?;
+        let pois = db.table("pois", POIS);
+        Ok(Self {
+            file,
+            db,
+            pois,
+            count: 0,
+            bounds: EMPTY_BOUNDS,
+            categories: category_layers.then(BTreeMap::new),
+        })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let properties = &feature.properties;
+        let coordinates = &feature.geometry["coordinates"];
+        let lon = coordinates[0].as_f64().unwrap_or_default();
+        let lat = coordinates[1].as_f64().unwrap_or_default();
+        let text = |key: &str| properties.get(key).and_then(Value::as_str);
+        let tags = properties["tags"].to_string();
+        let rest: Map<String, Value> = properties
+            .iter()
+            .filter(|(key, _)| !OWN_COLUMNS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let rest = Value::Object(rest).to_string();
+        let geometry = point(lon, lat);
+
+        self.count += 1;
+        let values = [
+            Datum::Null,
+            Datum::Blob(&geometry),
+            Datum::Text(&feature.id),
+            Datum::Integer(properties["osm_id"].as_i64().unwrap_or_default()),
+            Datum::Text(text("source_type").unwrap_or_default()),
+            text("name").map_or(Datum::Null, Datum::Text),
+            text("category").map_or(Datum::Null, Datum::Text),
+            text("subcategory").map_or(Datum::Null, Datum::Text),
+            Datum::Text(&tags),
+            Datum::Text(&rest),
+        ];
+        self.db.insert(&mut self.pois, self.count as i64, &values)?;
+        self.bounds = expand(self.bounds, lon, lat);
+        if let (Some(categories), Some(category)) = (&mut self.categories, text("category")) {
+            let bounds = categories
+                .entry(category.to_string())
+                .or_insert(EMPTY_BOUNDS);
+            *bounds = expand(*bounds, lon, lat);
+        }
+        Ok(())
+    }
+
+    /// Write the GeoPackage tables, handing back the file with the number of POIs in it.
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        self.db.end_table(self.pois)?;
+
+        let mut srs = self.db.table("gpkg_spatial_ref_sys", SPATIAL_REF_SYS);
+        let systems = [
+            (-1, "Undefined cartesian SRS", "undefined"),
+            (0, "Undefined geographic SRS", "undefined"),
+            (SRS_ID, "WGS 84 geodetic", WGS84),
+        ];
+        for (id, name, definition) in systems {
+            let organization = if id == SRS_ID { "EPSG" } else { "NONE" };
+            let values = [
+                Datum::Text(name),
+                Datum::Null,
+                Datum::Text(organization),
+                Datum::Integer(id),
+                Datum::Text(definition),
+                Datum::Null,
+            ];
+            self.db.insert(&mut srs, id, &values)?;
+        }
+        self.db.end_table(srs)?;
+
+        // The `pois` table, then a view per category.
+        let mut layers = vec![("pois".to_string(), None, self.bounds)];
+        let mut names = Vec::new();
+        for (category, bounds) in self.categories.take().unwrap_or_default() {
+            let mut name = format!("pois_{}", identifier(&category));
+            let base = name.clone();
+            let mut suffix = 1;
+            while names.contains(&name) {
+                suffix += 1;
+                name = format!("{base}_{suffix}");
+            }
+            names.push(name.clone());
+            let sql = format!(
+                "CREATE VIEW \"{name}\" AS SELECT * FROM pois WHERE category = '{}'",
+                category.replace('\'', "''")
+            );
+            self.db.view(&name, &sql);
+            layers.push((name, Some(category), bounds));
+        }
+
+        let last_change = logging::timestamp();
+        let mut contents = self.db.table("gpkg_contents", CONTENTS);
+        for (idx, (name, category, bounds)) in layers.iter().enumerate() {
+            let description = category.as_deref().map_or_else(
+                || "POIs".to_string(),
+                |category| format!("POIs in category {category}"),
+            );
+            let bound = |idx: usize| {
+                if bounds[0].is_finite() {
+                    Datum::Real(bounds[idx])
+                } else {
+                    Datum::Null
+                }
+            };
+            let values = [
+                Datum::Text(name),
+                Datum::Text("features"),
+                Datum::Text(name),
+                Datum::Text(&description),
+                Datum::Text(&last_change),
+                bound(0),
+                bound(1),
+                bound(2),
+                bound(3),
+                Datum::Integer(SRS_ID),
+            ];
+            self.db.insert(&mut contents, idx as i64 + 1, &values)?;
+        }
+        self.db.end_table(contents)?;
+        let rowid = |idx: usize| Datum::Integer(idx as i64 + 1);
+
+        let mut geometry_columns = self.db.table("gpkg_geometry_columns", GEOMETRY_COLUMNS);
+        for (idx, (name, _, _)) in layers.iter().enumerate() {
+            let values = [
+                Datum::Text(name),
+                Datum::Text("geom"),
+                Datum::Text("POINT"),
+                Datum::Integer(SRS_ID),
+                Datum::Integer(0),
+                Datum::Integer(0),
+            ];
+            self.db
+                .insert(&mut geometry_columns, idx as i64 + 1, &values)?;
+        }
+        self.db.end_table(geometry_columns)?;
+
+        // The indexes of the constraints, named as SQLite names them.
+        let keyed = |with_geom: bool| -> Vec<Vec<Datum>> {
+            layers
+                .iter()
+                .enumerate()
+                .map(|(idx, (name, _, _))| {
+                    let mut entry = vec![Datum::Text(name.as_str())];
+                    if with_geom {
+                        entry.push(Datum::Text("geom"));
+                    }
+                    entry.push(rowid(idx));
+                    entry
+                })
+                .collect()
+        };
+        for (index, table, entries) in [
+            (
+                "sqlite_autoindex_gpkg_contents_1",
+                "gpkg_contents",
+                keyed(false),
+            ),
+            (
+                "sqlite_autoindex_gpkg_contents_2",
+                "gpkg_contents",
+                keyed(false),
+            ),
+            (
+                "sqlite_autoindex_gpkg_geometry_columns_1",
+                "gpkg_geometry_columns",
+                keyed(true),
+            ),
+            (
+                "sqlite_autoindex_gpkg_geometry_columns_2",
+                "gpkg_geometry_columns",
+                keyed(false),
+            ),
+        ] {
+            self.db.index(index, table, None, entries)?;
+        }
+        self.db.finish(APPLICATION_ID, USER_VERSION)?;
+        Ok((self.file, self.count))
+    }
+}
+
+/// A point in GeoPackage's geometry encoding: the `GP` header, little endian without an
+/// envelope, the SRS ID, then WKB.
+fn point(lon: f64, lat: f64) -> Vec<u8> {
+    let mut geometry = b"GP\x00\x01".to_vec();
+    geometry.extend_from_slice(&(SRS_ID as i32).to_le_bytes());
+    geometry.extend_from_slice(&wkb_point(lon, lat));
+    geometry
+}
+
+fn expand(bounds: Bounds, x: f64, y: f64) -> Bounds {
+    [
+        bounds[0].min(x),
+        bounds[1].min(y),
+        bounds[2].max(x),
+        bounds[3].max(y),
+    ]
+}
+
+/// A category as part of a layer name: lowercase letters, digits and underscores.
+fn identifier(category: &str) -> String {
+    category
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
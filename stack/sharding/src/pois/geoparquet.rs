@@ -0,0 +1,271 @@
+//! GeoParquet POIs for `--format geoparquet`: a shard's POIs split by category into Hive
+//! partitions, `category=<category>/shard=<shard_id>/<shard_id>.parquet`, so Athena and
+//! DuckDB read the run's `pois/` prefix as one table partitioned by category and shard.
+//! POIs without a category (or without `--taxonomy`) go to Hive's default partition.
+//!
+//! Columns are the POI's `id`, `osm_id`, `source_type`, `name`, `subcategory`, its `tags`
+//! and remaining `properties` as JSON text, the point geometry as WKB, and a `bbox` struct
+//! declared as the geometry's covering, so readers can skip row groups by location.
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fs::File;
+use std::io::BufWriter;
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+use crate::geoparquet::{
+    wkb_point, Column, Values, Writer, CONVERTED_UTF8, TYPE_BYTE_ARRAY, TYPE_DOUBLE, TYPE_INT64,
+};
+
+/// Rows per row group: large enough for efficient scans, small enough to buffer.
+const ROW_GROUP_ROWS: usize = 65_536;
+
+/// Hive's partition for null values, which Athena and DuckDB read back as null.
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Properties with a column of their own, or given by the partition.
+const OWN_COLUMNS: [&str; 6] = [
+    "osm_id",
+    "source_type",
+    "name",
+    "category",
+    "subcategory",
+    "tags",
+];
+
+const fn string(path: &'static [&'static str], optional: bool) -> Column {
+    Column {
+        path,
+        physical: TYPE_BYTE_ARRAY,
+        converted: Some(CONVERTED_UTF8),
+        bits: 0,
+        optional,
+    }
+}
+
+const fn double(path: &'static [&'static str]) -> Column {
+    Column {
+        path,
+        physical: TYPE_DOUBLE,
+        converted: None,
+        bits: 0,
+        optional: false,
+    }
+}
+
+/// Columns of a category's partition, which gives the category.
+const PARTITION_COLUMNS: [Column; 12] = [
+    string(&["id"], false),
+    OSM_ID,
+    string(&["source_type"], false),
+    string(&["name"], true),
+    string(&["subcategory"], true),
+    string(&["tags"], false),
+    string(&["properties"], false),
+    GEOMETRY,
+    double(&["bbox", "xmin"]),
+    double(&["bbox", "ymin"]),
+    double(&["bbox", "xmax"]),
+    double(&["bbox", "ymax"]),
+];
+
+/// Columns of a file of all categories.
+const COLUMNS: [Column; 13] = [
+    string(&["id"], false),
+    OSM_ID,
+    string(&["source_type"], false),
+    string(&["name"], true),
+    string(&["category"], true),
+    string(&["subcategory"], true),
+    string(&["tags"], false),
+    string(&["properties"], false),
+    GEOMETRY,
+    double(&["bbox", "xmin"]),
+    double(&["bbox", "ymin"]),
+    double(&["bbox", "xmax"]),
+    double(&["bbox", "ymax"]),
+];
+
+const OSM_ID: Column = Column {
+    path: &["osm_id"],
+    physical: TYPE_INT64,
+    converted: None,
+    bits: 0,
+    optional: false,
+};
+
+const GEOMETRY: Column = Column {
+    path: &["geometry"],
+    physical: TYPE_BYTE_ARRAY,
+    converted: None,
+    bits: 0,
+    optional: false,
+};
+
+/// One GeoParquet file of POIs while it is written.
+pub struct Rows {
+    file: NamedTempFile,
+    columns: &'static [Column],
+    writer: Writer<BufWriter<File>>,
+    values: Vec<Values>,
+    /// Rows in `values`, not yet written as a row group.
+    buffered: usize,
+    /// Rows in all.
+    count: u64,
+    bbox: [f64; 4],
+}
+
+impl Rows {
+    /// A file of POIs of any category, with a `category` column.
+    pub fn new() -> Result<Self> {
+        Self::with_columns(&COLUMNS)
+    }
+
+    fn with_columns(columns: &'static [Column]) -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let writer = Writer::new(BufWriter::new(file.reopen()?), columns)?;
+        Ok(Self {
+            file,
+            columns,
+            writer,
+            values: columns.iter().map(|_| Values::default()).collect(),
+            buffered: 0,
+            count: 0,
+            bbox: [
+                f64::INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+            ],
+        })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let properties = &feature.properties;
+        let coordinates = &feature.geometry["coordinates"];
+        let lon = coordinates[0].as_f64().unwrap_or_default();
+        let lat = coordinates[1].as_f64().unwrap_or_default();
+        let rest: Map<String, Value> = properties
+            .iter()
+            .filter(|(key, _)| !OWN_COLUMNS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let rest = Value::Object(rest).to_string();
+        for (column, values) in self.columns.iter().zip(&mut self.values) {
+            match column.path {
+                ["id"] => values.bytes(feature.id.as_bytes()),
+                ["osm_id"] => values.i64(properties["osm_id"].as_i64().unwrap_or_default()),
+                ["source_type"] => {
+                    let source_type = properties["source_type"].as_str().unwrap_or_default();
+                    values.bytes(source_type.as_bytes());
+                }
+                ["tags"] => values.bytes(properties["tags"].to_string().as_bytes()),
+                ["properties"] => values.bytes(rest.as_bytes()),
+                ["geometry"] => values.bytes(&wkb_point(lon, lat)),
+                ["bbox", "xmin" | "xmax"] => values.f64(lon),
+                ["bbox", _] => values.f64(lat),
+                // `name`, `category` and `subcategory`.
+                [key, ..] => match properties.get(*key) {
+                    Some(Value::String(value)) => values.bytes(value.as_bytes()),
+                    _ => values.null(),
+                },
+                [] => unreachable!("column without a name"),
+            }
+        }
+        let bbox = &mut self.bbox;
+        *bbox = [
+            bbox[0].min(lon),
+            bbox[1].min(lat),
+            bbox[2].max(lon),
+            bbox[3].max(lat),
+        ];
+
+        self.buffered += 1;
+        self.count += 1;
+        if self.buffered == ROW_GROUP_ROWS {
+            self.writer.row_group(&mut self.values)?;
+            self.buffered = 0;
+        }
+        Ok(())
+    }
+
+    /// Close the file, handing it back with the number of POIs in it.
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        if self.buffered > 0 {
+            self.writer.row_group(&mut self.values)?;
+        }
+        let geo = json!({
+            "version": "1.1.0",
+            "primary_column": "geometry",
+            "columns": {
+                "geometry": {
+                    "encoding": "WKB",
+                    "geometry_types": ["Point"],
+                    "bbox": self.bbox,
+                    "covering": {
+                        "bbox": {
+                            "xmin": ["bbox", "xmin"],
+                            "ymin": ["bbox", "ymin"],
+                            "xmax": ["bbox", "xmax"],
+                            "ymax": ["bbox", "ymax"],
+                        }
+                    }
+                }
+            }
+        });
+        self.writer.finish(&[("geo", geo.to_string())])?;
+        Ok((self.file, self.count))
+    }
+}
+
+/// A shard's POIs while they are written, by category.
+#[derive(Default)]
+pub struct Partitions(BTreeMap<Option<String>, Rows>);
+
+impl Partitions {
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let category = feature
+            .properties
+            .get("category")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let rows = match self.0.entry(category) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(Rows::with_columns(&PARTITION_COLUMNS)?),
+        };
+        rows.write(feature)
+    }
+
+    /// Close the files, handing them back with their names under the POI prefix and the
+    /// number of POIs in each.
+    pub fn finish(self, shard_id: &str) -> Result<Vec<(String, NamedTempFile, u64)>> {
+        let mut files = Vec::with_capacity(self.0.len());
+        for (category, rows) in self.0 {
+            let (file, count) = rows.finish()?;
+            let category =
+                category.map_or(DEFAULT_PARTITION.to_string(), |category| escape(&category));
+            let name = format!(
+                "category={category}/shard={}/{shard_id}.parquet",
+                escape(shard_id)
+            );
+            files.push((name, file, count));
+        }
+        Ok(files)
+    }
+}
+
+/// A partition value as Hive writes it, with the characters that are not safe in a path
+/// segment as `%XX`.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.".contains(&byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    escaped
+}
@@ -0,0 +1,201 @@
+//! schema.org JSON-LD for `--schema schemaorg`: each POI as a `LocalBusiness` (or a more
+//! specific type such as `Restaurant` or `Hotel`) when it is a business, else a `Place`,
+//! with its name, coordinates, address, contact details, opening hours, brand and Wikidata
+//! item from the feature's properties.
+
+use serde_json::{json, Map, Value};
+
+use super::output::Feature;
+
+pub const CONTEXT: &str = "https://schema.org";
+
+/// schema.org types of OSM tags, tried in order.
+const TYPES: [(&str, &str, &str); 42] = [
+    ("amenity", "restaurant", "Restaurant"),
+    ("amenity", "cafe", "CafeOrCoffeeShop"),
+    ("amenity", "fast_food", "FastFoodRestaurant"),
+    ("amenity", "bar", "BarOrPub"),
+    ("amenity", "pub", "BarOrPub"),
+    ("amenity", "ice_cream", "IceCreamShop"),
+    ("amenity", "bank", "BankOrCreditUnion"),
+    ("amenity", "pharmacy", "Pharmacy"),
+    ("amenity", "hospital", "Hospital"),
+    ("amenity", "clinic", "MedicalClinic"),
+    ("amenity", "doctors", "Physician"),
+    ("amenity", "dentist", "Dentist"),
+    ("amenity", "veterinary", "VeterinaryCare"),
+    ("amenity", "fuel", "GasStation"),
+    ("amenity", "car_rental", "AutoRental"),
+    ("amenity", "cinema", "MovieTheater"),
+    ("amenity", "theatre", "PerformingArtsTheater"),
+    ("amenity", "nightclub", "NightClub"),
+    ("amenity", "library", "Library"),
+    ("amenity", "school", "School"),
+    ("amenity", "kindergarten", "Preschool"),
+    ("amenity", "university", "CollegeOrUniversity"),
+    ("amenity", "post_office", "PostOffice"),
+    ("amenity", "police", "PoliceStation"),
+    ("amenity", "fire_station", "FireStation"),
+    ("amenity", "parking", "ParkingFacility"),
+    ("amenity", "place_of_worship", "PlaceOfWorship"),
+    ("shop", "supermarket", "GroceryStore"),
+    ("shop", "convenience", "ConvenienceStore"),
+    ("shop", "bakery", "Bakery"),
+    ("shop", "clothes", "ClothingStore"),
+    ("shop", "books", "BookStore"),
+    ("shop", "hardware", "HardwareStore"),
+    ("shop", "florist", "Florist"),
+    ("shop", "hairdresser", "HairSalon"),
+    ("tourism", "hotel", "Hotel"),
+    ("tourism", "hostel", "Hostel"),
+    ("tourism", "motel", "Motel"),
+    ("tourism", "camp_site", "Campground"),
+    ("tourism", "museum", "Museum"),
+    ("tourism", "zoo", "Zoo"),
+    ("leisure", "park", "Park"),
+];
+
+/// Keys of businesses without a more specific type.
+const BUSINESSES: [(&str, &str); 4] = [
+    ("shop", "Store"),
+    ("office", "LocalBusiness"),
+    ("craft", "LocalBusiness"),
+    ("healthcare", "MedicalBusiness"),
+];
+
+/// Countries that write the house number before the street, `12 Main Street`.
+const NUMBER_FIRST: [&str; 12] = [
+    "US", "CA", "GB", "IE", "AU", "NZ", "FR", "ZA", "IN", "SG", "MY", "PH",
+];
+
+/// The schema.org type of a POI from its tags.
+pub fn schema_type(tags: &[(String, String)]) -> &'static str {
+    let has = |key: &str, value: &str| tags.iter().any(|(k, v)| k == key && v == value);
+    if let Some((_, _, schema)) = TYPES.iter().find(|(key, value, _)| has(key, value)) {
+        return schema;
+    }
+    BUSINESSES
+        .iter()
+        .find(|(key, _)| tags.iter().any(|(k, _)| k == key))
+        .map_or("Place", |(_, schema)| schema)
+}
+
+/// A POI as a JSON-LD node, without `@context`.
+pub fn document(feature: &Feature) -> Value {
+    let properties = &feature.properties;
+    let property = |key: &str| properties.get(key).filter(|value| !value.is_null());
+    let coordinates = &feature.geometry["coordinates"];
+
+    let mut document = Map::new();
+    document.insert(
+        "@id".into(),
+        json!(format!("https://www.openstreetmap.org/{}", feature.id)),
+    );
+    document.insert(
+        "@type".into(),
+        json!(feature.schema_type.unwrap_or("Place")),
+    );
+    if let Some(name) = property("name") {
+        document.insert("name".into(), name.clone());
+    }
+    document.insert(
+        "geo".into(),
+        json!({
+            "@type": "GeoCoordinates",
+            "latitude": coordinates[1],
+            "longitude": coordinates[0],
+        }),
+    );
+    if let Some(address) = property("address").and_then(postal_address) {
+        document.insert("address".into(), address);
+    }
+    if let Some(contact) = property("contact") {
+        for (field, schema) in [
+            ("phone", "telephone"),
+            ("email", "email"),
+            ("website", "url"),
+        ] {
+            if let Some(first) = contact[field].get(0) {
+                document.insert(schema.into(), first.clone());
+            }
+        }
+    }
+    let hours = property("opening_hours").and_then(|hours| hours["normalized"].as_str());
+    if let Some(hours) = hours.and_then(opening_hours) {
+        document.insert("openingHours".into(), json!(hours));
+    }
+    if let Some(brand) = property("brand") {
+        document.insert(
+            "brand".into(),
+            json!({"@type": "Brand", "name": brand["name"]}),
+        );
+    }
+    if let Some(item) = property("wikidata").and_then(|wikidata| wikidata["id"].as_str()) {
+        document.insert(
+            "sameAs".into(),
+            json!(format!("https://www.wikidata.org/wiki/{item}")),
+        );
+    }
+    Value::Object(document)
+}
+
+/// A `PostalAddress` from the `address` property, if it has any part.
+fn postal_address(address: &Value) -> Option<Value> {
+    let part = |field: &str| address[field].as_str();
+    let country = part("country").map(str::to_ascii_uppercase);
+    let street = match (part("street"), part("housenumber")) {
+        (Some(street), Some(number))
+            if country
+                .as_deref()
+                .is_some_and(|country| NUMBER_FIRST.contains(&country)) =>
+        {
+            Some(format!("{number} {street}"))
+        }
+        (Some(street), Some(number)) => Some(format!("{street} {number}")),
+        (Some(street), None) => Some(street.to_string()),
+        (None, _) => None,
+    };
+    let mut postal = Map::new();
+    let parts = [
+        ("streetAddress", street),
+        ("postalCode", part("postcode").map(str::to_string)),
+        ("addressLocality", part("city").map(str::to_string)),
+        ("addressCountry", country),
+    ];
+    for (field, value) in parts {
+        if let Some(value) = value {
+            postal.insert(field.into(), json!(value));
+        }
+    }
+    if postal.is_empty() {
+        return None;
+    }
+    postal.insert("@type".into(), json!("PostalAddress"));
+    Some(Value::Object(postal))
+}
+
+/// schema.org `openingHours` from a normalized `opening_hours` value, if it is only
+/// weekdays and times (`Mo-Fr 08:00-18:00; Sa 09:00-12:00`), which both notations share.
+fn opening_hours(normalized: &str) -> Option<Vec<String>> {
+    if normalized == "24/7" {
+        return Some(vec!["Mo-Su".to_string()]);
+    }
+    normalized
+        .split("; ")
+        .map(|rule| {
+            let (days, times) = rule.split_once(' ')?;
+            let days_ok = days
+                .split([',', '-'])
+                .all(|day| ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"].contains(&day));
+            let times_ok = times.split(',').all(|span| {
+                let bytes = span.as_bytes();
+                bytes.len() == 11
+                    && bytes[5] == b'-'
+                    && span
+                        .bytes()
+                        .all(|b| b.is_ascii_digit() || b == b':' || b == b'-')
+            });
+            (days_ok && times_ok).then(|| rule.to_string())
+        })
+        .collect()
+}
@@ -0,0 +1,60 @@
+//! Lifecycle prefixes: a place that has closed or gone often keeps its tags behind a prefix
+//! (`disused:shop=supermarket`, `was:amenity=cafe`), or keeps them as they were with a
+//! lifecycle key beside them (`shop=supermarket` and `disused=yes`). Either way it is no
+//! longer a POI, unless `--include-lifecycle` asks for such places, labelled with their
+//! stage.
+
+use super::filter::Filter;
+
+/// Lifecycle stages of places that are no longer in use, as prefixes and keys.
+const STAGES: [&str; 7] = [
+    "disused",
+    "abandoned",
+    "razed",
+    "demolished",
+    "destroyed",
+    "removed",
+    "was",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Live,
+    /// Selected by its prefixed tags, or marked with a lifecycle key.
+    Former(&'static str),
+}
+
+/// Whether `filter` selects an element, and at which stage: as it is, or once the tags
+/// behind one lifecycle prefix are read without it.
+pub fn select(filter: &Filter, tags: &[(String, String)]) -> Option<Stage> {
+    if filter.matches(tags) {
+        let marked = STAGES.into_iter().find(|stage| {
+            tags.iter()
+                .any(|(key, value)| key == stage && value != "no")
+        });
+        return Some(marked.map_or(Stage::Live, Stage::Former));
+    }
+    STAGES.into_iter().find_map(|stage| {
+        let prefixed = tags.iter().any(|(key, _)| unprefix(key, stage).is_some());
+        (prefixed && filter.matches(&unprefixed(tags, stage))).then_some(Stage::Former(stage))
+    })
+}
+
+/// The tags with the prefix `stage:` taken off the keys that have it, which replace any
+/// unprefixed tags with the same key.
+pub fn unprefixed(tags: &[(String, String)], stage: &str) -> Vec<(String, String)> {
+    let mut unprefixed: Vec<(String, String)> = tags
+        .iter()
+        .filter_map(|(key, value)| Some((unprefix(key, stage)?.to_string(), value.clone())))
+        .collect();
+    for (key, value) in tags {
+        if unprefix(key, stage).is_none() && !unprefixed.iter().any(|(taken, _)| taken == key) {
+            unprefixed.push((key.clone(), value.clone()));
+        }
+    }
+    unprefixed
+}
+
+fn unprefix<'a>(key: &'a str, stage: &str) -> Option<&'a str> {
+    key.strip_prefix(stage)?.strip_prefix(':')
+}
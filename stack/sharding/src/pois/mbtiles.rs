@@ -0,0 +1,95 @@
+//! MBTiles tilesets for `tile --format mbtiles` (<https://github.com/mapbox/mbtiles-spec>,
+//! version 1.3), for offline tools that read no other container: the gzipped tiles in a
+//! `tiles` table keyed by zoom, column and row, with rows counted from the south as in TMS,
+//! and what the TileJSON says about them in `metadata`.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use tempfile::NamedTempFile;
+
+use super::tiles::Tiler;
+use crate::sqlite::{Database, Datum};
+
+/// `MPBX`, the header's application ID.
+const APPLICATION_ID: u32 = 0x4D50_4258;
+
+// Table definitions from the specification.
+const METADATA: &str = "CREATE TABLE metadata (name TEXT, value TEXT)";
+const METADATA_INDEX: &str = "CREATE UNIQUE INDEX name ON metadata (name)";
+const TILES: &str = "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, \
+    tile_row INTEGER, tile_data BLOB)";
+const TILES_INDEX: &str =
+    "CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row)";
+
+/// Write the tileset, handing it back with the number of tiles in it.
+pub fn write(tiler: Tiler) -> Result<(NamedTempFile, u64)> {
+    let tilejson = tiler.tilejson();
+    let file = NamedTempFile::new()?;
+    let mut db = Database::create(file.reopen()?)?;
+
+    // Tiles come zoom by zoom in tile id order; their index is sorted once all are in.
+    let mut tiles = db.table("tiles", TILES);
+    let mut keys = Vec::new();
+    let count = tiler.finish_tiles(|zoom, column, y, tile| {
+        let row = (1 << zoom) - 1 - y;
+        let rowid = keys.len() as i64 + 1;
+        let values = [
+            Datum::Integer(zoom.into()),
+            Datum::Integer(column.into()),
+            Datum::Integer(row.into()),
+            Datum::Blob(&tile),
+        ];
+        db.insert(&mut tiles, rowid, &values)?;
+        keys.push((zoom, column, row, rowid));
+        Ok(())
+    })?;
+    db.end_table(tiles)?;
+    keys.sort_unstable();
+    let mut index = db.start_index("tile_index", "tiles", Some(TILES_INDEX));
+    for (zoom, column, row, rowid) in keys {
+        let entry = [
+            Datum::Integer(zoom.into()),
+            Datum::Integer(column.into()),
+            Datum::Integer(row.into()),
+            Datum::Integer(rowid),
+        ];
+        db.index_entry(&mut index, &entry)?;
+    }
+    db.end_index(index)?;
+
+    let text = |key: &str| tilejson[key].as_str().unwrap_or_default().to_string();
+    let bounds = tilejson["bounds"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let metadata = [
+        ("name", text("name")),
+        ("format", "pbf".to_string()),
+        ("type", "overlay".to_string()),
+        ("description", text("description")),
+        ("bounds", bounds),
+        ("minzoom", tilejson["minzoom"].to_string()),
+        ("maxzoom", tilejson["maxzoom"].to_string()),
+        (
+            "json",
+            json!({"vector_layers": tilejson["vector_layers"]}).to_string(),
+        ),
+    ];
+    let mut table = db.table("metadata", METADATA);
+    for (idx, (name, value)) in metadata.iter().enumerate() {
+        let values = [Datum::Text(name), Datum::Text(value)];
+        db.insert(&mut table, idx as i64 + 1, &values)?;
+    }
+    db.end_table(table)?;
+    let entries = metadata
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, _))| vec![Datum::Text(name), Datum::Integer(idx as i64 + 1)])
+        .collect();
+    db.index("name", "metadata", Some(METADATA_INDEX), entries)?;
+    db.finish(APPLICATION_ID, 0)?;
+    Ok((file, count))
+}
@@ -0,0 +1,453 @@
+//! `merge` subcommand: a run's POIs, as `extract-pois` wrote them per shard in GeoJSON or
+//! NDJSON, combined into one GeoParquet file and one PMTiles archive, and optionally one
+//! NDJSON file sorted by ID.
+//!
+//! A POI can be written by more than one shard, such as by overlapping shards, so POIs are
+//! sorted by ID and each is kept once, from the shard that owns it: the first file (in key
+//! order) whose shard, told by its name, contains the point of its copy, or the first file
+//! when none does. What was removed is reported in `dedup.json`. Both sorts spill to
+//! temporary files past `--memory-limit`, so a planet's POIs merge in bounded memory.
+//! GeoParquet rows then follow the tiles' order, keeping nearby POIs in the same row groups.
+//!
+//! With `--conflate-meters`, places mapped more than once under different IDs, such as a
+//! node and its building, are conflated too: see [`super::conflate`].
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+
+use super::conflate::{Clusters, Conflator};
+use super::geoparquet::Rows;
+use super::output::Feature;
+use super::sort::Sorter;
+use super::tantivy::Documents;
+use super::tiles::{self, Rules, Tiler};
+use super::Region;
+use crate::keys;
+use crate::spill;
+use crate::store::{self, Store};
+use crate::task;
+
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// POIs written by `extract-pois` with `--format geojson` or `ndjson`: its `--output`
+    /// directory, or the object store prefix they were uploaded under, such as
+    /// `s3://bucket/runs/{run_id}/pois/`.
+    #[arg(env = "POI_INPUT")]
+    input: String,
+
+    /// Where to write the merged POIs: a local directory, or an object store bucket or
+    /// prefix URI under which each file is keyed by `--merged-key-template`.
+    #[arg(short, long, env = "MERGED_OUTPUT")]
+    output: String,
+
+    /// Key of each merged file under `--output`, with the placeholders of
+    /// `--s3-key-template`; `{name}` is `pois.parquet`, `pois.pmtiles`, `pois.ndjson`,
+    /// `pois.tantivy.tar.gz` or `dedup.json`.
+    #[arg(
+        long,
+        env = "MERGED_KEY_TEMPLATE",
+        default_value = "runs/{run_id}/merged/{name}"
+    )]
+    merged_key_template: String,
+
+    /// Also write the POIs as NDJSON sorted by ID: nodes, ways, then relations, each by
+    /// number.
+    #[arg(long, env = "MERGED_NDJSON")]
+    ndjson: bool,
+
+    /// Also write a tantivy index of the POIs' names, categories and locations, as a gzipped
+    /// tarball of the index directory, built with `tantivy-cli`.
+    #[arg(long, env = "MERGED_TANTIVY")]
+    tantivy: bool,
+
+    /// Highest zoom level in the PMTiles archive; viewers overzoom past it.
+    #[arg(
+        long,
+        env = "POI_TILES_MAX_ZOOM",
+        default_value_t = 14,
+        value_parser = clap::value_parser!(u8).range(0..=14)
+    )]
+    tiles_max_zoom: u8,
+
+    /// Memory for sorting the POIs, beyond which they are spilled to temporary files.
+    #[arg(
+        long,
+        env = "MEMORY_LIMIT",
+        default_value = "1G",
+        value_parser = spill::parse_byte_size
+    )]
+    memory_limit: u64,
+
+    /// Conflate POIs of the same category within this many meters of each other whose
+    /// names are alike, keeping the one with the most tags and listing the IDs of the
+    /// others in its `duplicates` property.
+    #[arg(long, env = "CONFLATE_METERS")]
+    conflate_meters: Option<f64>,
+
+    /// How alike names must be to conflate their POIs, from 0 to 1: the better of their
+    /// normalized edit distance and the share of words they have in common.
+    #[arg(
+        long,
+        env = "CONFLATE_SIMILARITY",
+        default_value_t = 0.8,
+        requires = "conflate_meters"
+    )]
+    conflate_similarity: f64,
+}
+
+pub fn run(args: &MergeArgs) -> Result<()> {
+    let input = keys::expand(&args.input)?;
+    let mut files = Vec::new();
+    let mut skipped = 0;
+    for location in store::list(&input)? {
+        if location.ends_with(".geojson") || location.ends_with(".ndjson") {
+            files.push(location);
+        } else {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 {
+        warn!(
+            files = skipped,
+            "Skipped {skipped} files under {input} that are neither GeoJSON nor NDJSON."
+        );
+    }
+    if files.is_empty() {
+        bail!("no GeoJSON or NDJSON POIs under {input}");
+    }
+    info!(
+        files = files.len(),
+        "Merging the POIs of {} files under {input}...",
+        files.len()
+    );
+
+    // By ID, then by the file's place in key order, with the POI's tile in front of it.
+    let mut by_id = Sorter::new(args.memory_limit / 2);
+    let batch = rayon::current_num_threads();
+    for (first, locations) in files.chunks(batch).enumerate() {
+        let features = locations
+            .par_iter()
+            .map(|location| read_features(location))
+            .collect::<Result<Vec<_>>>()?;
+        for (idx, (location, features)) in locations.iter().zip(features).enumerate() {
+            let source = (first * batch + idx) as u64;
+            for feature in features {
+                let id = feature
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let Some(key) = id_key(id) else {
+                    bail!("{location} has a POI without an ID such as node/1: is it GeoJSON?");
+                };
+                let coordinates = &feature["geometry"]["coordinates"];
+                let lon = coordinates[0].as_f64().unwrap_or(f64::NAN);
+                let lat = coordinates[1].as_f64().unwrap_or(f64::NAN);
+                let mut record = tiles::order(args.tiles_max_zoom, lon, lat)
+                    .to_le_bytes()
+                    .to_vec();
+                serde_json::to_writer(&mut record, &feature)?;
+                by_id.push((key, source), record)?;
+            }
+        }
+    }
+
+    let mut ndjson = match args.ndjson {
+        true => {
+            let file = NamedTempFile::new()?;
+            let out = BufWriter::new(file.reopen()?);
+            Some((file, out))
+        }
+        false => None,
+    };
+    let sources: Vec<Source> = files.iter().map(|location| Source::of(location)).collect();
+    // The sort by ID is done with its memory as POIs are sorted by tile and, when
+    // conflating, by bucket.
+    let mut conflator = args
+        .conflate_meters
+        .map(|meters| Conflator::new(meters, args.conflate_similarity, args.memory_limit / 4))
+        .transpose()?;
+    let mut by_tile = Sorter::new(match conflator {
+        Some(_) => args.memory_limit / 4,
+        None => args.memory_limit / 2,
+    });
+    let mut report = Report::default();
+    let mut pois = 0u64;
+    let mut sorted = by_id.finish()?.peekable();
+    while let Some(record) = sorted.next() {
+        let ((key, source), record) = record?;
+        let mut copies = vec![(source as usize, record)];
+        while sorted
+            .peek()
+            .is_some_and(|next| matches!(next, Ok(((next, _), _)) if *next == key))
+        {
+            let ((_, source), record) = sorted.next().expect("peeked a record")?;
+            copies.push((source as usize, record));
+        }
+        let record = report.keep(copies, &sources)?;
+        pois += 1;
+        let (tile, feature) = record.split_at(8);
+        if let Some(conflator) = &mut conflator {
+            conflator.push(key, &serde_json::from_slice(feature)?)?;
+        }
+        if let Some((_, out)) = &mut ndjson {
+            out.write_all(feature)?;
+            out.write_all(b"\n")?;
+        }
+        let tile = u64::from_le_bytes(tile.try_into().expect("8 bytes"));
+        by_tile.push((tile, key), feature.to_vec())?;
+    }
+
+    let clusters = conflator.map(Conflator::finish).transpose()?;
+    if let Some(clusters) = &clusters {
+        pois -= clusters.merged.len() as u64;
+        report.conflated = Some(Conflated {
+            clusters: clusters.duplicates.len() as u64,
+            merged: clusters.merged.len() as u64,
+        });
+    }
+
+    let mut rows = Rows::new()?;
+    let mut documents = args.tantivy.then(Documents::new).transpose()?;
+    let mut tiler = Tiler::new(args.tiles_max_zoom, Rules::default())?;
+    for record in by_tile.finish()? {
+        let ((_, key), record) = record?;
+        let mut feature = Feature::from_value(serde_json::from_slice(&record)?)
+            .context("POI without properties")?;
+        if let Some(clusters) = &clusters {
+            if clusters.merged.contains(&key) {
+                continue;
+            }
+            if let Some(duplicates) = clusters.duplicates.get(&key) {
+                feature
+                    .properties
+                    .insert("duplicates".to_string(), duplicates.clone().into());
+            }
+        }
+        rows.write(&feature)?;
+        if let Some(documents) = &mut documents {
+            documents.write(&feature)?;
+        }
+        let coordinates = &feature.geometry["coordinates"];
+        let lon = coordinates[0].as_f64().unwrap_or(f64::NAN);
+        let lat = coordinates[1].as_f64().unwrap_or(f64::NAN);
+        let text = |key: &str| feature.properties.get(key).and_then(Value::as_str);
+        let properties = [
+            Some(feature.id.as_str()),
+            text("name"),
+            text("category"),
+            text("subcategory"),
+        ];
+        tiler.push(lon, lat, &properties)?;
+    }
+
+    let (parquet, _) = rows.finish()?;
+    let (pmtiles, tiles) = tiler.finish()?;
+    let mut dedup = NamedTempFile::new()?;
+    serde_json::to_writer_pretty(&mut dedup, &report)?;
+    dedup.flush()?;
+    let mut outputs = vec![
+        ("pois.parquet", parquet),
+        ("pois.pmtiles", pmtiles),
+        ("dedup.json", dedup),
+    ];
+    if let Some((file, mut out)) = ndjson {
+        out.flush()?;
+        let file = match &clusters {
+            Some(clusters) => conflate_ndjson(&file, clusters)?,
+            None => file,
+        };
+        outputs.push(("pois.ndjson", file));
+    }
+    if let Some(documents) = documents {
+        outputs.push(("pois.tantivy.tar.gz", documents.finish()?));
+    }
+    for (name, file) in outputs {
+        let store = Store::open_in(&args.output, &args.merged_key_template, name)?;
+        store.put_file(file.path(), None)?;
+        info!(destination = %store, "Wrote {store}.");
+        task::record_output(store.to_string());
+    }
+    info!(
+        pois = pois,
+        tiles = tiles,
+        "Merged {pois} POIs from {} files into {tiles} tiles.",
+        files.len()
+    );
+    info!(
+        duplicated = report.duplicated,
+        removed = report.removed,
+        unowned = report.unowned,
+        "Removed {} copies of {} POIs written by more than one shard; {} of them are in \
+         none of those shards, and were kept from the first file.",
+        report.removed,
+        report.duplicated,
+        report.unowned
+    );
+    if let Some(conflated) = &report.conflated {
+        info!(
+            clusters = conflated.clusters,
+            merged = conflated.merged,
+            "Conflated {} POIs into {} POIs mapped at the same place.",
+            conflated.merged,
+            conflated.clusters
+        );
+    }
+    Ok(())
+}
+
+/// The NDJSON POIs without those conflated into others, and with the `duplicates` of
+/// those kept.
+fn conflate_ndjson(file: &NamedTempFile, clusters: &Clusters) -> Result<NamedTempFile> {
+    let conflated = NamedTempFile::new()?;
+    let mut out = BufWriter::new(conflated.reopen()?);
+    for line in BufReader::new(file.reopen()?).lines() {
+        let line = line?;
+        let mut feature: Value = serde_json::from_str(&line)?;
+        let key = feature["id"]
+            .as_str()
+            .and_then(id_key)
+            .context("POI without an ID")?;
+        if clusters.merged.contains(&key) {
+            continue;
+        }
+        match clusters.duplicates.get(&key) {
+            Some(duplicates) => {
+                feature["properties"]["duplicates"] = duplicates.clone().into();
+                serde_json::to_writer(&mut out, &feature)?;
+            }
+            None => out.write_all(line.as_bytes())?,
+        }
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(conflated)
+}
+
+/// A file of POIs, and the shard its name says it holds.
+struct Source {
+    /// The shard ID, or the file name if it names no shard.
+    name: String,
+    region: Option<Region>,
+}
+
+impl Source {
+    fn of(location: &str) -> Self {
+        let name = location.rsplit('/').next().unwrap_or(location);
+        let stem = name.split('.').next().unwrap_or(name);
+        match Region::parse(stem) {
+            Ok((id, region)) => Self {
+                name: id,
+                region: Some(region),
+            },
+            Err(_) => Self {
+                name: name.to_string(),
+                region: None,
+            },
+        }
+    }
+}
+
+/// Copies of POIs removed, written as `dedup.json`.
+#[derive(Default, Serialize)]
+struct Report {
+    /// POIs written by more than one shard.
+    duplicated: u64,
+    /// Copies of them removed.
+    removed: u64,
+    /// Duplicated POIs whose point is in none of the shards that wrote them.
+    unowned: u64,
+    /// Copies removed, by the shard (or file) they were removed from.
+    removed_by_shard: BTreeMap<String, u64>,
+    /// With `--conflate-meters`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conflated: Option<Conflated>,
+}
+
+/// POIs conflated into others.
+#[derive(Serialize)]
+struct Conflated {
+    /// POIs kept with `duplicates`.
+    clusters: u64,
+    /// POIs removed as their duplicates.
+    merged: u64,
+}
+
+impl Report {
+    /// The copy of a POI to keep, of the `(source, record)` copies in key order of their
+    /// files: the first from a shard that contains its point, else the first.
+    fn keep(&mut self, mut copies: Vec<(usize, Vec<u8>)>, sources: &[Source]) -> Result<Vec<u8>> {
+        if copies.len() == 1 {
+            return Ok(copies.pop().expect("one copy").1);
+        }
+        self.duplicated += 1;
+        self.removed += copies.len() as u64 - 1;
+        let mut owner = None;
+        for (idx, (source, record)) in copies.iter().enumerate() {
+            let Some(region) = &sources[*source].region else {
+                continue;
+            };
+            let feature: Value = serde_json::from_slice(&record[8..])?;
+            let coordinates = &feature["geometry"]["coordinates"];
+            let lon = coordinates[0].as_f64().unwrap_or(f64::NAN);
+            let lat = coordinates[1].as_f64().unwrap_or(f64::NAN);
+            if region.contains(lon, lat) {
+                owner = Some(idx);
+                break;
+            }
+        }
+        if owner.is_none() {
+            self.unowned += 1;
+        }
+        let owner = owner.unwrap_or(0);
+        for (idx, &(source, _)) in copies.iter().enumerate() {
+            if idx != owner {
+                *self
+                    .removed_by_shard
+                    .entry(sources[source].name.clone())
+                    .or_default() += 1;
+            }
+        }
+        Ok(copies.swap_remove(owner).1)
+    }
+}
+
+/// The features of one of `extract-pois`'s files.
+pub(super) fn read_features(location: &str) -> Result<Vec<Value>> {
+    let store = Store::open(location, "")?;
+    let Some(bytes) = store.get()? else {
+        bail!("{store} disappeared while merging");
+    };
+    if location.ends_with(".ndjson") {
+        return bytes
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).with_context(|| format!("invalid {store}")))
+            .collect();
+    }
+    let mut collection: Value =
+        serde_json::from_slice(&bytes).with_context(|| format!("invalid {store}"))?;
+    match collection["features"].take() {
+        Value::Array(features) => Ok(features),
+        _ => bail!("{store} is not a GeoJSON FeatureCollection"),
+    }
+}
+
+/// Sort key of an OSM ID such as `way/42`: nodes, then ways, then relations, each by number.
+fn id_key(id: &str) -> Option<u64> {
+    let (kind, number) = id.split_once('/')?;
+    let rank = match kind {
+        "node" => 0,
+        "way" => 1,
+        "relation" => 2,
+        _ => return None,
+    };
+    let number: u64 = number.parse().ok()?;
+    (number < 1 << 62).then_some(rank << 62 | number)
+}
@@ -0,0 +1,56 @@
+//! Edit metadata of POIs for `--include-metadata`: the version, last-edit time, changeset
+//! and user of each element, where the input has them (PBF and OSM XML files often leave
+//! them out). With `--anonymize-users` the user is a salted hash of the user ID instead,
+//! the same for all of a user's edits but not naming them.
+
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::input::Meta;
+use crate::logging;
+
+/// Hex digits of the hash kept for an anonymized user.
+const ANONYMIZED_LEN: usize = 16;
+
+pub struct Metadata {
+    /// Salt of the user hashes when users are anonymized.
+    pub salt: Option<String>,
+}
+
+impl Metadata {
+    /// Add `version`, `timestamp`, `changeset`, `user` and `uid` to a feature's properties,
+    /// null where unknown; anonymized users have no `uid`.
+    pub fn insert(&self, properties: &mut Map<String, Value>, meta: &Meta) {
+        let timestamp = meta
+            .timestamp
+            .map(|secs| logging::format_rfc3339(secs, None));
+        properties.insert("version".into(), json!(meta.version));
+        properties.insert("timestamp".into(), json!(timestamp));
+        properties.insert("changeset".into(), json!(meta.changeset));
+        match &self.salt {
+            Some(salt) => {
+                properties.insert("user".into(), json!(anonymize(salt, meta)));
+            }
+            None => {
+                properties.insert("user".into(), json!(meta.user));
+                properties.insert("uid".into(), json!(meta.uid));
+            }
+        }
+    }
+}
+
+/// A user's hash, from the user ID, which survives renames, or else the name.
+fn anonymize(salt: &str, meta: &Meta) -> Option<String> {
+    let user = match (meta.uid, &meta.user) {
+        (Some(uid), _) => format!("uid:{uid}"),
+        (None, Some(user)) => format!("user:{user}"),
+        (None, None) => return None,
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update([0]);
+    hasher.update(user.as_bytes());
+    let mut hash = format!("{:x}", hasher.finalize());
+    hash.truncate(ANONYMIZED_LEN);
+    Some(hash)
+}
@@ -0,0 +1,59 @@
+//! Names of POIs: the `name:<language>` variants as a map from language to name, and the
+//! name to show, the first of `--name-languages` the element has a name in, else its
+//! `name`. Names are NFC-normalized, with control characters dropped and whitespace
+//! trimmed, so the same name is always the same string.
+
+use icu_normalizer::ComposingNormalizerBorrowed;
+use serde_json::{json, Map, Value};
+
+pub struct Names {
+    /// Languages in order of preference for the name shown.
+    pub languages: Vec<String>,
+}
+
+impl Names {
+    /// Add the `name` and `names` properties, `name` null when there is none.
+    pub fn insert(&self, properties: &mut Map<String, Value>, tags: &[(String, String)]) {
+        let names: Map<String, Value> = tags
+            .iter()
+            .filter_map(|(key, value)| {
+                let language = key.strip_prefix("name:").filter(|code| is_language(code))?;
+                Some((language.to_string(), normalize(value)?))
+            })
+            .map(|(language, name)| (language, json!(name)))
+            .collect();
+        let name = self
+            .languages
+            .iter()
+            .find_map(|language| names.get(language).cloned())
+            .or_else(|| {
+                let (_, name) = tags.iter().find(|(key, _)| key == "name")?;
+                normalize(name).map(Value::String)
+            });
+        properties.insert("name".into(), name.unwrap_or(Value::Null));
+        properties.insert("names".into(), Value::Object(names));
+    }
+}
+
+/// A BCP 47 style language code (`en`, `zh-Hans`, `sr-Latn`), as opposed to the other uses
+/// of `name:` keys (`name:left`, `name:etymology`).
+fn is_language(code: &str) -> bool {
+    let mut subtags = code.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    (2..=3).contains(&primary.len())
+        && primary.bytes().all(|b| b.is_ascii_lowercase())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
+}
+
+/// NFC without control characters or surrounding whitespace; `None` if nothing is left.
+fn normalize(name: &str) -> Option<String> {
+    let name: String = ComposingNormalizerBorrowed::new_nfc()
+        .normalize(name)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
@@ -0,0 +1,451 @@
+//! `opening_hours` values, parsed and written back in canonical form: `Mo-Fr 08:00-12:00,
+//! 13:00-18:00; Sa 09:00-12:00; PH off`. Parsing covers the common parts of the
+//! specification: `24/7`, year, month and day ranges, weeks, weekdays with `[n]`,
+//! holidays, times and variable times (`sunrise`, `(sunset-01:00)`), open ends, intervals,
+//! the `open`, `closed`, `off` and `unknown` states and comments, in rules separated by
+//! `;`, `,` or `||`. Spelling is made canonical (`mon` to `Mo`, `8:00` to `08:00`, en dashes
+//! to `-`) and so is spacing; values that do not parse are marked invalid instead.
+
+use serde_json::{json, Value};
+
+/// The `opening_hours` property: the tag as it is, its canonical form, and whether it
+/// parsed, with `normalized` null when it did not.
+pub fn opening_hours(raw: &str) -> Value {
+    let normalized = normalize(raw);
+    json!({
+        "raw": raw,
+        "normalized": normalized,
+        "valid": normalized.is_some(),
+    })
+}
+
+/// The canonical form of a value, `None` if it does not parse.
+pub fn normalize(raw: &str) -> Option<String> {
+    let tokens = tokenize(raw)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+    };
+    let mut normalized = parser.rule()?;
+    while let Some(token) = parser.next() {
+        let separator = match token {
+            Token::Punct(';') => "; ",
+            Token::Punct(',') => ", ",
+            Token::Or => " || ",
+            _ => return None,
+        };
+        // A trailing `;` is common and harmless.
+        if parser.peek().is_none() && separator == "; " {
+            break;
+        }
+        normalized.push_str(separator);
+        normalized.push_str(&parser.rule()?);
+    }
+    Some(normalized)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    /// A number, with its count of digits.
+    Number(u32, usize),
+    /// `hh:mm`, in minutes.
+    Time(u32),
+    Punct(char),
+    Or,
+    Comment(String),
+}
+
+fn tokenize(raw: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = raw.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        chars.next();
+        match c {
+            c if c.is_whitespace() => {}
+            '0'..='9' => {
+                let mut digits = String::from(c);
+                while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(d);
+                    chars.next();
+                }
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                    let minutes = [chars.next()?, chars.next()?];
+                    if digits.len() > 2 || !minutes.iter().all(char::is_ascii_digit) {
+                        return None;
+                    }
+                    let hours: u32 = digits.parse().ok()?;
+                    let minutes = minutes.iter().collect::<String>().parse::<u32>().ok()?;
+                    if minutes >= 60 {
+                        return None;
+                    }
+                    tokens.push(Token::Time(hours * 60 + minutes));
+                } else {
+                    tokens.push(Token::Number(digits.parse().ok()?, digits.len()));
+                }
+            }
+            c if c.is_alphabetic() => {
+                let mut word = String::from(c);
+                while let Some(&l) = chars.peek().filter(|l| l.is_alphabetic()) {
+                    word.push(l);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word.to_lowercase()));
+            }
+            '"' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        c => comment.push(c),
+                    }
+                }
+                tokens.push(Token::Comment(comment));
+            }
+            '|' if chars.next() == Some('|') => tokens.push(Token::Or),
+            '-' | '\u{2013}' | '\u{2014}' => tokens.push(Token::Punct('-')),
+            ',' | ';' | ':' | '/' | '+' | '[' | ']' | '(' | ')' => tokens.push(Token::Punct(c)),
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const FULL_WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+const FULL_MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// A weekday as `Mo`, from its two or three letter abbreviation or full name.
+fn weekday(word: &str) -> Option<&'static str> {
+    (0..7).find_map(|idx| {
+        let full = FULL_WEEKDAYS[idx];
+        let known = word.len() >= 2 && full.starts_with(word) && (word.len() <= 3 || word == full);
+        let known = known || matches!((idx, word), (1, "tues") | (3, "thur" | "thurs"));
+        known.then_some(WEEKDAYS[idx])
+    })
+}
+
+/// A month as `Jan`, from its three letter abbreviation or full name.
+fn month(word: &str) -> Option<&'static str> {
+    (0..12).find_map(|idx| {
+        let full = FULL_MONTHS[idx];
+        let known = (word.len() == 3 && full.starts_with(word)) || word == full;
+        (known || (idx == 8 && word == "sept")).then_some(MONTHS[idx])
+    })
+}
+
+fn is_variable_time(word: &str) -> bool {
+    matches!(word, "sunrise" | "sunset" | "dawn" | "dusk")
+}
+
+fn format_time(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Consume the punctuation `c` if it comes next.
+    fn punct(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(&Token::Punct(c));
+        self.pos += usize::from(found);
+        found
+    }
+
+    fn word(&self) -> Option<&str> {
+        match self.peek()? {
+            Token::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn rule(&mut self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.peek() == Some(&Token::Number(24, 2))
+            && self.peek_at(1) == Some(&Token::Punct('/'))
+            && self.peek_at(2) == Some(&Token::Number(7, 1))
+        {
+            self.pos += 3;
+            parts.push("24/7".to_string());
+        } else {
+            self.selectors(&mut parts)?;
+        }
+        if self.at_time() {
+            parts.push(self.times()?);
+        }
+        if let Some(state @ ("open" | "closed" | "off" | "unknown")) = self.word() {
+            parts.push(state.to_string());
+            self.pos += 1;
+        }
+        if let Some(Token::Comment(comment)) = self.peek() {
+            parts.push(format!("\"{comment}\""));
+            self.pos += 1;
+        }
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    fn selectors(&mut self, parts: &mut Vec<String>) -> Option<()> {
+        if let Some(&Token::Number(_, 4)) = self.peek() {
+            let mut years = self.number(4)?;
+            if self.punct('-') {
+                years = format!("{years}-{}", self.number(4)?);
+            }
+            if self.punct('+') {
+                years.push('+');
+            }
+            parts.push(years);
+            self.wide_colon(parts);
+        }
+        if self.word().and_then(month).is_some() {
+            let mut months = vec![self.month_range()?];
+            while self.continues(|parser| parser.word().and_then(month).is_some()) {
+                months.push(self.month_range()?);
+            }
+            parts.push(months.join(","));
+            self.wide_colon(parts);
+        }
+        if self.word() == Some("week") {
+            self.pos += 1;
+            let mut weeks = vec![self.week_range()?];
+            while self.continues(|parser| matches!(parser.peek(), Some(Token::Number(..)))) {
+                weeks.push(self.week_range()?);
+            }
+            parts.push(format!("week {}", weeks.join(",")));
+            self.wide_colon(parts);
+        }
+        if self.at_weekday() {
+            let mut days = vec![self.weekday_range()?];
+            while self.continues(Parser::at_weekday) {
+                days.push(self.weekday_range()?);
+            }
+            parts.push(days.join(","));
+        }
+        Some(())
+    }
+
+    /// Consume a `,` that continues a list, one followed by what `more` looks for.
+    fn continues(&mut self, more: impl Fn(&Self) -> bool) -> bool {
+        if self.peek() != Some(&Token::Punct(',')) {
+            return false;
+        }
+        self.pos += 1;
+        let continues = more(self);
+        self.pos -= usize::from(!continues);
+        continues
+    }
+
+    /// Keep the optional `:` after a wide range selector.
+    fn wide_colon(&mut self, parts: &mut [String]) {
+        if self.punct(':') {
+            if let Some(last) = parts.last_mut() {
+                last.push(':');
+            }
+        }
+    }
+
+    fn number(&mut self, digits: usize) -> Option<String> {
+        match self.next()? {
+            Token::Number(number, len) if len <= digits => Some(format!("{number:0digits$}")),
+            _ => None,
+        }
+    }
+
+    fn day(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Number(day @ 1..=31, ..=2) => Some(format!("{day:02}")),
+            _ => None,
+        }
+    }
+
+    fn month(&mut self) -> Option<&'static str> {
+        let month = self.word().and_then(month)?;
+        self.pos += 1;
+        Some(month)
+    }
+
+    /// `Jan`, `Jan-Mar`, `Dec 24`, `Dec 24-26` or `Dec 24-Jan 02`.
+    fn month_range(&mut self) -> Option<String> {
+        let mut range = self.month()?.to_string();
+        let has_day = matches!(self.peek(), Some(Token::Number(..)));
+        if has_day {
+            range = format!("{range} {}", self.day()?);
+        }
+        if self.punct('-') {
+            if self.word().and_then(month).is_some() {
+                range = format!("{range}-{}", self.month()?);
+                if has_day {
+                    range = format!("{range} {}", self.day()?);
+                }
+            } else if has_day {
+                range = format!("{range}-{}", self.day()?);
+            } else {
+                return None;
+            }
+        }
+        Some(range)
+    }
+
+    fn week_range(&mut self) -> Option<String> {
+        let mut range = self.number(2)?;
+        if self.punct('-') {
+            range = format!("{range}-{}", self.number(2)?);
+        }
+        if self.punct('/') {
+            let Token::Number(step, ..=2) = self.next()? else {
+                return None;
+            };
+            range = format!("{range}/{step}");
+        }
+        Some(range)
+    }
+
+    fn at_weekday(&self) -> bool {
+        self.word()
+            .is_some_and(|word| weekday(word).is_some() || matches!(word, "ph" | "sh"))
+    }
+
+    /// `Mo`, `Mo-Fr`, `Su[1]`, `Su[-1]`, `PH` or `SH`.
+    fn weekday_range(&mut self) -> Option<String> {
+        let word = self.word()?.to_string();
+        self.pos += 1;
+        if let "ph" | "sh" = word.as_str() {
+            return Some(word.to_uppercase());
+        }
+        let mut range = weekday(&word)?.to_string();
+        if self.punct('-') {
+            let end = self.word().and_then(weekday)?;
+            self.pos += 1;
+            range = format!("{range}-{end}");
+        } else if self.punct('[') {
+            let mut nths = vec![self.nth()?];
+            while self.punct(',') {
+                nths.push(self.nth()?);
+            }
+            if !self.punct(']') {
+                return None;
+            }
+            range = format!("{range}[{}]", nths.join(","));
+        }
+        Some(range)
+    }
+
+    /// `1`, `-1` or `1-2` inside weekday brackets.
+    fn nth(&mut self) -> Option<String> {
+        let negative = self.punct('-');
+        let first = match self.next()? {
+            Token::Number(n @ 1..=5, 1) => n,
+            _ => return None,
+        };
+        if negative {
+            return Some(format!("-{first}"));
+        }
+        if self.punct('-') {
+            return match self.next()? {
+                Token::Number(n @ 1..=5, 1) => Some(format!("{first}-{n}")),
+                _ => None,
+            };
+        }
+        Some(first.to_string())
+    }
+
+    fn at_time(&self) -> bool {
+        match self.peek() {
+            Some(Token::Time(_) | Token::Punct('(')) => true,
+            Some(Token::Word(word)) => is_variable_time(word),
+            _ => false,
+        }
+    }
+
+    fn times(&mut self) -> Option<String> {
+        let mut spans = vec![self.time_span()?];
+        while self.continues(Parser::at_time) {
+            spans.push(self.time_span()?);
+        }
+        Some(spans.join(","))
+    }
+
+    /// `08:00`, `08:00-12:00`, `22:00-02:00`, `17:00+`, `10:00-16:00/01:30` or
+    /// `sunrise-sunset`.
+    fn time_span(&mut self) -> Option<String> {
+        let mut span = self.time(24 * 60)?;
+        if self.punct('-') {
+            span = format!("{span}-{}", self.time(48 * 60)?);
+            if self.punct('/') {
+                let interval = match self.next()? {
+                    Token::Time(minutes) => format_time(minutes),
+                    Token::Number(minutes, ..=2) => minutes.to_string(),
+                    _ => return None,
+                };
+                span = format!("{span}/{interval}");
+            }
+        }
+        if self.punct('+') {
+            span.push('+');
+        }
+        Some(span)
+    }
+
+    fn time(&mut self, max: u32) -> Option<String> {
+        match self.next()? {
+            Token::Time(minutes) if minutes <= max => Some(format_time(minutes)),
+            Token::Word(word) if is_variable_time(&word) => Some(word),
+            Token::Punct('(') => {
+                let Token::Word(word) = self.next()? else {
+                    return None;
+                };
+                let sign = match self.next()? {
+                    Token::Punct(sign @ ('+' | '-')) => sign,
+                    _ => return None,
+                };
+                let Token::Time(offset) = self.next()? else {
+                    return None;
+                };
+                (is_variable_time(&word) && self.punct(')'))
+                    .then(|| format!("({word}{sign}{})", format_time(offset)))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Streaming writers for POI features.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use tempfile::NamedTempFile;
+
+use super::dynamodb::Items;
+use super::flatgeobuf::Spool;
+use super::geopackage::Package;
+use super::geoparquet::Partitions;
+use super::jsonld;
+use super::opensearch::Bulk;
+use super::pelias::Venues;
+use super::postgis::CopyFile;
+use super::redis::Commands;
+use super::stream::Messages;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PoiFormat {
+    /// One GeoJSON FeatureCollection.
+    Geojson,
+    /// Newline-delimited GeoJSON features.
+    Ndjson,
+    /// GeoParquet, partitioned by category and shard.
+    Geoparquet,
+    /// FlatGeobuf with a spatial index.
+    Flatgeobuf,
+    /// GeoPackage, with a `pois` layer.
+    Gpkg,
+    /// OpenSearch (or Elasticsearch) bulk API NDJSON.
+    Opensearch,
+    /// CSV for the Pelias csv-importer, the named POIs as venues.
+    Pelias,
+}
+
+/// Where the POIs go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PoiSink {
+    /// Files in `--format` under `--output`.
+    Files,
+    /// A PostGIS table.
+    Postgis,
+    /// A DynamoDB table.
+    Dynamodb,
+    /// Redis geospatial sets.
+    Redis,
+    /// A Kafka topic.
+    Kafka,
+    /// A Kinesis data stream.
+    Kinesis,
+}
+
+/// Vocabulary of the POI records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PoiSchema {
+    /// GeoJSON features with OSM-derived properties.
+    Geojson,
+    /// schema.org JSON-LD documents (`LocalBusiness`, `Place`, ...).
+    Schemaorg,
+}
+
+impl PoiFormat {
+    pub fn extension(self, schema: PoiSchema) -> &'static str {
+        match (self, schema) {
+            (PoiFormat::Geojson, PoiSchema::Geojson) => "geojson",
+            (PoiFormat::Geojson, PoiSchema::Schemaorg) => "jsonld",
+            (PoiFormat::Ndjson, _) => "ndjson",
+            (PoiFormat::Geoparquet, _) => "parquet",
+            (PoiFormat::Flatgeobuf, _) => "fgb",
+            (PoiFormat::Gpkg, _) => "gpkg",
+            (PoiFormat::Opensearch, _) => "bulk",
+            (PoiFormat::Pelias, _) => "csv",
+        }
+    }
+}
+
+/// A POI as a GeoJSON feature.
+#[derive(Serialize)]
+pub struct Feature {
+    #[serde(rename = "type")]
+    feature_type: &'static str,
+    /// `node/<id>`, `way/<id>` or `relation/<id>`.
+    pub id: String,
+    pub geometry: Value,
+    pub properties: Map<String, Value>,
+    /// The schema.org type, for `--schema schemaorg`.
+    #[serde(skip)]
+    pub schema_type: Option<&'static str>,
+}
+
+impl Feature {
+    pub fn point(id: String, lon: f64, lat: f64) -> Self {
+        Self {
+            feature_type: "Feature",
+            id,
+            geometry: json!({"type": "Point", "coordinates": [round(lon), round(lat)]}),
+            properties: Map::new(),
+            schema_type: None,
+        }
+    }
+
+    /// A feature read back from the GeoJSON `extract-pois` writes, `None` if it is not one.
+    pub fn from_value(mut value: Value) -> Option<Self> {
+        let id = value.get("id")?.as_str()?.to_string();
+        let Value::Object(properties) = value["properties"].take() else {
+            return None;
+        };
+        Some(Self {
+            feature_type: "Feature",
+            id,
+            geometry: value["geometry"].take(),
+            properties,
+            schema_type: None,
+        })
+    }
+}
+
+/// Degrees at OSM's own precision of 7 decimals.
+fn round(degrees: f64) -> f64 {
+    (degrees * 1e7).round() / 1e7
+}
+
+/// Writes features one at a time, so a shard's POIs never need to be held in memory.
+/// As schema.org, a shard is one JSON-LD document with the POIs in its `@graph`, or a
+/// document per line.
+pub struct FeatureWriter<W: Write> {
+    out: W,
+    format: PoiFormat,
+    schema: PoiSchema,
+    count: u64,
+}
+
+impl<W: Write> FeatureWriter<W> {
+    pub fn new(mut out: W, format: PoiFormat, schema: PoiSchema) -> Result<Self> {
+        match (format, schema) {
+            (PoiFormat::Geojson, PoiSchema::Geojson) => {
+                out.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[")?
+            }
+            (PoiFormat::Geojson, PoiSchema::Schemaorg) => {
+                write!(out, "{{\"@context\":\"{}\",\"@graph\":[", jsonld::CONTEXT)?
+            }
+            _ => {}
+        }
+        Ok(Self {
+            out,
+            format,
+            schema,
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        if self.format == PoiFormat::Geojson {
+            if self.count > 0 {
+                self.out.write_all(b",")?;
+            }
+            self.out.write_all(b"\n")?;
+        }
+        match self.schema {
+            PoiSchema::Geojson => serde_json::to_writer(&mut self.out, feature)?,
+            PoiSchema::Schemaorg => {
+                let mut document = jsonld::document(feature);
+                if self.format == PoiFormat::Ndjson {
+                    document["@context"] = json!(jsonld::CONTEXT);
+                }
+                serde_json::to_writer(&mut self.out, &document)?;
+            }
+        }
+        if self.format == PoiFormat::Ndjson {
+            self.out.write_all(b"\n")?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Close the collection and hand back the output with the number of features written.
+    pub fn finish(mut self) -> Result<(W, u64)> {
+        if self.format == PoiFormat::Geojson {
+            self.out.write_all(b"\n]}\n")?;
+        }
+        self.out.flush()?;
+        Ok((self.out, self.count))
+    }
+}
+
+/// One shard's POI files while they are written: a single GeoJSON, NDJSON, FlatGeobuf,
+/// GeoPackage, bulk, Pelias CSV, PostGIS `COPY`, DynamoDB item, Redis command or message file, or a
+/// GeoParquet file per category.
+pub enum ShardWriter {
+    Stream {
+        file: NamedTempFile,
+        writer: FeatureWriter<BufWriter<File>>,
+    },
+    Geoparquet(Partitions),
+    Flatgeobuf(Spool),
+    Geopackage(Package),
+    Opensearch(Bulk),
+    Pelias(Venues),
+    Postgis(CopyFile),
+    Dynamodb(Items),
+    Redis(Commands),
+    Messages(Messages),
+}
+
+impl ShardWriter {
+    /// `category_layers` adds a layer per category to a GeoPackage; `index` is the index of
+    /// OpenSearch bulk actions.
+    pub fn new(
+        format: PoiFormat,
+        schema: PoiSchema,
+        category_layers: bool,
+        index: Option<&str>,
+    ) -> Result<Self> {
+        match format {
+            PoiFormat::Geoparquet => return Ok(ShardWriter::Geoparquet(Partitions::default())),
+            PoiFormat::Flatgeobuf => return Ok(ShardWriter::Flatgeobuf(Spool::new()?)),
+            PoiFormat::Gpkg => {
+                return Ok(ShardWriter::Geopackage(Package::new(category_layers)?));
+            }
+            PoiFormat::Opensearch => {
+                let index = index.context("no OpenSearch index for bulk actions")?;
+                return Ok(ShardWriter::Opensearch(Bulk::new(index)?));
+            }
+            PoiFormat::Pelias => return Ok(ShardWriter::Pelias(Venues::new()?)),
+            PoiFormat::Geojson | PoiFormat::Ndjson => {}
+        }
+        let file = NamedTempFile::new()?;
+        let writer = FeatureWriter::new(BufWriter::new(file.reopen()?), format, schema)?;
+        Ok(ShardWriter::Stream { file, writer })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        match self {
+            ShardWriter::Stream { writer, .. } => writer.write(feature),
+            ShardWriter::Geoparquet(partitions) => partitions.write(feature),
+            ShardWriter::Flatgeobuf(spool) => spool.write(feature),
+            ShardWriter::Geopackage(package) => package.write(feature),
+            ShardWriter::Opensearch(bulk) => bulk.write(feature),
+            ShardWriter::Pelias(venues) => venues.write(feature),
+            ShardWriter::Postgis(copy) => copy.write(feature),
+            ShardWriter::Dynamodb(items) => items.write(feature),
+            ShardWriter::Redis(commands) => commands.write(feature),
+            ShardWriter::Messages(messages) => messages.write(feature),
+        }
+    }
+
+    /// Close the files, handing them back with their names (`{name}` of
+    /// `--pois-key-template`) and the number of POIs in each.
+    pub fn finish(
+        self,
+        shard_id: &str,
+        format: PoiFormat,
+        schema: PoiSchema,
+    ) -> Result<Vec<(String, NamedTempFile, u64)>> {
+        match self {
+            ShardWriter::Stream { file, writer } => {
+                let (_, count) = writer.finish()?;
+                let name = format!("{shard_id}.{}", format.extension(schema));
+                Ok(vec![(name, file, count)])
+            }
+            ShardWriter::Geoparquet(partitions) => partitions.finish(shard_id),
+            ShardWriter::Flatgeobuf(spool) => {
+                let (file, count) = spool.finish()?;
+                Ok(vec![(format!("{shard_id}.fgb"), file, count)])
+            }
+            ShardWriter::Geopackage(package) => {
+                let (file, count) = package.finish()?;
+                Ok(vec![(format!("{shard_id}.gpkg"), file, count)])
+            }
+            ShardWriter::Opensearch(bulk) => {
+                let (file, count) = bulk.finish()?;
+                Ok(vec![(format!("{shard_id}.bulk"), file, count)])
+            }
+            ShardWriter::Pelias(venues) => {
+                let (file, count) = venues.finish()?;
+                Ok(vec![(format!("{shard_id}.csv"), file, count)])
+            }
+            ShardWriter::Postgis(copy) => {
+                let (file, count) = copy.finish()?;
+                Ok(vec![(format!("{shard_id}.pgcopy"), file, count)])
+            }
+            ShardWriter::Dynamodb(items) => {
+                let (file, count) = items.finish()?;
+                Ok(vec![(format!("{shard_id}.items"), file, count)])
+            }
+            ShardWriter::Redis(commands) => {
+                let (file, count) = commands.finish()?;
+                Ok(vec![(format!("{shard_id}.resp"), file, count)])
+            }
+            ShardWriter::Messages(messages) => {
+                let (file, count) = messages.finish()?;
+                Ok(vec![(format!("{shard_id}.messages"), file, count)])
+            }
+        }
+    }
+}
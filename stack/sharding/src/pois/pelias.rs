@@ -0,0 +1,145 @@
+//! Pelias POIs for `--format pelias`: a shard's POIs as a CSV for Pelias's csv-importer,
+//! each a `venue` of source `openstreetmap` with the POI's ID (`node/123`) as its `id`, the
+//! gid the openstreetmap importer gives the same element, so an import replaces its venues
+//! rather than duplicating them. Rows have the name and names by language, the house
+//! number, street and postcode, the point, the taxonomy category, and an `osm` addendum
+//! with the website, phone, opening hours, brand, Wikidata item, operator and wheelchair
+//! access.
+//!
+//! Pelias has no use for a venue without a name, so POIs without one are left out.
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+use crate::delimited::quote;
+
+const COLUMNS: [&str; 12] = [
+    "id",
+    "source",
+    "layer",
+    "name",
+    "name_json",
+    "housenumber",
+    "street",
+    "postcode",
+    "lat",
+    "lon",
+    "category",
+    "addendum_json_osm",
+];
+const SOURCE: &str = "openstreetmap";
+const LAYER: &str = "venue";
+
+/// A shard's CSV while it is written.
+pub struct Venues {
+    file: NamedTempFile,
+    out: BufWriter<File>,
+    count: u64,
+}
+
+impl Venues {
+    pub fn new() -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let mut out = BufWriter::new(file.reopen()?);
+        writeln!(out, "{}", COLUMNS.join(","))?;
+        Ok(Self {
+            file,
+            out,
+            count: 0,
+        })
+    }
+
+    /// Add a POI's row, unless it has no name or point.
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let properties = &feature.properties;
+        let property = |key: &str| properties.get(key).filter(|value| !value.is_null());
+        let Some(name) = property("name").and_then(Value::as_str) else {
+            return Ok(());
+        };
+        let coordinates = &feature.geometry["coordinates"];
+        let (Some(lon), Some(lat)) = (coordinates[0].as_f64(), coordinates[1].as_f64()) else {
+            return Ok(());
+        };
+        let names = property("names")
+            .and_then(Value::as_object)
+            .filter(|names| !names.is_empty())
+            .map(|names| Value::Object(names.clone()).to_string());
+        let address = property("address");
+        let part = |field: &str| {
+            address
+                .and_then(|address| address[field].as_str())
+                .map(str::to_string)
+        };
+        let text = |key: &str| property(key).and_then(Value::as_str).map(str::to_string);
+        let fields = [
+            Some(feature.id.clone()),
+            Some(SOURCE.to_string()),
+            Some(LAYER.to_string()),
+            Some(name.to_string()),
+            names,
+            part("housenumber"),
+            part("street"),
+            part("postcode"),
+            Some(lat.to_string()),
+            Some(lon.to_string()),
+            text("category"),
+            addendum(feature),
+        ];
+        let row: Vec<String> = fields
+            .iter()
+            .map(|field| quote(field.as_deref().unwrap_or_default(), ','))
+            .collect();
+        writeln!(self.out, "{}", row.join(","))?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        self.out.flush()?;
+        Ok((self.file, self.count))
+    }
+}
+
+/// The `osm` addendum, as JSON text, if the POI has any of its fields.
+fn addendum(feature: &Feature) -> Option<String> {
+    let properties = &feature.properties;
+    let property = |key: &str| properties.get(key).filter(|value| !value.is_null());
+    let tag = |key: &str| properties["tags"][key].as_str();
+    let contact = |field: &str| {
+        property("contact")
+            .and_then(|contact| contact[field].get(0))
+            .and_then(Value::as_str)
+    };
+    let fields = [
+        ("website", contact("website").or_else(|| tag("website"))),
+        ("phone", contact("phone").or_else(|| tag("phone"))),
+        (
+            "opening_hours",
+            property("opening_hours")
+                .and_then(|hours| hours["normalized"].as_str().or(hours["raw"].as_str())),
+        ),
+        (
+            "brand",
+            property("brand")
+                .and_then(|brand| brand["name"].as_str())
+                .or_else(|| tag("brand")),
+        ),
+        (
+            "wikidata",
+            property("wikidata")
+                .and_then(|wikidata| wikidata["id"].as_str())
+                .or_else(|| tag("wikidata")),
+        ),
+        ("operator", tag("operator")),
+        ("wheelchair", tag("wheelchair")),
+    ];
+    let addendum: Map<String, Value> = fields
+        .into_iter()
+        .filter_map(|(field, value)| Some((field.to_string(), json!(value?))))
+        .collect();
+    (!addendum.is_empty()).then(|| Value::Object(addendum).to_string())
+}
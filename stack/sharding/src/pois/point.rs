@@ -0,0 +1,142 @@
+//! Representative points of way and multipolygon POIs, computed on longitude/latitude as
+//! planar coordinates, which is accurate enough at the size of a building or campus.
+
+use clap::ValueEnum;
+
+/// One polygon: its outer ring, then any inner rings. Rings are closed.
+pub type Polygon = Vec<Vec<[f64; 2]>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RepresentativePoint {
+    /// The center of mass, which can fall outside a concave or holed area (the courtyard
+    /// of a U-shaped building).
+    Centroid,
+    /// A point guaranteed to be inside the area, or on the line.
+    PointOnSurface,
+}
+
+impl RepresentativePoint {
+    /// The representative point of an area, `None` if it has no area at all.
+    pub fn of_area(self, polygons: &[Polygon]) -> Option<[f64; 2]> {
+        match self {
+            RepresentativePoint::Centroid => area_centroid(polygons),
+            RepresentativePoint::PointOnSurface => interior_point(polygons),
+        }
+    }
+
+    /// The representative point of a line, `None` if it has no positions.
+    pub fn of_line(self, line: &[[f64; 2]]) -> Option<[f64; 2]> {
+        let length: f64 = line.windows(2).map(|pair| distance(pair[0], pair[1])).sum();
+        if length == 0.0 {
+            return line.first().copied();
+        }
+        match self {
+            RepresentativePoint::Centroid => {
+                let (mut lon, mut lat) = (0.0, 0.0);
+                for pair in line.windows(2) {
+                    let weight = distance(pair[0], pair[1]);
+                    lon += weight * (pair[0][0] + pair[1][0]) / 2.0;
+                    lat += weight * (pair[0][1] + pair[1][1]) / 2.0;
+                }
+                Some([lon / length, lat / length])
+            }
+            // Halfway along the line.
+            RepresentativePoint::PointOnSurface => {
+                let mut remaining = length / 2.0;
+                for pair in line.windows(2) {
+                    let segment = distance(pair[0], pair[1]);
+                    if segment >= remaining && segment > 0.0 {
+                        let t = remaining / segment;
+                        return Some([
+                            pair[0][0] + t * (pair[1][0] - pair[0][0]),
+                            pair[0][1] + t * (pair[1][1] - pair[0][1]),
+                        ]);
+                    }
+                    remaining -= segment;
+                }
+                line.last().copied()
+            }
+        }
+    }
+}
+
+/// Whether a way's positions form a ring.
+pub fn is_closed(positions: &[[f64; 2]]) -> bool {
+    positions.len() >= 4 && positions.first() == positions.last()
+}
+
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (b[0] - a[0]).hypot(b[1] - a[1])
+}
+
+/// Area-weighted centroid; inner rings subtract from their polygon.
+fn area_centroid(polygons: &[Polygon]) -> Option<[f64; 2]> {
+    let (mut area, mut lon, mut lat) = (0.0, 0.0, 0.0);
+    for polygon in polygons {
+        for (idx, ring) in polygon.iter().enumerate() {
+            // Outer rings count positively, inner rings negatively, whatever their winding.
+            let sign = if idx == 0 { 1.0 } else { -1.0 };
+            let (ring_area, ring_lon, ring_lat) = ring_moments(ring);
+            let orientation = ring_area.signum();
+            area += sign * orientation * ring_area;
+            lon += sign * orientation * ring_lon;
+            lat += sign * orientation * ring_lat;
+        }
+    }
+    (area > 0.0).then(|| [lon / area, lat / area])
+}
+
+/// Signed area of a closed ring and its first moments (area times centroid).
+fn ring_moments(ring: &[[f64; 2]]) -> (f64, f64, f64) {
+    let (mut area, mut lon, mut lat) = (0.0, 0.0, 0.0);
+    for pair in ring.windows(2) {
+        let ([x0, y0], [x1, y1]) = (pair[0], pair[1]);
+        let cross = x0 * y1 - x1 * y0;
+        area += cross;
+        lon += (x0 + x1) * cross;
+        lat += (y0 + y1) * cross;
+    }
+    (area / 2.0, lon / 6.0, lat / 6.0)
+}
+
+/// A point inside the area: the middle of the widest stretch of the horizontal line through
+/// the middle of the largest polygon's bounding box that lies inside the area.
+fn interior_point(polygons: &[Polygon]) -> Option<[f64; 2]> {
+    let largest = polygons
+        .iter()
+        .filter(|polygon| !polygon.is_empty())
+        .max_by(|a, b| {
+            ring_moments(&a[0])
+                .0
+                .abs()
+                .total_cmp(&ring_moments(&b[0]).0.abs())
+        })?;
+    let (south, north) = largest[0]
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(south, north), p| {
+            (south.min(p[1]), north.max(p[1]))
+        });
+    let mut lat = (south + north) / 2.0;
+    // A scanline through a vertex would count it twice; nudge it off any vertex latitude.
+    if largest.iter().flatten().any(|p| p[1] == lat) {
+        lat += (north - south) * 1e-6;
+    }
+
+    // Crossings of the scanline with every ring: by the even-odd rule, the stretches
+    // between the first and second, third and fourth, ... crossing are inside.
+    let mut crossings: Vec<f64> = Vec::new();
+    for ring in polygons.iter().flatten() {
+        for pair in ring.windows(2) {
+            let ([x0, y0], [x1, y1]) = (pair[0], pair[1]);
+            if (y0 > lat) != (y1 > lat) {
+                crossings.push(x0 + (lat - y0) / (y1 - y0) * (x1 - x0));
+            }
+        }
+    }
+    crossings.sort_by(f64::total_cmp);
+    crossings
+        .chunks_exact(2)
+        .max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+        .map(|stretch| [(stretch[0] + stretch[1]) / 2.0, lat])
+        .or_else(|| area_centroid(polygons))
+}
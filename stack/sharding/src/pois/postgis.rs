@@ -0,0 +1,187 @@
+//! PostGIS POIs for `--sink postgis`: each shard's POIs copied into a table with binary
+//! `COPY`, through `psql`, so no file store is needed. Columns are those of GeoPackage,
+//! with the shard's ID, `tags` and `properties` as JSONB and `geom` a
+//! `geometry(Point, 4326)`.
+//!
+//! The table and its indexes are created when missing. Each shard is loaded in a
+//! transaction of its own that first deletes the rows it loaded before, so a shard that is
+//! extracted again replaces its POIs, and a failed one leaves the table as it was. A POI
+//! written by more than one shard, such as by overlapping shards, has a row for each.
+
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+use crate::store::run_cli;
+
+/// Properties with a column of their own.
+const OWN_COLUMNS: [&str; 6] = [
+    "osm_id",
+    "source_type",
+    "name",
+    "category",
+    "subcategory",
+    "tags",
+];
+
+/// The columns, in the order of the copied rows.
+const COLUMNS: &str =
+    "shard_id, id, osm_id, source_type, name, category, subcategory, tags, properties, geom";
+
+const SRID: u32 = 4326;
+
+/// Binary `COPY` signature, flags and header extension length.
+const HEADER: &[u8] = b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0";
+
+/// A shard's binary `COPY` file while it is written.
+pub struct CopyFile {
+    file: NamedTempFile,
+    out: BufWriter<File>,
+    shard_id: String,
+    count: u64,
+}
+
+impl CopyFile {
+    pub fn new(shard_id: &str) -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let mut out = BufWriter::new(file.reopen()?);
+        out.write_all(HEADER)?;
+        Ok(Self {
+            file,
+            out,
+            shard_id: shard_id.to_string(),
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let properties = &feature.properties;
+        let text = |key: &str| properties.get(key).and_then(Value::as_str);
+        let rest: Map<String, Value> = properties
+            .iter()
+            .filter(|(key, _)| !OWN_COLUMNS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let coordinates = &feature.geometry["coordinates"];
+        let lon = coordinates[0].as_f64().unwrap_or_default();
+        let lat = coordinates[1].as_f64().unwrap_or_default();
+        let osm_id = properties["osm_id"].as_i64().unwrap_or_default();
+
+        let fields = [
+            Some(self.shard_id.as_bytes().to_vec()),
+            Some(feature.id.as_bytes().to_vec()),
+            Some(osm_id.to_be_bytes().to_vec()),
+            text("source_type").map(|value| value.as_bytes().to_vec()),
+            text("name").map(|value| value.as_bytes().to_vec()),
+            text("category").map(|value| value.as_bytes().to_vec()),
+            text("subcategory").map(|value| value.as_bytes().to_vec()),
+            Some(jsonb(&properties["tags"])),
+            Some(jsonb(&Value::Object(rest))),
+            Some(ewkb_point(lon, lat)),
+        ];
+        self.out.write_all(&(fields.len() as i16).to_be_bytes())?;
+        for field in &fields {
+            match field {
+                Some(bytes) => {
+                    self.out.write_all(&(bytes.len() as i32).to_be_bytes())?;
+                    self.out.write_all(bytes)?;
+                }
+                None => self.out.write_all(&(-1i32).to_be_bytes())?,
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        self.out.write_all(&(-1i16).to_be_bytes())?;
+        self.out.flush()?;
+        Ok((self.file, self.count))
+    }
+}
+
+/// JSONB in its binary format: a version byte, then the JSON text.
+fn jsonb(value: &Value) -> Vec<u8> {
+    let mut bytes = vec![1];
+    bytes.extend_from_slice(value.to_string().as_bytes());
+    bytes
+}
+
+/// A point as EWKB, the binary format of PostGIS geometries: little endian, with an SRID.
+fn ewkb_point(x: f64, y: f64) -> Vec<u8> {
+    let mut ewkb = vec![1];
+    ewkb.extend_from_slice(&(1u32 | 0x2000_0000).to_le_bytes());
+    ewkb.extend_from_slice(&SRID.to_le_bytes());
+    ewkb.extend_from_slice(&x.to_le_bytes());
+    ewkb.extend_from_slice(&y.to_le_bytes());
+    ewkb
+}
+
+/// `table`, optionally schema-qualified (`osm.pois`), as a quoted identifier, with the
+/// unqualified name for its indexes.
+fn identifier(table: &str) -> Result<(String, &str)> {
+    let parts: Vec<&str> = table.split('.').collect();
+    if parts.len() > 2
+        || parts
+            .iter()
+            .any(|part| part.is_empty() || part.contains('"'))
+    {
+        bail!("invalid PostGIS table {table}, expected table or schema.table");
+    }
+    let quoted: Vec<String> = parts.iter().map(|part| format!("\"{part}\"")).collect();
+    Ok((quoted.join("."), parts[parts.len() - 1]))
+}
+
+/// Create the table and its indexes unless they exist. Concurrent jobs take turns, so no
+/// two create them at once.
+pub fn create_table(dsn: &str, table: &str) -> Result<()> {
+    let (quoted, name) = identifier(table)?;
+    let sql = format!(
+        "SET LOCAL client_min_messages = warning;\n\
+         SELECT pg_advisory_xact_lock(hashtext('osm-planet-sharding {table}'));\n\
+         CREATE TABLE IF NOT EXISTS {quoted} (shard_id TEXT NOT NULL, id TEXT NOT NULL, \
+         osm_id BIGINT NOT NULL, source_type TEXT, name TEXT, category TEXT, \
+         subcategory TEXT, tags JSONB, properties JSONB, \
+         geom geometry(Point, {SRID}) NOT NULL, PRIMARY KEY (shard_id, id));\n\
+         CREATE INDEX IF NOT EXISTS \"{name}_geom_idx\" ON {quoted} USING GIST (geom);\n\
+         CREATE INDEX IF NOT EXISTS \"{name}_category_idx\" ON {quoted} (category);\n"
+    );
+    psql(dsn, &sql)
+}
+
+/// Replace a shard's rows with those of its `COPY` file, in one transaction.
+pub fn load(dsn: &str, table: &str, shard_id: &str, path: &Path) -> Result<()> {
+    let (quoted, _) = identifier(table)?;
+    let sql = format!(
+        "DELETE FROM {quoted} WHERE shard_id = '{shard_id}';\n\
+         \\copy {quoted} ({COLUMNS}) FROM '{}' WITH (FORMAT binary)\n",
+        path.display()
+    );
+    psql(dsn, &sql)
+}
+
+/// Run a script with `psql` in one transaction, stopping at the first error.
+fn psql(dsn: &str, sql: &str) -> Result<()> {
+    let mut script = NamedTempFile::new()?;
+    script.write_all(sql.as_bytes())?;
+    script.flush()?;
+    let path = script.path().to_string_lossy();
+    let args = [
+        "--no-psqlrc",
+        "--quiet",
+        "--single-transaction",
+        "--set=ON_ERROR_STOP=1",
+        "--dbname",
+        dsn,
+        "--file",
+        &path,
+    ];
+    if let Err(stderr) = run_cli("psql", &args)? {
+        bail!("PostGIS request failed: {stderr}");
+    }
+    Ok(())
+}
@@ -0,0 +1,240 @@
+//! Redis POIs for `--sink redis`: each POI's ID added at its point to a geospatial set, one
+//! per category or per shard, for `GEOSEARCH` to find the POIs near a point. A shard's
+//! POIs are spooled as `GEOADD` commands, then sent in pipelines of [`PIPELINE`] commands,
+//! reading the replies of each pipeline before the next.
+//!
+//! Like the metrics exporters, this speaks the protocol over plain TCP, which is what a
+//! cache inside the VPC listens on. Members are added or moved, never removed, so a run
+//! that should not see the POIs of an earlier one writes under a prefix of its own, such
+//! as `pois:{run_id}:`.
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+
+/// Commands sent before their replies are read.
+const PIPELINE: usize = 1000;
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Latitudes Redis can index, those of Web Mercator.
+const MAX_LATITUDE: f64 = 85.051_128_78;
+/// Set of the POIs without a category, with `--redis-sets category`.
+const UNCATEGORIZED: &str = "uncategorized";
+
+/// Which set each POI is added to.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum RedisSets {
+    /// `<prefix><category>`, or `<prefix>uncategorized`.
+    Category,
+    /// `<prefix><shard_id>`.
+    Shard,
+}
+
+/// A shard's `GEOADD` commands while they are written.
+pub struct Commands {
+    file: NamedTempFile,
+    out: BufWriter<File>,
+    /// Key of the shard's set, with `--redis-sets shard`, or the prefix of the category sets.
+    key: String,
+    sets: RedisSets,
+    count: u64,
+}
+
+impl Commands {
+    pub fn new(sets: RedisSets, prefix: &str, shard_id: &str) -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let out = BufWriter::new(file.reopen()?);
+        let key = match sets {
+            RedisSets::Category => prefix.to_string(),
+            RedisSets::Shard => format!("{prefix}{shard_id}"),
+        };
+        Ok(Self {
+            file,
+            out,
+            key,
+            sets,
+            count: 0,
+        })
+    }
+
+    /// Add a POI's command, unless its point is one Redis cannot index.
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let coordinates = &feature.geometry["coordinates"];
+        let (Some(lon), Some(lat)) = (coordinates[0].as_f64(), coordinates[1].as_f64()) else {
+            return Ok(());
+        };
+        if lat.abs() > MAX_LATITUDE {
+            return Ok(());
+        }
+        let key = match self.sets {
+            RedisSets::Category => {
+                let category = feature.properties.get("category").and_then(Value::as_str);
+                format!("{}{}", self.key, category.unwrap_or(UNCATEGORIZED))
+            }
+            RedisSets::Shard => self.key.clone(),
+        };
+        let args = [
+            "GEOADD",
+            &key,
+            &lon.to_string(),
+            &lat.to_string(),
+            &feature.id,
+        ];
+        write!(self.out, "*{}\r\n", args.len())?;
+        for arg in args {
+            write!(self.out, "${}\r\n{arg}\r\n", arg.len())?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        self.out.flush()?;
+        Ok((self.file, self.count))
+    }
+}
+
+/// A connection to Redis, authenticated and with its database selected.
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    address: String,
+}
+
+impl Connection {
+    /// Connect to `redis://[[user]:password@]host[:port][/db]`.
+    pub fn open(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("redis://") else {
+            bail!("only redis:// URLs are supported, got a URL with another scheme");
+        };
+        let (credentials, rest) = match rest.rsplit_once('@') {
+            Some((credentials, rest)) => (Some(credentials), rest),
+            None => (None, rest),
+        };
+        let (host, db) = match rest.split_once('/') {
+            Some((host, db)) => (host, Some(db).filter(|db| !db.is_empty())),
+            None => (rest, None),
+        };
+        let address = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{host}:6379"),
+        };
+        let stream = TcpStream::connect(&address)
+            .with_context(|| format!("unable to connect to Redis at {address}"))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut connection = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            address,
+        };
+        if let Some(credentials) = credentials {
+            let credentials = percent_decode(credentials)?;
+            match credentials.split_once(':') {
+                Some(("", password)) => connection.command(&["AUTH", password])?,
+                Some((user, password)) => connection.command(&["AUTH", user, password])?,
+                None => connection.command(&["AUTH", &credentials])?,
+            }
+        }
+        if let Some(db) = db {
+            connection.command(&["SELECT", db])?;
+        }
+        connection.command(&["PING"])?;
+        Ok(connection)
+    }
+
+    /// Send a shard's commands, checking every reply.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let mut commands = BufReader::new(File::open(path)?);
+        loop {
+            let mut sent = 0;
+            while sent < PIPELINE && self.copy_command(&mut commands)? {
+                sent += 1;
+            }
+            self.writer.flush()?;
+            for _ in 0..sent {
+                self.reply()?;
+            }
+            if sent < PIPELINE {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Copy a command from a spool, by the lengths of its arguments; `false` at its end.
+    fn copy_command(&mut self, commands: &mut impl BufRead) -> Result<bool> {
+        let mut line = String::new();
+        if commands.read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+        self.writer.write_all(line.as_bytes())?;
+        let args: usize = line[1..].trim_end().parse()?;
+        for _ in 0..args {
+            line.clear();
+            commands.read_line(&mut line)?;
+            self.writer.write_all(line.as_bytes())?;
+            let len: usize = line[1..].trim_end().parse()?;
+            let mut arg = vec![0; len + 2];
+            commands.read_exact(&mut arg)?;
+            self.writer.write_all(&arg)?;
+        }
+        Ok(true)
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<()> {
+        write!(self.writer, "*{}\r\n", args.len())?;
+        for arg in args {
+            write!(self.writer, "${}\r\n{arg}\r\n", arg.len())?;
+        }
+        self.writer.flush()?;
+        self.reply()
+            .with_context(|| format!("Redis {} failed", args[0]))
+    }
+
+    /// Read a reply, failing on an error reply. Only the simple replies of the commands sent
+    /// here are expected: statuses, integers and bulk strings.
+    fn reply(&mut self) -> Result<()> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("Redis at {} closed the connection", self.address);
+        }
+        let line = line.trim_end();
+        match line.split_at(line.len().min(1)) {
+            ("+" | ":", _) => Ok(()),
+            ("-", error) => bail!("Redis at {} replied {error}", self.address),
+            ("$", len) => {
+                let len: i64 = len.parse().context("malformed Redis reply")?;
+                if len >= 0 {
+                    let mut bulk = vec![0; len as usize + 2];
+                    self.reader.read_exact(&mut bulk)?;
+                }
+                Ok(())
+            }
+            _ => bail!("unexpected Redis reply {line}"),
+        }
+    }
+}
+
+/// Decode the `%XX` escapes of a URL's credentials.
+fn percent_decode(text: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' && tail.len() >= 2 {
+            let hex = std::str::from_utf8(&tail[..2])?;
+            bytes.push(u8::from_str_radix(hex, 16).context("malformed escape in Redis URL")?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
@@ -0,0 +1,116 @@
+//! Polygons of multipolygon relations, joined from the lines of their member ways.
+
+use anyhow::Result;
+
+use super::point::Polygon;
+use crate::input::{Member, MemberType};
+
+/// Positions of a way, or of several joined end to end.
+type Line = Vec<[f64; 2]>;
+
+/// The polygons a relation's members could be joined into.
+pub struct Area {
+    pub polygons: Vec<Polygon>,
+    /// Whether every member way was available and every ring closed. An incomplete area
+    /// holds only the rings that did close; `lines` are what could not be joined.
+    pub complete: bool,
+    pub lines: Vec<Line>,
+}
+
+/// Join the `outer` (or unlabelled) and `inner` member ways into rings, each inner ring in
+/// the outer ring around it. `resolve` gives a way's positions, `None` if it is unknown.
+pub fn assemble(
+    members: &[Member],
+    mut resolve: impl FnMut(i64) -> Result<Option<Line>>,
+) -> Result<Area> {
+    let mut complete = true;
+    let (mut outer, mut inner) = (Vec::new(), Vec::new());
+    for member in members {
+        if member.member_type != MemberType::Way {
+            continue;
+        }
+        let lines = match member.role.as_str() {
+            "outer" | "" => &mut outer,
+            "inner" => &mut inner,
+            _ => continue,
+        };
+        match resolve(member.id)? {
+            Some(positions) if positions.len() >= 2 => lines.push(positions),
+            Some(_) => {}
+            None => complete = false,
+        }
+    }
+
+    let (outer_rings, mut lines) = join(outer);
+    let (inner_rings, inner_lines) = join(inner);
+    lines.extend(inner_lines);
+    complete &= lines.is_empty();
+
+    let mut polygons: Vec<Polygon> = outer_rings.into_iter().map(|ring| vec![ring]).collect();
+    for ring in inner_rings {
+        // An inner ring lies in the outer ring that contains its first vertex.
+        let around = polygons
+            .iter_mut()
+            .find(|polygon| contains(&polygon[0], ring[0]));
+        match around {
+            Some(polygon) => polygon.push(ring),
+            None => complete = false,
+        }
+    }
+    Ok(Area {
+        polygons,
+        complete,
+        lines,
+    })
+}
+
+/// Join lines end to end into closed rings, returning the rings and the lines left open.
+fn join(mut lines: Vec<Line>) -> (Vec<Line>, Vec<Line>) {
+    let (mut rings, mut open) = (Vec::new(), Vec::new());
+    while let Some(mut ring) = lines.pop() {
+        while ring.first() != ring.last() {
+            let (start, end) = (ring[0], ring[ring.len() - 1]);
+            let next = lines
+                .iter()
+                .position(|line| line[0] == end || line[line.len() - 1] == end);
+            if let Some(idx) = next {
+                let mut line = lines.swap_remove(idx);
+                if line[0] != end {
+                    line.reverse();
+                }
+                ring.extend_from_slice(&line[1..]);
+                continue;
+            }
+            let previous = lines
+                .iter()
+                .position(|line| line[0] == start || line[line.len() - 1] == start);
+            let Some(idx) = previous else {
+                break;
+            };
+            let mut line = lines.swap_remove(idx);
+            if line[line.len() - 1] != start {
+                line.reverse();
+            }
+            line.extend_from_slice(&ring[1..]);
+            ring = line;
+        }
+        if super::point::is_closed(&ring) {
+            rings.push(ring);
+        } else {
+            open.push(ring);
+        }
+    }
+    (rings, open)
+}
+
+/// Even-odd test of a point against a closed ring.
+pub fn contains(ring: &[[f64; 2]], [lon, lat]: [f64; 2]) -> bool {
+    let mut inside = false;
+    for pair in ring.windows(2) {
+        let ([x0, y0], [x1, y1]) = (pair[0], pair[1]);
+        if (y0 > lat) != (y1 > lat) && lon < x0 + (lat - y0) / (y1 - y0) * (x1 - x0) {
+            inside = !inside;
+        }
+    }
+    inside
+}
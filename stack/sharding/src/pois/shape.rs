@@ -0,0 +1,127 @@
+//! Areas read from GeoJSON polygons, such as admin boundaries and timezones, for placing
+//! POIs in. An area's edges are kept in horizontal bands, so a point is tested against the
+//! edges at its latitude only, however detailed the area is.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use super::read_text;
+use crate::tally::BBox;
+
+/// The polygon features of a GeoJSON FeatureCollection at a local path or object store URI
+/// (`what` it is, for errors), with their properties, keeping those that reach into any of
+/// `within`.
+pub fn read(location: &str, what: &str, within: &[BBox]) -> Result<Vec<(Value, Shape)>> {
+    let mut collection: Value = serde_json::from_str(&read_text(location, what)?)
+        .with_context(|| format!("invalid {what} {location}"))?;
+    let Value::Array(features) = collection["features"].take() else {
+        bail!("{what} {location} are not a GeoJSON FeatureCollection");
+    };
+    let mut shapes = Vec::new();
+    for mut feature in features {
+        let geometry = &feature["geometry"];
+        let rings: Vec<Vec<[f64; 2]>> = match geometry["type"].as_str() {
+            Some("Polygon") => polygon(&geometry["coordinates"]),
+            Some("MultiPolygon") => geometry["coordinates"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .flat_map(polygon)
+                .collect(),
+            _ => continue,
+        };
+        let Some(shape) = Shape::new(rings) else {
+            continue;
+        };
+        if within.iter().any(|bbox| shape.intersects(bbox)) {
+            shapes.push((feature["properties"].take(), shape));
+        }
+    }
+    Ok(shapes)
+}
+
+/// The rings of a GeoJSON polygon's coordinates.
+fn polygon(coordinates: &Value) -> Vec<Vec<[f64; 2]>> {
+    let position = |position: &Value| Some([position[0].as_f64()?, position[1].as_f64()?]);
+    coordinates
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|ring| {
+            ring.as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(position)
+                .collect()
+        })
+        .collect()
+}
+
+/// The rings of one area, outer and inner alike.
+pub struct Shape {
+    bbox: BBox,
+    /// Height of each band in degrees.
+    band: f64,
+    bands: Vec<Vec<[[f64; 2]; 2]>>,
+}
+
+impl Shape {
+    /// The shape of closed rings, `None` if it has no area.
+    pub fn new(rings: Vec<Vec<[f64; 2]>>) -> Option<Self> {
+        let edges: Vec<[[f64; 2]; 2]> = rings
+            .iter()
+            .flat_map(|ring| ring.windows(2).map(|pair| [pair[0], pair[1]]))
+            // Horizontal edges are never crossed.
+            .filter(|[a, b]| a[1] != b[1])
+            .collect();
+        let mut bbox = BBox {
+            west: f64::INFINITY,
+            south: f64::INFINITY,
+            east: f64::NEG_INFINITY,
+            north: f64::NEG_INFINITY,
+        };
+        for &[lon, lat] in edges.iter().flatten() {
+            bbox.west = bbox.west.min(lon);
+            bbox.south = bbox.south.min(lat);
+            bbox.east = bbox.east.max(lon);
+            bbox.north = bbox.north.max(lat);
+        }
+        if edges.is_empty() || bbox.north <= bbox.south {
+            return None;
+        }
+        let count = (edges.len() / 16).clamp(1, 4096);
+        let band = (bbox.north - bbox.south) / count as f64;
+        let mut bands = vec![Vec::new(); count];
+        for [a, b] in edges {
+            let (low, high) = (a[1].min(b[1]), a[1].max(b[1]));
+            let first = ((low - bbox.south) / band) as usize;
+            let last = (((high - bbox.south) / band) as usize).min(count - 1);
+            for edges in &mut bands[first..=last] {
+                edges.push([a, b]);
+            }
+        }
+        Some(Self { bbox, band, bands })
+    }
+
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.bbox.west <= other.east
+            && other.west <= self.bbox.east
+            && self.bbox.south <= other.north
+            && other.south <= self.bbox.north
+    }
+
+    /// Even-odd test of a point against every ring.
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        if !self.bbox.contains(lon, lat) {
+            return false;
+        }
+        let band = (((lat - self.bbox.south) / self.band) as usize).min(self.bands.len() - 1);
+        let mut inside = false;
+        for &[[x0, y0], [x1, y1]] in &self.bands[band] {
+            if (y0 > lat) != (y1 > lat) && lon < x0 + (lat - y0) / (y1 - y0) * (x1 - x0) {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+}
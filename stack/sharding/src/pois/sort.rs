@@ -0,0 +1,149 @@
+//! External sort of records for `merge`: records are buffered up to a memory limit, then
+//! sorted and written to a run file, and the runs are k-way merged back in key order.
+//! Records with equal keys come back in the order they were pushed.
+
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+
+pub type Key = (u64, u64);
+
+/// Bytes a buffered record takes besides its own: the key, the vector and its place in the
+/// buffer.
+const RECORD_OVERHEAD: usize = 48;
+
+pub struct Sorter {
+    limit: usize,
+    buffer: Vec<(Key, Vec<u8>)>,
+    bytes: usize,
+    runs: Vec<File>,
+}
+
+impl Sorter {
+    /// A sorter buffering up to `limit` bytes of records.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit: limit as usize,
+            buffer: Vec::new(),
+            bytes: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: Key, record: Vec<u8>) -> Result<()> {
+        self.bytes += record.len() + RECORD_OVERHEAD;
+        self.buffer.push((key, record));
+        if self.bytes > self.limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sort the buffered records and write them to a new run file.
+    fn spill(&mut self) -> Result<()> {
+        self.buffer.sort_by_key(|&(key, _)| key);
+        let mut out = BufWriter::new(tempfile::tempfile()?);
+        for ((first, second), record) in self.buffer.drain(..) {
+            out.write_all(&first.to_le_bytes())?;
+            out.write_all(&second.to_le_bytes())?;
+            out.write_all(&(record.len() as u32).to_le_bytes())?;
+            out.write_all(&record)?;
+        }
+        let mut file = out.into_inner().map_err(|err| err.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        self.runs.push(file);
+        self.bytes = 0;
+        Ok(())
+    }
+
+    /// All records pushed, in key order.
+    pub fn finish(mut self) -> Result<Sorted> {
+        if self.runs.is_empty() {
+            self.buffer.sort_by_key(|&(key, _)| key);
+            return Ok(Sorted::Memory(self.buffer.into_iter()));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut readers = Vec::with_capacity(self.runs.len());
+        let mut heads = Vec::with_capacity(self.runs.len());
+        let mut heap = BinaryHeap::with_capacity(self.runs.len());
+        for (idx, run) in self.runs.into_iter().enumerate() {
+            let mut reader = BufReader::new(run);
+            match next_record(&mut reader)? {
+                Some((key, record)) => {
+                    heap.push(Reverse((key, idx)));
+                    heads.push(record);
+                }
+                None => heads.push(Vec::new()),
+            }
+            readers.push(reader);
+        }
+        Ok(Sorted::Runs {
+            readers,
+            heads,
+            heap,
+        })
+    }
+}
+
+/// The sorted records, from memory or merged from the run files.
+pub enum Sorted {
+    Memory(std::vec::IntoIter<(Key, Vec<u8>)>),
+    Runs {
+        readers: Vec<BufReader<File>>,
+        /// Each run's next record, whose key is in the heap.
+        heads: Vec<Vec<u8>>,
+        heap: BinaryHeap<Reverse<(Key, usize)>>,
+    },
+}
+
+impl Sorted {
+    fn next_record(&mut self) -> Result<Option<(Key, Vec<u8>)>> {
+        match self {
+            Sorted::Memory(records) => Ok(records.next()),
+            Sorted::Runs {
+                readers,
+                heads,
+                heap,
+            } => {
+                let Some(Reverse((key, idx))) = heap.pop() else {
+                    return Ok(None);
+                };
+                let record = match next_record(&mut readers[idx])? {
+                    Some((next_key, next)) => {
+                        heap.push(Reverse((next_key, idx)));
+                        std::mem::replace(&mut heads[idx], next)
+                    }
+                    None => std::mem::take(&mut heads[idx]),
+                };
+                Ok(Some((key, record)))
+            }
+        }
+    }
+}
+
+impl Iterator for Sorted {
+    type Item = Result<(Key, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+fn next_record(reader: &mut impl Read) -> Result<Option<(Key, Vec<u8>)>> {
+    let mut header = [0u8; 20];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let first = u64::from_le_bytes(header[..8].try_into().expect("8 bytes"));
+    let second = u64::from_le_bytes(header[8..16].try_into().expect("8 bytes"));
+    let len = u32::from_le_bytes(header[16..].try_into().expect("4 bytes"));
+    let mut record = vec![0; len as usize];
+    reader.read_exact(&mut record)?;
+    Ok(Some(((first, second), record)))
+}
@@ -0,0 +1,85 @@
+//! Data-quality statistics of a shard's POIs, written as `<shard_id>.json` next to them:
+//! POIs per category, how many lack a name or any address, and the share of POIs that have
+//! each of the tags written with them.
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Write;
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+
+/// Counts of a shard's POIs as they are written.
+#[derive(Default)]
+pub struct Stats {
+    pois: u64,
+    categories: BTreeMap<String, u64>,
+    uncategorized: u64,
+    missing_name: u64,
+    missing_address: u64,
+    /// POIs with a value for each tag.
+    tags: BTreeMap<String, u64>,
+}
+
+/// The statistics file.
+#[derive(Serialize)]
+struct Report<'a> {
+    shard_id: &'a str,
+    pois: u64,
+    /// POIs by `category`, with those without one as `uncategorized`.
+    categories: &'a BTreeMap<String, u64>,
+    uncategorized: u64,
+    missing_name: u64,
+    /// POIs without any part of an address.
+    missing_address: u64,
+    /// Share of POIs with a value for each tag, from 0 to 1.
+    tag_completeness: BTreeMap<&'a str, f64>,
+}
+
+impl Stats {
+    pub fn add(&mut self, feature: &Feature) {
+        let properties = &feature.properties;
+        self.pois += 1;
+        match properties.get("category").and_then(Value::as_str) {
+            Some(category) => *self.categories.entry(category.to_string()).or_default() += 1,
+            None => self.uncategorized += 1,
+        }
+        self.missing_name += u64::from(properties.get("name").is_none_or(Value::is_null));
+        let addressed = properties
+            .get("address")
+            .and_then(Value::as_object)
+            .is_some_and(|address| address.values().any(|part| !part.is_null()));
+        self.missing_address += u64::from(!addressed);
+        if let Some(Value::Object(tags)) = properties.get("tags") {
+            for (key, value) in tags {
+                let count = self.tags.entry(key.clone()).or_default();
+                *count += u64::from(!value.is_null());
+            }
+        }
+    }
+
+    /// Write the statistics of shard `shard_id` to a file.
+    pub fn finish(&self, shard_id: &str) -> Result<NamedTempFile> {
+        let pois = self.pois.max(1) as f64;
+        let report = Report {
+            shard_id,
+            pois: self.pois,
+            categories: &self.categories,
+            uncategorized: self.uncategorized,
+            missing_name: self.missing_name,
+            missing_address: self.missing_address,
+            tag_completeness: self
+                .tags
+                .iter()
+                .map(|(key, &count)| (key.as_str(), (count as f64 / pois * 1e4).round() / 1e4))
+                .collect(),
+        };
+        let mut file = NamedTempFile::new()?;
+        serde_json::to_writer_pretty(&mut file, &report)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        Ok(file)
+    }
+}
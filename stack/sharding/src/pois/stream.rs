@@ -0,0 +1,268 @@
+//! Streamed POIs for `--sink kafka` and `--sink kinesis`: each POI published as a message,
+//! a GeoJSON feature with the `shard_id` it was extracted for and the `run_id`, if any, as
+//! foreign members, so consumers follow a run's POIs as they are extracted instead of
+//! polling the bucket. Messages are keyed by shard or by the geohash of the POI's point,
+//! which decides their partition or shard, and so which of them keep their order.
+//!
+//! A shard's messages are spooled, a key and a message per line, then published once it is
+//! extracted: to Kafka with `kcat`, or to Kinesis with `aws kinesis put-records`, retrying
+//! the records the stream throttles.
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tracing::warn;
+
+use super::dynamodb::geohash;
+use super::output::Feature;
+use crate::keys;
+use crate::store::run_cli;
+
+/// Most records, and bytes, in a `PutRecords` request.
+const KINESIS_RECORDS: usize = 500;
+const KINESIS_BYTES: usize = 5 << 20;
+/// Attempts of a Kinesis batch, throttled requests and failed records alike.
+const MAX_ATTEMPTS: u32 = 10;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// Errors of a request Kinesis turned away for capacity, which are worth retrying.
+const THROTTLED: [&str; 3] = [
+    "ProvisionedThroughputExceededException",
+    "ThrottlingException",
+    "KMSThrottlingException",
+];
+
+/// What the messages are keyed by.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum PartitionBy {
+    /// The shard's ID, so a shard's POIs stay in order on one partition.
+    Shard,
+    /// The geohash of the POI's point, cut to `--stream-geohash-precision`.
+    Geohash,
+}
+
+/// A shard's messages while they are written.
+pub struct Messages {
+    file: NamedTempFile,
+    out: BufWriter<File>,
+    shard_id: String,
+    partition_by: PartitionBy,
+    precision: usize,
+    count: u64,
+}
+
+impl Messages {
+    pub fn new(partition_by: PartitionBy, precision: u8, shard_id: &str) -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let out = BufWriter::new(file.reopen()?);
+        Ok(Self {
+            file,
+            out,
+            shard_id: shard_id.to_string(),
+            partition_by,
+            precision: usize::from(precision),
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let coordinates = &feature.geometry["coordinates"];
+        let key = match self.partition_by {
+            PartitionBy::Shard => self.shard_id.clone(),
+            PartitionBy::Geohash => match (coordinates[0].as_f64(), coordinates[1].as_f64()) {
+                (Some(lon), Some(lat)) => geohash(lon, lat, self.precision),
+                _ => self.shard_id.clone(),
+            },
+        };
+        let mut message = Map::with_capacity(6);
+        message.insert("type".into(), json!("Feature"));
+        message.insert("id".into(), json!(feature.id));
+        message.insert("geometry".into(), feature.geometry.clone());
+        message.insert("properties".into(), json!(feature.properties));
+        message.insert("shard_id".into(), json!(self.shard_id));
+        if let Some(run_id) = keys::run_id() {
+            message.insert("run_id".into(), json!(run_id));
+        }
+        // JSON escapes tabs and newlines, so neither delimiter can occur in a message.
+        write!(self.out, "{key}\t")?;
+        serde_json::to_writer(&mut self.out, &message)?;
+        self.out.write_all(b"\n")?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        self.out.flush()?;
+        Ok((self.file, self.count))
+    }
+}
+
+/// Where shards' messages are published.
+pub enum Publisher {
+    Kafka {
+        brokers: String,
+        topic: String,
+        properties: Vec<String>,
+    },
+    Kinesis {
+        stream: String,
+    },
+}
+
+impl Publisher {
+    /// Check that the topic or stream can be reached, so a run fails before reading its
+    /// input rather than at its first shard.
+    pub fn kafka(brokers: &str, topic: &str, properties: &[String]) -> Result<Self> {
+        let stdout = kcat(brokers, topic, properties, &["-L", "-J"])?;
+        let metadata: Value =
+            serde_json::from_slice(&stdout).context("unexpected kcat metadata")?;
+        let found = metadata["topics"].as_array().and_then(|topics| {
+            topics
+                .iter()
+                .find(|found| found["topic"].as_str() == Some(topic))
+        });
+        if let Some(error) = found.and_then(|found| found["error"].as_str()) {
+            bail!("unable to publish to Kafka topic {topic}: {error}");
+        }
+        Ok(Self::Kafka {
+            brokers: brokers.to_string(),
+            topic: topic.to_string(),
+            properties: properties.to_vec(),
+        })
+    }
+
+    /// See [`Publisher::kafka`].
+    pub fn kinesis(stream: &str) -> Result<Self> {
+        let args = [
+            "kinesis",
+            "describe-stream-summary",
+            "--output",
+            "json",
+            "--stream-name",
+            stream,
+        ];
+        if let Err(stderr) = run_cli("aws", &args)? {
+            bail!("unable to describe Kinesis stream {stream}: {stderr}");
+        }
+        Ok(Self::Kinesis {
+            stream: stream.to_string(),
+        })
+    }
+
+    /// Publish the messages of a shard's file.
+    pub fn publish(&self, path: &Path) -> Result<()> {
+        match self {
+            Self::Kafka {
+                brokers,
+                topic,
+                properties,
+            } => {
+                let path = path.to_string_lossy();
+                kcat(brokers, topic, properties, &["-P", "-K", "\t", "-l", &path])?;
+                Ok(())
+            }
+            Self::Kinesis { stream } => {
+                let mut batch = Vec::with_capacity(KINESIS_RECORDS);
+                let mut bytes = 0;
+                for line in BufReader::new(File::open(path)?).lines() {
+                    let line = line?;
+                    let Some((key, message)) = line.split_once('\t') else {
+                        bail!("malformed message in {}", path.display());
+                    };
+                    let full = bytes + key.len() + message.len() > KINESIS_BYTES;
+                    if batch.len() == KINESIS_RECORDS || (full && !batch.is_empty()) {
+                        put_records(stream, std::mem::take(&mut batch))?;
+                        bytes = 0;
+                    }
+                    bytes += key.len() + message.len();
+                    let data = base64::engine::general_purpose::STANDARD.encode(message);
+                    batch.push(json!({"Data": data, "PartitionKey": key}));
+                }
+                if !batch.is_empty() {
+                    put_records(stream, batch)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Run `kcat` against a topic, with the brokers and `-X` properties.
+fn kcat(brokers: &str, topic: &str, properties: &[String], args: &[&str]) -> Result<Vec<u8>> {
+    let mut all = vec!["-b", brokers, "-t", topic];
+    for property in properties {
+        all.extend(["-X", property.as_str()]);
+    }
+    all.extend(args);
+    match run_cli("kcat", &all)? {
+        Ok(stdout) => Ok(stdout),
+        Err(stderr) => bail!("unable to publish to Kafka topic {topic}: {stderr}"),
+    }
+}
+
+/// Put a batch of records, retrying those the stream throttles until all of it is put.
+fn put_records(stream: &str, mut records: Vec<Value>) -> Result<()> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let mut body = NamedTempFile::new()?;
+        serde_json::to_writer(&mut body, &records)?;
+        body.flush()?;
+        let path = format!("file://{}", body.path().display());
+        let args = [
+            "kinesis",
+            "put-records",
+            "--output",
+            "json",
+            "--stream-name",
+            stream,
+            "--records",
+            &path,
+        ];
+        match run_cli("aws", &args)? {
+            Ok(stdout) => {
+                let response: Value =
+                    serde_json::from_slice(&stdout).context("unexpected put-records response")?;
+                if response["FailedRecordCount"].as_u64().unwrap_or(0) == 0 {
+                    return Ok(());
+                }
+                // Results are in the order of the records; failed ones have an error code.
+                let results = response["Records"].as_array().map(Vec::as_slice);
+                let failed: Vec<(Value, &Value)> = records
+                    .into_iter()
+                    .zip(results.unwrap_or_default())
+                    .filter(|(_, result)| result.get("ErrorCode").is_some())
+                    .collect();
+                if attempt == MAX_ATTEMPTS {
+                    bail!(
+                        "Kinesis stream {stream} still failed {} records after {attempt} \
+                         attempts, the first with {}",
+                        failed.len(),
+                        failed.first().map_or(&Value::Null, |(_, result)| result)
+                    );
+                }
+                records = failed.into_iter().map(|(record, _)| record).collect();
+            }
+            Err(stderr)
+                if attempt < MAX_ATTEMPTS && THROTTLED.iter().any(|e| stderr.contains(e)) =>
+            {
+                warn!(
+                    attempt,
+                    "Puts to Kinesis stream {stream} throttled (attempt {attempt} of \
+                     {MAX_ATTEMPTS}), retrying in {backoff:?}."
+                );
+            }
+            Err(stderr) => bail!("unable to put records to Kinesis stream {stream}: {stderr}"),
+        }
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        attempt += 1;
+    }
+}
@@ -0,0 +1,143 @@
+//! Tantivy index of merged POIs for `merge --tantivy`, for an autocomplete service to
+//! download and open instead of indexing a run's POIs itself: a document per POI with its
+//! `id`, `name`, `alt_names`, `category`, `subcategory`, `lat` and `lon`, all stored.
+//! Names are tokenized with tantivy's `default` tokenizer, for term and phrase prefix
+//! queries; the ID and categories are indexed whole, and the coordinates are fast fields,
+//! for range filters and sorting by distance.
+//!
+//! The index is built with `tantivy-cli` from the schema in [`meta`], merged into a single
+//! segment and packed into a gzipped tarball of the index directory. The service must open
+//! it with a tantivy whose index format matches the CLI's.
+
+use anyhow::{bail, Result};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use tempfile::NamedTempFile;
+
+use super::output::Feature;
+use crate::store::run_cli;
+
+/// Tags of other names a POI is known by, `;`-separated as OSM lists them.
+const ALT_NAME_TAGS: [&str; 4] = ["alt_name", "official_name", "short_name", "old_name"];
+
+/// The merged POIs' documents while they are written, one JSON object per line as
+/// `tantivy index` reads them.
+pub struct Documents {
+    file: NamedTempFile,
+    out: BufWriter<File>,
+}
+
+impl Documents {
+    pub fn new() -> Result<Self> {
+        let file = NamedTempFile::new()?;
+        let out = BufWriter::new(file.reopen()?);
+        Ok(Self { file, out })
+    }
+
+    pub fn write(&mut self, feature: &Feature) -> Result<()> {
+        let properties = &feature.properties;
+        let text = |key: &str| properties.get(key).and_then(Value::as_str);
+        let mut document = Map::new();
+        document.insert("id".into(), json!(feature.id));
+        // Tantivy takes a missing field for no value; a null is not a value of any type.
+        for key in ["name", "category", "subcategory"] {
+            if let Some(value) = text(key) {
+                document.insert(key.into(), json!(value));
+            }
+        }
+        let tags = &properties["tags"];
+        let alt_names: BTreeSet<&str> = properties["names"]
+            .as_object()
+            .into_iter()
+            .flat_map(Map::values)
+            .filter_map(Value::as_str)
+            .chain(
+                ALT_NAME_TAGS
+                    .iter()
+                    .filter_map(|key| tags[key].as_str())
+                    .flat_map(|names| names.split(';').map(str::trim)),
+            )
+            .filter(|name| !name.is_empty() && Some(*name) != text("name"))
+            .collect();
+        if !alt_names.is_empty() {
+            document.insert("alt_names".into(), json!(alt_names));
+        }
+        let coordinates = &feature.geometry["coordinates"];
+        if let (Some(lon), Some(lat)) = (coordinates[0].as_f64(), coordinates[1].as_f64()) {
+            document.insert("lat".into(), json!(lat));
+            document.insert("lon".into(), json!(lon));
+        }
+        serde_json::to_writer(&mut self.out, &document)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Index the documents, handing back the index as a gzipped tarball.
+    pub fn finish(mut self) -> Result<NamedTempFile> {
+        self.out.flush()?;
+        let dir = tempfile::tempdir()?;
+        let index = dir.path().join("index");
+        fs::create_dir(&index)?;
+        fs::write(index.join("meta.json"), serde_json::to_vec_pretty(&meta())?)?;
+        let index = index.to_string_lossy();
+        let documents = self.file.path().to_string_lossy();
+        tantivy(&["index", "--index", &index, "--file", &documents])?;
+        tantivy(&["merge", "--index", &index])?;
+
+        let tarball = NamedTempFile::new()?;
+        let args = ["-czf", &tarball.path().to_string_lossy(), "-C", &index, "."];
+        if let Err(stderr) = run_cli("tar", &args)? {
+            bail!("unable to pack the tantivy index: {stderr}");
+        }
+        Ok(tarball)
+    }
+}
+
+/// `meta.json` of an empty index with the documents' schema.
+fn meta() -> Value {
+    let text = |name: &str, tokenizer: &str| {
+        let (record, fieldnorms) = match tokenizer {
+            "raw" => ("basic", false),
+            _ => ("position", true),
+        };
+        json!({
+            "name": name,
+            "type": "text",
+            "options": {
+                "indexing": {"record": record, "fieldnorms": fieldnorms, "tokenizer": tokenizer},
+                "stored": true,
+                "fast": false,
+            },
+        })
+    };
+    let coordinate = |name: &str| {
+        json!({
+            "name": name,
+            "type": "f64",
+            "options": {"indexed": true, "fieldnorms": false, "fast": true, "stored": true},
+        })
+    };
+    json!({
+        "index_settings": {"docstore_compression": "lz4", "docstore_blocksize": 16384},
+        "segments": [],
+        "schema": [
+            text("id", "raw"),
+            text("name", "default"),
+            text("alt_names", "default"),
+            text("category", "raw"),
+            text("subcategory", "raw"),
+            coordinate("lat"),
+            coordinate("lon"),
+        ],
+        "opstamp": 0,
+    })
+}
+
+fn tantivy(args: &[&str]) -> Result<()> {
+    if let Err(stderr) = run_cli("tantivy", args)? {
+        bail!("tantivy {} failed: {stderr}", args[0]);
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! POI taxonomy: rules mapping OSM tags to a category and subcategory, read from JSON:
+//!
+//! ```json
+//! {"rules": [
+//!   {"category": "food_and_drink", "subcategory": "cafe", "filter": "amenity=cafe"},
+//!   {"category": "lodging", "filter": "tourism in (hotel, hostel, guest_house)"},
+//!   {"category": "food_and_drink", "subcategory": "cafe", "priority": 1,
+//!    "filter": "shop=coffee and cuisine=coffee_shop"}
+//! ]}
+//! ```
+//!
+//! Each rule's `filter` is a `--filter` expression. When several rules match, the one with
+//! the highest `priority` (default 0) wins, and among equal priorities the one listed first.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::cmp::Reverse;
+
+use super::filter::Filter;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaxonomyFile {
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    category: String,
+    subcategory: Option<String>,
+    filter: String,
+    #[serde(default)]
+    priority: i32,
+}
+
+pub struct Rule {
+    pub category: String,
+    pub subcategory: Option<String>,
+    filter: Filter,
+}
+
+/// Rules in the order they are tried: highest priority first, then as listed.
+pub struct Taxonomy(Vec<Rule>);
+
+impl Taxonomy {
+    pub fn parse(json: &str) -> Result<Self> {
+        let file: TaxonomyFile = serde_json::from_str(json)?;
+        let mut rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(idx, spec)| {
+                let filter = Filter::parse(&spec.filter)
+                    .with_context(|| format!("rule {} ({})", idx + 1, spec.category))?;
+                let rule = Rule {
+                    category: spec.category,
+                    subcategory: spec.subcategory,
+                    filter,
+                };
+                Ok((spec.priority, rule))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Stable, so equal priorities keep the file's order.
+        rules.sort_by_key(|(priority, _)| Reverse(*priority));
+        Ok(Self(rules.into_iter().map(|(_, rule)| rule).collect()))
+    }
+
+    /// The rule that classifies an element, if any matches.
+    pub fn classify(&self, tags: &[(String, String)]) -> Option<&Rule> {
+        self.0.iter().find(|rule| rule.filter.matches(tags))
+    }
+}
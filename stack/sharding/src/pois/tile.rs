@@ -0,0 +1,214 @@
+//! `tile` subcommand: merged POIs cut into a vector tileset of their own, from zoom 0 up to
+//! a max zoom, with [`Rules`] for which categories show from which zoom and how densely,
+//! and the same `pois` layer and attributes as `merge`'s PMTiles archive.
+//!
+//! The tiles are written as `{z}/{x}/{y}.mvt` files, uncompressed, with a TileJSON
+//! `metadata.json` next to them, as one `pois.pmtiles` archive, which viewers read with
+//! range requests straight from a bucket, or as one `pois.mbtiles` database: see
+//! [`super::mbtiles`].
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use flate2::read::GzDecoder;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::io::Read;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+
+use super::mbtiles;
+use super::merge::read_features;
+use super::sort::Sorter;
+use super::tiles::{self, Rules, Tiler};
+use crate::keys;
+use crate::spill;
+use crate::store::{self, Store};
+use crate::task;
+
+/// Tiles uploaded at a time.
+const BATCH: usize = 256;
+
+#[derive(Args, Debug)]
+pub struct TileArgs {
+    /// Merged POIs: `merge --ndjson`'s `pois.ndjson`, or a directory or object store prefix
+    /// of GeoJSON or NDJSON files of POIs, each POI once.
+    #[arg(env = "POI_INPUT")]
+    input: String,
+
+    /// Where to write the tiles: a local directory, or an object store bucket or prefix URI
+    /// under which each file is keyed by `--tiles-key-template`.
+    #[arg(short, long, env = "TILES_OUTPUT")]
+    output: String,
+
+    /// Key of each file under `--output`, with the placeholders of `--s3-key-template`;
+    /// `{name}` is `{z}/{x}/{y}.mvt` of each tile and `metadata.json`, `pois.pmtiles` or
+    /// `pois.mbtiles`.
+    #[arg(
+        long,
+        env = "TILES_KEY_TEMPLATE",
+        default_value = "runs/{run_id}/tiles/{name}"
+    )]
+    tiles_key_template: String,
+
+    /// How the tileset is written.
+    #[arg(long, env = "TILE_FORMAT", value_enum, default_value_t = TileFormat::Directory)]
+    format: TileFormat,
+
+    /// Highest zoom level of the tiles; viewers overzoom past it.
+    #[arg(
+        long,
+        env = "POI_TILES_MAX_ZOOM",
+        default_value_t = 14,
+        value_parser = clap::value_parser!(u8).range(0..=14)
+    )]
+    max_zoom: u8,
+
+    /// JSON rules for which POIs zooms below the max zoom keep: a local path or object
+    /// store URI. By default, one POI per 16 × 16 tile units.
+    #[arg(long, env = "TILE_RULES")]
+    rules: Option<String>,
+
+    /// Memory for sorting the POIs, beyond which they are spilled to temporary files.
+    #[arg(
+        long,
+        env = "MEMORY_LIMIT",
+        default_value = "1G",
+        value_parser = spill::parse_byte_size
+    )]
+    memory_limit: u64,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TileFormat {
+    /// One `{z}/{x}/{y}.mvt` file per tile.
+    Directory,
+    /// A PMTiles archive.
+    Pmtiles,
+    /// An MBTiles database.
+    Mbtiles,
+}
+
+pub fn run(args: &TileArgs) -> Result<()> {
+    let input = keys::expand(&args.input)?;
+    let listed = match Path::new(input.strip_prefix("file://").unwrap_or(&input)).is_file() {
+        true => vec![input.clone()],
+        false => store::list(&input)?,
+    };
+    let (files, skipped): (Vec<String>, Vec<String>) = listed
+        .into_iter()
+        .partition(|location| location.ends_with(".geojson") || location.ends_with(".ndjson"));
+    if !skipped.is_empty() {
+        warn!(
+            files = skipped.len(),
+            "Skipped {} files under {input} that are neither GeoJSON nor NDJSON.",
+            skipped.len()
+        );
+    }
+    if files.is_empty() {
+        bail!("no GeoJSON or NDJSON POIs under {input}");
+    }
+    let rules = match &args.rules {
+        Some(location) => Rules::read(location)?,
+        None => Rules::default(),
+    };
+    info!(
+        files = files.len(),
+        "Tiling the POIs of {} files under {input}...",
+        files.len()
+    );
+
+    // In tile order, then in the order they were read.
+    let mut by_tile = Sorter::new(args.memory_limit);
+    let mut pois = 0u64;
+    let batch = rayon::current_num_threads();
+    for locations in files.chunks(batch) {
+        let features = locations
+            .par_iter()
+            .map(|location| read_features(location))
+            .collect::<Result<Vec<_>>>()?;
+        for feature in features.into_iter().flatten() {
+            let (lon, lat) = lon_lat(&feature);
+            let record = serde_json::to_vec(&feature)?;
+            by_tile.push((tiles::order(args.max_zoom, lon, lat), pois), record)?;
+            pois += 1;
+        }
+    }
+
+    let mut tiler = Tiler::new(args.max_zoom, rules)?;
+    for record in by_tile.finish()? {
+        let (_, record) = record?;
+        let feature: Value = serde_json::from_slice(&record)?;
+        let (lon, lat) = lon_lat(&feature);
+        let properties = &feature["properties"];
+        let properties = [
+            feature["id"].as_str(),
+            properties["name"].as_str(),
+            properties["category"].as_str(),
+            properties["subcategory"].as_str(),
+        ];
+        tiler.push(lon, lat, &properties)?;
+    }
+
+    let (store, tiles) = match args.format {
+        TileFormat::Directory => write_directory(args, tiler)?,
+        TileFormat::Pmtiles => put_file(args, "pois.pmtiles", tiler.finish()?)?,
+        TileFormat::Mbtiles => put_file(args, "pois.mbtiles", mbtiles::write(tiler)?)?,
+    };
+    info!(
+        destination = %store,
+        pois = pois,
+        tiles = tiles,
+        "Tiled {pois} POIs into {tiles} tiles in {store}."
+    );
+    task::record_output(store.to_string());
+    Ok(())
+}
+
+/// Upload each tile, then the TileJSON, handing back where it is with the number of tiles.
+fn write_directory(args: &TileArgs, tiler: Tiler) -> Result<(Store, u64)> {
+    let mut tilejson = tiler.tilejson();
+    tilejson["tiles"] = json!(["{z}/{x}/{y}.mvt"]);
+    let mut pending = Vec::with_capacity(BATCH);
+    let tiles = tiler.finish_tiles(|z, x, y, tile| {
+        pending.push((format!("{z}/{x}/{y}.mvt"), tile));
+        if pending.len() == BATCH {
+            put_tiles(args, &mut pending)?;
+        }
+        Ok(())
+    })?;
+    put_tiles(args, &mut pending)?;
+    let store = Store::open_in(&args.output, &args.tiles_key_template, "metadata.json")?;
+    store.put(serde_json::to_vec_pretty(&tilejson)?)?;
+    Ok((store, tiles))
+}
+
+/// Upload a tileset of one file, handing back where it is with the number of tiles.
+fn put_file(
+    args: &TileArgs,
+    name: &str,
+    (file, tiles): (NamedTempFile, u64),
+) -> Result<(Store, u64)> {
+    let store = Store::open_in(&args.output, &args.tiles_key_template, name)?;
+    store.put_file(file.path(), None)?;
+    Ok((store, tiles))
+}
+
+/// Upload gzipped tiles uncompressed, emptying `pending`.
+fn put_tiles(args: &TileArgs, pending: &mut Vec<(String, Vec<u8>)>) -> Result<()> {
+    pending.par_drain(..).try_for_each(|(name, tile)| {
+        let mut mvt = Vec::new();
+        GzDecoder::new(tile.as_slice())
+            .read_to_end(&mut mvt)
+            .with_context(|| format!("unable to decompress tile {name}"))?;
+        Store::open_in(&args.output, &args.tiles_key_template, &name)?.put(mvt)
+    })
+}
+
+/// Position of a POI, NaN where it has none.
+fn lon_lat(feature: &Value) -> (f64, f64) {
+    let coordinates = &feature["geometry"]["coordinates"];
+    let lon = coordinates[0].as_f64().unwrap_or(f64::NAN);
+    let lat = coordinates[1].as_f64().unwrap_or(f64::NAN);
+    (lon, lat)
+}
@@ -0,0 +1,335 @@
+//! Vector tiles of merged POIs: a `pois` layer of points from zoom 0 up to a max zoom, with
+//! every POI at the max zoom and, below it, the first POI in each 16 × 16 unit cell of a
+//! tile, so low zoom tiles stay small. [`Rules`] change that per category: from which zoom
+//! its POIs show, and how far apart they are kept.
+//!
+//! POIs come in PMTiles tile id order at the max zoom, which is tile id order at every zoom
+//! below it as well (the Hilbert curve nests), so all zooms are cut in one pass, each into
+//! a spool file of its own, and the spools are concatenated into a PMTiles archive or read
+//! back tile by tile.
+
+use anyhow::{bail, Context, Result};
+use hashbrown::{HashMap, HashSet};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+use super::read_text;
+use crate::lon_lat_to_tile;
+use crate::pmtiles::{self, Directory, PointLayer, Tileset, EXTENT};
+
+const LAYER: &str = "pois";
+/// Properties of each point, in the order [`Tiler::push`] takes them.
+pub const KEYS: [&str; 4] = ["id", "name", "category", "subcategory"];
+/// Bits of the tile units: an extent of 4096.
+const EXTENT_BITS: u8 = 12;
+/// Tile units per side of a cell below the max zoom, by default.
+const SPACING: u32 = 16;
+
+/// Position of a POI in world tile units at `max_zoom`, `None` if it has none.
+fn position(max_zoom: u8, lon: f64, lat: f64) -> Option<(u32, u32)> {
+    debug_assert_eq!(1 << EXTENT_BITS, EXTENT);
+    lon_lat_to_tile(lon, lat, max_zoom + EXTENT_BITS)
+}
+
+/// Sort key of a POI for [`Tiler::push`]: the tile id of its tile at `max_zoom`, or last
+/// if it has no position.
+pub fn order(max_zoom: u8, lon: f64, lat: f64) -> u64 {
+    position(max_zoom, lon, lat).map_or(u64::MAX, |(x, y)| {
+        pmtiles::tile_id(max_zoom, x >> EXTENT_BITS, y >> EXTENT_BITS)
+    })
+}
+
+/// Which POIs the zooms below the max zoom keep, as JSON:
+///
+/// ```json
+/// {"spacing": 32, "categories": {"health": {"spacing": 8}, "shop": {"min_zoom": 12}}}
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    /// Tile units per side of the cells that keep one POI each; 0 keeps every POI.
+    #[serde(default = "default_spacing")]
+    spacing: u32,
+    #[serde(default)]
+    categories: BTreeMap<String, CategoryRule>,
+}
+
+/// Rules for the POIs of one category. A category with a `spacing` of its own is thinned
+/// apart from the others, so its POIs are not crowded out by theirs.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CategoryRule {
+    /// Lowest zoom the category's POIs show at; at the max zoom every POI does.
+    #[serde(default)]
+    min_zoom: u8,
+    spacing: Option<u32>,
+}
+
+fn default_spacing() -> u32 {
+    SPACING
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            spacing: SPACING,
+            categories: BTreeMap::new(),
+        }
+    }
+}
+
+impl Rules {
+    /// Read rules from a local path or object store URI.
+    pub fn read(location: &str) -> Result<Self> {
+        let rules: Self = serde_json::from_str(&read_text(location, "tile rules")?)
+            .with_context(|| format!("invalid tile rules {location}"))?;
+        let spacings = std::iter::once(rules.spacing)
+            .chain(rules.categories.values().filter_map(|rule| rule.spacing));
+        for spacing in spacings {
+            if spacing > EXTENT {
+                bail!(
+                    "tile rules {location}: a spacing of {spacing} is wider than a tile ({EXTENT})"
+                );
+            }
+        }
+        Ok(rules)
+    }
+}
+
+/// How a POI is thinned: the occupancy of which cells it goes by, the lowest zoom it shows
+/// at and the size of its cells.
+#[derive(Clone, Copy)]
+struct Thinning {
+    slot: u64,
+    min_zoom: u8,
+    spacing: u32,
+}
+
+/// The tiles of one zoom.
+struct Level {
+    zoom: u8,
+    tile: Option<(u32, u32)>,
+    layer: PointLayer,
+    /// Cells of the tile that have a POI, below the max zoom, by slot.
+    occupied: HashSet<u64>,
+    spool: BufWriter<File>,
+    /// Tile column and row, offset in the spool and length of each tile written.
+    tiles: Vec<(u32, u32, u64, u32)>,
+    len: u64,
+}
+
+impl Level {
+    fn flush(&mut self) -> Result<()> {
+        let Some((x, y)) = self.tile.take() else {
+            return Ok(());
+        };
+        let layer = std::mem::replace(&mut self.layer, PointLayer::new(LAYER, &KEYS));
+        self.occupied.clear();
+        if layer.is_empty() {
+            return Ok(());
+        }
+        let tile = layer.finish()?;
+        self.spool.write_all(&tile)?;
+        self.tiles.push((x, y, self.len, tile.len() as u32));
+        self.len += tile.len() as u64;
+        Ok(())
+    }
+}
+
+/// A POI tileset while it is cut.
+pub struct Tiler {
+    max_zoom: u8,
+    levels: Vec<Level>,
+    bounds: [f64; 4],
+    default: Thinning,
+    categories: HashMap<String, Thinning>,
+}
+
+impl Tiler {
+    pub fn new(max_zoom: u8, rules: Rules) -> Result<Self> {
+        let levels = (0..=max_zoom)
+            .map(|zoom| {
+                Ok(Level {
+                    zoom,
+                    tile: None,
+                    layer: PointLayer::new(LAYER, &KEYS),
+                    occupied: HashSet::new(),
+                    spool: BufWriter::new(tempfile::tempfile()?),
+                    tiles: Vec::new(),
+                    len: 0,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            max_zoom,
+            levels,
+            bounds: [
+                f64::INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+            ],
+            default: Thinning {
+                slot: 0,
+                min_zoom: 0,
+                spacing: rules.spacing,
+            },
+            categories: rules
+                .categories
+                .into_iter()
+                .enumerate()
+                .map(|(slot, (category, rule))| {
+                    let thinning = Thinning {
+                        slot: rule.spacing.map_or(0, |_| slot as u64 + 1),
+                        min_zoom: rule.min_zoom,
+                        spacing: rule.spacing.unwrap_or(rules.spacing),
+                    };
+                    (category, thinning)
+                })
+                .collect(),
+        })
+    }
+
+    /// Add a POI, in [`order`], with its value for each of [`KEYS`] that it has.
+    pub fn push(&mut self, lon: f64, lat: f64, properties: &[Option<&str>]) -> Result<()> {
+        let Some((x, y)) = position(self.max_zoom, lon, lat) else {
+            return Ok(());
+        };
+        self.bounds = [
+            self.bounds[0].min(lon),
+            self.bounds[1].min(lat),
+            self.bounds[2].max(lon),
+            self.bounds[3].max(lat),
+        ];
+        let thinning = properties[2]
+            .and_then(|category| self.categories.get(category))
+            .copied()
+            .unwrap_or(self.default);
+        let mask = EXTENT - 1;
+        for level in &mut self.levels {
+            let shift = self.max_zoom - level.zoom;
+            let (x, y) = (x >> shift, y >> shift);
+            let tile = (x >> EXTENT_BITS, y >> EXTENT_BITS);
+            if level.tile != Some(tile) {
+                level.flush()?;
+                level.tile = Some(tile);
+            }
+            let (x, y) = (x & mask, y & mask);
+            if shift > 0 {
+                if level.zoom < thinning.min_zoom {
+                    continue;
+                }
+                let cells = x
+                    .checked_div(thinning.spacing)
+                    .zip(y.checked_div(thinning.spacing));
+                if let Some((column, row)) = cells {
+                    let cell = thinning.slot << 32 | u64::from(column) << 16 | u64::from(row);
+                    if !level.occupied.insert(cell) {
+                        continue;
+                    }
+                }
+            }
+            level.layer.push(x as i32, y as i32, properties);
+        }
+        Ok(())
+    }
+
+    /// Write the archive, handing it back with the number of tiles in it.
+    pub fn finish(mut self) -> Result<(NamedTempFile, u64)> {
+        let mut directory = Directory::default();
+        let mut data_len = 0;
+        for level in &mut self.levels {
+            level.flush()?;
+            for &(x, y, offset, length) in &level.tiles {
+                directory.push(
+                    pmtiles::tile_id(level.zoom, x, y),
+                    data_len + offset,
+                    length,
+                );
+            }
+            data_len += level.len;
+        }
+        let tileset = Tileset {
+            min_zoom: 0,
+            max_zoom: self.max_zoom,
+            bounds: self.bounds(),
+            contents: directory.addressed(),
+            metadata: self.metadata(),
+        };
+
+        let file = NamedTempFile::new()?;
+        let mut out = BufWriter::new(file.reopen()?);
+        pmtiles::write_archive(&mut out, &directory, &tileset, data_len, |out| {
+            for level in self.levels {
+                let mut spool = level.spool.into_inner().map_err(|err| err.into_error())?;
+                spool.seek(SeekFrom::Start(0))?;
+                io::copy(&mut spool, out)?;
+            }
+            Ok(())
+        })?;
+        out.flush()?;
+        Ok((file, directory.addressed()))
+    }
+
+    /// Hand each gzipped tile to `write` with its zoom, column and row, zoom by zoom,
+    /// returning the number of tiles.
+    pub fn finish_tiles(
+        self,
+        mut write: impl FnMut(u8, u32, u32, Vec<u8>) -> Result<()>,
+    ) -> Result<u64> {
+        let mut tiles = 0;
+        for mut level in self.levels {
+            level.flush()?;
+            let mut spool = level.spool.into_inner().map_err(|err| err.into_error())?;
+            spool.seek(SeekFrom::Start(0))?;
+            for (x, y, _, length) in level.tiles {
+                let mut tile = vec![0; length as usize];
+                spool.read_exact(&mut tile)?;
+                write(level.zoom, x, y, tile)?;
+                tiles += 1;
+            }
+        }
+        Ok(tiles)
+    }
+
+    /// TileJSON of the tileset so far, without the URLs of its tiles.
+    pub fn tilejson(&self) -> Value {
+        let mut tilejson = self.metadata();
+        tilejson["tilejson"] = json!("3.0.0");
+        tilejson["minzoom"] = json!(0);
+        tilejson["maxzoom"] = json!(self.max_zoom);
+        tilejson["bounds"] = json!(self.bounds());
+        tilejson
+    }
+
+    /// Bounds of the POIs, or of the world if there are none.
+    fn bounds(&self) -> [f64; 4] {
+        if self.bounds[0].is_finite() {
+            self.bounds
+        } else {
+            [-180.0, -85.051_128_78, 180.0, 85.051_128_78]
+        }
+    }
+
+    fn metadata(&self) -> Value {
+        json!({
+            "name": "pois",
+            "description": "POIs merged by osm-planet-sharding",
+            "vector_layers": [{
+                "id": LAYER,
+                "minzoom": 0,
+                "maxzoom": self.max_zoom,
+                "fields": {
+                    "id": "String",
+                    "name": "String",
+                    "category": "String",
+                    "subcategory": "String",
+                },
+            }],
+        })
+    }
+}
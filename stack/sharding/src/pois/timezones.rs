@@ -0,0 +1,46 @@
+//! IANA timezones for `--timezones`: each POI gets the `timezone` (`America/Denver`) of
+//! the zone its point is in, from the GeoJSON of timezone-boundary-builder
+//! (<https://github.com/evansiroky/timezone-boundary-builder>), whose `tzid` properties name
+//! the zones. The container image bundles its release with oceans, so POIs out at sea get
+//! a zone such as `Etc/GMT+7` rather than none.
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use tracing::info;
+
+use super::shape::{self, Shape};
+use crate::tally::BBox;
+
+/// The timezones around the shards.
+pub struct Timezones {
+    zones: Vec<(String, Shape)>,
+}
+
+impl Timezones {
+    /// Read the timezone boundaries at a local path or object store URI, keeping the zones
+    /// that reach into any of `within`.
+    pub fn read(location: &str, within: &[BBox]) -> Result<Self> {
+        let zones: Vec<(String, Shape)> = shape::read(location, "timezones", within)?
+            .into_iter()
+            .filter_map(|(properties, shape)| {
+                Some((properties["tzid"].as_str()?.to_string(), shape))
+            })
+            .collect();
+        info!(
+            zones = zones.len(),
+            "Placing POIs in {} timezones around the shards.",
+            zones.len()
+        );
+        Ok(Self { zones })
+    }
+
+    /// Add the `timezone` of a point to a POI's properties, null outside every zone.
+    pub fn insert(&self, [lon, lat]: [f64; 2], properties: &mut Map<String, Value>) {
+        let zone = self
+            .zones
+            .iter()
+            .find(|(_, shape)| shape.contains(lon, lat))
+            .map(|(tzid, _)| tzid);
+        properties.insert("timezone".into(), json!(zone));
+    }
+}
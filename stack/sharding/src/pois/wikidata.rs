@@ -0,0 +1,259 @@
+//! Wikidata items of POIs: the item of a `wikidata=Q…` tag, or the one a `wikipedia=en:…`
+//! article belongs to, with its labels. Items are resolved in batches once the input has
+//! been read, from a lookup file of Wikidata entities, so workers need no network, or from
+//! the Wikidata API through `curl`.
+
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+use crate::store::run_cli;
+
+/// Items or articles per API request, the API's limit.
+const BATCH: usize = 50;
+
+/// What a POI refers to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Reference {
+    Item(String),
+    /// A Wikipedia article, by site (`enwiki`) and title.
+    Article(String, String),
+}
+
+impl Reference {
+    /// The item of a POI's `wikidata` tag, else the article of its `wikipedia` tag.
+    pub fn of(tags: &[(String, String)]) -> Option<Self> {
+        let tag = |key: &str| {
+            tags.iter()
+                .find(|(tag, _)| tag == key)
+                .map(|(_, value)| value.trim())
+        };
+        if let Some(item) = tag("wikidata").filter(|item| is_item(item)) {
+            return Some(Reference::Item(item.to_string()));
+        }
+        let (language, title) = tag("wikipedia")?.split_once(':')?;
+        let language = language.trim();
+        if language.is_empty()
+            || !language
+                .bytes()
+                .all(|b| b.is_ascii_alphabetic() || b == b'-')
+        {
+            return None;
+        }
+        let site = format!("{}wiki", language.to_lowercase().replace('-', "_"));
+        Some(Reference::Article(site, normalize_title(title)?))
+    }
+}
+
+/// Where items are resolved.
+pub enum Source {
+    /// Wikidata entities in the JSON dump's format, one per line, such as a dump filtered
+    /// to the items of interest.
+    Lookup(String),
+    /// The `api.php` endpoint of Wikidata.
+    Api(String),
+}
+
+pub struct Wikidata {
+    pub source: Source,
+    /// Languages of the labels written.
+    pub languages: Vec<String>,
+}
+
+/// Items with their labels, and the items of articles.
+#[derive(Default)]
+pub struct Resolved {
+    labels: HashMap<String, Map<String, Value>>,
+    articles: HashMap<(String, String), String>,
+}
+
+impl Resolved {
+    /// Count of the items found.
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// The `wikidata` property of a POI: the `id` and `labels` of its item, null when it
+    /// refers to an article whose item is unknown.
+    pub fn property(&self, reference: &Reference) -> Value {
+        let item = match reference {
+            Reference::Item(item) => Some(item),
+            Reference::Article(site, title) => self.articles.get(&(site.clone(), title.clone())),
+        };
+        match item {
+            Some(item) => {
+                let labels = self.labels.get(item).cloned().unwrap_or_default();
+                json!({"id": item, "labels": labels})
+            }
+            None => Value::Null,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Entity {
+    /// Missing from the API's entries for unknown articles.
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    labels: BTreeMap<String, Label>,
+    #[serde(default)]
+    sitelinks: BTreeMap<String, Sitelink>,
+    #[serde(default)]
+    missing: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Sitelink {
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    #[serde(default)]
+    entities: BTreeMap<String, Entity>,
+    error: Option<Value>,
+}
+
+impl Wikidata {
+    pub fn resolve(&self, references: &HashSet<Reference>) -> Result<Resolved> {
+        let mut resolved = Resolved::default();
+        match &self.source {
+            Source::Lookup(location) => {
+                let text = super::read_text(location, "Wikidata lookup")?;
+                for (idx, line) in text.lines().enumerate() {
+                    // The dump is one JSON array with an entity per line.
+                    let line = line.trim().trim_end_matches(',');
+                    if matches!(line, "" | "[" | "]") {
+                        continue;
+                    }
+                    let entity: Entity = serde_json::from_str(line)
+                        .with_context(|| format!("line {} of {location}", idx + 1))?;
+                    self.add(&mut resolved, entity, references);
+                }
+            }
+            Source::Api(endpoint) => {
+                let (mut items, mut articles) = (Vec::new(), BTreeMap::<_, Vec<_>>::new());
+                for reference in references {
+                    match reference {
+                        Reference::Item(item) => items.push(item.as_str()),
+                        Reference::Article(site, title) => articles
+                            .entry(site.as_str())
+                            .or_default()
+                            .push(title.as_str()),
+                    }
+                }
+                items.sort_unstable();
+                articles
+                    .values_mut()
+                    .for_each(|titles| titles.sort_unstable());
+                for batch in items.chunks(BATCH) {
+                    let query = [("ids", batch.join("|")), ("props", "labels".into())];
+                    self.request(endpoint, &query, &mut resolved, references);
+                }
+                for (site, titles) in articles {
+                    for batch in titles.chunks(BATCH) {
+                        let query = [
+                            ("sites", site.to_string()),
+                            ("titles", batch.join("|")),
+                            ("props", "labels|sitelinks".into()),
+                            ("sitefilter", site.to_string()),
+                        ];
+                        self.request(endpoint, &query, &mut resolved, references);
+                    }
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// One `wbgetentities` request; a failure leaves its items unresolved.
+    fn request(
+        &self,
+        endpoint: &str,
+        query: &[(&str, String)],
+        resolved: &mut Resolved,
+        references: &HashSet<Reference>,
+    ) {
+        let mut params = vec![
+            "action=wbgetentities".to_string(),
+            "format=json".to_string(),
+            format!("languages={}", self.languages.join("|")),
+        ];
+        params.extend(query.iter().map(|(key, value)| format!("{key}={value}")));
+        let mut args = vec!["-sSf", "--get", "-A", "osm-planet-sharding", endpoint];
+        for param in &params {
+            args.extend(["--data-urlencode", param.as_str()]);
+        }
+        let response = match run_cli("curl", &args) {
+            Ok(Ok(stdout)) => serde_json::from_slice::<ApiResponse>(&stdout)
+                .map_err(|err| format!("unexpected response: {err}")),
+            Ok(Err(stderr)) => Err(stderr),
+            Err(err) => Err(err.to_string()),
+        };
+        match response {
+            Ok(ApiResponse {
+                error: None,
+                entities,
+            }) => {
+                for entity in entities.into_values() {
+                    self.add(resolved, entity, references);
+                }
+            }
+            Ok(ApiResponse {
+                error: Some(error), ..
+            }) => warn!("Wikidata API request failed: {error}"),
+            Err(err) => warn!("Wikidata API request failed: {err}"),
+        }
+    }
+
+    /// Keep an entity's labels and articles, if any POI refers to it.
+    fn add(&self, resolved: &mut Resolved, entity: Entity, references: &HashSet<Reference>) {
+        if entity.missing.is_some() || entity.id.is_empty() {
+            return;
+        }
+        let mut wanted = references.contains(&Reference::Item(entity.id.clone()));
+        for (site, link) in entity.sitelinks {
+            let Some(title) = normalize_title(&link.title) else {
+                continue;
+            };
+            if references.contains(&Reference::Article(site.clone(), title.clone())) {
+                resolved.articles.insert((site, title), entity.id.clone());
+                wanted = true;
+            }
+        }
+        if wanted {
+            let labels = entity
+                .labels
+                .into_iter()
+                .filter(|(language, _)| self.languages.contains(language))
+                .map(|(language, label)| (language, json!(label.value)))
+                .collect();
+            resolved.labels.insert(entity.id, labels);
+        }
+    }
+}
+
+fn is_item(value: &str) -> bool {
+    value
+        .strip_prefix('Q')
+        .is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// An article title as Wikipedia keeps it: spaces for underscores, the first letter upper
+/// case.
+fn normalize_title(title: &str) -> Option<String> {
+    let title = title.replace('_', " ");
+    let title = title.trim();
+    let mut chars = title.chars();
+    let first = chars.next()?;
+    Some(first.to_uppercase().chain(chars).collect())
+}
@@ -0,0 +1,84 @@
+//! Single-file HTML map of a shard plan, for reviewing it in a browser without GIS tools.
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use crate::Feature;
+
+/// Page template; the `__SHARDS__` and `__MAX_NODES__` placeholders are filled in. Leaflet
+/// and the OpenStreetMap basemap are loaded from their CDNs; the shards are inline.
+const TEMPLATE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Shard plan</title>
+<link rel="stylesheet" href="https://unpkg.com/leaflet@1.9.4/dist/leaflet.css"
+      integrity="sha256-p4NxAoJBhIIN+hmNHrzRCf9tD/miZyoHS5obTRR9BMY=" crossorigin="">
+<script src="https://unpkg.com/leaflet@1.9.4/dist/leaflet.js"
+        integrity="sha256-20nQCchB9co0qIjJZRGuk2/Z9VM+kNiyxNV1lvTlZBo=" crossorigin=""></script>
+<style>
+  html, body, #map { height: 100%; margin: 0; }
+  .legend { background: #fff; padding: 6px 8px; font: 12px sans-serif; line-height: 18px; }
+  .legend i { display: inline-block; width: 14px; height: 14px; margin-right: 6px; vertical-align: middle; }
+</style>
+</head>
+<body>
+<div id="map"></div>
+<script>
+const shards = __SHARDS__;
+const maxNodes = __MAX_NODES__;
+const colors = ["#ffffcc", "#ffeda0", "#fed976", "#feb24c", "#fd8d3c",
+                "#fc4e2a", "#e31a1c", "#bd0026", "#800026", "#4d0019"];
+const over = "#000000";
+
+function color(nodes) {
+  if (nodes > maxNodes) return over;
+  return colors[Math.min(colors.length - 1, Math.floor(nodes / maxNodes * colors.length))];
+}
+
+const map = L.map("map");
+L.tileLayer("https://tile.openstreetmap.org/{z}/{x}/{y}.png", {
+  maxZoom: 19,
+  attribution: '&copy; <a href="https://www.openstreetmap.org/copyright">OpenStreetMap</a> contributors'
+}).addTo(map);
+
+const layer = L.geoJSON(shards, {
+  style: f => ({ color: "#333", weight: 0.5, fillColor: color(f.properties.node_count), fillOpacity: 0.6 }),
+  onEachFeature: (f, l) => l.bindPopup(
+    `<b>${f.properties.shard_id}</b><br>z/x/y: ${f.properties.z}/${f.properties.x}/${f.properties.y}` +
+    `<br>nodes: ${f.properties.node_count.toLocaleString()}`)
+}).addTo(map);
+if (shards.features.length) map.fitBounds(layer.getBounds()); else map.setView([0, 0], 1);
+
+const legend = L.control({ position: "bottomright" });
+legend.onAdd = () => {
+  const div = L.DomUtil.create("div", "legend");
+  const step = maxNodes / colors.length;
+  div.innerHTML = `<b>${shards.features.length.toLocaleString()} shards</b><br>` + colors.map((c, i) =>
+    `<i style="background:${c}"></i>${Math.round(i * step).toLocaleString()}&ndash;${Math.round((i + 1) * step).toLocaleString()}`
+  ).join("<br>") + `<br><i style="background:${over}"></i>&gt; ${maxNodes.toLocaleString()}`;
+  return div;
+};
+legend.addTo(map);
+</script>
+</body>
+</html>
+"##;
+
+/// Write an HTML page at `path` drawing `features` coloured by node count relative to
+/// `max_nodes`.
+pub fn write(path: &Path, features: &[Feature], max_nodes: u64) -> Result<()> {
+    let shards = json!({ "type": "FeatureCollection", "features": features });
+    // Keep the inline data from closing the script element.
+    let shards = shards.to_string().replace("</", "<\\/");
+    let html = TEMPLATE
+        .replace("__MAX_NODES__", &max_nodes.to_string())
+        .replace("__SHARDS__", &shards);
+    fs::write(path, html).with_context(|| format!("unable to write {}", path.display()))?;
+    info!(path = %path.display(), "Wrote shard plan preview to {}.", path.display());
+    Ok(())
+}
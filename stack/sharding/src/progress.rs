@@ -0,0 +1,161 @@
+//! Periodic scan progress on stderr, as human-readable text or JSON lines.
+
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// One readable line per update.
+    Text,
+    /// One JSON object per line, for dashboards.
+    Json,
+    /// No progress output.
+    None,
+}
+
+/// Machine-readable progress line.
+#[derive(Serialize)]
+struct ProgressEvent<'a> {
+    event: &'static str,
+    file: &'a str,
+    bytes_done: Option<u64>,
+    bytes_total: Option<u64>,
+    percent: Option<f64>,
+    nodes: u64,
+    nodes_per_sec: f64,
+    elapsed_secs: f64,
+    eta_secs: Option<f64>,
+}
+
+/// Progress tracker for one input file.
+pub struct Progress {
+    format: ProgressFormat,
+    interval: Duration,
+    file: String,
+    bytes_total: Option<u64>,
+    /// Bytes already done when this run started (e.g. when resuming from a checkpoint).
+    bytes_start: u64,
+    nodes_start: u64,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    /// `bytes_total` is the input size when progress through it can be measured.
+    pub fn new(
+        format: ProgressFormat,
+        interval: Duration,
+        path: &Path,
+        bytes_total: Option<u64>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            format,
+            interval,
+            file: path.display().to_string(),
+            bytes_total,
+            bytes_start: 0,
+            nodes_start: 0,
+            started: now,
+            last_report: now,
+        }
+    }
+
+    /// Discount work done before this run from the rates.
+    pub fn resumed_at(&mut self, bytes_done: u64, nodes: u64) {
+        self.bytes_start = bytes_done;
+        self.nodes_start = nodes;
+    }
+
+    /// Report if the interval has elapsed since the last update.
+    pub fn update(&mut self, bytes_done: Option<u64>, nodes: u64) {
+        if self.last_report.elapsed() >= self.interval {
+            self.report("progress", bytes_done, nodes);
+        }
+    }
+
+    /// Final report once the file is done (JSON mode only; text mode has its own summary).
+    pub fn finish(&mut self, nodes: u64) {
+        if self.format == ProgressFormat::Json {
+            self.report("done", self.bytes_total, nodes);
+        }
+    }
+
+    fn report(&mut self, event: &'static str, bytes_done: Option<u64>, nodes: u64) {
+        self.last_report = Instant::now();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let nodes_per_sec = if elapsed > 0.0 {
+            nodes.saturating_sub(self.nodes_start) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let percent = bytes_done
+            .zip(self.bytes_total)
+            .filter(|&(_, total)| total > 0)
+            .map(|(done, total)| 100.0 * done as f64 / total as f64);
+        let eta_secs = bytes_done.zip(self.bytes_total).and_then(|(done, total)| {
+            let rate = done.saturating_sub(self.bytes_start) as f64 / elapsed;
+            (rate > 0.0).then(|| total.saturating_sub(done) as f64 / rate)
+        });
+
+        match self.format {
+            ProgressFormat::None => {}
+            ProgressFormat::Json => {
+                let line = ProgressEvent {
+                    event,
+                    file: &self.file,
+                    bytes_done,
+                    bytes_total: self.bytes_total,
+                    percent,
+                    nodes,
+                    nodes_per_sec,
+                    elapsed_secs: elapsed,
+                    eta_secs,
+                };
+                if let Ok(line) = serde_json::to_string(&line) {
+                    eprintln!("{line}");
+                }
+            }
+            ProgressFormat::Text => {
+                let mut line = String::from("Progress:");
+                if let (Some(percent), Some(done), Some(total)) =
+                    (percent, bytes_done, self.bytes_total)
+                {
+                    line.push_str(&format!(
+                        " {percent:.1}% ({} / {}),",
+                        format_bytes(done),
+                        format_bytes(total)
+                    ));
+                }
+                line.push_str(&format!(" {nodes} nodes, {:.0} nodes/s", nodes_per_sec));
+                if let Some(eta) = eta_secs {
+                    line.push_str(&format!(", ETA {}", format_duration(eta)));
+                }
+                info!(bytes_done, nodes, nodes_per_sec, eta_secs, "{line}");
+            }
+        }
+    }
+}
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, _) => format!("{h}h{m:02}m"),
+    }
+}
@@ -0,0 +1,106 @@
+//! Publishing the manifest to its `--output` location so readers never see a partial one.
+//!
+//! The manifest is staged in a local file and only then uploaded; every store replaces an
+//! object in one step. With `--latest-pointer`, the manifest is first uploaded under a
+//! content-addressed key (its SHA-256 in the file name), then under its usual key, and the
+//! pointer naming the run and the content-addressed copy is written last, so a reader that
+//! follows the pointer always finds a complete manifest that never changes afterwards.
+//!
+//! With `--presign-expiry`, the published manifest and summary are also shared through
+//! presigned URLs, for readers without access to the bucket.
+
+use anyhow::Result;
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::store::Store;
+use crate::{keys, logging, notify, summary};
+
+/// Upload the `staged` manifest to `store`, and with `latest` set, publish it through the
+/// pointer there. `name` is the manifest's file name for key templates. Returns the
+/// manifest's hex SHA-256.
+pub fn publish(
+    store: &Store,
+    staged: &Path,
+    name: &str,
+    content_encoding: Option<&str>,
+    latest: Option<&str>,
+) -> Result<String> {
+    let sha256 = summary::sha256_file(staged)?;
+    let Some(latest) = latest else {
+        store.put_hashed_file(staged, content_encoding, &sha256)?;
+        info!(destination = %store, sha256, "Uploaded manifest to {store}.");
+        return Ok(sha256);
+    };
+
+    let addressed = Store::open(&content_addressed(&store.to_string(), &sha256), name)?;
+    addressed.put_hashed_file(staged, content_encoding, &sha256)?;
+    store.put_hashed_file(staged, content_encoding, &sha256)?;
+    info!(
+        destination = %store,
+        content_addressed = %addressed,
+        "Uploaded manifest to {store} and {addressed}."
+    );
+
+    let pointer = json!({
+        "run_id": keys::run_id(),
+        "manifest": addressed.to_string(),
+        "sha256": sha256,
+        "published_at": logging::timestamp(),
+    });
+    let latest = Store::open(latest, "latest.json")?;
+    latest.put(serde_json::to_vec_pretty(&pointer)?)?;
+    info!(pointer = %latest, "Pointed {latest} at {addressed}.");
+    Ok(sha256)
+}
+
+/// Print presigned GET URLs for the `(kind, store)` artifacts, valid for `expires_in`, as one
+/// JSON object keyed by kind, and add them to the run event. The URLs go to stdout only with
+/// `stdout_free` set, that is when the manifest was not written there; otherwise to stderr.
+pub fn presign(
+    artifacts: &[(&'static str, &Store)],
+    expires_in: Duration,
+    stdout_free: bool,
+) -> Result<()> {
+    let mut urls = serde_json::Map::new();
+    for &(kind, store) in artifacts {
+        match store.presigned_url(expires_in)? {
+            Some(url) => {
+                urls.insert(kind.to_string(), url.into());
+            }
+            None => warn!(destination = %store, "cannot presign {store}: not an s3:// location"),
+        }
+    }
+    if urls.is_empty() {
+        return Ok(());
+    }
+    info!(
+        expires_in_secs = expires_in.as_secs(),
+        "Presigned URLs for {}, valid for {expires_in:?}.",
+        urls.keys().cloned().collect::<Vec<_>>().join(" and ")
+    );
+    let urls = serde_json::Value::Object(urls);
+    if stdout_free {
+        println!("{urls}");
+    } else {
+        eprintln!("{urls}");
+    }
+    notify::record_urls(urls);
+    Ok(())
+}
+
+/// `location` with `-<sha256>` inserted into its file name before the extensions, e.g.
+/// `runs/r1/shards/manifest-<sha256>.json.gz`.
+fn content_addressed(location: &str, sha256: &str) -> String {
+    let file_start = location.rfind('/').map_or(0, |slash| slash + 1);
+    let stem_end = location[file_start..]
+        .find('.')
+        .map_or(location.len(), |dot| file_start + dot);
+    format!(
+        "{}-{sha256}{}",
+        &location[..stem_end],
+        &location[stem_end..]
+    )
+}
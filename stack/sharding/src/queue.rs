@@ -0,0 +1,370 @@
+//! SQS work queue of shards: `enqueue` sends a message per shard of a manifest, and
+//! `extract-pois --queue-url` long-polls the queue for shards to extract, so any number of
+//! workers, on spot capacity too, share a run's shards.
+//!
+//! A received shard stays invisible to other workers while it is extracted, its visibility
+//! timeout extended as it goes, and its message is deleted once its POIs are written. A
+//! shard that fails is left on the queue, to be received again when its visibility timeout
+//! runs out, by this worker or another; the queue's redrive policy moves it to a dead-letter
+//! queue after `maxReceiveCount` receives. A worker told to stop by SIGTERM receives no more
+//! shards, and returns the one it was reading to the queue at once.
+//!
+//! Like the notifications, this goes through `aws`.
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tracing::{info, info_span, warn};
+
+use crate::extract::read_manifest;
+use crate::interrupt;
+use crate::keys;
+use crate::store::run_cli;
+
+/// Most messages in a `SendMessageBatch` request.
+const BATCH: usize = 10;
+/// Longest long poll SQS allows.
+const WAIT_SECONDS: &str = "20";
+/// Attempts of a batch's failed messages before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+#[derive(Args, Debug)]
+pub struct EnqueueArgs {
+    /// Manifest of the shards to enqueue, in any format `extract` reads: a local path or a
+    /// URI.
+    #[arg(long, env = "MANIFEST")]
+    manifest: String,
+
+    /// SQS queue to send a message per shard to; a FIFO queue (`.fifo`) gets each shard
+    /// once per run, however often it is enqueued.
+    #[arg(long, env = "SHARD_QUEUE_URL")]
+    queue_url: String,
+
+    /// Only enqueue these shards (by shard ID, e.g. `12-2048-1361`) instead of every shard
+    /// in the manifest.
+    #[arg(long, env = "SHARDS", value_delimiter = ',')]
+    shards: Vec<String>,
+}
+
+/// A message received from the queue.
+pub struct Received {
+    message_id: String,
+    body: Value,
+    receipt_handle: String,
+    /// How often the message has been received, this time included.
+    pub receive_count: u32,
+}
+
+impl Received {
+    /// The shard to extract, if the message names one of this run.
+    pub fn shard_id(&self) -> Result<&str> {
+        shard_id(&self.message_id, &self.body)
+    }
+}
+
+/// The shard a message's body names, if it is one of this run's.
+pub fn shard_id<'a>(message_id: &str, body: &'a Value) -> Result<&'a str> {
+    let Some(shard_id) = body["shard_id"].as_str() else {
+        bail!("message {message_id} names no shard");
+    };
+    if let (Some(enqueued), Some(run_id)) = (body["run_id"].as_str(), keys::run_id()) {
+        if enqueued != run_id {
+            bail!("shard {shard_id} was enqueued for run {enqueued}, not {run_id}");
+        }
+    }
+    Ok(shard_id)
+}
+
+pub struct Queue {
+    url: String,
+}
+
+impl Queue {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+        }
+    }
+
+    /// Send a message per shard, `{"shard_id": ..., "run_id": ...}`.
+    pub fn send(&self, shard_ids: &[String]) -> Result<()> {
+        let fifo = self.url.ends_with(".fifo");
+        let run_id = keys::run_id();
+        for batch in shard_ids.chunks(BATCH) {
+            let entries: Vec<Value> = batch
+                .iter()
+                .enumerate()
+                .map(|(idx, shard_id)| {
+                    let body = json!({"shard_id": shard_id, "run_id": run_id});
+                    let mut entry = json!({"Id": idx.to_string(), "MessageBody": body.to_string()});
+                    if fifo {
+                        entry["MessageGroupId"] = json!(shard_id);
+                        entry["MessageDeduplicationId"] =
+                            json!(format!("{}-{shard_id}", run_id.unwrap_or("run")));
+                    }
+                    entry
+                })
+                .collect();
+            self.send_batch(entries)?;
+        }
+        Ok(())
+    }
+
+    /// Send a batch, retrying the messages SQS failed on its side.
+    fn send_batch(&self, mut entries: Vec<Value>) -> Result<()> {
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut body = NamedTempFile::new()?;
+            serde_json::to_writer(&mut body, &entries)?;
+            body.flush()?;
+            let path = format!("file://{}", body.path().display());
+            let args = [
+                "sqs",
+                "send-message-batch",
+                "--output",
+                "json",
+                "--queue-url",
+                &self.url,
+                "--entries",
+                &path,
+            ];
+            let stdout = match run_cli("aws", &args)? {
+                Ok(stdout) => stdout,
+                Err(stderr) => bail!("unable to send to SQS queue {}: {stderr}", self.url),
+            };
+            let response: Value = serde_json::from_slice(&stdout)
+                .context("unexpected send-message-batch response")?;
+            let failed = response["Failed"].as_array().cloned().unwrap_or_default();
+            if failed.is_empty() {
+                return Ok(());
+            }
+            if let Some(fault) = failed.iter().find(|f| f["SenderFault"] == json!(true)) {
+                bail!(
+                    "SQS queue {} rejected a message: {}",
+                    self.url,
+                    fault["Message"].as_str().unwrap_or("no reason given")
+                );
+            }
+            if attempt == MAX_ATTEMPTS {
+                bail!(
+                    "SQS queue {} still failed {} messages after {attempt} attempts",
+                    self.url,
+                    failed.len()
+                );
+            }
+            entries.retain(|entry| failed.iter().any(|f| f["Id"] == entry["Id"]));
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+        unreachable!("the last attempt returns")
+    }
+
+    /// Long-poll for a message, hiding it from other workers for `visibility_timeout`
+    /// seconds; `None` if none came within the poll.
+    pub fn receive(&self, visibility_timeout: u32) -> Result<Option<Received>> {
+        let visibility_timeout = visibility_timeout.to_string();
+        let args = [
+            "sqs",
+            "receive-message",
+            "--output",
+            "json",
+            "--queue-url",
+            &self.url,
+            "--max-number-of-messages",
+            "1",
+            "--wait-time-seconds",
+            WAIT_SECONDS,
+            "--visibility-timeout",
+            &visibility_timeout,
+            "--attribute-names",
+            "ApproximateReceiveCount",
+        ];
+        let stdout = match run_cli("aws", &args)? {
+            Ok(stdout) => stdout,
+            Err(stderr) => bail!("unable to receive from SQS queue {}: {stderr}", self.url),
+        };
+        // The CLI prints nothing when the poll comes back empty.
+        if stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        let response: Value =
+            serde_json::from_slice(&stdout).context("unexpected receive-message response")?;
+        let Some(message) = response["Messages"].get(0) else {
+            return Ok(None);
+        };
+        let receipt_handle = message["ReceiptHandle"]
+            .as_str()
+            .context("message without a receipt handle")?
+            .to_string();
+        let receive_count = message["Attributes"]["ApproximateReceiveCount"]
+            .as_str()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(1);
+        let body = message["Body"].as_str().unwrap_or_default();
+        Ok(Some(Received {
+            message_id: message["MessageId"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            body: serde_json::from_str(body).unwrap_or_default(),
+            receipt_handle,
+            receive_count,
+        }))
+    }
+
+    /// Keep a received shard hidden for another `visibility_timeout` seconds from now.
+    pub fn extend(&self, received: &Received, visibility_timeout: u32) -> Result<()> {
+        let visibility_timeout = visibility_timeout.to_string();
+        let args = [
+            "sqs",
+            "change-message-visibility",
+            "--queue-url",
+            &self.url,
+            "--receipt-handle",
+            &received.receipt_handle,
+            "--visibility-timeout",
+            &visibility_timeout,
+        ];
+        if let Err(stderr) = run_cli("aws", &args)? {
+            bail!("{stderr}");
+        }
+        Ok(())
+    }
+
+    /// Make a received shard visible to other workers again now, as if its visibility
+    /// timeout had run out.
+    pub fn release(&self, received: &Received) -> Result<()> {
+        self.extend(received, 0)
+    }
+
+    /// Take a done shard off the queue.
+    pub fn delete(&self, received: &Received) -> Result<()> {
+        let args = [
+            "sqs",
+            "delete-message",
+            "--queue-url",
+            &self.url,
+            "--receipt-handle",
+            &received.receipt_handle,
+        ];
+        if let Err(stderr) = run_cli("aws", &args)? {
+            bail!(
+                "unable to delete message {} from SQS queue {}: {stderr}",
+                received.message_id,
+                self.url
+            );
+        }
+        Ok(())
+    }
+}
+
+pub fn enqueue(args: &EnqueueArgs) -> Result<()> {
+    let mut shard_ids: Vec<String> = read_manifest(&args.manifest)?
+        .into_iter()
+        .map(|shard| shard.id)
+        .collect();
+    if !args.shards.is_empty() {
+        for id in &args.shards {
+            if !shard_ids.contains(id) {
+                bail!("shard {id} is not in {}", args.manifest);
+            }
+        }
+        shard_ids.retain(|id| args.shards.contains(id));
+    }
+    info!(
+        shards = shard_ids.len(),
+        "Enqueueing {} shard(s) on {}...",
+        shard_ids.len(),
+        args.queue_url
+    );
+    Queue::new(&args.queue_url).send(&shard_ids)?;
+    info!("Enqueued {} shard(s).", shard_ids.len());
+    Ok(())
+}
+
+/// Extract shards from the queue until it has had none for `idle_timeout`. A shard's
+/// visibility is extended every half `visibility_timeout` while `extract` runs; one that
+/// fails is left to be received again, and the run fails once the queue is drained.
+pub fn work(
+    queue: &Queue,
+    visibility_timeout: u32,
+    idle_timeout: Duration,
+    mut extract: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let (mut done, mut failed) = (0u64, 0u64);
+    let mut idle_since = Instant::now();
+    info!("Taking shards from {}...", queue.url);
+    loop {
+        if interrupt::requested() {
+            warn!("Stopping: {done} done, {failed} failed before SIGTERM.");
+            return Err(interrupt::Interrupted.into());
+        }
+        let received = match queue.receive(visibility_timeout)? {
+            Some(received) => received,
+            None if idle_since.elapsed() >= idle_timeout => break,
+            None => continue,
+        };
+        idle_since = Instant::now();
+        let shard_id = match received.shard_id() {
+            Ok(shard_id) => shard_id,
+            // A message no worker can take is left for the redrive policy.
+            Err(err) => {
+                warn!("Skipping a message: {err:#}");
+                failed += 1;
+                continue;
+            }
+        };
+        let _shard = info_span!("queued_shard", shard = %shard_id).entered();
+        info!(
+            attempt = received.receive_count,
+            "Received shard {shard_id} (attempt {}).", received.receive_count
+        );
+        let interval = Duration::from_secs(u64::from(visibility_timeout).div_ceil(2));
+        let received = &received;
+        let result = std::thread::scope(|scope| {
+            let (stop, stopped) = mpsc::channel::<()>();
+            scope.spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if let Err(err) = queue.extend(received, visibility_timeout) {
+                        warn!("unable to extend the visibility of shard {shard_id}: {err:#}");
+                    }
+                }
+            });
+            let result = extract(shard_id);
+            drop(stop);
+            result
+        });
+        match result.and_then(|()| queue.delete(received)) {
+            Ok(()) => done += 1,
+            Err(err) if interrupt::is_interrupted(&err) => {
+                match queue.release(received) {
+                    Ok(()) => warn!("Shard {shard_id} was interrupted; returned it to the queue."),
+                    Err(err) => warn!(
+                        "Shard {shard_id} was interrupted, but could not be returned to the \
+                         queue, which will give it out again after its visibility timeout: \
+                         {err:#}"
+                    ),
+                }
+                return Err(err);
+            }
+            Err(err) => {
+                warn!("Shard {shard_id} failed, leaving it to be received again: {err:#}");
+                failed += 1;
+            }
+        }
+        idle_since = Instant::now();
+    }
+    info!(
+        done,
+        failed, "The queue has had no shard for {idle_timeout:?}: {done} done, {failed} failed."
+    );
+    if failed > 0 {
+        bail!("{failed} shard(s) failed and were left on the queue to be retried");
+    }
+    Ok(())
+}
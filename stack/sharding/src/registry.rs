@@ -0,0 +1,126 @@
+//! Run registry: one DynamoDB item per run, keyed by `run_id`, so the history of shard runs
+//! can be queried without listing S3.
+//!
+//! The item is built from the run summary and written at exit, whether the run succeeded
+//! or not, through the `aws` CLI like the run events (see [`crate::notify`]).
+
+use anyhow::{bail, Result};
+use clap::Args;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+use tracing::{info, warn};
+
+use crate::keys;
+use crate::store::run_cli;
+use crate::summary::Summary;
+
+#[derive(Args, Debug)]
+pub struct RegistryArgs {
+    /// DynamoDB table to record the run in at exit, with `run_id` (a string) as its
+    /// partition key. Needs `--run-id`.
+    #[arg(long, env = "RUNS_TABLE")]
+    runs_table: Option<String>,
+}
+
+/// Summary fields copied into the item.
+const FIELDS: [&str; 10] = [
+    "tool_version",
+    "started_at",
+    "finished_at",
+    "elapsed_secs",
+    "parameters",
+    "inputs",
+    "nodes",
+    "shards",
+    "artifacts",
+    "timings",
+];
+
+/// Put the run's item in the registry table. Failures are logged, not returned, so a
+/// registry outage never fails a run.
+pub fn record(args: &RegistryArgs, summary: &mut Summary, error: Option<&anyhow::Error>) {
+    let Some(table) = &args.runs_table else {
+        return;
+    };
+    match put_run(table, summary, error) {
+        Ok(run_id) => info!(table = %table, run_id, "Recorded run {run_id} in {table}."),
+        Err(err) => warn!(table = %table, "unable to record the run in {table}: {err:#}"),
+    }
+}
+
+fn put_run(table: &str, summary: &mut Summary, error: Option<&anyhow::Error>) -> Result<String> {
+    let Some(run_id) = keys::run_id().filter(|run_id| !run_id.is_empty()) else {
+        bail!("no --run-id (RUN_ID) was given");
+    };
+    summary.finish()?;
+    let Value::Object(mut fields) = serde_json::to_value(&*summary)? else {
+        bail!("run summary is not an object");
+    };
+
+    let mut item = Map::new();
+    item.insert("run_id".into(), json!(run_id));
+    item.insert(
+        "status".into(),
+        json!(if error.is_some() {
+            "failed"
+        } else {
+            "succeeded"
+        }),
+    );
+    if let Some(error) = error {
+        item.insert("error".into(), json!(format!("{error:#}")));
+    }
+    // Top-level copy of the planet state, so runs can be filtered on it.
+    let replication_timestamp = fields["inputs"]
+        .get(0)
+        .and_then(|input| input.get("replication_timestamp"))
+        .filter(|timestamp| timestamp.is_string())
+        .cloned();
+    if let Some(timestamp) = replication_timestamp {
+        item.insert("replication_timestamp".into(), timestamp);
+    }
+    for field in FIELDS {
+        if let Some(value) = fields.remove(field) {
+            item.insert(field.into(), value);
+        }
+    }
+    let item: Map<String, Value> = item
+        .into_iter()
+        .map(|(name, value)| (name, attribute(value)))
+        .collect();
+
+    // Passed as a file, since large items overflow the command line.
+    let mut file = tempfile::NamedTempFile::new()?;
+    file.write_all(Value::Object(item).to_string().as_bytes())?;
+    file.flush()?;
+    let item_arg = format!("file://{}", file.path().display());
+    let args = [
+        "dynamodb",
+        "put-item",
+        "--table-name",
+        table,
+        "--item",
+        &item_arg,
+    ];
+    if let Err(stderr) = run_cli("aws", &args)? {
+        bail!("{stderr}");
+    }
+    Ok(run_id.to_string())
+}
+
+/// A JSON value in DynamoDB's attribute value encoding.
+pub(crate) fn attribute(value: Value) -> Value {
+    match value {
+        Value::Null => json!({"NULL": true}),
+        Value::Bool(value) => json!({"BOOL": value}),
+        Value::Number(value) => json!({"N": value.to_string()}),
+        Value::String(value) => json!({"S": value}),
+        Value::Array(values) => json!({"L": values.into_iter().map(attribute).collect::<Vec<_>>()}),
+        Value::Object(fields) => json!({
+            "M": fields
+                .into_iter()
+                .map(|(name, value)| (name, attribute(value)))
+                .collect::<Map<_, _>>()
+        }),
+    }
+}
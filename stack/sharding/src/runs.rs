@@ -0,0 +1,174 @@
+//! `runs` subcommand: the runs uploaded to a bucket, one `<prefix><run_id>/` prefix each,
+//! listed, shown through their run summary, or pruned past a retention window.
+
+use anyhow::{bail, Result};
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use clap::{Args, Subcommand};
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+use crate::s3::{self, Object, S3Location};
+
+#[derive(Args, Debug)]
+pub struct RunsArgs {
+    /// Bucket the runs were uploaded to.
+    #[arg(long, env = "S3_BUCKET")]
+    bucket: String,
+
+    /// Prefix under which each run has a `<run_id>/` prefix.
+    #[arg(long, env = "RUNS_PREFIX", default_value = "runs/")]
+    prefix: String,
+
+    #[command(subcommand)]
+    command: RunsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum RunsCommand {
+    /// List the runs, newest first: run ID, time of the last upload, objects and bytes.
+    List,
+    /// Print a run's `summary.json`.
+    Show { run_id: String },
+    /// Delete the runs last written to more than `--older-than-days` ago.
+    Prune {
+        /// Retention window in days.
+        #[arg(long)]
+        older_than_days: u64,
+
+        /// Always keep this many of the newest runs, however old.
+        #[arg(long, default_value_t = 1)]
+        keep: usize,
+
+        /// Only report which runs would be deleted.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// One run's objects.
+struct Run {
+    id: String,
+    objects: Vec<Object>,
+}
+
+impl Run {
+    /// When the run's newest object was written.
+    fn last_modified(&self) -> Option<DateTime> {
+        self.objects
+            .iter()
+            .filter_map(|object| object.last_modified)
+            .max()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.objects.iter().map(|object| object.size).sum()
+    }
+}
+
+pub fn run(args: &RunsArgs) -> Result<()> {
+    let mut prefix = args.prefix.trim_start_matches('/').to_string();
+    if !prefix.is_empty() && !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    let client = s3::client();
+    match &args.command {
+        RunsCommand::List => {
+            let mut stdout = std::io::stdout().lock();
+            for run in list(&client, &args.bucket, &prefix)? {
+                writeln!(
+                    stdout,
+                    "{}\t{}\t{}\t{}",
+                    run.id,
+                    format_time(run.last_modified()),
+                    run.objects.len(),
+                    run.bytes()
+                )?;
+            }
+            Ok(())
+        }
+        RunsCommand::Show { run_id } => {
+            let run_prefix = format!("{prefix}{run_id}/");
+            let summary = s3::list_objects(&client, &args.bucket, &run_prefix)?
+                .into_iter()
+                .filter(|object| object.key.rsplit('/').next() == Some("summary.json"))
+                .max_by_key(|object| object.last_modified);
+            let Some(summary) = summary else {
+                bail!("no summary.json under s3://{}/{run_prefix}", args.bucket);
+            };
+            let location = S3Location {
+                bucket: args.bucket.clone(),
+                key: summary.key,
+            };
+            let Some(bytes) = s3::get_object(&client, &location)? else {
+                bail!("{location} disappeared while reading it");
+            };
+            std::io::stdout().write_all(&bytes)?;
+            Ok(())
+        }
+        RunsCommand::Prune {
+            older_than_days,
+            keep,
+            dry_run,
+        } => {
+            let cutoff = SystemTime::now() - Duration::from_secs(older_than_days * 24 * 60 * 60);
+            let cutoff = DateTime::from(cutoff);
+            let runs = list(&client, &args.bucket, &prefix)?;
+            let expired: Vec<_> = runs
+                .into_iter()
+                .skip(*keep)
+                .filter(|run| run.last_modified().is_some_and(|time| time < cutoff))
+                .collect();
+            for run in &expired {
+                info!(
+                    run_id = %run.id,
+                    objects = run.objects.len(),
+                    bytes = run.bytes(),
+                    "{} run {} ({} objects, {} bytes, last written {}).",
+                    if *dry_run { "Would delete" } else { "Deleting" },
+                    run.id,
+                    run.objects.len(),
+                    run.bytes(),
+                    format_time(run.last_modified())
+                );
+                if !dry_run {
+                    let keys: Vec<_> = run.objects.iter().map(|o| o.key.clone()).collect();
+                    s3::delete_objects(&client, &args.bucket, &keys)?;
+                }
+            }
+            info!(
+                runs = expired.len(),
+                "{} {} run(s) older than {older_than_days} days.",
+                if *dry_run { "Would prune" } else { "Pruned" },
+                expired.len()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// The runs under `prefix`, newest first. Objects directly under the prefix (such as a
+/// latest-manifest pointer) belong to no run.
+fn list(client: &aws_sdk_s3::Client, bucket: &str, prefix: &str) -> Result<Vec<Run>> {
+    let mut runs: Vec<Run> = Vec::new();
+    for object in s3::list_objects(client, bucket, prefix)? {
+        let Some((id, _)) = object.key[prefix.len()..].split_once('/') else {
+            continue;
+        };
+        // Keys are listed in order, so a run's objects are contiguous.
+        match runs.last_mut() {
+            Some(run) if run.id == id => run.objects.push(object),
+            _ => runs.push(Run {
+                id: id.to_string(),
+                objects: vec![object],
+            }),
+        }
+    }
+    runs.sort_by_key(|run| std::cmp::Reverse(run.last_modified()));
+    Ok(runs)
+}
+
+fn format_time(time: Option<DateTime>) -> String {
+    time.and_then(|time| time.fmt(DateTimeFormat::DateTime).ok())
+        .unwrap_or_else(|| "-".to_string())
+}
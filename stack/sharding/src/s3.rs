@@ -0,0 +1,743 @@
+//! Thin helpers around the S3 SDK for `s3://bucket/key` locations.
+//!
+//! The scanner itself is synchronous (rayon does the heavy lifting), so these helpers block
+//! on the ambient tokio runtime rather than making the whole pipeline async.
+//!
+//! Uploads larger than one part go up as multipart uploads, read part by part, so memory use
+//! is bounded by the part size. Every request carries a checksum for S3 to validate (SHA-256
+//! for single-part objects, CRC32 per part otherwise), and failed requests are retried with
+//! exponential backoff; a failed part is retried on its own rather than restarting the
+//! upload. Every object gets its SHA-256 as `sha256` metadata, plus the encryption, storage
+//! class and tags from [`UploadArgs`].
+
+use anyhow::{bail, Context, Result};
+use aws_config::sts::AssumeRoleProvider;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::config::timeout::TimeoutConfig;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::put_object::PutObjectError;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::{ByteStream, DateTime};
+use aws_sdk_s3::types::{
+    ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier,
+    RequestPayer, ServerSideEncryption, StorageClass,
+};
+use aws_sdk_s3::Client;
+use base64::Engine;
+use clap::Args;
+use flate2::Crc;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::future::Future;
+use std::io::Read;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::metrics::{self, Counter};
+
+/// S3 limits: parts are at least 5 MiB (except the last) and an upload has at most 10,000.
+const MIN_PART_SIZE: u64 = 5 << 20;
+const MAX_PARTS: u64 = 10_000;
+/// Longest wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// S3 connection and upload options, shared by every subcommand.
+#[derive(Args, Debug, Clone)]
+pub struct UploadArgs {
+    /// Part size in MiB for multipart S3 uploads; smaller objects are uploaded in one request.
+    #[arg(
+        long,
+        env = "S3_PART_SIZE_MB",
+        default_value_t = 64,
+        value_parser = clap::value_parser!(u64).range(5..=5120),
+        global = true
+    )]
+    s3_part_size_mb: u64,
+
+    /// Attempts per S3 request (or multipart part) before giving up. Only transient failures
+    /// are retried.
+    #[arg(
+        long,
+        env = "S3_MAX_ATTEMPTS",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1..),
+        global = true
+    )]
+    s3_max_attempts: u32,
+
+    /// Wait before the first retry of a failed S3 request, doubling on each further retry up
+    /// to 30 seconds.
+    #[arg(
+        long,
+        env = "S3_INITIAL_BACKOFF_MS",
+        default_value_t = 1000,
+        global = true
+    )]
+    s3_initial_backoff_ms: u64,
+
+    /// Give up on an S3 request attempt after this many seconds (then retry it).
+    #[arg(long, env = "S3_TIMEOUT", global = true)]
+    s3_timeout: Option<u64>,
+
+    /// Accept requester-pays charges when reading from S3, e.g. for the public planet bucket.
+    #[arg(long, env = "REQUESTER_PAYS", global = true)]
+    requester_pays: bool,
+
+    /// S3 endpoint URL, for S3-compatible stores such as MinIO or LocalStack.
+    #[arg(long, env = "S3_ENDPOINT", global = true)]
+    s3_endpoint: Option<String>,
+
+    /// Address buckets as `endpoint/bucket` rather than `bucket.endpoint`, as most
+    /// S3-compatible stores expect.
+    #[arg(long, env = "S3_FORCE_PATH_STYLE", global = true)]
+    s3_force_path_style: bool,
+
+    /// Assume this IAM role (e.g. in the account owning the bucket) for all S3 access, using
+    /// the default credentials to call STS.
+    #[arg(long, env = "ASSUME_ROLE_ARN", global = true)]
+    assume_role_arn: Option<String>,
+
+    /// External ID the role's trust policy requires, for `--assume-role-arn`.
+    #[arg(
+        long,
+        env = "ASSUME_ROLE_EXTERNAL_ID",
+        requires = "assume_role_arn",
+        global = true
+    )]
+    assume_role_external_id: Option<String>,
+
+    /// Session name for `--assume-role-arn`, shown in the bucket owner's CloudTrail.
+    #[arg(
+        long,
+        env = "ASSUME_ROLE_SESSION_NAME",
+        default_value = "osm-planet-sharding",
+        global = true
+    )]
+    assume_role_session_name: String,
+
+    /// Encrypt uploaded objects with this KMS key (SSE-KMS), by ID, ARN or alias.
+    #[arg(long, env = "SSE_KMS_KEY_ID", global = true)]
+    sse_kms_key_id: Option<String>,
+
+    /// Storage class of uploaded objects, e.g. STANDARD_IA or INTELLIGENT_TIERING.
+    #[arg(long, env = "S3_STORAGE_CLASS", value_parser = parse_storage_class, global = true)]
+    storage_class: Option<StorageClass>,
+
+    /// Tag every uploaded object with `key=value`; repeatable, or comma-separated in the
+    /// environment.
+    #[arg(
+        long = "s3-tag",
+        env = "S3_TAGS",
+        value_delimiter = ',',
+        value_parser = parse_tag,
+        global = true
+    )]
+    s3_tags: Vec<(String, String)>,
+}
+
+fn parse_storage_class(raw: &str) -> Result<StorageClass, String> {
+    if StorageClass::values().contains(&raw) {
+        Ok(StorageClass::from(raw))
+    } else {
+        Err(format!(
+            "expected one of {}",
+            StorageClass::values().join(", ")
+        ))
+    }
+}
+
+fn parse_tag(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("expected key=value".to_string()),
+    }
+}
+
+impl UploadArgs {
+    /// Tags as the URL-encoded query string S3 takes in the `x-amz-tagging` header.
+    fn tagging(&self) -> Option<String> {
+        if self.s3_tags.is_empty() {
+            return None;
+        }
+        let pairs: Vec<String> = self
+            .s3_tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", url_encode(key), url_encode(value)))
+            .collect();
+        Some(pairs.join("&"))
+    }
+}
+
+fn url_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+static UPLOAD: OnceLock<UploadArgs> = OnceLock::new();
+
+/// Set the upload options for the rest of the run.
+pub fn configure(args: &UploadArgs) {
+    let _ = UPLOAD.set(args.clone());
+}
+
+fn upload_args() -> &'static UploadArgs {
+    UPLOAD.get_or_init(|| UploadArgs {
+        s3_part_size_mb: 64,
+        s3_max_attempts: 5,
+        s3_initial_backoff_ms: 1000,
+        s3_timeout: None,
+        requester_pays: false,
+        s3_endpoint: None,
+        s3_force_path_style: false,
+        assume_role_arn: None,
+        assume_role_external_id: None,
+        assume_role_session_name: "osm-planet-sharding".to_string(),
+        sse_kms_key_id: None,
+        storage_class: None,
+        s3_tags: Vec::new(),
+    })
+}
+
+/// A parsed `s3://bucket/key` URI.
+#[derive(Clone, Debug)]
+pub struct S3Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Location {
+    /// Parse `s3://bucket/key`; returns `None` for anything that is not an S3 URI.
+    pub fn parse(uri: &str) -> Option<Self> {
+        let rest = uri.strip_prefix("s3://")?;
+        let (bucket, key) = rest.split_once('/')?;
+        if bucket.is_empty() || key.is_empty() {
+            return None;
+        }
+        Some(Self {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for S3Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "s3://{}/{}", self.bucket, self.key)
+    }
+}
+
+/// Run a future to completion from synchronous code inside the tokio runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+/// Build a client from the default credential and region chain, with the role, endpoint,
+/// addressing and timeout options applied.
+pub fn client() -> Client {
+    let args = upload_args();
+    let config = block_on(aws_config::load_defaults(
+        aws_config::BehaviorVersion::latest(),
+    ));
+    // Retries are ours (see `with_retries`), so attempts do not multiply with the SDK's.
+    let mut builder = aws_sdk_s3::config::Builder::from(&config)
+        .force_path_style(args.s3_force_path_style)
+        .retry_config(RetryConfig::disabled());
+    if let Some(timeout) = args.s3_timeout {
+        builder = builder.timeout_config(
+            TimeoutConfig::builder()
+                .operation_attempt_timeout(Duration::from_secs(timeout))
+                .build(),
+        );
+    }
+    if let Some(role_arn) = &args.assume_role_arn {
+        let mut role = AssumeRoleProvider::builder(role_arn)
+            .session_name(&args.assume_role_session_name)
+            .configure(&config);
+        if let Some(external_id) = &args.assume_role_external_id {
+            role = role.external_id(external_id);
+        }
+        builder = builder.credentials_provider(block_on(role.build()));
+    }
+    if let Some(endpoint) = &args.s3_endpoint {
+        builder = builder.endpoint_url(endpoint);
+    }
+    Client::from_conf(builder.build())
+}
+
+/// Fetch an object, returning `None` if it does not exist.
+pub fn get_object(client: &Client, location: &S3Location) -> Result<Option<Vec<u8>>> {
+    let request_payer = upload_args()
+        .requester_pays
+        .then_some(RequestPayer::Requester);
+    let response = with_retries(&format!("fetch of {location}"), || {
+        client
+            .get_object()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .set_request_payer(request_payer.clone())
+            .send()
+    });
+    let output = match response {
+        Ok(output) => output,
+        Err(err)
+            if err
+                .downcast_ref::<SdkError<GetObjectError, HttpResponse>>()
+                .and_then(SdkError::as_service_error)
+                .is_some_and(GetObjectError::is_no_such_key) =>
+        {
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    let body =
+        block_on(output.body.collect()).with_context(|| format!("unable to read {location}"))?;
+    Ok(Some(body.into_bytes().to_vec()))
+}
+
+/// Fetch `length` bytes of an object from `offset` on: fewer at its end, none past it.
+pub fn get_range(
+    client: &Client,
+    location: &S3Location,
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u8>> {
+    let request_payer = upload_args()
+        .requester_pays
+        .then_some(RequestPayer::Requester);
+    let range = format!("bytes={offset}-{}", offset + length.max(1) - 1);
+    let response = with_retries(&format!("ranged fetch of {location}"), || {
+        client
+            .get_object()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .range(&range)
+            .set_request_payer(request_payer.clone())
+            .send()
+    });
+    let output = match response {
+        Ok(output) => output,
+        Err(err)
+            if err
+                .downcast_ref::<SdkError<GetObjectError, HttpResponse>>()
+                .and_then(SdkError::as_service_error)
+                .is_some_and(|err| err.code() == Some("InvalidRange")) =>
+        {
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(err),
+    };
+    let body =
+        block_on(output.body.collect()).with_context(|| format!("unable to read {location}"))?;
+    Ok(body.into_bytes().to_vec())
+}
+
+/// Upload `body` as an object.
+pub fn put_object(client: &Client, location: &S3Location, body: Vec<u8>) -> Result<()> {
+    let len = body.len() as u64;
+    let sha256 = format!("{:x}", Sha256::digest(&body));
+    upload(client, location, body.as_slice(), len, None, &sha256)
+}
+
+/// Upload `body` as an object unless one exists already, with `If-None-Match: *`; `false`
+/// if it did.
+pub fn put_object_if_absent(client: &Client, location: &S3Location, body: Vec<u8>) -> Result<bool> {
+    let args = upload_args();
+    let digest = Sha256::digest(&body);
+    let checksum = base64::engine::general_purpose::STANDARD.encode(digest);
+    let sha256 = format!("{digest:x}");
+    let tagging = args.tagging();
+    let response = with_retries(&format!("conditional upload of {location}"), || {
+        let mut request = client
+            .put_object()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .if_none_match("*")
+            .checksum_sha256(&checksum)
+            .metadata("sha256", &sha256)
+            .set_storage_class(args.storage_class.clone())
+            .set_tagging(tagging.clone())
+            .body(ByteStream::from(body.clone()));
+        if let Some(key_id) = &args.sse_kms_key_id {
+            request = request
+                .server_side_encryption(ServerSideEncryption::AwsKms)
+                .ssekms_key_id(key_id);
+        }
+        request.send()
+    });
+    match response {
+        Ok(_) => {
+            metrics::add(Counter::S3UploadBytes, body.len() as u64);
+            Ok(true)
+        }
+        Err(err)
+            if err
+                .downcast_ref::<SdkError<PutObjectError, HttpResponse>>()
+                .is_some_and(|err| err.code() == Some("PreconditionFailed")) =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Upload `len` bytes from `reader`, as a multipart upload when they exceed one part.
+/// `content_encoding` is set as the object's `Content-Encoding`, e.g. `gzip`, and `sha256`,
+/// the hex SHA-256 of the whole object, as its `sha256` metadata. The bytes read are checked
+/// against `sha256` before the object is committed.
+pub fn upload(
+    client: &Client,
+    location: &S3Location,
+    mut reader: impl Read,
+    len: u64,
+    content_encoding: Option<&str>,
+    sha256: &str,
+) -> Result<()> {
+    let args = upload_args();
+    // Grow the parts if the object would otherwise need more than S3 allows.
+    let part_size = (args.s3_part_size_mb << 20)
+        .max(len.div_ceil(MAX_PARTS))
+        .max(MIN_PART_SIZE);
+    if len <= part_size {
+        let body = read_part(&mut reader, len)?;
+        let digest = Sha256::digest(&body);
+        if format!("{digest:x}") != sha256 {
+            bail!("upload source for {location} does not match its SHA-256");
+        }
+        // S3 checks the whole object against this, so one part can use SHA-256 itself.
+        let checksum = base64::engine::general_purpose::STANDARD.encode(digest);
+        let tagging = args.tagging();
+        with_retries(&format!("upload of {location}"), || {
+            let mut request = client
+                .put_object()
+                .bucket(&location.bucket)
+                .key(&location.key)
+                .checksum_sha256(&checksum)
+                .metadata("sha256", sha256)
+                .set_storage_class(args.storage_class.clone())
+                .set_tagging(tagging.clone())
+                .set_content_encoding(content_encoding.map(str::to_string))
+                .body(ByteStream::from(body.clone()));
+            if let Some(key_id) = &args.sse_kms_key_id {
+                request = request
+                    .server_side_encryption(ServerSideEncryption::AwsKms)
+                    .ssekms_key_id(key_id);
+            }
+            request.send()
+        })?;
+        metrics::add(Counter::S3UploadBytes, len);
+        return Ok(());
+    }
+
+    let tagging = args.tagging();
+    let upload_id = with_retries(&format!("start of upload of {location}"), || {
+        let mut request = client
+            .create_multipart_upload()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .checksum_algorithm(ChecksumAlgorithm::Crc32)
+            .metadata("sha256", sha256)
+            .set_storage_class(args.storage_class.clone())
+            .set_tagging(tagging.clone())
+            .set_content_encoding(content_encoding.map(str::to_string));
+        if let Some(key_id) = &args.sse_kms_key_id {
+            request = request
+                .server_side_encryption(ServerSideEncryption::AwsKms)
+                .ssekms_key_id(key_id);
+        }
+        request.send()
+    })?
+    .upload_id
+    .with_context(|| format!("no upload id for {location}"))?;
+    info!(
+        location = %location,
+        bytes = len,
+        part_size,
+        "Uploading {} bytes to {} in {} parts.",
+        len,
+        location,
+        len.div_ceil(part_size)
+    );
+
+    let result = upload_parts(client, location, &upload_id, &mut reader, len, part_size).and_then(
+        |(parts, digest)| {
+            if digest != sha256 {
+                bail!("upload source for {location} does not match its SHA-256");
+            }
+            Ok(parts)
+        },
+    );
+    let parts = match result {
+        Ok(parts) => parts,
+        Err(err) => {
+            // Free the parts already stored; the upload is failing either way.
+            let _ = block_on(
+                client
+                    .abort_multipart_upload()
+                    .bucket(&location.bucket)
+                    .key(&location.key)
+                    .upload_id(&upload_id)
+                    .send(),
+            );
+            return Err(err);
+        }
+    };
+    with_retries(&format!("completion of upload of {location}"), || {
+        client
+            .complete_multipart_upload()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts.clone()))
+                    .build(),
+            )
+            .send()
+    })?;
+    Ok(())
+}
+
+fn upload_parts(
+    client: &Client,
+    location: &S3Location,
+    upload_id: &str,
+    reader: &mut impl Read,
+    len: u64,
+    part_size: u64,
+) -> Result<(Vec<CompletedPart>, String)> {
+    let mut parts = Vec::new();
+    let mut hasher = Sha256::new();
+    let mut remaining = len;
+    let mut number = 1;
+    while remaining > 0 {
+        let body = read_part(reader, remaining.min(part_size))?;
+        hasher.update(&body);
+        let checksum = crc32(&body);
+        let output = with_retries(&format!("part {number} of {location}"), || {
+            client
+                .upload_part()
+                .bucket(&location.bucket)
+                .key(&location.key)
+                .upload_id(upload_id)
+                .part_number(number)
+                .checksum_crc32(&checksum)
+                .body(ByteStream::from(body.clone()))
+                .send()
+        })?;
+        parts.push(
+            CompletedPart::builder()
+                .part_number(number)
+                .set_e_tag(output.e_tag)
+                .checksum_crc32(checksum)
+                .build(),
+        );
+        metrics::add(Counter::S3UploadBytes, body.len() as u64);
+        remaining -= body.len() as u64;
+        number += 1;
+    }
+    Ok((parts, format!("{:x}", hasher.finalize())))
+}
+
+fn read_part(reader: &mut impl Read, len: u64) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(len as usize);
+    reader.take(len).read_to_end(&mut body)?;
+    if (body.len() as u64) < len {
+        bail!(
+            "upload source ended {} bytes early",
+            len - body.len() as u64
+        );
+    }
+    Ok(body)
+}
+
+/// Base64 of the big-endian CRC32, as S3 expects in checksum headers.
+fn crc32(bytes: &[u8]) -> String {
+    let mut crc = Crc::new();
+    crc.update(bytes);
+    base64::engine::general_purpose::STANDARD.encode(crc.sum().to_be_bytes())
+}
+
+/// Send the request built by `request`, retrying transient failures (throttling, server
+/// errors, timeouts, dropped connections, checksum mismatches) with exponential backoff.
+/// Other errors fail at once.
+fn with_retries<T, E, F>(what: &str, mut request: impl FnMut() -> F) -> Result<T>
+where
+    F: Future<Output = std::result::Result<T, SdkError<E, HttpResponse>>>,
+    E: std::error::Error + ProvideErrorMetadata + Send + Sync + 'static,
+{
+    let args = upload_args();
+    let attempts = args.s3_max_attempts;
+    let mut backoff = Duration::from_millis(args.s3_initial_backoff_ms);
+    let mut attempt = 1;
+    loop {
+        match block_on(request()) {
+            Ok(output) => return Ok(output),
+            Err(err) if attempt < attempts && retryable(&err) => {
+                warn!(
+                    attempt,
+                    "{what} failed (attempt {attempt} of {attempts}), retrying in {:?}: {}",
+                    backoff,
+                    describe(&err)
+                );
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                attempt += 1;
+            }
+            Err(err) if attempt > 1 => {
+                return Err(err).with_context(|| format!("{what} failed after {attempt} attempts"))
+            }
+            Err(err) => return Err(err).with_context(|| format!("{what} failed")),
+        }
+    }
+}
+
+/// The S3 error code and message, or the chain of causes for transport errors.
+fn describe<E>(err: &SdkError<E, HttpResponse>) -> String
+where
+    E: std::error::Error + ProvideErrorMetadata + 'static,
+{
+    if let Some(code) = err.code() {
+        return match err.message() {
+            Some(message) => format!("{code}: {message}"),
+            None => code.to_string(),
+        };
+    }
+    let mut text = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        text.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    text
+}
+
+fn retryable<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    match err {
+        SdkError::ConstructionFailure(_) => false,
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        _ => {
+            let status = err
+                .raw_response()
+                .map(|response| response.status().as_u16());
+            matches!(status, Some(408 | 429 | 500..=599))
+                || matches!(
+                    err.code(),
+                    Some("BadDigest" | "SlowDown" | "RequestTimeout")
+                )
+        }
+    }
+}
+
+/// Delete an object; deleting a missing key is not an error.
+pub fn delete_object(client: &Client, location: &S3Location) -> Result<()> {
+    with_retries(&format!("deletion of {location}"), || {
+        client
+            .delete_object()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .send()
+    })?;
+    Ok(())
+}
+
+/// Presigned GET URL for an object, valid for `expires_in`. Signing happens locally, so the
+/// URL stops working early if the credentials it was signed with (e.g. an assumed role's
+/// session) expire first.
+pub fn presign_get(client: &Client, location: &S3Location, expires_in: Duration) -> Result<String> {
+    let config = PresigningConfig::expires_in(expires_in)
+        .with_context(|| format!("invalid presigned URL expiry {expires_in:?}"))?;
+    let request = block_on(
+        client
+            .get_object()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .set_request_payer(
+                upload_args()
+                    .requester_pays
+                    .then_some(RequestPayer::Requester),
+            )
+            .presigned(config),
+    )
+    .with_context(|| format!("unable to presign {location}"))?;
+    Ok(request.uri().to_string())
+}
+
+/// An object found by [`list_objects`].
+pub struct Object {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<DateTime>,
+}
+
+/// Every object in `bucket` whose key starts with `prefix`.
+pub fn list_objects(client: &Client, bucket: &str, prefix: &str) -> Result<Vec<Object>> {
+    let mut objects = Vec::new();
+    let mut token = None;
+    loop {
+        let page = with_retries(&format!("listing of s3://{bucket}/{prefix}"), || {
+            client
+                .list_objects_v2()
+                .bucket(bucket)
+                .prefix(prefix)
+                .set_continuation_token(token.clone())
+                .set_request_payer(
+                    upload_args()
+                        .requester_pays
+                        .then_some(RequestPayer::Requester),
+                )
+                .send()
+        })?;
+        objects.extend(page.contents().iter().map(|object| Object {
+            key: object.key().unwrap_or_default().to_string(),
+            size: object.size().unwrap_or(0) as u64,
+            last_modified: object.last_modified().copied(),
+        }));
+        token = page.next_continuation_token().map(str::to_string);
+        if token.is_none() {
+            return Ok(objects);
+        }
+    }
+}
+
+/// Delete `keys` from `bucket`, a thousand (the most one request takes) at a time.
+pub fn delete_objects(client: &Client, bucket: &str, keys: &[String]) -> Result<()> {
+    for batch in keys.chunks(1000) {
+        let objects = batch
+            .iter()
+            .map(|key| ObjectIdentifier::builder().key(key).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        let delete = Delete::builder()
+            .set_objects(Some(objects))
+            .quiet(true)
+            .build()?;
+        let output = with_retries(&format!("deletion from s3://{bucket}"), || {
+            client
+                .delete_objects()
+                .bucket(bucket)
+                .delete(delete.clone())
+                .send()
+        })?;
+        if let Some(error) = output.errors().first() {
+            bail!(
+                "unable to delete s3://{bucket}/{} ({} of {} failed): {}",
+                error.key().unwrap_or_default(),
+                output.errors().len(),
+                batch.len(),
+                error.message().or(error.code()).unwrap_or("unknown error")
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,607 @@
+//! The counting pass: stream nodes from every input and tally them per tile and zoom level.
+
+use anyhow::{bail, Context, Result};
+use hashbrown::HashMap;
+use osmpbf::{Blob, BlobDecode, BlobReader, BlobType, ByteOffset};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, info_span, warn};
+
+use crate::blob_index::BlobIndex;
+use crate::checkpoint::{Checkpointer, Position};
+use crate::histogram;
+use crate::input::{self, InputFormat, OsmElement};
+use crate::logging;
+use crate::lon_lat_to_tile;
+use crate::metrics::{self, Counter};
+use crate::node_set::NodeIdSet;
+use crate::progress::{Progress, ProgressFormat};
+use crate::spill::{Spilled, Spiller};
+use crate::tally::{BBox, Tally, TileRange};
+
+/// PBF blobs decoded in parallel between checkpoint opportunities.
+const BLOBS_PER_BATCH: usize = 256;
+/// Nodes counted between memory and progress checks when scanning non-PBF input.
+const SPILL_CHECK_INTERVAL: u64 = 1 << 20;
+
+/// Aggregated counts for every resolution plus the total number of nodes we saw.
+///
+/// While scanning only the max-zoom level is filled in; the coarser levels are derived from
+/// it in a single pass once all inputs are counted.
+pub struct ScanResult {
+    pub counts: Vec<HashMap<(u32, u32), u64>>,
+    pub node_total: u64,
+    pub duplicate_total: u64,
+    /// Sampling fraction the counts were scaled up from, if any.
+    pub sample: Option<f64>,
+    /// With `--memory-limit`, the max-zoom counts live in these sorted runs instead of
+    /// `counts` (which is left empty).
+    pub spilled: Option<Spilled>,
+    /// With [`ScanOptions::blob_index_zoom`], the node blobs of the input.
+    pub blob_index: Option<BlobIndex>,
+}
+
+impl ScanResult {
+    pub(crate) fn empty(max_zoom: u8) -> Self {
+        Self {
+            counts: (0..=max_zoom).map(|_| HashMap::new()).collect(),
+            node_total: 0,
+            duplicate_total: 0,
+            sample: None,
+            spilled: None,
+            blob_index: None,
+        }
+    }
+
+    /// Approximate heap use of the count maps.
+    pub fn heap_bytes(&self) -> usize {
+        let entry = std::mem::size_of::<((u32, u32), u64)>() + 1;
+        self.counts
+            .iter()
+            .map(|level| level.capacity() * entry)
+            .sum()
+    }
+
+    /// Rebuild every coarser level from the max-zoom counts.
+    fn aggregate(&mut self) {
+        let max_zoom = self.counts.len() - 1;
+        let leaves = std::mem::take(&mut self.counts[max_zoom]);
+        self.counts = histogram::aggregate_levels(leaves, max_zoom as u8);
+    }
+
+    fn merge(&mut self, other: ScanResult) {
+        for (level, other_level) in self.counts.iter_mut().zip(other.counts) {
+            for (cell, count) in other_level {
+                *level.entry(cell).or_insert(0) += count;
+            }
+        }
+        self.node_total += other.node_total;
+        self.duplicate_total += other.duplicate_total;
+    }
+}
+
+/// Parameters of one scan.
+pub struct ScanOptions<'a> {
+    pub format: InputFormat,
+    pub max_zoom: u8,
+    /// Only count this fraction of nodes, see [`sample_keeps`].
+    pub sample: Option<f64>,
+    /// Periodically persist progress (single PBF inputs only).
+    pub checkpoint: Option<&'a Checkpointer>,
+    /// Keep the count maps under a memory limit by spilling them to disk.
+    pub spill: Option<&'a Spiller>,
+    /// Expected extent of the data, for dense counting; PBF header boxes are used otherwise.
+    pub bbox: Option<BBox>,
+    pub progress: ProgressFormat,
+    /// Time between progress updates.
+    pub progress_interval: Duration,
+    /// Index the node blobs by the tiles they touch at this zoom (single PBF inputs only).
+    pub blob_index_zoom: Option<u8>,
+}
+
+/// Scan every input into one hierarchical histogram. With several inputs, nodes are
+/// de-duplicated by ID so overlapping extracts are not double counted. With `sample`, the
+/// returned counts are estimates scaled up from the sampled nodes.
+pub fn scan_osm(paths: &[PathBuf], options: &ScanOptions<'_>) -> Result<ScanResult> {
+    let max_zoom = options.max_zoom;
+    let seen = (paths.len() > 1).then(NodeIdSet::new);
+    let mut checkpoint = options.checkpoint;
+    if checkpoint.is_some() && paths.len() > 1 {
+        warn!("checkpoints are only supported for single-file scans, disabling.");
+        checkpoint = None;
+    }
+    if checkpoint.is_some() && options.spill.is_some() {
+        warn!("checkpoints are not supported with --memory-limit, disabling.");
+        checkpoint = None;
+    }
+    if options.blob_index_zoom.is_some()
+        && (paths.len() > 1 || options.format.resolve(&paths[0])? != InputFormat::Pbf)
+    {
+        bail!("a blob index needs a single PBF input");
+    }
+    let mut total = ScanResult::empty(max_zoom);
+
+    for (idx, path) in paths.iter().enumerate() {
+        let _file = info_span!("scan_file", file = %path.display()).entered();
+        info!(
+            file = %path.display(),
+            max_zoom,
+            "Scanning {} ({}/{}, max zoom = {})...",
+            path.display(),
+            idx + 1,
+            paths.len(),
+            max_zoom
+        );
+        let scan = match options.format.resolve(path)? {
+            InputFormat::Pbf => scan_file(path, options, seen.as_ref(), checkpoint)?,
+            other => {
+                if checkpoint.is_some() {
+                    warn!("checkpoints are only supported for PBF input, disabling.");
+                    checkpoint = None;
+                }
+                scan_source(path, other, options, seen.as_ref())?
+            }
+        };
+        if idx == 0 {
+            total = scan;
+            continue;
+        }
+        total.merge(scan);
+        if let Some(spill) = options.spill {
+            spill.maybe_spill(&mut total)?;
+        }
+    }
+
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.clear()?;
+    }
+
+    if let Some(fraction) = options.sample {
+        info!(
+            sampled_nodes = total.node_total,
+            sample = fraction,
+            "Sampled {} nodes ({:.4}%); scaling counts by {:.2}.",
+            total.node_total,
+            fraction * 100.0,
+            1.0 / fraction
+        );
+        let scale = |count: u64| (count as f64 / fraction).round() as u64;
+        // Spilled counts are scaled as they are merged back, so only scale them here when
+        // they stay in memory.
+        if options.spill.is_none() {
+            for count in total.counts[usize::from(max_zoom)].values_mut() {
+                *count = scale(*count);
+            }
+        }
+        total.node_total = scale(total.node_total);
+        total.duplicate_total = scale(total.duplicate_total);
+        total.sample = Some(fraction);
+    }
+
+    match options.spill {
+        Some(spill) => spill.finish(&mut total)?,
+        None => total.aggregate(),
+    }
+
+    Ok(total)
+}
+
+/// Stream the PBF in batches of blobs, decode each batch in parallel, map every node to its
+/// max-zoom cell, and keep tallies. Every worker thread counts into its own
+/// long-lived accumulator; they are only folded together when a checkpoint or spill needs
+/// the running totals, and once at the end. Checkpoints record the offset of the next blob.
+///
+/// Files sorted by type (the usual case for planet dumps) store all nodes first, so reading
+/// stops at the first batch containing a way or relation block instead of decompressing the
+/// remaining blobs only to discard them.
+fn scan_file(
+    path: &Path,
+    options: &ScanOptions<'_>,
+    seen: Option<&NodeIdSet>,
+    checkpoint: Option<&Checkpointer>,
+) -> Result<ScanResult> {
+    let (max_zoom, sample) = (options.max_zoom, options.sample);
+    let mut reader = BlobReader::seekable_from_path(path)
+        .with_context(|| format!("unable to open {}", path.display()))?;
+
+    let header = read_header(&mut reader)
+        .with_context(|| format!("unable to read the header of {}", path.display()))?;
+    reader.seek(ByteOffset(0))?;
+
+    // Dense grids are not spilled, so they are only used without a memory limit.
+    let threads = rayon::current_num_threads();
+    let range = options
+        .bbox
+        .or(header.bbox)
+        .filter(|_| options.spill.is_none())
+        .and_then(|bbox| TileRange::dense_for(&bbox, max_zoom, threads));
+    if let Some(range) = range {
+        info!(
+            width = range.width(),
+            height = range.height(),
+            "Counting z{max_zoom} tiles in a dense {}x{} grid over the bounding box.",
+            range.width(),
+            range.height()
+        );
+    }
+
+    let mut progress = Progress::new(
+        options.progress,
+        options.progress_interval,
+        path,
+        Some(fs::metadata(path)?.len()),
+    );
+    let mut scan = ScanResult::empty(max_zoom);
+    let mut index = options.blob_index_zoom.map(BlobIndex::new);
+    let mut position = match checkpoint {
+        Some(_) => Some(Position::new(path, max_zoom, sample)?),
+        None => None,
+    };
+    if let (Some(checkpoint), Some(position)) = (checkpoint, &position) {
+        if let Some((offset, partial)) = checkpoint.resume_from(position)? {
+            reader.seek(ByteOffset(offset))?;
+            progress.resumed_at(offset, partial.node_total);
+            if index.take().is_some() {
+                warn!("the blobs before the checkpoint were not indexed, skipping the blob index.");
+            }
+            scan = partial;
+            // Coarser levels are re-derived once the scan completes.
+            for level in &mut scan.counts[..usize::from(max_zoom)] {
+                *level = HashMap::new();
+            }
+        }
+    }
+
+    let locals: Vec<Mutex<Tally>> = (0..threads)
+        .map(|_| Mutex::new(Tally::new(max_zoom, range)))
+        .collect();
+    let collect_locals = |scan: &mut ScanResult| {
+        for local in &locals {
+            scan.merge(local.lock().expect("scan accumulator lock poisoned").take());
+        }
+    };
+
+    loop {
+        let batch = reader
+            .by_ref()
+            .take(BLOBS_PER_BATCH)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        let Some(first) = batch.first() else {
+            break;
+        };
+
+        if let (Some(checkpoint), Some(position)) = (checkpoint, &mut position) {
+            if checkpoint.due() {
+                position.offset = first.offset().map_or(0, |offset| offset.0);
+                collect_locals(&mut scan);
+                checkpoint.save(position, &scan)?;
+            }
+        }
+
+        let index_zoom = index.as_ref().map(BlobIndex::zoom);
+        let scanned = batch
+            .par_iter()
+            .map(|blob| {
+                let thread = rayon::current_thread_index().unwrap_or(0) % locals.len();
+                let mut local = locals[thread]
+                    .lock()
+                    .expect("scan accumulator lock poisoned");
+                let mut tiles = index_zoom.map(|zoom| (zoom, Vec::new()));
+                let past_nodes = scan_blob(blob, &mut local, sample, seen, tiles.as_mut())?;
+                Ok(ScannedBlob {
+                    past_nodes,
+                    tiles: tiles.map(|(_, tiles)| tiles),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let past_nodes = scanned.iter().any(|blob| blob.past_nodes);
+        let end = reader.seek_raw(SeekFrom::Current(0))?;
+        if let Some(index) = &mut index {
+            index_batch(index, &batch, scanned, end, header.sorted);
+        }
+
+        let nodes = scan.node_total
+            + locals
+                .iter()
+                .map(|local| {
+                    local
+                        .lock()
+                        .expect("scan accumulator lock poisoned")
+                        .node_total()
+                })
+                .sum::<u64>();
+        progress.update(Some(end), nodes);
+
+        if let Some(spill) = options.spill {
+            let local_bytes: usize = locals
+                .iter()
+                .map(|local| {
+                    local
+                        .lock()
+                        .expect("scan accumulator lock poisoned")
+                        .heap_bytes()
+                })
+                .sum();
+            if spill.exceeded(scan.heap_bytes() + local_bytes) {
+                collect_locals(&mut scan);
+                spill.maybe_spill(&mut scan)?;
+            }
+        }
+
+        if header.sorted && past_nodes {
+            info!("Reached the way and relation blocks of a type-sorted file, stopping.");
+            break;
+        }
+    }
+
+    collect_locals(&mut scan);
+    progress.finish(scan.node_total);
+    scan.blob_index = index;
+    Ok(scan)
+}
+
+/// What [`scan_blob`] found in a blob.
+struct ScannedBlob {
+    past_nodes: bool,
+    /// Tiles of the blob's nodes at the blob index zoom.
+    tiles: Option<Vec<(u32, u32)>>,
+}
+
+/// Add a batch of blobs to the index. Each blob runs up to the next one, the last up to
+/// `end`. Data blobs without nodes only mark where the ways start, if the file is sorted.
+fn index_batch(
+    index: &mut BlobIndex,
+    batch: &[Blob],
+    scanned: Vec<ScannedBlob>,
+    end: u64,
+    sorted: bool,
+) {
+    let offsets: Vec<u64> = batch
+        .iter()
+        .map(|blob| blob.offset().map_or(0, |offset| offset.0))
+        .collect();
+    let ends = offsets.iter().skip(1).copied().chain([end]);
+    for ((blob, (offset, end)), scanned) in batch.iter().zip(offsets.iter().zip(ends)).zip(scanned)
+    {
+        let length = end - offset;
+        match blob.get_type() {
+            BlobType::OsmHeader => index.set_header(*offset, length),
+            BlobType::OsmData if scanned.past_nodes => {
+                if sorted {
+                    index.set_ways_offset(*offset);
+                }
+            }
+            BlobType::OsmData => index.push(*offset, length, scanned.tiles.unwrap_or_default()),
+            BlobType::Unknown(_) => {}
+        }
+    }
+}
+
+/// Provenance recorded in a PBF header: the planet state the file represents and what
+/// wrote it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SourceMetadata {
+    pub replication_timestamp: Option<String>,
+    pub replication_sequence_number: Option<i64>,
+    pub replication_base_url: Option<String>,
+    pub writing_program: Option<String>,
+    pub source: Option<String>,
+    /// `[west, south, east, north]`.
+    pub bbox: Option<[f64; 4]>,
+}
+
+/// Read the provenance fields of a PBF file's header.
+pub fn source_metadata(path: &Path) -> Result<SourceMetadata> {
+    let mut reader = BlobReader::seekable_from_path(path)
+        .with_context(|| format!("unable to open {}", path.display()))?;
+    let header = read_header(&mut reader)
+        .with_context(|| format!("unable to read the header of {}", path.display()))?;
+    Ok(header.metadata)
+}
+
+/// What the PBF header block tells us about the data.
+#[derive(Default)]
+struct FileHeader {
+    /// `Sort.Type_then_ID` ordering is declared.
+    sorted: bool,
+    bbox: Option<BBox>,
+    metadata: SourceMetadata,
+}
+
+fn read_header(reader: &mut BlobReader<BufReader<File>>) -> Result<FileHeader> {
+    let Some(blob) = reader.next().transpose()? else {
+        return Ok(FileHeader::default());
+    };
+    let BlobDecode::OsmHeader(header) = blob.decode()? else {
+        return Ok(FileHeader::default());
+    };
+    let bbox = header.bbox().map(|bbox| BBox {
+        west: bbox.left,
+        south: bbox.top.min(bbox.bottom),
+        east: bbox.right,
+        north: bbox.top.max(bbox.bottom),
+    });
+    Ok(FileHeader {
+        sorted: header
+            .optional_features()
+            .iter()
+            .any(|feature| feature == "Sort.Type_then_ID"),
+        bbox,
+        metadata: SourceMetadata {
+            replication_timestamp: header
+                .osmosis_replication_timestamp()
+                .map(|secs| logging::format_rfc3339(secs, None)),
+            replication_sequence_number: header.osmosis_replication_sequence_number(),
+            replication_base_url: header.osmosis_replication_base_url().map(str::to_string),
+            writing_program: header.writing_program().map(str::to_string),
+            source: header.source().map(str::to_string),
+            bbox: bbox.map(|bbox| [bbox.west, bbox.south, bbox.east, bbox.north]),
+        },
+    })
+}
+
+/// Tally the nodes of a single PBF blob. Way and relation groups are skipped without
+/// iterating their elements; returns true for data blobs that held no nodes at all. With
+/// `tiles`, also collects the tiles of the blob's nodes at the given zoom.
+fn scan_blob(
+    blob: &Blob,
+    tally: &mut Tally,
+    sample: Option<f64>,
+    seen: Option<&NodeIdSet>,
+    mut tiles: Option<&mut (u8, Vec<(u32, u32)>)>,
+) -> Result<bool> {
+    let BlobDecode::OsmData(block) = blob.decode()? else {
+        return Ok(false);
+    };
+    metrics::add(Counter::BlobsProcessed, 1);
+
+    let mut saw_nodes = false;
+    let mut visit = |id: i64, lat: f64, lon: f64| {
+        saw_nodes = true;
+        if !(lat.is_finite() && lon.is_finite()) {
+            return;
+        }
+        // Every node is indexed, sampled or not.
+        if let Some((zoom, tiles)) = tiles.as_deref_mut() {
+            tiles.extend(lon_lat_to_tile(lon, lat, *zoom));
+        }
+        if sample.is_some_and(|fraction| !sample_keeps(id, fraction)) {
+            return;
+        }
+        if seen.is_some_and(|seen| !seen.insert(id)) {
+            tally.count_duplicate();
+            return;
+        }
+        tally.count(lon, lat);
+    };
+    for group in block.groups() {
+        for node in group.dense_nodes() {
+            visit(node.id(), node.lat(), node.lon());
+        }
+        for node in group.nodes() {
+            visit(node.id(), node.lat(), node.lon());
+        }
+    }
+
+    Ok(!saw_nodes)
+}
+
+/// Scan a non-PBF input sequentially through the generic element pipeline.
+fn scan_source(
+    path: &Path,
+    format: InputFormat,
+    options: &ScanOptions<'_>,
+    seen: Option<&NodeIdSet>,
+) -> Result<ScanResult> {
+    let (max_zoom, sample) = (options.max_zoom, options.sample);
+    let mut source = input::open_source(path, format)?;
+    let mut scan = ScanResult::empty(max_zoom);
+    // Decompressed stream positions do not map onto the file size, so only rates are shown.
+    let mut progress = Progress::new(options.progress, options.progress_interval, path, None);
+
+    source.for_each_element(&mut |element| {
+        let OsmElement::Node(node) = element else {
+            return Ok(());
+        };
+        if !(node.lat.is_finite() && node.lon.is_finite()) {
+            return Ok(());
+        }
+        if sample.is_some_and(|fraction| !sample_keeps(node.id, fraction)) {
+            return Ok(());
+        }
+        if seen.is_some_and(|seen| !seen.insert(node.id)) {
+            scan.duplicate_total += 1;
+            return Ok(());
+        }
+        if tally_node(&mut scan.counts, max_zoom, node.lon, node.lat) {
+            scan.node_total += 1;
+            if scan.node_total.is_multiple_of(SPILL_CHECK_INTERVAL) {
+                progress.update(None, scan.node_total);
+                if let Some(spill) = options.spill {
+                    spill.maybe_spill(&mut scan)?;
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    progress.finish(scan.node_total);
+    Ok(scan)
+}
+
+/// Deterministically keep about `fraction` of all node IDs. IDs are hashed first so the
+/// sample is not biased towards any ID (and therefore mapping-era) range.
+fn sample_keeps(id: i64, fraction: f64) -> bool {
+    // splitmix64 finalizer.
+    let mut h = id as u64;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h as f64) < fraction * u64::MAX as f64
+}
+
+/// Count one node in its max-zoom tile. Returns false if the coordinate could not be
+/// mapped to a tile.
+fn tally_node(counts: &mut [HashMap<(u32, u32), u64>], max_zoom: u8, lon: f64, lat: f64) -> bool {
+    let Some(tile) = lon_lat_to_tile(lon, lat, max_zoom) else {
+        return false;
+    };
+    *counts[usize::from(max_zoom)].entry(tile).or_insert(0) += 1;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spill::Spiller;
+    use std::io::Write;
+
+    fn options(spill: Option<&Spiller>) -> ScanOptions<'_> {
+        ScanOptions {
+            format: InputFormat::Xml,
+            max_zoom: 12,
+            sample: Some(0.5),
+            checkpoint: None,
+            spill,
+            bbox: None,
+            progress: ProgressFormat::None,
+            progress_interval: Duration::from_secs(60),
+            blob_index_zoom: None,
+        }
+    }
+
+    #[test]
+    fn sampled_counts_are_scaled_once_when_spilled() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("nodes.osm");
+        let mut xml = File::create(&path)?;
+        writeln!(xml, "<osm version=\"0.6\">")?;
+        for id in 1..=2000 {
+            let (lon, lat) = (f64::from(id % 40) * 0.01, f64::from(id / 40) * 0.01);
+            writeln!(xml, "<node id=\"{id}\" lat=\"{lat}\" lon=\"{lon}\"/>")?;
+        }
+        writeln!(xml, "</osm>")?;
+        drop(xml);
+        let paths = [path];
+
+        let in_memory = scan_osm(&paths, &options(None))?;
+        let in_memory_cells: u64 = in_memory.counts[12].values().sum();
+
+        let spiller = Spiller::new(0, Some(dir.path()));
+        let spilled = scan_osm(&paths, &options(Some(&spiller)))?;
+        let mut spilled_cells = 0;
+        for cell in spilled.spilled.expect("spilled counts").merge()? {
+            spilled_cells += cell?.1;
+        }
+
+        assert_eq!(in_memory.node_total, spilled.node_total);
+        assert_eq!(in_memory_cells, spilled_cells);
+        Ok(())
+    }
+}
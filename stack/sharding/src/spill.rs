@@ -0,0 +1,225 @@
+//! Bounded-memory counting for very high max zooms.
+//!
+//! When the in-memory tallies outgrow `--memory-limit`, the max-zoom cells are sorted by
+//! Morton key and written to a temporary run file, and the maps are cleared. Once the scan
+//! is done the runs are k-way merged back into a single sorted stream, which the histogram
+//! writer and the streaming planner consume without ever materializing all cells at once.
+
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::TempPath;
+use tracing::info;
+
+use crate::histogram::{read_varint, write_varint};
+use crate::scan::ScanResult;
+use crate::tile_key;
+
+/// Parse a byte size such as `512M`, `4G`, `1.5GiB` or a plain byte count.
+pub fn parse_byte_size(raw: &str) -> Result<u64, String> {
+    let trimmed = raw.trim();
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{raw}'"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => return Err(format!("unknown size unit '{other}' in '{raw}'")),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Spills the max-zoom counts of a scan to sorted run files whenever they grow too large.
+pub struct Spiller {
+    limit: u64,
+    dir: PathBuf,
+    runs: Mutex<Vec<TempPath>>,
+}
+
+impl Spiller {
+    pub fn new(limit: u64, dir: Option<&Path>) -> Self {
+        Self {
+            limit,
+            dir: dir.map_or_else(std::env::temp_dir, Path::to_path_buf),
+            runs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Whether `bytes` of count maps are over the memory limit.
+    pub fn exceeded(&self, bytes: usize) -> bool {
+        bytes as u64 > self.limit
+    }
+
+    /// Spill `scan` if its maps use more than the memory limit.
+    pub fn maybe_spill(&self, scan: &mut ScanResult) -> Result<()> {
+        if self.exceeded(scan.heap_bytes()) {
+            self.spill(scan)?;
+        }
+        Ok(())
+    }
+
+    /// Spill whatever is left in memory and hand all runs over to `scan`.
+    pub fn finish(&self, scan: &mut ScanResult) -> Result<()> {
+        self.spill(scan)?;
+        let runs = std::mem::take(&mut *self.runs.lock().expect("spill runs lock poisoned"));
+        scan.spilled = Some(Spilled {
+            runs,
+            sample: scan.sample,
+        });
+        Ok(())
+    }
+
+    fn spill(&self, scan: &mut ScanResult) -> Result<()> {
+        let max_zoom = scan.counts.len() - 1;
+        let leaves = std::mem::take(&mut scan.counts[max_zoom]);
+        for level in &mut scan.counts {
+            *level = HashMap::new();
+        }
+        if leaves.is_empty() {
+            return Ok(());
+        }
+
+        let mut cells: Vec<(u64, u64)> = leaves
+            .into_iter()
+            .map(|((x, y), count)| (tile_key(x, y), count))
+            .collect();
+        cells.sort_unstable_by_key(|&(key, _)| key);
+
+        let file = tempfile::Builder::new()
+            .prefix("osm-counts-")
+            .suffix(".run")
+            .tempfile_in(&self.dir)
+            .with_context(|| format!("unable to create a spill file in {}", self.dir.display()))?;
+        let mut out = BufWriter::new(file.as_file());
+        let mut prev = 0;
+        for &(key, count) in &cells {
+            write_varint(&mut out, key - prev)?;
+            write_varint(&mut out, count)?;
+            prev = key;
+        }
+        out.flush()?;
+        drop(out);
+
+        let mut runs = self.runs.lock().expect("spill runs lock poisoned");
+        runs.push(file.into_temp_path());
+        info!(
+            cells = cells.len(),
+            run = runs.len(),
+            "Spilled {} max-zoom cells to disk (run {}).",
+            cells.len(),
+            runs.len()
+        );
+        Ok(())
+    }
+}
+
+/// Sorted runs left behind by a bounded-memory scan. The files are removed on drop.
+pub struct Spilled {
+    runs: Vec<TempPath>,
+    sample: Option<f64>,
+}
+
+impl Spilled {
+    /// Merge all runs into one stream of `(Morton key, count)` cells in key order, with
+    /// sampled counts scaled back up.
+    pub fn merge(self) -> Result<Merge> {
+        let mut readers = Vec::with_capacity(self.runs.len());
+        let mut heap = BinaryHeap::with_capacity(self.runs.len());
+        for (idx, path) in self.runs.iter().enumerate() {
+            let file = File::open(path)
+                .with_context(|| format!("unable to open spill file {}", path.display()))?;
+            let mut reader = RunReader {
+                input: BufReader::new(file),
+                key: 0,
+            };
+            if let Some((key, count)) = reader.next_cell()? {
+                heap.push(Reverse((key, idx, count)));
+            }
+            readers.push(reader);
+        }
+        Ok(Merge {
+            _runs: self.runs,
+            readers,
+            heap,
+            sample: self.sample,
+        })
+    }
+}
+
+/// K-way merge over the spilled runs, summing cells that appear in several runs.
+pub struct Merge {
+    _runs: Vec<TempPath>,
+    readers: Vec<RunReader>,
+    heap: BinaryHeap<Reverse<(u64, usize, u64)>>,
+    sample: Option<f64>,
+}
+
+impl Merge {
+    fn next_cell(&mut self) -> Result<Option<(u64, u64)>> {
+        let Some(Reverse((key, idx, mut count))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        self.advance(idx)?;
+        while let Some(&Reverse((next_key, next_idx, next_count))) = self.heap.peek() {
+            if next_key != key {
+                break;
+            }
+            self.heap.pop();
+            count += next_count;
+            self.advance(next_idx)?;
+        }
+        if let Some(fraction) = self.sample {
+            count = (count as f64 / fraction).round() as u64;
+        }
+        Ok(Some((key, count)))
+    }
+
+    fn advance(&mut self, idx: usize) -> Result<()> {
+        if let Some((key, count)) = self.readers[idx].next_cell()? {
+            self.heap.push(Reverse((key, idx, count)));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<(u64, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_cell().transpose()
+    }
+}
+
+struct RunReader {
+    input: BufReader<File>,
+    key: u64,
+}
+
+impl RunReader {
+    fn next_cell(&mut self) -> Result<Option<(u64, u64)>> {
+        let mut first = [0u8; 1];
+        if self.input.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+        let delta = if first[0] & 0x80 == 0 {
+            u64::from(first[0])
+        } else {
+            u64::from(first[0] & 0x7f) | (read_varint(&mut self.input)? << 7)
+        };
+        let count = read_varint(&mut self.input)?;
+        self.key += delta;
+        Ok(Some((self.key, count)))
+    }
+}
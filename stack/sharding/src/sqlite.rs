@@ -0,0 +1,565 @@
+//! SQLite database writer (<https://www.sqlite.org/fileformat.html>), for GeoPackage and
+//! MBTiles.
+//!
+//! Tables are written a row at a time in rowid order: leaf pages are appended as they fill,
+//! with overflow pages for large rows, and the interior pages once the table is complete,
+//! so a table never needs to be held in memory. Indexes, such as the automatic ones of
+//! `PRIMARY KEY` and `UNIQUE` constraints, are written the same way from their entries in
+//! index order, without overflow pages; views only need their SQL.
+//! The schema table goes into page 1 last. There is no free space to manage and nothing is
+//! ever updated, which keeps this far smaller than a SQLite dependency.
+
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+const PAGE_SIZE: usize = 4096;
+const HEADER_BYTES: usize = 100;
+const PAGE_INTERIOR_INDEX: u8 = 0x02;
+const PAGE_INTERIOR_TABLE: u8 = 0x05;
+const PAGE_LEAF_INDEX: u8 = 0x0A;
+const PAGE_LEAF_TABLE: u8 = 0x0D;
+/// Largest payload kept in a table leaf cell, and the part of a larger one kept there at
+/// least (the file format's X and M).
+const MAX_LOCAL: usize = PAGE_SIZE - 35;
+const MIN_LOCAL: usize = (PAGE_SIZE - 12) * 32 / 255 - 23;
+/// Largest payload of an index entry, which is kept without overflow.
+const MAX_INDEX_LOCAL: usize = (PAGE_SIZE - 12) * 64 / 255 - 23;
+/// Children of an interior table page, whose cells take at most 15 bytes: a page number, a
+/// rowid varint and a cell pointer.
+const INTERIOR_CHILDREN: usize = (PAGE_SIZE - 12) / 15 + 1;
+/// Children of the schema's root in page 1.
+const ROOT_CHILDREN: usize = (PAGE_SIZE - HEADER_BYTES - 12) / 15 + 1;
+
+/// A value of a row.
+#[derive(Clone, Copy, Debug)]
+pub enum Datum<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(&'a str),
+    Blob(&'a [u8]),
+}
+
+impl Datum<'_> {
+    /// SQLite's order of values with the BINARY collation: NULL, numbers, text, blobs.
+    fn order(&self, other: &Self) -> Ordering {
+        let class = |datum: &Datum| match datum {
+            Datum::Null => 0,
+            Datum::Integer(_) | Datum::Real(_) => 1,
+            Datum::Text(_) => 2,
+            Datum::Blob(_) => 3,
+        };
+        let number = |datum: &Datum| match *datum {
+            Datum::Integer(value) => value as f64,
+            Datum::Real(value) => value,
+            _ => 0.0,
+        };
+        match (self, other) {
+            (Datum::Integer(a), Datum::Integer(b)) => a.cmp(b),
+            (Datum::Text(a), Datum::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (Datum::Blob(a), Datum::Blob(b)) => a.cmp(b),
+            _ => class(self).cmp(&class(other)).then_with(|| {
+                number(self)
+                    .partial_cmp(&number(other))
+                    .unwrap_or(Ordering::Equal)
+            }),
+        }
+    }
+}
+
+/// A row of `sqlite_schema`.
+struct SchemaEntry {
+    kind: &'static str,
+    name: String,
+    table: String,
+    root: u32,
+    sql: Option<String>,
+}
+
+/// A table being written.
+pub struct Table {
+    schema: usize,
+    leaf: Vec<Vec<u8>>,
+    leaf_bytes: usize,
+    last_rowid: Option<i64>,
+    /// Page and largest rowid of each leaf written.
+    leaves: Vec<(u32, i64)>,
+}
+
+/// An index being written.
+pub struct Index {
+    schema: usize,
+    leaf: Vec<Vec<u8>>,
+    leaf_bytes: usize,
+    /// Leaves written, each followed by the payload of the entry between it and the next.
+    leaves: Vec<u32>,
+    dividers: Vec<Vec<u8>>,
+}
+
+pub struct Database {
+    out: BufWriter<File>,
+    /// Pages written, page 1 included.
+    pages: u32,
+    schema: Vec<SchemaEntry>,
+}
+
+impl Database {
+    /// Start a database in `file`, which must be empty.
+    pub fn create(file: File) -> Result<Self> {
+        let mut out = BufWriter::new(file);
+        // Page 1 holds the header and the schema, written last.
+        out.write_all(&[0; PAGE_SIZE])?;
+        Ok(Self {
+            out,
+            pages: 1,
+            schema: Vec::new(),
+        })
+    }
+
+    /// Start a table created by `sql`; rows follow with [`Database::insert`].
+    pub fn table(&mut self, name: &str, sql: &str) -> Table {
+        self.schema.push(SchemaEntry {
+            kind: "table",
+            name: name.to_string(),
+            table: name.to_string(),
+            root: 0,
+            sql: Some(sql.to_string()),
+        });
+        Table {
+            schema: self.schema.len() - 1,
+            leaf: Vec::new(),
+            leaf_bytes: 0,
+            last_rowid: None,
+            leaves: Vec::new(),
+        }
+    }
+
+    /// Add a row; rowids must increase.
+    pub fn insert(&mut self, table: &mut Table, rowid: i64, values: &[Datum]) -> Result<()> {
+        if table.last_rowid.is_some_and(|last| rowid <= last) {
+            bail!("rowid {rowid} out of order");
+        }
+        let payload = record(values);
+        let mut cell = Vec::new();
+        varint(&mut cell, payload.len() as u64);
+        varint(&mut cell, rowid as u64);
+        let local = if payload.len() <= MAX_LOCAL {
+            payload.len()
+        } else {
+            let local = MIN_LOCAL + (payload.len() - MIN_LOCAL) % (PAGE_SIZE - 4);
+            if local <= MAX_LOCAL {
+                local
+            } else {
+                MIN_LOCAL
+            }
+        };
+        cell.extend_from_slice(&payload[..local]);
+        if local < payload.len() {
+            let first = self.overflow(&payload[local..])?;
+            cell.extend_from_slice(&first.to_be_bytes());
+        }
+
+        if !table.leaf.is_empty() && 8 + table.leaf_bytes + cell.len() + 2 > PAGE_SIZE {
+            self.flush_leaf(table)?;
+        }
+        table.leaf_bytes += cell.len() + 2;
+        table.leaf.push(cell);
+        table.last_rowid = Some(rowid);
+        Ok(())
+    }
+
+    /// Write the table's remaining leaf and its interior pages.
+    pub fn end_table(&mut self, mut table: Table) -> Result<()> {
+        if !table.leaf.is_empty() || table.leaves.is_empty() {
+            self.flush_leaf(&mut table)?;
+        }
+        let mut level = table.leaves;
+        while level.len() > 1 {
+            level = self.interior_level(&level)?;
+        }
+        self.schema[table.schema].root = level[0].0;
+        Ok(())
+    }
+
+    /// An index of `table`, named as SQLite names the index of a constraint
+    /// (`sqlite_autoindex_<table>_<n>`) or with `sql` creating it, of `entries`: the indexed
+    /// values of each row followed by its rowid.
+    pub fn index(
+        &mut self,
+        name: &str,
+        table: &str,
+        sql: Option<&str>,
+        mut entries: Vec<Vec<Datum>>,
+    ) -> Result<()> {
+        entries.sort_by(|a, b| {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| a.order(b))
+                .find(|order| order.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        let mut index = self.start_index(name, table, sql);
+        for entry in &entries {
+            self.index_entry(&mut index, entry)?;
+        }
+        self.end_index(index)
+    }
+
+    /// Start an index as [`Database::index`] makes one, for entries too many to hold in
+    /// memory; they follow in index order with [`Database::index_entry`].
+    pub fn start_index(&mut self, name: &str, table: &str, sql: Option<&str>) -> Index {
+        self.schema.push(SchemaEntry {
+            kind: "index",
+            name: name.to_string(),
+            table: table.to_string(),
+            root: 0,
+            sql: sql.map(str::to_string),
+        });
+        Index {
+            schema: self.schema.len() - 1,
+            leaf: Vec::new(),
+            leaf_bytes: 0,
+            leaves: Vec::new(),
+            dividers: Vec::new(),
+        }
+    }
+
+    /// Add an entry, after those before it in index order.
+    pub fn index_entry(&mut self, index: &mut Index, entry: &[Datum]) -> Result<()> {
+        let payload = record(entry);
+        if payload.len() > MAX_INDEX_LOCAL {
+            let name = &self.schema[index.schema].name;
+            bail!("index {name} has an entry too large for a page");
+        }
+        let cell = index_cell(None, &payload);
+        if 8 + index.leaf_bytes + cell.len() + 2 > PAGE_SIZE {
+            // The entry goes between this leaf and the next, in their parent.
+            let leaf = page(PAGE_LEAF_INDEX, &index.leaf, None, 0);
+            index.leaves.push(self.write_page(&leaf)?);
+            index.dividers.push(payload);
+            index.leaf.clear();
+            index.leaf_bytes = 0;
+            return Ok(());
+        }
+        index.leaf_bytes += cell.len() + 2;
+        index.leaf.push(cell);
+        Ok(())
+    }
+
+    /// Write the index's remaining leaf and its interior pages.
+    pub fn end_index(&mut self, mut index: Index) -> Result<()> {
+        if index.leaf.is_empty() {
+            // The last entry went into no leaf, waiting for one after it.
+            if let Some(payload) = index.dividers.pop() {
+                index.leaf.push(index_cell(None, &payload));
+            }
+        }
+        let leaf = page(PAGE_LEAF_INDEX, &index.leaf, None, 0);
+        index.leaves.push(self.write_page(&leaf)?);
+        let (mut level, mut dividers) = (index.leaves, index.dividers);
+        while level.len() > 1 {
+            (level, dividers) = self.index_interior_level(&level, &dividers)?;
+        }
+        self.schema[index.schema].root = level[0];
+        Ok(())
+    }
+
+    pub fn view(&mut self, name: &str, sql: &str) {
+        self.schema.push(SchemaEntry {
+            kind: "view",
+            name: name.to_string(),
+            table: name.to_string(),
+            root: 0,
+            sql: Some(sql.to_string()),
+        });
+    }
+
+    /// Write the schema and the header, with the `application_id` and `user_version` that
+    /// identify file formats built on SQLite.
+    pub fn finish(mut self, application_id: u32, user_version: u32) -> Result<()> {
+        let schema = std::mem::take(&mut self.schema);
+        let cells: Vec<Vec<u8>> = schema
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| {
+                let values = [
+                    Datum::Text(entry.kind),
+                    Datum::Text(&entry.name),
+                    Datum::Text(&entry.table),
+                    Datum::Integer(i64::from(entry.root)),
+                    entry.sql.as_deref().map_or(Datum::Null, Datum::Text),
+                ];
+                let payload = record(&values);
+                let mut cell = Vec::new();
+                varint(&mut cell, payload.len() as u64);
+                varint(&mut cell, idx as u64 + 1);
+                cell.extend_from_slice(&payload);
+                cell
+            })
+            .collect();
+        if cells.iter().any(|cell| cell.len() > MAX_LOCAL) {
+            bail!("schema SQL too long");
+        }
+
+        // The schema's root is page 1, after the header.
+        let room = PAGE_SIZE - HEADER_BYTES;
+        let root = if 8 + cells.iter().map(|cell| cell.len() + 2).sum::<usize>() <= room {
+            page(PAGE_LEAF_TABLE, &cells, None, HEADER_BYTES)
+        } else {
+            let mut level = Vec::new();
+            let (mut start, mut bytes) = (0, 8);
+            for (idx, cell) in cells.iter().enumerate() {
+                if bytes + cell.len() + 2 > PAGE_SIZE {
+                    let leaf = page(PAGE_LEAF_TABLE, &cells[start..idx], None, 0);
+                    level.push((self.write_page(&leaf)?, idx as i64));
+                    (start, bytes) = (idx, 8);
+                }
+                bytes += cell.len() + 2;
+            }
+            let leaf = page(PAGE_LEAF_TABLE, &cells[start..], None, 0);
+            level.push((self.write_page(&leaf)?, cells.len() as i64));
+            while level.len() > ROOT_CHILDREN {
+                level = self.interior_level(&level)?;
+            }
+            interior_page(&level, HEADER_BYTES)
+        };
+
+        let mut first = vec![0; PAGE_SIZE];
+        first[HEADER_BYTES..].copy_from_slice(&root[HEADER_BYTES..]);
+        first[..16].copy_from_slice(b"SQLite format 3\0");
+        first[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+        // Rollback journal, no reserved bytes, the fixed payload fractions.
+        first[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+        first[24..28].copy_from_slice(&1u32.to_be_bytes());
+        first[28..32].copy_from_slice(&self.pages.to_be_bytes());
+        // Schema cookie, schema format 4, UTF-8.
+        first[40..44].copy_from_slice(&1u32.to_be_bytes());
+        first[44..48].copy_from_slice(&4u32.to_be_bytes());
+        first[56..60].copy_from_slice(&1u32.to_be_bytes());
+        first[60..64].copy_from_slice(&user_version.to_be_bytes());
+        first[68..72].copy_from_slice(&application_id.to_be_bytes());
+        first[92..96].copy_from_slice(&1u32.to_be_bytes());
+        first[96..100].copy_from_slice(&3_046_000u32.to_be_bytes());
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&first)?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn write_page(&mut self, page: &[u8]) -> Result<u32> {
+        debug_assert_eq!(page.len(), PAGE_SIZE);
+        self.out.write_all(page)?;
+        self.pages += 1;
+        Ok(self.pages)
+    }
+
+    /// Write `rest` of a payload to a chain of overflow pages, returning the first.
+    fn overflow(&mut self, rest: &[u8]) -> Result<u32> {
+        let chunks: Vec<&[u8]> = rest.chunks(PAGE_SIZE - 4).collect();
+        let first = self.pages + 1;
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let next = if idx + 1 < chunks.len() {
+                self.pages + 2
+            } else {
+                0
+            };
+            let mut page = vec![0; PAGE_SIZE];
+            page[..4].copy_from_slice(&next.to_be_bytes());
+            page[4..4 + chunk.len()].copy_from_slice(chunk);
+            self.write_page(&page)?;
+        }
+        Ok(first)
+    }
+
+    fn flush_leaf(&mut self, table: &mut Table) -> Result<()> {
+        let leaf = page(PAGE_LEAF_TABLE, &table.leaf, None, 0);
+        let number = self.write_page(&leaf)?;
+        table
+            .leaves
+            .push((number, table.last_rowid.unwrap_or_default()));
+        table.leaf.clear();
+        table.leaf_bytes = 0;
+        Ok(())
+    }
+
+    /// Interior pages over `children` (page and largest rowid), returning the level above.
+    /// Children are spread evenly, so no page is left with a single one.
+    fn interior_level(&mut self, children: &[(u32, i64)]) -> Result<Vec<(u32, i64)>> {
+        let pages = children.len().div_ceil(INTERIOR_CHILDREN);
+        let (per_page, extra) = (children.len() / pages, children.len() % pages);
+        let mut parents = Vec::with_capacity(pages);
+        let mut start = 0;
+        for idx in 0..pages {
+            let end = start + per_page + usize::from(idx < extra);
+            let page = interior_page(&children[start..end], 0);
+            parents.push((self.write_page(&page)?, children[end - 1].1));
+            start = end;
+        }
+        Ok(parents)
+    }
+
+    /// Interior index pages over `children`, between each two of which is the entry of
+    /// one of `dividers`, returning the level above and the entries between its pages.
+    /// Children are spread evenly, as in [`Database::interior_level`].
+    fn index_interior_level(
+        &mut self,
+        children: &[u32],
+        dividers: &[Vec<u8>],
+    ) -> Result<(Vec<u32>, Vec<Vec<u8>>)> {
+        let largest = dividers.iter().map(Vec::len).max().unwrap_or_default();
+        let per_page = (PAGE_SIZE - 12) / (index_cell(Some(0), &vec![0; largest]).len() + 2) + 1;
+        let pages = children.len().div_ceil(per_page);
+        let (per_page, extra) = (children.len() / pages, children.len() % pages);
+        let (mut parents, mut between) = (Vec::with_capacity(pages), Vec::new());
+        let mut start = 0;
+        for idx in 0..pages {
+            let end = start + per_page + usize::from(idx < extra);
+            let cells: Vec<Vec<u8>> = (start..end - 1)
+                .map(|child| index_cell(Some(children[child]), &dividers[child]))
+                .collect();
+            let page = page(PAGE_INTERIOR_INDEX, &cells, Some(children[end - 1]), 0);
+            parents.push(self.write_page(&page)?);
+            if end < children.len() {
+                between.push(dividers[end - 1].clone());
+            }
+            start = end;
+        }
+        Ok((parents, between))
+    }
+}
+
+/// A cell of an index page: the left child's page on interior pages, then the entry.
+fn index_cell(child: Option<u32>, payload: &[u8]) -> Vec<u8> {
+    let mut cell = child.map_or_else(Vec::new, |page| page.to_be_bytes().to_vec());
+    varint(&mut cell, payload.len() as u64);
+    cell.extend_from_slice(payload);
+    cell
+}
+
+/// Cells of an interior table page: each child's page and largest rowid.
+fn interior_cells(children: &[(u32, i64)]) -> Vec<Vec<u8>> {
+    children
+        .iter()
+        .map(|&(page, rowid)| {
+            let mut cell = page.to_be_bytes().to_vec();
+            varint(&mut cell, rowid as u64);
+            cell
+        })
+        .collect()
+}
+
+fn interior_page(children: &[(u32, i64)], offset: usize) -> Vec<u8> {
+    let (last, rest) = children
+        .split_last()
+        .expect("interior page without children");
+    page(
+        PAGE_INTERIOR_TABLE,
+        &interior_cells(rest),
+        Some(last.0),
+        offset,
+    )
+}
+
+/// A B-tree page of `cells`, in key order, with the content at the end of the page.
+/// `offset` is where the page header starts: 100 on page 1, after the database header.
+fn page(kind: u8, cells: &[Vec<u8>], right: Option<u32>, offset: usize) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    let header = if right.is_some() { 12 } else { 8 };
+    let mut content = PAGE_SIZE;
+    for (idx, cell) in cells.iter().enumerate() {
+        content -= cell.len();
+        page[content..content + cell.len()].copy_from_slice(cell);
+        let pointer = offset + header + 2 * idx;
+        page[pointer..pointer + 2].copy_from_slice(&(content as u16).to_be_bytes());
+    }
+    page[offset] = kind;
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    // A content start of 65536 is written as 0.
+    page[offset + 5..offset + 7].copy_from_slice(&(content as u16).to_be_bytes());
+    if let Some(right) = right {
+        page[offset + 8..offset + 12].copy_from_slice(&right.to_be_bytes());
+    }
+    page
+}
+
+/// A row in the record format: a header of serial types, then the values.
+fn record(values: &[Datum]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial = match *value {
+            Datum::Null => 0,
+            Datum::Integer(0) => 8,
+            Datum::Integer(1) => 9,
+            Datum::Integer(value) => {
+                let (serial, bytes) = match value {
+                    -0x80..=0x7F => (1, 1),
+                    -0x8000..=0x7FFF => (2, 2),
+                    -0x80_0000..=0x7F_FFFF => (3, 3),
+                    -0x8000_0000..=0x7FFF_FFFF => (4, 4),
+                    -0x8000_0000_0000..=0x7FFF_FFFF_FFFF => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&value.to_be_bytes()[8 - bytes..]);
+                serial
+            }
+            Datum::Real(value) => {
+                body.extend_from_slice(&value.to_be_bytes());
+                7
+            }
+            Datum::Text(value) => {
+                body.extend_from_slice(value.as_bytes());
+                value.len() as u64 * 2 + 13
+            }
+            Datum::Blob(value) => {
+                body.extend_from_slice(value);
+                value.len() as u64 * 2 + 12
+            }
+        };
+        varint(&mut types, serial);
+    }
+    // The header size counts itself: one byte, unless that makes it longer.
+    let mut size = types.len() + 1;
+    let mut header = Vec::new();
+    loop {
+        header.clear();
+        varint(&mut header, size as u64);
+        if header.len() + types.len() == size {
+            break;
+        }
+        size = header.len() + types.len();
+    }
+    header.extend_from_slice(&types);
+    header.extend_from_slice(&body);
+    header
+}
+
+/// SQLite's big-endian varint: 7 bits per byte with the high bit set on all but the last,
+/// and a full 8 bits in the ninth.
+fn varint(out: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        let mut bytes = [0u8; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest as u8 & 0x7F) | 0x80;
+            rest >>= 7;
+        }
+        out.extend_from_slice(&bytes);
+        return;
+    }
+    let mut bytes = Vec::with_capacity(8);
+    let mut rest = value;
+    loop {
+        bytes.push(rest as u8 & 0x7F);
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    for (idx, byte) in bytes.iter().enumerate().rev() {
+        out.push(if idx > 0 { byte | 0x80 } else { *byte });
+    }
+}
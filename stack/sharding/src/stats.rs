@@ -0,0 +1,151 @@
+//! Shard size distribution, for judging how balanced a sharding configuration is.
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::Shard;
+
+/// Number of equal-width buckets between zero and the node threshold.
+const BUCKETS: u64 = 10;
+/// Width of the widest bar in the text report.
+const BAR_WIDTH: usize = 40;
+
+/// Shards whose node count falls in `(lower, upper]` (the first bucket includes zero); the
+/// last bucket is open-ended and holds the max-zoom tiles over the threshold.
+#[derive(Serialize)]
+pub struct Bucket {
+    pub lower: u64,
+    pub upper: Option<u64>,
+    pub shards: usize,
+}
+
+#[derive(Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub min: u64,
+    pub mean: f64,
+    pub max: u64,
+    pub p95: u64,
+    pub stddev: f64,
+    /// Coefficient of variation, `stddev / mean`.
+    pub cv: f64,
+    /// Gini coefficient of the node counts: 0 when all shards are equal, towards 1 when a
+    /// few shards hold most nodes.
+    pub gini: f64,
+    /// Largest shard relative to the mean; the slowest worker's share of a parallel run.
+    pub imbalance: f64,
+    pub buckets: Vec<Bucket>,
+}
+
+impl Distribution {
+    /// Statistics of the node counts of `shards`, bucketed by tenths of `max_nodes`.
+    pub fn of(shards: &[Shard], max_nodes: u64) -> Option<Self> {
+        let mut sizes: Vec<u64> = shards.iter().map(|shard| shard.node_count).collect();
+        if sizes.is_empty() {
+            return None;
+        }
+        sizes.sort_unstable();
+
+        let n = sizes.len() as f64;
+        let total: u64 = sizes.iter().sum();
+        let mean = total as f64 / n;
+        let variance = sizes
+            .iter()
+            .map(|&size| (size as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        let stddev = variance.sqrt();
+        // Gini over sorted values: sum((2i - n - 1) * x_i) / (n * sum(x)), with 1-based i.
+        let weighted: f64 = sizes
+            .iter()
+            .enumerate()
+            .map(|(idx, &size)| (2.0 * (idx + 1) as f64 - n - 1.0) * size as f64)
+            .sum();
+        let gini = if total > 0 {
+            weighted / (n * total as f64)
+        } else {
+            0.0
+        };
+
+        let width = max_nodes.div_ceil(BUCKETS).max(1);
+        let mut buckets: Vec<Bucket> = (0..BUCKETS)
+            .map(|idx| Bucket {
+                lower: idx * width,
+                upper: Some((idx + 1) * width),
+                shards: 0,
+            })
+            .collect();
+        buckets.push(Bucket {
+            lower: BUCKETS * width,
+            upper: None,
+            shards: 0,
+        });
+        for &size in &sizes {
+            let idx = if size > max_nodes {
+                BUCKETS
+            } else {
+                (size.saturating_sub(1) / width).min(BUCKETS - 1)
+            };
+            buckets[idx as usize].shards += 1;
+        }
+
+        let max = sizes[sizes.len() - 1];
+        Some(Self {
+            count: sizes.len(),
+            min: sizes[0],
+            mean,
+            max,
+            p95: sizes[(sizes.len() * 95).div_ceil(100) - 1],
+            stddev,
+            cv: if mean > 0.0 { stddev / mean } else { 0.0 },
+            gini,
+            imbalance: if mean > 0.0 { max as f64 / mean } else { 0.0 },
+            buckets,
+        })
+    }
+
+    /// Log the statistics and a text histogram of the buckets.
+    pub fn report(&self, max_nodes: u64) {
+        info!(
+            shards = self.count,
+            mean = self.mean,
+            stddev = self.stddev,
+            cv = self.cv,
+            gini = self.gini,
+            imbalance = self.imbalance,
+            "Shard size distribution ({} shards, max {} nodes per shard): min {}, mean {:.1}, \
+             p95 {}, max {}, stddev {:.1}, CV {:.3}, Gini {:.3}, max/mean {:.2}",
+            self.count,
+            max_nodes,
+            self.min,
+            self.mean,
+            self.p95,
+            self.max,
+            self.stddev,
+            self.cv,
+            self.gini,
+            self.imbalance
+        );
+        let widest = self
+            .buckets
+            .iter()
+            .map(|b| b.shards)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        for bucket in &self.buckets {
+            let range = match bucket.upper {
+                Some(upper) => format!("{}-{}", bucket.lower, upper),
+                None => format!(">{}", max_nodes),
+            };
+            let bar = "#".repeat((bucket.shards * BAR_WIDTH).div_ceil(widest));
+            info!(
+                lower = bucket.lower,
+                upper = bucket.upper,
+                shards = bucket.shards,
+                "  {range:>17} {:>8} {bar}",
+                bucket.shards
+            );
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! Object stores that checkpoints and manifests are written to, addressed by URI: a local
+//! path or `file://` URL, `s3://bucket/key`, `gs://bucket/object` or `az://container/blob`.
+//!
+//! S3 goes through the SDK (see [`crate::s3`]). Google Cloud Storage and Azure Blob Storage
+//! go through the `gcloud` and `az` CLIs, the same way compressed inputs go through `bzip2`,
+//! so credentials are resolved from each cloud's usual environment (application default
+//! credentials, `AZURE_STORAGE_ACCOUNT` and friends). Those CLIs must be on the `PATH` to
+//! use their schemes; the AWS image does not ship them.
+
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::keys;
+use crate::s3::{self, S3Location};
+use crate::summary;
+
+/// Bytes fetched per request by [`Store::copy_to`].
+pub const COPY_RANGE: u64 = 16 << 20;
+
+/// Where an object lives.
+pub enum Store {
+    Local(PathBuf),
+    S3 {
+        client: aws_sdk_s3::Client,
+        location: S3Location,
+    },
+    /// A `gs://bucket/object` URI.
+    Gcs(String),
+    Azure {
+        container: String,
+        blob: String,
+    },
+}
+
+impl Store {
+    /// Parse `uri`, after filling in its key from the template (see [`keys::location`]) for
+    /// an object called `name`. Anything without a scheme is a local path.
+    pub fn open(uri: &str, name: &str) -> Result<Self> {
+        let uri = &keys::location(uri, name)?;
+        if let Some(path) = uri.strip_prefix("file://") {
+            return Ok(Store::Local(PathBuf::from(path)));
+        }
+        if uri.starts_with("s3://") {
+            let location = S3Location::parse(uri)
+                .with_context(|| format!("invalid location {uri}, expected s3://bucket/key"))?;
+            return Ok(Store::S3 {
+                client: s3::client(),
+                location,
+            });
+        }
+        if let Some(rest) = uri.strip_prefix("gs://") {
+            if !rest
+                .split_once('/')
+                .is_some_and(|(bucket, object)| !bucket.is_empty() && !object.is_empty())
+            {
+                bail!("invalid location {uri}, expected gs://bucket/object");
+            }
+            return Ok(Store::Gcs(uri.to_string()));
+        }
+        if let Some(rest) = uri.strip_prefix("az://") {
+            return match rest.split_once('/') {
+                Some((container, blob)) if !container.is_empty() && !blob.is_empty() => {
+                    Ok(Store::Azure {
+                        container: container.to_string(),
+                        blob: blob.to_string(),
+                    })
+                }
+                _ => bail!("invalid location {uri}, expected az://container/blob"),
+            };
+        }
+        if let Some((scheme, _)) = uri.split_once("://") {
+            bail!("unsupported location {uri}: {scheme}:// is not a known object store");
+        }
+        Ok(Store::Local(PathBuf::from(uri)))
+    }
+
+    /// Open the object called `name` in `output`: a local directory, created if missing,
+    /// or a bucket or prefix URI under which the object is keyed by `template` (see
+    /// [`keys::location_with`]).
+    pub fn open_in(output: &str, template: &str, name: &str) -> Result<Self> {
+        if !output.contains("://") || output.starts_with("file://") {
+            let path = Path::new(output.strip_prefix("file://").unwrap_or(output)).join(name);
+            // `name` may have directories of its own, such as Hive partitions.
+            let dir = path.parent().expect("joined path without a parent");
+            fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create {}", dir.display()))?;
+            return Ok(Store::Local(path));
+        }
+        // Always a prefix: one location per object.
+        let prefix = match output.ends_with('/') {
+            true => output.to_string(),
+            false => format!("{output}/"),
+        };
+        Store::open(&keys::location_with(&prefix, template, name)?, name)
+    }
+
+    /// Fetch the object, returning `None` if it does not exist.
+    pub fn get(&self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Store::Local(path) => match fs::read(path) {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err).with_context(|| format!("unable to read {}", path.display())),
+            },
+            Store::S3 { client, location } => s3::get_object(client, location),
+            Store::Gcs(uri) => match run_cli("gcloud", &["storage", "cat", uri])? {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(stderr) if stderr.contains("matched no objects") => Ok(None),
+                Err(stderr) => bail!("unable to fetch {uri}: {stderr}"),
+            },
+            Store::Azure { container, blob } => {
+                let tmp = tempfile::NamedTempFile::new()?;
+                let file = tmp.path().to_string_lossy();
+                let args = blob_args("download", container, blob, &["--file", &file]);
+                match run_cli("az", &args)? {
+                    Ok(_) => Ok(Some(fs::read(tmp.path())?)),
+                    Err(stderr) if stderr.contains("BlobNotFound") => Ok(None),
+                    Err(stderr) => bail!("unable to fetch {self}: {stderr}"),
+                }
+            }
+        }
+    }
+
+    /// Fetch `length` bytes of the object from `offset` on: fewer at its end, none past it.
+    pub fn get_range(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let last = offset + length.max(1) - 1;
+        match self {
+            Store::Local(path) => {
+                let mut file = File::open(path)
+                    .with_context(|| format!("unable to read {}", path.display()))?;
+                let mut bytes = Vec::new();
+                file.seek(SeekFrom::Start(offset))?;
+                file.take(length).read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Store::S3 { client, location } => s3::get_range(client, location, offset, length),
+            Store::Gcs(uri) => {
+                let range = format!("--range={offset}-{last}");
+                match run_cli("gcloud", &["storage", "cat", &range, uri])? {
+                    Ok(bytes) => Ok(bytes),
+                    Err(stderr) if stderr.contains("416") => Ok(Vec::new()),
+                    Err(stderr) => bail!("unable to fetch {uri}: {stderr}"),
+                }
+            }
+            Store::Azure { container, blob } => {
+                let tmp = tempfile::NamedTempFile::new()?;
+                let file = tmp.path().to_string_lossy();
+                let (start, end) = (offset.to_string(), last.to_string());
+                let extra = [
+                    "--file",
+                    &file,
+                    "--start-range",
+                    &start,
+                    "--end-range",
+                    &end,
+                ];
+                match run_cli("az", &blob_args("download", container, blob, &extra))? {
+                    Ok(_) => Ok(fs::read(tmp.path())?),
+                    Err(stderr) if stderr.contains("InvalidRange") => Ok(Vec::new()),
+                    Err(stderr) => bail!("unable to fetch {self}: {stderr}"),
+                }
+            }
+        }
+    }
+
+    /// Copy the object to `out` a range at a time, so it is never held in memory whole,
+    /// failing once it is larger than `max_size`; returns its size.
+    pub fn copy_to(&self, out: &mut dyn Write, max_size: Option<u64>) -> Result<u64> {
+        let mut size = 0;
+        loop {
+            let bytes = self.get_range(size, COPY_RANGE)?;
+            size += bytes.len() as u64;
+            if let Some(max_size) = max_size.filter(|&max_size| size > max_size) {
+                bail!("{self} is larger than {max_size} bytes");
+            }
+            out.write_all(&bytes)?;
+            if (bytes.len() as u64) < COPY_RANGE {
+                return Ok(size);
+            }
+        }
+    }
+
+    /// Write `bytes` as the object, replacing any previous one.
+    pub fn put(&self, bytes: Vec<u8>) -> Result<()> {
+        match self {
+            Store::Local(path) => {
+                // Write-then-rename so a crash mid-write leaves the previous object intact.
+                let mut tmp = stage(path)?;
+                tmp.write_all(&bytes)
+                    .with_context(|| format!("unable to write {}", tmp.path().display()))?;
+                make_readable(tmp.path())?;
+                tmp.persist(path)
+                    .map_err(|err| err.error)
+                    .with_context(|| format!("unable to replace {}", path.display()))?;
+                Ok(())
+            }
+            Store::S3 { client, location } => s3::put_object(client, location, bytes),
+            Store::Gcs(_) | Store::Azure { .. } => {
+                let mut tmp = tempfile::NamedTempFile::new()?;
+                tmp.write_all(&bytes)?;
+                tmp.flush()?;
+                self.put_file(tmp.path(), None)
+            }
+        }
+    }
+
+    /// Write `bytes` as the object unless it exists already, as one atomic check, so of two
+    /// writers only one succeeds; `false` if it existed.
+    pub fn put_new(&self, bytes: Vec<u8>) -> Result<bool> {
+        match self {
+            Store::Local(path) => {
+                // Persisting without clobbering fails if the object exists, where renaming
+                // would replace it; the temporary file is removed either way.
+                let mut tmp = stage(path)?;
+                tmp.write_all(&bytes)
+                    .with_context(|| format!("unable to write {}", tmp.path().display()))?;
+                make_readable(tmp.path())?;
+                match tmp.persist_noclobber(path) {
+                    Ok(_) => Ok(true),
+                    Err(err) if err.error.kind() == ErrorKind::AlreadyExists => Ok(false),
+                    Err(err) => Err(err.error)
+                        .with_context(|| format!("unable to write {}", path.display())),
+                }
+            }
+            Store::S3 { client, location } => s3::put_object_if_absent(client, location, bytes),
+            Store::Gcs(uri) => {
+                let mut tmp = tempfile::NamedTempFile::new()?;
+                tmp.write_all(&bytes)?;
+                tmp.flush()?;
+                let file = tmp.path().to_string_lossy();
+                let args = ["storage", "cp", "--if-generation-match=0", &file, uri];
+                match run_cli("gcloud", &args)? {
+                    Ok(_) => Ok(true),
+                    Err(stderr) if stderr.contains("412") => Ok(false),
+                    Err(stderr) => bail!("unable to upload {uri}: {stderr}"),
+                }
+            }
+            Store::Azure { container, blob } => {
+                let mut tmp = tempfile::NamedTempFile::new()?;
+                tmp.write_all(&bytes)?;
+                tmp.flush()?;
+                let file = tmp.path().to_string_lossy();
+                let extra = ["--file", &*file, "--if-none-match", "*"];
+                match run_cli("az", &blob_args("upload", container, blob, &extra))? {
+                    Ok(_) => Ok(true),
+                    Err(stderr)
+                        if stderr.contains("BlobAlreadyExists")
+                            || stderr.contains("ConditionNotMet") =>
+                    {
+                        Ok(false)
+                    }
+                    Err(stderr) => bail!("unable to upload {self}: {stderr}"),
+                }
+            }
+        }
+    }
+
+    /// Upload the file at `path` as the object, replacing any previous one, with
+    /// `content_encoding` (e.g. `gzip`) as its `Content-Encoding` where the store has one.
+    /// The file's hex SHA-256 is stored as the object's `sha256` metadata.
+    pub fn put_file(&self, path: &Path, content_encoding: Option<&str>) -> Result<()> {
+        self.put_hashed_file(path, content_encoding, &summary::sha256_file(path)?)
+    }
+
+    /// [`Store::put_file`] for a file whose SHA-256 is already known.
+    pub fn put_hashed_file(
+        &self,
+        path: &Path,
+        content_encoding: Option<&str>,
+        sha256: &str,
+    ) -> Result<()> {
+        match self {
+            Store::Local(dest) => {
+                // Copy-then-rename so readers never see a partly written file.
+                let tmp = stage(dest)?;
+                fs::copy(path, tmp.path())
+                    .with_context(|| format!("unable to write {}", tmp.path().display()))?;
+                make_readable(tmp.path())?;
+                tmp.persist(dest)
+                    .map_err(|err| err.error)
+                    .with_context(|| format!("unable to replace {}", dest.display()))?;
+                Ok(())
+            }
+            Store::S3 { client, location } => {
+                let file = File::open(path)
+                    .with_context(|| format!("unable to open {}", path.display()))?;
+                let len = file.metadata()?.len();
+                s3::upload(client, location, file, len, content_encoding, sha256)
+            }
+            Store::Gcs(uri) => {
+                let file = path.to_string_lossy();
+                let metadata = format!("--custom-metadata=sha256={sha256}");
+                let mut args = vec!["storage", "cp", &metadata];
+                let encoding;
+                if let Some(content_encoding) = content_encoding {
+                    encoding = format!("--content-encoding={content_encoding}");
+                    args.push(&encoding);
+                }
+                args.extend([&*file, uri]);
+                if let Err(stderr) = run_cli("gcloud", &args)? {
+                    bail!("unable to upload {uri}: {stderr}");
+                }
+                Ok(())
+            }
+            Store::Azure { container, blob } => {
+                let file = path.to_string_lossy();
+                let metadata = format!("sha256={sha256}");
+                let mut extra = vec!["--file", &*file, "--overwrite", "--metadata", &metadata];
+                if let Some(content_encoding) = content_encoding {
+                    extra.extend(["--content-encoding", content_encoding]);
+                }
+                let args = blob_args("upload", container, blob, &extra);
+                if let Err(stderr) = run_cli("az", &args)? {
+                    bail!("unable to upload {self}: {stderr}");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Presigned GET URL for the object, valid for `expires_in`, or `None` for stores without
+    /// them (only S3 has them here).
+    pub fn presigned_url(&self, expires_in: Duration) -> Result<Option<String>> {
+        match self {
+            Store::S3 { client, location } => {
+                s3::presign_get(client, location, expires_in).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Delete the object; deleting a missing object is not an error.
+    pub fn delete(&self) -> Result<()> {
+        match self {
+            Store::Local(path) => match fs::remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    Err(err).with_context(|| format!("unable to remove {}", path.display()))
+                }
+                _ => Ok(()),
+            },
+            Store::S3 { client, location } => s3::delete_object(client, location),
+            Store::Gcs(uri) => match run_cli("gcloud", &["storage", "rm", uri])? {
+                Err(stderr) if !stderr.contains("matched no objects") => {
+                    bail!("unable to delete {uri}: {stderr}")
+                }
+                _ => Ok(()),
+            },
+            Store::Azure { container, blob } => {
+                match run_cli("az", &blob_args("delete", container, blob, &[]))? {
+                    Err(stderr) if !stderr.contains("BlobNotFound") => {
+                        bail!("unable to delete {self}: {stderr}")
+                    }
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Store::Local(path) => write!(f, "{}", path.display()),
+            Store::S3 { location, .. } => write!(f, "{location}"),
+            Store::Gcs(uri) => write!(f, "{uri}"),
+            Store::Azure { container, blob } => write!(f, "az://{container}/{blob}"),
+        }
+    }
+}
+
+/// The objects under `prefix`, in key order: the files in a local directory and its
+/// subdirectories, or the objects whose keys start with an object store prefix, as
+/// locations for [`Store::open`].
+pub fn list(prefix: &str) -> Result<Vec<String>> {
+    let bucket_and_key = |rest: &str| match rest.split_once('/') {
+        Some((bucket, key)) => (bucket.to_string(), key.to_string()),
+        None => (rest.to_string(), String::new()),
+    };
+    let mut locations = Vec::new();
+    if let Some(rest) = prefix.strip_prefix("s3://") {
+        let (bucket, key) = bucket_and_key(rest);
+        for object in s3::list_objects(&s3::client(), &bucket, &key)? {
+            locations.push(format!("s3://{bucket}/{}", object.key));
+        }
+    } else if prefix.starts_with("gs://") {
+        match run_cli("gcloud", &["storage", "ls", &format!("{prefix}**")])? {
+            Ok(stdout) => locations.extend(
+                String::from_utf8_lossy(&stdout)
+                    .lines()
+                    .filter(|line| !line.is_empty() && !line.ends_with('/'))
+                    .map(str::to_string),
+            ),
+            Err(stderr) if stderr.contains("matched no objects") => {}
+            Err(stderr) => bail!("unable to list {prefix}: {stderr}"),
+        }
+    } else if let Some(rest) = prefix.strip_prefix("az://") {
+        let (container, key) = bucket_and_key(rest);
+        let args = [
+            "storage",
+            "blob",
+            "list",
+            "--container-name",
+            &container,
+            "--prefix",
+            &key,
+            "--num-results",
+            "*",
+            "--query",
+            "[].name",
+            "--output",
+            "tsv",
+            "--only-show-errors",
+        ];
+        match run_cli("az", &args)? {
+            Ok(stdout) => locations.extend(
+                String::from_utf8_lossy(&stdout)
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(|blob| format!("az://{container}/{blob}")),
+            ),
+            Err(stderr) => bail!("unable to list {prefix}: {stderr}"),
+        }
+    } else if let Some((scheme, _)) = prefix.split_once("://").filter(|(s, _)| *s != "file") {
+        bail!("unsupported location {prefix}: {scheme}:// is not a known object store");
+    } else {
+        let root = PathBuf::from(prefix.strip_prefix("file://").unwrap_or(prefix));
+        let mut dirs = vec![root];
+        while let Some(dir) = dirs.pop() {
+            let entries =
+                fs::read_dir(&dir).with_context(|| format!("unable to list {}", dir.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    locations.push(path.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    locations.sort();
+    Ok(locations)
+}
+
+/// A temporary file of its own next to `path`, for an object to be written to before it
+/// takes `path`'s place, so writers of the same object never share one.
+fn stage(path: &Path) -> Result<tempfile::NamedTempFile> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    tempfile::Builder::new()
+        .suffix(".tmp")
+        .tempfile_in(dir)
+        .with_context(|| format!("unable to create a temporary file in {}", dir.display()))
+}
+
+/// Let every user read a file about to be published locally, as files are by default:
+/// one copied from a temporary file would keep its owner-only mode, leaving tilesets served
+/// from a shared directory unreadable.
+#[cfg(unix)]
+fn make_readable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o644))
+        .with_context(|| format!("unable to set the mode of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn make_readable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Arguments for `az storage blob <action>`; the account and credentials come from the
+/// `AZURE_STORAGE_*` environment.
+fn blob_args<'a>(
+    action: &'a str,
+    container: &'a str,
+    blob: &'a str,
+    extra: &[&'a str],
+) -> Vec<&'a str> {
+    let mut args = vec![
+        "storage",
+        "blob",
+        action,
+        "--container-name",
+        container,
+        "--name",
+        blob,
+        "--only-show-errors",
+    ];
+    args.extend_from_slice(extra);
+    args
+}
+
+/// Run a cloud CLI, returning its stdout, or its stderr if it fails.
+pub fn run_cli(program: &str, args: &[&str]) -> Result<std::result::Result<Vec<u8>, String>> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("unable to run {program}; is it installed?"))?;
+    if output.status.success() {
+        Ok(Ok(output.stdout))
+    } else {
+        Ok(Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()))
+    }
+}
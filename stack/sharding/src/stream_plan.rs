@@ -0,0 +1,106 @@
+//! Shard planning over max-zoom cells streamed in Morton order.
+//!
+//! In Morton order every tile's descendants are contiguous, so a stack with one open tile
+//! per zoom level is enough: when the stream leaves a tile its count is final. A tile over
+//! the threshold resolves to the shards collected from its children; a tile under it
+//! replaces whatever its children collected with itself. The result matches
+//! [`crate::build_shards`], including the order of the shards.
+
+use crate::{key_tile, report_oversized, Shard};
+
+/// One open tile: its key at its own zoom, its running count and the shards its finished
+/// children resolved to.
+struct Frame {
+    key: u64,
+    count: u64,
+    shards: Vec<Shard>,
+}
+
+pub struct StreamPlanner {
+    max_zoom: u8,
+    max_nodes: u64,
+    /// Bits to drop from incoming keys to coarsen them from the scanned zoom to `max_zoom`.
+    shift: u32,
+    frames: Vec<Frame>,
+    shards: Vec<Shard>,
+    oversized: Vec<Shard>,
+}
+
+impl StreamPlanner {
+    pub fn new(scanned_zoom: u8, max_zoom: u8, max_nodes: u64) -> Self {
+        Self {
+            max_zoom,
+            max_nodes,
+            shift: 2 * u32::from(scanned_zoom - max_zoom),
+            frames: Vec::with_capacity(usize::from(max_zoom) + 1),
+            shards: Vec::new(),
+            oversized: Vec::new(),
+        }
+    }
+
+    /// Add one scanned cell. Keys must be non-decreasing.
+    pub fn push(&mut self, key: u64, count: u64) {
+        if count == 0 {
+            return;
+        }
+        let leaf = key >> self.shift;
+
+        let mut open = 0;
+        while open < self.frames.len() && self.frames[open].key == self.key_at(leaf, open) {
+            open += 1;
+        }
+        while self.frames.len() > open {
+            self.close();
+        }
+        for zoom in self.frames.len()..=usize::from(self.max_zoom) {
+            self.frames.push(Frame {
+                key: self.key_at(leaf, zoom),
+                count: 0,
+                shards: Vec::new(),
+            });
+        }
+        self.frames.last_mut().expect("leaf frame").count += count;
+    }
+
+    /// Close every open tile and return the shards.
+    pub fn finish(mut self) -> Vec<Shard> {
+        while !self.frames.is_empty() {
+            self.close();
+        }
+        report_oversized(&self.oversized, self.max_nodes);
+        self.shards
+    }
+
+    fn key_at(&self, leaf: u64, zoom: usize) -> u64 {
+        leaf >> (2 * (usize::from(self.max_zoom) - zoom))
+    }
+
+    fn close(&mut self) {
+        let frame = self.frames.pop().expect("open frame");
+        let zoom = self.frames.len() as u8;
+        let (x, y) = key_tile(frame.key);
+        let shard = Shard {
+            zoom,
+            x,
+            y,
+            node_count: frame.count,
+        };
+
+        let resolved = if frame.count <= self.max_nodes {
+            vec![shard]
+        } else if zoom == self.max_zoom {
+            self.oversized.push(shard);
+            vec![shard]
+        } else {
+            frame.shards
+        };
+
+        match self.frames.last_mut() {
+            Some(parent) => {
+                parent.count += frame.count;
+                parent.shards.extend(resolved);
+            }
+            None => self.shards.extend(resolved),
+        }
+    }
+}
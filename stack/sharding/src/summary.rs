@@ -0,0 +1,202 @@
+//! Machine-readable run summary written next to the manifest, so orchestration can judge a
+//! run from one JSON file instead of parsing stderr.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use tracing::info;
+
+use crate::histogram::Header;
+use crate::scan::SourceMetadata;
+use crate::stats::Distribution;
+use crate::store::Store;
+use crate::{logging, metrics, Parameters, Shard};
+
+/// One input file, with its checksum once hashing has finished and, for PBF input, the
+/// provenance from its header.
+#[derive(Clone, Serialize)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub sha256: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub header: Option<SourceMetadata>,
+}
+
+/// Hash an input on a background thread, so the checksum costs no wall-clock time next to
+/// the (longer) scan of the same file.
+pub fn spawn_sha256(path: &Path) -> JoinHandle<Result<String>> {
+    let path = path.to_path_buf();
+    std::thread::spawn(move || sha256_file(&path))
+}
+
+/// Hex SHA-256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::with_capacity(1 << 20, file), &mut hasher)
+        .with_context(|| format!("unable to hash {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Serialize)]
+struct NodeTotals {
+    nodes: u64,
+    duplicates: u64,
+    estimated: bool,
+}
+
+#[derive(Serialize)]
+struct TileCount {
+    z: u8,
+    x: u32,
+    y: u32,
+    node_count: u64,
+}
+
+/// A file the run produced, with its checksum so consumers can verify what they fetched.
+#[derive(Serialize)]
+struct Artifact {
+    /// `manifest`, `counts`, `pmtiles` or `preview`.
+    kind: &'static str,
+    location: String,
+    bytes: u64,
+    sha256: String,
+}
+
+#[derive(Serialize)]
+struct Timing {
+    phase: &'static str,
+    seconds: f64,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    tool_version: &'static str,
+    started_at: String,
+    finished_at: Option<String>,
+    elapsed_secs: f64,
+    parameters: Option<Parameters>,
+    inputs: Vec<InputFile>,
+    /// Histogram the plan was made from, for `plan` runs.
+    counts: Option<PathBuf>,
+    nodes: Option<NodeTotals>,
+    shards: Option<Distribution>,
+    oversized: Vec<TileCount>,
+    artifacts: Vec<Artifact>,
+    timings: Vec<Timing>,
+    /// Local artifacts, hashed when the summary is written.
+    #[serde(skip)]
+    files: Vec<(&'static str, PathBuf)>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl Summary {
+    pub fn start() -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION"),
+            started_at: logging::timestamp(),
+            finished_at: None,
+            elapsed_secs: 0.0,
+            parameters: None,
+            inputs: Vec::new(),
+            counts: None,
+            nodes: None,
+            shards: None,
+            oversized: Vec::new(),
+            artifacts: Vec::new(),
+            timings: Vec::new(),
+            files: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn set_parameters(&mut self, parameters: Parameters) {
+        self.parameters = Some(parameters);
+    }
+
+    pub fn set_inputs(&mut self, inputs: Vec<InputFile>) {
+        self.inputs = inputs;
+    }
+
+    pub fn set_counts(&mut self, path: &Path) {
+        self.counts = Some(path.to_path_buf());
+    }
+
+    pub fn set_totals(&mut self, header: &Header) {
+        self.nodes = Some(NodeTotals {
+            nodes: header.node_total,
+            duplicates: header.duplicate_total,
+            estimated: header.sample.is_some(),
+        });
+    }
+
+    /// Record the size distribution of the plan; shards over `max_nodes` are the max-zoom
+    /// tiles that could not be split further.
+    pub fn set_shards(&mut self, shards: &[Shard], max_nodes: u64) {
+        self.shards = Distribution::of(shards, max_nodes);
+        self.oversized = shards
+            .iter()
+            .filter(|shard| shard.node_count > max_nodes)
+            .map(|shard| TileCount {
+                z: shard.zoom,
+                x: shard.x,
+                y: shard.y,
+                node_count: shard.node_count,
+            })
+            .collect();
+    }
+
+    /// Record an artifact already uploaded to `location`.
+    pub fn add_artifact(
+        &mut self,
+        kind: &'static str,
+        location: String,
+        bytes: u64,
+        sha256: String,
+    ) {
+        self.artifacts.push(Artifact {
+            kind,
+            location,
+            bytes,
+            sha256,
+        });
+    }
+
+    /// Record an artifact written to the local file at `path`.
+    pub fn add_file(&mut self, kind: &'static str, path: &Path) {
+        self.files.push((kind, path.to_path_buf()));
+    }
+
+    /// Stamp the end time and phase timings, and hash the local artifacts.
+    pub fn finish(&mut self) -> Result<()> {
+        for (kind, file) in std::mem::take(&mut self.files) {
+            let bytes = fs::metadata(&file)
+                .with_context(|| format!("unable to stat {}", file.display()))?
+                .len();
+            let sha256 = sha256_file(&file)?;
+            self.add_artifact(kind, file.display().to_string(), bytes, sha256);
+        }
+        self.finished_at = Some(logging::timestamp());
+        self.elapsed_secs = self.started.elapsed().as_secs_f64();
+        self.timings = metrics::phases()
+            .into_iter()
+            .map(|(phase, seconds)| Timing { phase, seconds })
+            .collect();
+        Ok(())
+    }
+
+    /// Finish the summary and write it to `store`.
+    pub fn write(&mut self, store: &Store) -> Result<()> {
+        self.finish()?;
+        store.put(serde_json::to_vec_pretty(self)?)?;
+        info!(destination = %store, "Wrote run summary to {store}.");
+        Ok(())
+    }
+}
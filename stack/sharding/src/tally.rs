@@ -0,0 +1,172 @@
+//! Per-thread max-zoom counters for the PBF scan.
+//!
+//! For regional extracts the populated tiles cover a compact rectangle, so counting into a
+//! dense array offset by that rectangle's tile range is much cheaper than hashing every
+//! coordinate pair. Anything that falls outside the rectangle still goes to the hash map.
+
+use hashbrown::HashMap;
+use std::str::FromStr;
+
+use crate::lon_lat_to_tile;
+use crate::scan::ScanResult;
+
+/// Dense grids across all worker threads may use at most this much memory.
+const DENSE_MAX_BYTES: usize = 1 << 30;
+
+/// A lon/lat bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl BBox {
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        (self.west..=self.east).contains(&lon) && (self.south..=self.north).contains(&lat)
+    }
+}
+
+impl FromStr for BBox {
+    type Err = String;
+
+    /// Parse `west,south,east,north`.
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let values = raw
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("invalid bounding box '{raw}': {err}"))?;
+        let [west, south, east, north] = values[..] else {
+            return Err(format!(
+                "bounding box '{raw}' must be west,south,east,north"
+            ));
+        };
+        if south > north {
+            return Err(format!("bounding box '{raw}' has south above north"));
+        }
+        Ok(BBox {
+            west,
+            south,
+            east,
+            north,
+        })
+    }
+}
+
+/// Inclusive rectangle of tiles at one zoom level.
+#[derive(Clone, Copy, Debug)]
+pub struct TileRange {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+}
+
+impl TileRange {
+    /// Tiles covering `bbox` at `zoom`, if small enough to count densely on `threads`
+    /// threads. Boxes crossing the antimeridian are not supported.
+    pub fn dense_for(bbox: &BBox, zoom: u8, threads: usize) -> Option<Self> {
+        if bbox.west > bbox.east {
+            return None;
+        }
+        let (x0, y0) = lon_lat_to_tile(bbox.west, bbox.north, zoom)?;
+        let (x1, y1) = lon_lat_to_tile(bbox.east, bbox.south, zoom)?;
+        let range = TileRange {
+            x0,
+            y0,
+            width: x1 - x0 + 1,
+            height: y1 - y0 + 1,
+        };
+        let bytes = range.cells() * std::mem::size_of::<u64>() * threads.max(1);
+        (bytes <= DENSE_MAX_BYTES).then_some(range)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn cells(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+/// Max-zoom counts of one worker: totals and sparse counts in `scan`, plus an optional
+/// dense grid.
+pub struct Tally {
+    scan: ScanResult,
+    grid: Option<(TileRange, Vec<u64>)>,
+}
+
+impl Tally {
+    pub fn new(max_zoom: u8, range: Option<TileRange>) -> Self {
+        Self {
+            scan: ScanResult::empty(max_zoom),
+            grid: range.map(|range| (range, vec![0; range.cells()])),
+        }
+    }
+
+    /// Count one node. Returns false if the coordinate could not be mapped to a tile.
+    pub fn count(&mut self, lon: f64, lat: f64) -> bool {
+        let max_zoom = self.scan.counts.len() - 1;
+        let Some((x, y)) = lon_lat_to_tile(lon, lat, max_zoom as u8) else {
+            return false;
+        };
+        self.scan.node_total += 1;
+
+        if let Some((range, cells)) = &mut self.grid {
+            let (dx, dy) = (x.wrapping_sub(range.x0), y.wrapping_sub(range.y0));
+            if dx < range.width && dy < range.height {
+                cells[dy as usize * range.width as usize + dx as usize] += 1;
+                return true;
+            }
+        }
+        *self.scan.counts[max_zoom].entry((x, y)).or_insert(0) += 1;
+        true
+    }
+
+    pub fn node_total(&self) -> u64 {
+        self.scan.node_total
+    }
+
+    pub fn count_duplicate(&mut self) {
+        self.scan.duplicate_total += 1;
+    }
+
+    /// Approximate heap use of the map and grid.
+    pub fn heap_bytes(&self) -> usize {
+        let grid = self
+            .grid
+            .as_ref()
+            .map_or(0, |(_, cells)| cells.len() * std::mem::size_of::<u64>());
+        self.scan.heap_bytes() + grid
+    }
+
+    /// Hand over everything counted so far, leaving the tally empty (the grid keeps its
+    /// allocation).
+    pub fn take(&mut self) -> ScanResult {
+        let max_zoom = self.scan.counts.len() - 1;
+        let mut scan = std::mem::replace(&mut self.scan, ScanResult::empty(max_zoom as u8));
+        if let Some((range, cells)) = &mut self.grid {
+            drain_grid(range, cells, &mut scan.counts[max_zoom]);
+        }
+        scan
+    }
+}
+
+fn drain_grid(range: &TileRange, cells: &mut [u64], leaves: &mut HashMap<(u32, u32), u64>) {
+    for (idx, count) in cells.iter_mut().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        let x = range.x0 + (idx % range.width as usize) as u32;
+        let y = range.y0 + (idx / range.width as usize) as u32;
+        *leaves.entry((x, y)).or_insert(0) += *count;
+        *count = 0;
+    }
+}
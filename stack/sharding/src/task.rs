@@ -0,0 +1,167 @@
+//! Step Functions callback tasks: with the task token a `.waitForTaskToken` state hands the
+//! job, a run sends heartbeats while it works, through long scans and extractions alike,
+//! then reports its success, with where it wrote its output, or its failure, so the state
+//! machine needs no shim to watch the job.
+//!
+//! Like the notifications, this goes through `aws`.
+
+use anyhow::{bail, Result};
+use clap::Args;
+use serde_json::json;
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+
+use crate::store::run_cli;
+use crate::{keys, logging};
+
+/// Error name of a failed run, for the state machine's `Catch` to match.
+pub const ERROR: &str = "OsmPlanetSharding.Failed";
+/// Most output locations listed, to stay well under the 256 KB of a task's output.
+const MAX_OUTPUTS: usize = 1000;
+/// Longest `cause` Step Functions takes.
+const MAX_CAUSE: usize = 32_768;
+
+#[derive(Args, Debug)]
+pub struct TaskArgs {
+    /// Task token of the Step Functions task this run is, to send heartbeats and its
+    /// success or failure to.
+    #[arg(long, env = "TASK_TOKEN", global = true, hide_env_values = true)]
+    task_token: Option<String>,
+
+    /// Seconds between heartbeats; keep them well under the state's `HeartbeatSeconds`.
+    #[arg(
+        long,
+        env = "TASK_HEARTBEAT_INTERVAL",
+        default_value_t = 60,
+        global = true
+    )]
+    task_heartbeat_interval: u64,
+}
+
+static OUTPUTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Record the location of an artifact the run wrote, for the task's output.
+pub fn record_output(location: String) {
+    OUTPUTS.lock().expect("task lock poisoned").push(location);
+}
+
+/// Sends heartbeats until it is dropped.
+pub struct Heartbeat {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    pub fn start(args: &TaskArgs) -> Self {
+        let Some(token) = args.task_token.clone() else {
+            return Self {
+                stop: None,
+                thread: None,
+            };
+        };
+        let interval = Duration::from_secs(args.task_heartbeat_interval.max(1));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(err) = send(&["send-task-heartbeat", "--task-token", &token]) {
+                    warn!("unable to send a task heartbeat: {err:#}");
+                }
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Report the run's success or failure to the task. Failures to report are logged, not
+/// returned, as the run's outcome is already decided.
+pub fn report(args: &TaskArgs, error: Option<&anyhow::Error>) {
+    let Some(token) = &args.task_token else {
+        return;
+    };
+    let result = match error {
+        None => {
+            let outputs = OUTPUTS.lock().expect("task lock poisoned");
+            let mut output = json!({
+                "run_id": keys::run_id(),
+                "outputs": &outputs[..outputs.len().min(MAX_OUTPUTS)],
+                "output_count": outputs.len(),
+                "finished_at": logging::timestamp(),
+            });
+            if outputs.len() > MAX_OUTPUTS {
+                output["truncated"] = json!(true);
+            }
+            with_file(output.to_string(), |output| {
+                send(&[
+                    "send-task-success",
+                    "--task-token",
+                    token,
+                    "--task-output",
+                    output,
+                ])
+            })
+        }
+        Some(err) => {
+            let mut cause = format!("{err:#}");
+            if cause.len() > MAX_CAUSE {
+                let mut end = MAX_CAUSE;
+                while !cause.is_char_boundary(end) {
+                    end -= 1;
+                }
+                cause.truncate(end);
+            }
+            with_file(cause, |cause| {
+                send(&[
+                    "send-task-failure",
+                    "--task-token",
+                    token,
+                    "--error",
+                    ERROR,
+                    "--cause",
+                    cause,
+                ])
+            })
+        }
+    };
+    let status = match error {
+        Some(_) => "failure",
+        None => "success",
+    };
+    match result {
+        Ok(()) => info!("Reported the run's {status} to its Step Functions task."),
+        Err(err) => warn!("unable to report the run's {status} to its task: {err:#}"),
+    }
+}
+
+/// Call `send` with `text` as a `file://` parameter, as it may be too long for an argument.
+fn with_file(text: String, send: impl FnOnce(&str) -> Result<()>) -> Result<()> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+    send(&format!("file://{}", file.path().display()))
+}
+
+fn send(args: &[&str]) -> Result<()> {
+    let mut all = vec!["stepfunctions"];
+    all.extend(args);
+    if let Err(stderr) = run_cli("aws", &all)? {
+        bail!("{stderr}");
+    }
+    Ok(())
+}
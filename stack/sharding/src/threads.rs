@@ -0,0 +1,108 @@
+//! Sizing and pinning of the rayon worker pool used by the scan.
+
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// A set of CPU indices written like `0-3,8,10-11`.
+#[derive(Clone, Debug)]
+pub struct CpuList(Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let mut cpus = Vec::new();
+        for part in raw
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let parse = |value: &str| {
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|err| format!("invalid CPU index '{value}': {err}"))
+            };
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(format!("invalid CPU range '{part}'"));
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => cpus.push(parse(part)?),
+            }
+        }
+        if cpus.is_empty() {
+            return Err("empty CPU list".to_string());
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuList(cpus))
+    }
+}
+
+/// Configure the global rayon pool: `threads` workers (defaulting to one per listed CPU, or
+/// one per core), each pinned round-robin to a CPU of `cpus` when given.
+pub fn configure(threads: Option<usize>, cpus: Option<&CpuList>) -> Result<()> {
+    if threads.is_none() && cpus.is_none() {
+        return Ok(());
+    }
+    if threads == Some(0) {
+        bail!("--threads must be at least 1");
+    }
+    if cpus.is_some() && !cfg!(target_os = "linux") {
+        bail!("CPU affinity is only supported on Linux");
+    }
+
+    let mut builder = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = threads.or(cpus.map(|cpus| cpus.0.len())) {
+        builder = builder.num_threads(threads);
+    }
+    if let Some(cpus) = cpus.cloned() {
+        builder = builder.start_handler(move |index| {
+            let cpu = cpus.0[index % cpus.0.len()];
+            if let Err(err) = pin_current_thread(cpu) {
+                warn!(
+                    worker = index,
+                    cpu, "unable to pin scan worker {index} to CPU {cpu}: {err}"
+                );
+            }
+        });
+    }
+    builder
+        .build_global()
+        .context("unable to configure the scan thread pool")?;
+
+    info!(
+        threads = rayon::current_num_threads(),
+        "Scanning with {} worker threads{}.",
+        rayon::current_num_threads(),
+        match cpus {
+            Some(cpus) => format!(" pinned to CPUs {:?}", cpus.0),
+            None => String::new(),
+        }
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn pin_current_thread(cpu: usize) -> std::io::Result<()> {
+    // SAFETY: cpu_set_t is a plain bitmask; zeroed is the empty set, and CPU_SET ignores
+    // indices beyond its capacity.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_cpu: usize) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}